@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use collab::core::awareness::AwarenessUpdate;
+use collab::lock::RwLock;
+use collab::preclude::Collab;
+use collab_entity::CollabObject;
+use tokio::select;
+use tokio::spawn;
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::{error, trace};
+use yrs::block::ClientID;
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::Encode;
+
+use crate::cloud_storage::remote_collab::{RemoteAwarenessReceiver, RemoteCollabStorage};
+
+/// The clients whose awareness states were received from the remote. The value is the instant
+/// the state of the client was last received, or `None` if the state was removed.
+///
+/// A client is never forgotten, so its state is not sent back to the remote even after it was
+/// removed locally.
+type RemotePeers = Arc<Mutex<HashMap<ClientID, Option<Instant>>>>;
+
+/// Sync the awareness states of a collab with the remote.
+///
+/// The local awareness states are not persisted nor acked. Only the latest one is kept and sent
+/// when the remote is enabled, and it's renewed periodically so the other clients don't time it
+/// out. The awareness states of the other clients are applied to the local collab, and removed
+/// when they are not received again within the `timeout`, or when the remote is disabled.
+pub(crate) struct RemoteAwareness {
+  local_update: watch::Sender<Option<Vec<u8>>>,
+  remote_peers: RemotePeers,
+}
+
+impl RemoteAwareness {
+  pub(crate) fn new(
+    object: CollabObject,
+    storage: Weak<dyn RemoteCollabStorage>,
+    local_collab: Weak<RwLock<Collab>>,
+    remote_updates: Option<RemoteAwarenessReceiver>,
+    timeout: Duration,
+  ) -> Self {
+    let (local_update, local_update_rx) = watch::channel(None);
+    let remote_peers = RemotePeers::default();
+    let runner = AwarenessRunner {
+      object,
+      storage,
+      local_collab,
+      remote_peers: remote_peers.clone(),
+      timeout,
+    };
+    spawn(runner.run(local_update_rx, remote_updates));
+    Self {
+      local_update,
+      remote_peers,
+    }
+  }
+
+  /// Queue the awareness update of the local collab. The states of the remote clients are
+  /// filtered out, so the states received from the remote are not sent back.
+  pub(crate) fn push_update(&self, update: &AwarenessUpdate) {
+    let mut update = update.clone();
+    {
+      let remote_peers = self.remote_peers.lock().unwrap();
+      update
+        .clients
+        .retain(|client_id, _| !remote_peers.contains_key(client_id));
+    }
+    if !update.clients.is_empty() {
+      self.local_update.send_replace(Some(update.encode_v1()));
+    }
+  }
+}
+
+struct AwarenessRunner {
+  object: CollabObject,
+  storage: Weak<dyn RemoteCollabStorage>,
+  local_collab: Weak<RwLock<Collab>>,
+  remote_peers: RemotePeers,
+  timeout: Duration,
+}
+
+impl AwarenessRunner {
+  async fn run(
+    self,
+    mut local_update_rx: watch::Receiver<Option<Vec<u8>>>,
+    mut remote_updates: Option<RemoteAwarenessReceiver>,
+  ) {
+    let mut interval = tokio::time::interval((self.timeout / 4).max(Duration::from_millis(10)));
+    let mut unsent_update: Option<Vec<u8>> = None;
+    let mut last_sent_at: Option<Instant> = None;
+    loop {
+      select! {
+        result = local_update_rx.changed() => {
+          // The [RemoteCollab] was dropped.
+          if result.is_err() {
+            break;
+          }
+          unsent_update = local_update_rx.borrow_and_update().clone();
+        },
+        update = recv_remote_update(&mut remote_updates) => {
+          match update {
+            Some(update) => self.apply_remote_update(&update).await,
+            None => {
+              trace!("{} remote awareness closed", self.object);
+              remote_updates = None;
+              self.remove_remote_peers(|_| true).await;
+            },
+          }
+        },
+        _ = interval.tick() => {
+          let storage = self.storage.upgrade();
+          if storage.map(|storage| storage.is_enable()).unwrap_or(false) {
+            let now = Instant::now();
+            self.remove_remote_peers(|last_seen_at| now - last_seen_at >= self.timeout).await;
+            // Renew the local state before the other clients time it out.
+            if last_sent_at.is_some_and(|at| now - at >= self.timeout / 2) {
+              if let Some(update) = self.renew_local_state().await {
+                // Skip the renewed state if it was pushed by the plugin of the collab.
+                local_update_rx.borrow_and_update();
+                unsent_update = Some(update);
+              }
+            }
+          } else {
+            // The remote is disconnected, so the states of the other clients are stale.
+            self.remove_remote_peers(|_| true).await;
+          }
+        },
+      }
+
+      if let Some(update) = unsent_update.take() {
+        if self.send_local_update(update.clone()).await {
+          last_sent_at = Some(Instant::now());
+        } else {
+          unsent_update = Some(update);
+        }
+      }
+    }
+  }
+
+  /// Set the local state again to increase its clock. The other clients ignore the states whose
+  /// clock is not greater than the one they have, so a state with the same clock would not be
+  /// applied again after it was timed out.
+  async fn renew_local_state(&self) -> Option<Vec<u8>> {
+    let local_collab = self.local_collab.upgrade()?;
+    let lock = local_collab.read().await;
+    let awareness = lock.get_awareness();
+    let state = awareness.local_state_raw()?;
+    awareness.set_local_state_raw(state);
+    let update = awareness
+      .update_with_clients([awareness.client_id()])
+      .ok()?;
+    Some(update.encode_v1())
+  }
+
+  /// Return true if the update was sent. Otherwise, it's sent again after the next event.
+  async fn send_local_update(&self, update: Vec<u8>) -> bool {
+    let storage = match self.storage.upgrade() {
+      None => return false,
+      Some(storage) => storage,
+    };
+    if !storage.is_enable() {
+      return false;
+    }
+    match storage.send_awareness_update(&self.object, update).await {
+      Ok(_) => true,
+      Err(err) => {
+        error!(
+          "🔴Failed to send awareness update of {}: {:?}",
+          self.object, err
+        );
+        false
+      },
+    }
+  }
+
+  async fn apply_remote_update(&self, update: &[u8]) {
+    let update = match AwarenessUpdate::decode_v1(update) {
+      Ok(update) => update,
+      Err(err) => {
+        error!("🔴Failed to decode remote awareness update: {:?}", err);
+        return;
+      },
+    };
+    let local_collab = match self.local_collab.upgrade() {
+      None => return,
+      Some(local_collab) => local_collab,
+    };
+    let lock = local_collab.read().await;
+    let awareness = lock.get_awareness();
+    let local_client_id = awareness.client_id();
+    {
+      let now = Instant::now();
+      let mut remote_peers = self.remote_peers.lock().unwrap();
+      for (client_id, entry) in update.clients.iter() {
+        if *client_id != local_client_id {
+          let last_seen_at = (entry.json.as_ref() != "null").then_some(now);
+          remote_peers.insert(*client_id, last_seen_at);
+        }
+      }
+    }
+    if let Err(err) = awareness.apply_update(update) {
+      error!("🔴Failed to apply remote awareness update: {:?}", err);
+    }
+  }
+
+  /// Remove the states of the remote clients whose last seen instant matches the predicate.
+  async fn remove_remote_peers<F>(&self, predicate: F)
+  where
+    F: Fn(Instant) -> bool,
+  {
+    let client_ids = {
+      let mut remote_peers = self.remote_peers.lock().unwrap();
+      remote_peers
+        .iter_mut()
+        .filter(|(_, last_seen_at)| last_seen_at.is_some_and(&predicate))
+        .map(|(client_id, last_seen_at)| {
+          *last_seen_at = None;
+          *client_id
+        })
+        .collect::<Vec<_>>()
+    };
+    if client_ids.is_empty() {
+      return;
+    }
+    if let Some(local_collab) = self.local_collab.upgrade() {
+      trace!("{} remove stale awareness: {:?}", self.object, client_ids);
+      let lock = local_collab.read().await;
+      for client_id in client_ids {
+        lock.get_awareness().remove_state(client_id);
+      }
+    }
+  }
+}
+
+async fn recv_remote_update(
+  remote_updates: &mut Option<RemoteAwarenessReceiver>,
+) -> Option<Vec<u8>> {
+  match remote_updates {
+    Some(remote_updates) => remote_updates.recv().await,
+    None => std::future::pending().await,
+  }
+}
@@ -7,7 +7,7 @@ use tokio::sync::mpsc::UnboundedSender;
 
 use crate::cloud_storage::error::SyncError;
 
-#[allow(dead_code)]
+/// A connection that sends and receives the messages of the collab sync.
 pub trait CollabConnect<Item>: Sink<Item> + Stream {}
 
 pub struct TokioUnboundedSink<T>(pub UnboundedSender<T>);
@@ -3,12 +3,35 @@ pub enum SyncError {
   #[error("failed to deserialize message: {0}")]
   DecodingError(#[from] yrs::encoding::read::Error),
 
+  #[error("failed to apply update: {0}")]
+  UpdateError(#[from] yrs::error::UpdateError),
+
+  #[error("failed to update awareness: {0}")]
+  AwarenessError(#[from] yrs::sync::awareness::Error),
+
+  #[error("failed to acquire transaction: {0}")]
+  TransactionAcqError(#[from] yrs::TransactionAcqError),
+
+  #[error("unexpected sync message: {0}")]
+  UnexpectedMessage(String),
+
   #[error(transparent)]
   SerdeError(#[from] serde_json::Error),
 
   #[error(transparent)]
   TokioTask(#[from] tokio::task::JoinError),
 
+  #[error(transparent)]
+  Persistence(#[from] crate::local_storage::kv::PersistenceError),
+
+  #[cfg(feature = "sync_server")]
+  #[error(transparent)]
+  WebSocket(#[from] tokio_tungstenite::tungstenite::Error),
+
+  #[cfg(feature = "sync_server")]
+  #[error(transparent)]
+  ProtobufDecoding(#[from] prost::DecodeError),
+
   #[error(transparent)]
   IO(#[from] std::io::Error),
 
@@ -1,15 +1,42 @@
+pub use channel::{CollabConnect, TokioUnboundedSink};
+pub use error::SyncError;
+pub use mux::{CollabMultiplexer, MultiplexedConnect, MultiplexerConfig};
+pub use outbox::{CollabOutbox, SinkOutbox};
+pub use peer::{PeerSync, PeerSyncState};
+pub use protocol::{
+  handle_init_sync, handle_init_sync_with_updates, make_init_sync, ServerInitPayload,
+};
 pub use remote_collab::{
-  RemoteCollabSnapshot, RemoteCollabState, RemoteCollabStorage, RemoteUpdateReceiver,
+  RemoteAwarenessReceiver, RemoteAwarenessSender, RemoteBroadcastReceiver, RemoteBroadcastSender,
+  RemoteCollab, RemoteCollabSnapshot, RemoteCollabState, RemoteCollabStorage, RemoteUpdateReceiver,
   RemoteUpdateSender,
 };
+pub use retry::RetryPolicy;
+pub use seq::{RemoteBroadcast, SeqNumCheck, SeqNumTracker};
+#[cfg(feature = "sync_server")]
+pub use server::{LocalSyncServer, LoopbackConnect, ACK_CODE_INVALID_MESSAGE, ACK_CODE_SUCCESS};
+pub use sink::{SinkConfig, SinkStrategy};
+#[cfg(feature = "sync_server")]
+pub use ws::WebSocketConnect;
 pub use yrs::merge_updates_v1;
 pub use yrs::updates::decoder::Decode;
 pub use yrs::Update as YrsUpdate;
 
 pub mod postgres;
 
+mod awareness;
 mod channel;
 mod error;
 mod msg;
+mod mux;
+mod outbox;
+mod peer;
+mod protocol;
 mod remote_collab;
+mod retry;
+mod seq;
+#[cfg(feature = "sync_server")]
+mod server;
 mod sink;
+#[cfg(feature = "sync_server")]
+mod ws;
@@ -67,6 +67,8 @@ pub(crate) struct PendingMessage<Msg> {
   msg_id: MsgId,
   state: MessageState,
   tx: Option<oneshot::Sender<MsgId>>,
+  /// The number of times the message was sent without being acked.
+  attempts: u32,
 }
 
 impl<Msg> PendingMessage<Msg>
@@ -79,6 +81,7 @@ where
       msg_id,
       state: MessageState::Pending,
       tx: None,
+      attempts: 0,
     }
   }
 
@@ -104,6 +107,14 @@ where
   pub fn msg_id(&self) -> MsgId {
     self.msg_id
   }
+
+  pub fn attempts(&self) -> u32 {
+    self.attempts
+  }
+
+  pub fn increase_attempts(&mut self) {
+    self.attempts = self.attempts.saturating_add(1);
+  }
 }
 
 impl<Msg> PendingMessage<Msg>
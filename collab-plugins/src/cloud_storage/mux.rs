@@ -0,0 +1,540 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use collab_entity::proto::collab::collab_message::Message;
+use collab_entity::proto::collab::CollabMessage;
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use tokio::spawn;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{AcquireError, Notify, OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+use tracing::{error, trace, warn};
+
+use crate::cloud_storage::channel::CollabConnect;
+use crate::cloud_storage::error::SyncError;
+
+#[derive(Clone, Debug)]
+pub struct MultiplexerConfig {
+  /// `max_queued_messages` is the maximum number of messages that are queued but not written to
+  /// the connection yet. It's shared by all the objects, so a [MultiplexedConnect] waits to send
+  /// when the connection can't keep up with the other objects.
+  pub max_queued_messages: usize,
+  /// `max_pending_init_syncs` is the maximum number of init syncs that are waiting for the
+  /// answer of the remote. The other init syncs stay in the queue.
+  pub max_pending_init_syncs: usize,
+  /// `max_batch_size` is the maximum number of messages that are written before the connection
+  /// is flushed.
+  pub max_batch_size: usize,
+  /// `init_sync_timeout` is the time to wait for the answer of an init sync. After that, the
+  /// init sync doesn't count towards [MultiplexerConfig::max_pending_init_syncs] anymore.
+  pub init_sync_timeout: Duration,
+}
+
+impl MultiplexerConfig {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn with_max_queued_messages(mut self, max_queued_messages: usize) -> Self {
+    self.max_queued_messages = max_queued_messages.max(1);
+    self
+  }
+
+  pub fn with_max_pending_init_syncs(mut self, max_pending_init_syncs: usize) -> Self {
+    self.max_pending_init_syncs = max_pending_init_syncs.max(1);
+    self
+  }
+
+  pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+    self.max_batch_size = max_batch_size.max(1);
+    self
+  }
+
+  pub fn with_init_sync_timeout(mut self, init_sync_timeout: Duration) -> Self {
+    self.init_sync_timeout = init_sync_timeout;
+    self
+  }
+}
+
+impl Default for MultiplexerConfig {
+  fn default() -> Self {
+    Self {
+      max_queued_messages: 1024,
+      max_pending_init_syncs: 20,
+      max_batch_size: 64,
+      init_sync_timeout: Duration::from_secs(30),
+    }
+  }
+}
+
+/// Sync many collabs over one [CollabConnect].
+///
+/// Each object gets a [MultiplexedConnect] from [CollabMultiplexer::connect]. The messages it
+/// sends are queued and written to the connection by one task, and the messages received from
+/// the connection are routed to the [MultiplexedConnect] of their object id. The next message to
+/// write is chosen in this order:
+/// 1. The messages of the foreground object, see [CollabMultiplexer::set_foreground].
+/// 2. The other messages, in the order they were sent.
+/// 3. The [Message::ClientInitSync]s, as long as less than
+///    [MultiplexerConfig::max_pending_init_syncs] are waiting for the answer of the remote. So
+///    opening a database with hundreds of rows doesn't flood the remote with init syncs. An init
+///    sync that isn't answered within [MultiplexerConfig::init_sync_timeout] stops waiting.
+///
+/// The other messages of an object are held back while its init sync is queued, so the remote
+/// always receives the init sync of an object first.
+///
+/// The connection is closed when the [CollabMultiplexer] and all its [MultiplexedConnect]s are
+/// dropped.
+#[derive(Clone)]
+pub struct CollabMultiplexer {
+  inner: Arc<MuxInner>,
+}
+
+impl CollabMultiplexer {
+  pub fn new<C>(connect: C, config: MultiplexerConfig) -> Self
+  where
+    C: CollabConnect<CollabMessage>
+      + Sink<CollabMessage, Error = SyncError>
+      + Stream<Item = Result<CollabMessage, SyncError>>
+      + Send
+      + 'static,
+  {
+    let writer_notify = Arc::new(Notify::new());
+    let inner = Arc::new(MuxInner {
+      permits: Arc::new(Semaphore::new(config.max_queued_messages)),
+      config,
+      state: Mutex::new(MuxState::default()),
+      writer_notify: writer_notify.clone(),
+    });
+    let (sink, stream) = connect.split();
+    spawn(write_messages(Arc::downgrade(&inner), writer_notify, sink));
+    spawn(read_messages(Arc::downgrade(&inner), stream));
+    Self { inner }
+  }
+
+  /// Return the [MultiplexedConnect] of the given object id. If the object id was connected
+  /// before, the previous [MultiplexedConnect] doesn't receive the messages anymore.
+  pub fn connect(&self, object_id: &str) -> MultiplexedConnect {
+    let (sender, receiver) = unbounded_channel();
+    let mut state = self.inner.state.lock().unwrap();
+    if !state.is_closed {
+      state.routes.insert(object_id.to_string(), sender);
+    }
+    drop(state);
+    MultiplexedConnect {
+      object_id: object_id.to_string(),
+      inner: self.inner.clone(),
+      receiver,
+      permit: None,
+      acquire: None,
+    }
+  }
+
+  /// Write the messages of the given object before the messages of the other objects. Usually,
+  /// it's the object that is opened by the user.
+  pub fn set_foreground(&self, object_id: Option<&str>) {
+    self.inner.state.lock().unwrap().foreground = object_id.map(|id| id.to_string());
+    self.inner.writer_notify.notify_one();
+  }
+
+  /// Return the number of messages that are not written to the connection yet.
+  pub fn number_of_queued_messages(&self) -> usize {
+    let state = self.inner.state.lock().unwrap();
+    state.messages.len() + state.init_syncs.len()
+  }
+
+  /// Return the number of init syncs that are waiting for the answer of the remote.
+  pub fn number_of_pending_init_syncs(&self) -> usize {
+    self.inner.state.lock().unwrap().pending_init_syncs.len()
+  }
+
+  /// Return true if the connection failed or was closed.
+  pub fn is_closed(&self) -> bool {
+    self.inner.state.lock().unwrap().is_closed
+  }
+}
+
+struct MuxInner {
+  config: MultiplexerConfig,
+  state: Mutex<MuxState>,
+  permits: Arc<Semaphore>,
+  writer_notify: Arc<Notify>,
+}
+
+impl Drop for MuxInner {
+  fn drop(&mut self) {
+    // Wake up the writer to close the connection.
+    self.writer_notify.notify_one();
+  }
+}
+
+impl MuxInner {
+  fn queue_msg(
+    &self,
+    object_id: &str,
+    msg: Message,
+    permit: OwnedSemaphorePermit,
+  ) -> Result<(), SyncError> {
+    let mut state = self.state.lock().unwrap();
+    if state.is_closed {
+      return Err(connection_closed());
+    }
+    let queued = QueuedMessage {
+      object_id: object_id.to_string(),
+      msg,
+      _permit: permit,
+    };
+    if matches!(queued.msg, Message::ClientInitSync(_)) {
+      state.init_syncs.push_back(queued);
+    } else {
+      state.messages.push_back(queued);
+    }
+    drop(state);
+    self.writer_notify.notify_one();
+    Ok(())
+  }
+
+  /// Return the next messages to write. When no message can be written, also return the time
+  /// the oldest pending init sync expires, if an init sync is waiting for it.
+  fn next_batch(&self) -> (Vec<QueuedMessage>, Option<Instant>) {
+    let mut state = self.state.lock().unwrap();
+    state.expire_init_syncs(Instant::now());
+    let mut batch = vec![];
+    while batch.len() < self.config.max_batch_size {
+      match state.pop_next(&self.config) {
+        None => break,
+        Some(queued) => batch.push(queued),
+      }
+    }
+    let deadline = if batch.is_empty() && !state.init_syncs.is_empty() {
+      state
+        .pending_init_syncs
+        .values()
+        .map(|pending| pending.expires_at)
+        .min()
+    } else {
+      None
+    };
+    (batch, deadline)
+  }
+
+  fn route(&self, msg: Message) {
+    let object_id = object_id_of(&msg).to_string();
+    let mut state = self.state.lock().unwrap();
+    let is_init_sync_answered = match &msg {
+      Message::ServerInitSync(_) => true,
+      // The remote acks the init sync that it can't handle.
+      Message::ClientAck(ack) => state
+        .pending_init_syncs
+        .get(&object_id)
+        .map_or(false, |pending| pending.msg_id == ack.msg_id),
+      _ => false,
+    };
+    if is_init_sync_answered && state.pending_init_syncs.remove(&object_id).is_some() {
+      self.writer_notify.notify_one();
+    }
+
+    let msg = CollabMessage { message: Some(msg) };
+    match state.routes.get(&object_id) {
+      None => trace!("skip the message of unknown object:{}", object_id),
+      Some(sender) => {
+        if sender.send(msg).is_err() {
+          state.routes.remove(&object_id);
+        }
+      },
+    }
+  }
+
+  fn disconnect(&self, object_id: &str) {
+    let mut state = self.state.lock().unwrap();
+    let is_disconnected = state
+      .routes
+      .get(object_id)
+      .map(|sender| sender.is_closed())
+      .unwrap_or(false);
+    if is_disconnected {
+      state.routes.remove(object_id);
+      if state.pending_init_syncs.remove(object_id).is_some() {
+        self.writer_notify.notify_one();
+      }
+    }
+  }
+
+  /// Stop routing the messages and reject the new ones.
+  fn close(&self) {
+    let mut state = self.state.lock().unwrap();
+    state.is_closed = true;
+    state.routes.clear();
+    state.messages.clear();
+    state.init_syncs.clear();
+    state.pending_init_syncs.clear();
+    drop(state);
+    self.permits.close();
+    self.writer_notify.notify_one();
+  }
+}
+
+#[derive(Default)]
+struct MuxState {
+  routes: HashMap<String, UnboundedSender<CollabMessage>>,
+  foreground: Option<String>,
+  /// The queued messages except the init syncs.
+  messages: VecDeque<QueuedMessage>,
+  init_syncs: VecDeque<QueuedMessage>,
+  /// The init syncs that are waiting for the answer of the remote, by object id.
+  pending_init_syncs: HashMap<String, PendingInitSync>,
+  is_closed: bool,
+}
+
+struct PendingInitSync {
+  msg_id: u64,
+  expires_at: Instant,
+}
+
+impl MuxState {
+  fn pop_next(&mut self, config: &MultiplexerConfig) -> Option<QueuedMessage> {
+    if let Some(foreground) = &self.foreground {
+      let position = self
+        .init_syncs
+        .iter()
+        .position(|queued| &queued.object_id == foreground);
+      if let Some(queued) = position.and_then(|index| self.init_syncs.remove(index)) {
+        return Some(self.on_init_sync_sent(queued, config.init_sync_timeout));
+      }
+
+      let position = self
+        .messages
+        .iter()
+        .position(|queued| &queued.object_id == foreground);
+      if let Some(queued) = position.and_then(|index| self.messages.remove(index)) {
+        return Some(queued);
+      }
+    }
+
+    // Hold back the messages of the objects whose init sync is still queued.
+    let held_back = self
+      .init_syncs
+      .iter()
+      .map(|queued| queued.object_id.as_str())
+      .collect::<HashSet<_>>();
+    let position = self
+      .messages
+      .iter()
+      .position(|queued| !held_back.contains(queued.object_id.as_str()));
+    if let Some(queued) = position.and_then(|index| self.messages.remove(index)) {
+      return Some(queued);
+    }
+
+    if self.pending_init_syncs.len() < config.max_pending_init_syncs {
+      let queued = self.init_syncs.pop_front()?;
+      return Some(self.on_init_sync_sent(queued, config.init_sync_timeout));
+    }
+    None
+  }
+
+  fn on_init_sync_sent(&mut self, queued: QueuedMessage, timeout: Duration) -> QueuedMessage {
+    if let Message::ClientInitSync(init_sync) = &queued.msg {
+      self.pending_init_syncs.insert(
+        queued.object_id.clone(),
+        PendingInitSync {
+          msg_id: init_sync.msg_id,
+          expires_at: Instant::now() + timeout,
+        },
+      );
+    }
+    queued
+  }
+
+  /// Stop waiting for the init syncs that were not answered in time.
+  fn expire_init_syncs(&mut self, now: Instant) {
+    self.pending_init_syncs.retain(|object_id, pending| {
+      let is_expired = pending.expires_at <= now;
+      if is_expired {
+        warn!(
+          "🟡The init sync:{} of {} was not answered in time",
+          pending.msg_id, object_id
+        );
+      }
+      !is_expired
+    });
+  }
+}
+
+struct QueuedMessage {
+  object_id: String,
+  msg: Message,
+  /// Release the room of the message in the queue after it was written.
+  _permit: OwnedSemaphorePermit,
+}
+
+async fn write_messages<S>(weak_inner: Weak<MuxInner>, writer_notify: Arc<Notify>, mut sink: S)
+where
+  S: Sink<CollabMessage, Error = SyncError> + Unpin,
+{
+  loop {
+    let (batch, deadline) = match weak_inner.upgrade() {
+      None => break,
+      Some(inner) if inner.state.lock().unwrap().is_closed => break,
+      Some(inner) => inner.next_batch(),
+    };
+    if batch.is_empty() {
+      match deadline {
+        // Wake up when the oldest pending init sync expires, to write the next one.
+        Some(deadline) => {
+          let _ = tokio::time::timeout_at(deadline, writer_notify.notified()).await;
+        },
+        None => writer_notify.notified().await,
+      }
+      continue;
+    }
+
+    trace!("write {} collab messages", batch.len());
+    let mut result = Ok(());
+    for queued in batch {
+      let msg = CollabMessage {
+        message: Some(queued.msg),
+      };
+      result = sink.feed(msg).await;
+      if result.is_err() {
+        break;
+      }
+    }
+    if result.is_ok() {
+      result = sink.flush().await;
+    }
+    if let Err(err) = result {
+      error!("🔴Failed to write collab messages: {}", err);
+      if let Some(inner) = weak_inner.upgrade() {
+        inner.close();
+      }
+      break;
+    }
+  }
+  let _ = sink.close().await;
+}
+
+async fn read_messages<S>(weak_inner: Weak<MuxInner>, mut stream: S)
+where
+  S: Stream<Item = Result<CollabMessage, SyncError>> + Unpin,
+{
+  while let Some(msg) = stream.next().await {
+    let inner = match weak_inner.upgrade() {
+      None => break,
+      Some(inner) => inner,
+    };
+    match msg {
+      Ok(CollabMessage { message: Some(msg) }) => inner.route(msg),
+      Ok(_) => {},
+      #[cfg(feature = "sync_server")]
+      Err(SyncError::ProtobufDecoding(err)) => tracing::warn!("🟡skip invalid message: {}", err),
+      Err(err) => {
+        error!("🔴Failed to read collab messages: {}", err);
+        break;
+      },
+    }
+  }
+  if let Some(inner) = weak_inner.upgrade() {
+    inner.close();
+  }
+}
+
+fn object_id_of(msg: &Message) -> &str {
+  match msg {
+    Message::ClientInitSync(init_sync) => &init_sync.object_id,
+    Message::ClientUpdateSync(update_sync) => &update_sync.object_id,
+    Message::ClientAck(ack) => &ack.object_id,
+    Message::ServerInitSync(server_init) => &server_init.object_id,
+    Message::AwarenessSync(awareness_sync) => &awareness_sync.object_id,
+    Message::ServerBroadcast(broadcast) => &broadcast.object_id,
+  }
+}
+
+fn connection_closed() -> SyncError {
+  SyncError::IO(std::io::Error::new(
+    std::io::ErrorKind::BrokenPipe,
+    "multiplexed connection is closed",
+  ))
+}
+
+type AcquireFuture =
+  Pin<Box<dyn Future<Output = Result<OwnedSemaphorePermit, AcquireError>> + Send>>;
+
+/// The [CollabConnect] of one object of a [CollabMultiplexer]. It receives the messages of its
+/// object id only.
+pub struct MultiplexedConnect {
+  object_id: String,
+  inner: Arc<MuxInner>,
+  receiver: UnboundedReceiver<CollabMessage>,
+  /// The room in the queue of the [CollabMultiplexer] for the next message.
+  permit: Option<OwnedSemaphorePermit>,
+  acquire: Option<AcquireFuture>,
+}
+
+impl MultiplexedConnect {
+  pub fn object_id(&self) -> &str {
+    &self.object_id
+  }
+}
+
+impl Drop for MultiplexedConnect {
+  fn drop(&mut self) {
+    self.receiver.close();
+    self.inner.disconnect(&self.object_id);
+  }
+}
+
+impl Sink<CollabMessage> for MultiplexedConnect {
+  type Error = SyncError;
+
+  fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    if self.permit.is_some() {
+      return Poll::Ready(Ok(()));
+    }
+    let permits = self.inner.permits.clone();
+    let acquire = self
+      .acquire
+      .get_or_insert_with(|| Box::pin(permits.acquire_owned()));
+    let result = ready!(acquire.as_mut().poll(cx));
+    self.acquire = None;
+    match result {
+      Ok(permit) => {
+        self.permit = Some(permit);
+        Poll::Ready(Ok(()))
+      },
+      Err(_) => Poll::Ready(Err(connection_closed())),
+    }
+  }
+
+  fn start_send(mut self: Pin<&mut Self>, item: CollabMessage) -> Result<(), Self::Error> {
+    let permit = self.permit.take().ok_or_else(|| {
+      SyncError::Internal("start_send is called before poll_ready".to_string().into())
+    })?;
+    match item.message {
+      None => Ok(()),
+      Some(msg) => self.inner.queue_msg(&self.object_id, msg, permit),
+    }
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    // The queued messages are flushed by the writer of the [CollabMultiplexer].
+    Poll::Ready(Ok(()))
+  }
+
+  fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl Stream for MultiplexedConnect {
+  type Item = Result<CollabMessage, SyncError>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    self.receiver.poll_recv(cx).map(|msg| msg.map(Ok))
+  }
+}
+
+impl CollabConnect<CollabMessage> for MultiplexedConnect {}
@@ -0,0 +1,103 @@
+use std::sync::Weak;
+
+use collab_entity::CollabObject;
+
+use crate::cloud_storage::error::SyncError;
+use crate::cloud_storage::msg::MsgId;
+use crate::local_storage::kv::outbox::{OutboxAction, OutboxMsg};
+use crate::local_storage::kv::KVTransactionDB;
+
+/// The [SinkOutbox] persists the messages queued in the `CollabSink`, so the messages that were
+/// not acked by the remote are sent again after a restart.
+pub trait SinkOutbox<Msg>: Send + Sync + 'static {
+  /// Return the persisted messages ordered by their msg id.
+  fn load(&self) -> Result<Vec<(MsgId, Msg)>, SyncError>;
+
+  /// Persist the message that was queued.
+  fn push(&self, msg_id: MsgId, msg: &Msg) -> Result<(), SyncError>;
+
+  /// Persist the message that the messages of `merged_msg_ids` were merged into.
+  fn merge(&self, msg_id: MsgId, msg: &Msg, merged_msg_ids: &[MsgId]) -> Result<(), SyncError>;
+
+  /// Remove the message that was acked by the remote.
+  fn remove(&self, msg_id: MsgId) -> Result<(), SyncError>;
+
+  /// Remove all the persisted messages.
+  fn clear(&self) -> Result<(), SyncError>;
+
+  /// Return the number of local changes that are not synced yet.
+  fn number_of_unsynced_changes(&self) -> u64;
+}
+
+/// The outbox of a collab object that is stored in the collab KV database.
+pub struct CollabOutbox<DB> {
+  pub(crate) object: CollabObject,
+  collab_db: Weak<DB>,
+}
+
+impl<DB> CollabOutbox<DB>
+where
+  DB: KVTransactionDB,
+{
+  pub fn new(object: CollabObject, collab_db: Weak<DB>) -> Self {
+    Self { object, collab_db }
+  }
+
+  pub(crate) fn load_msgs(&self) -> Vec<OutboxMsg> {
+    self
+      .collab_db
+      .upgrade()
+      .map(|db| {
+        db.read_txn()
+          .get_outbox_msgs(self.object.uid, &self.object.object_id)
+      })
+      .unwrap_or_default()
+  }
+
+  pub(crate) fn push_msg(&self, msg: &OutboxMsg) -> Result<(), SyncError> {
+    if let Some(db) = self.collab_db.upgrade() {
+      db.with_write_txn(|txn| txn.push_outbox_msg(self.object.uid, &self.object.object_id, msg))?;
+    }
+    Ok(())
+  }
+
+  pub(crate) fn merge_msgs(
+    &self,
+    msg: &OutboxMsg,
+    merged_msg_ids: &[MsgId],
+  ) -> Result<(), SyncError> {
+    if let Some(db) = self.collab_db.upgrade() {
+      db.with_write_txn(|txn| {
+        txn.merge_outbox_msgs(self.object.uid, &self.object.object_id, msg, merged_msg_ids)
+      })?;
+    }
+    Ok(())
+  }
+
+  pub(crate) fn remove_msg(&self, msg_id: MsgId) -> Result<(), SyncError> {
+    if let Some(db) = self.collab_db.upgrade() {
+      db.with_write_txn(|txn| {
+        txn.remove_outbox_msg(self.object.uid, &self.object.object_id, msg_id)
+      })?;
+    }
+    Ok(())
+  }
+
+  pub(crate) fn clear_msgs(&self) -> Result<(), SyncError> {
+    if let Some(db) = self.collab_db.upgrade() {
+      db.with_write_txn(|txn| txn.clear_outbox(self.object.uid, &self.object.object_id))?;
+    }
+    Ok(())
+  }
+
+  pub(crate) fn unsynced_changes(&self) -> u64 {
+    self
+      .collab_db
+      .upgrade()
+      .map(|db| {
+        db.read_txn()
+          .number_of_unsynced_changes(self.object.uid, &self.object.object_id)
+      })
+      .unwrap_or(0)
+  }
+}
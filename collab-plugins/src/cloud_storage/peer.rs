@@ -0,0 +1,248 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+
+use collab::lock::RwLock;
+use collab::preclude::Collab;
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use tokio::spawn;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tracing::{error, trace, warn};
+use yrs::encoding::read::Cursor;
+use yrs::sync::{Message, MessageReader, SyncMessage};
+use yrs::updates::decoder::{Decode, DecoderV1};
+use yrs::updates::encoder::Encode;
+use yrs::{Origin, ReadTxn, Subscription, Transact, Update};
+
+use crate::cloud_storage::channel::TokioUnboundedSink;
+use crate::cloud_storage::error::SyncError;
+
+static PEER_SYNC_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PeerSyncState {
+  /// Waiting for the updates that are missing in the local collab.
+  Syncing,
+  /// The updates that were missing in the local collab when the sync started are applied. The
+  /// following updates of both sides are exchanged as they happen.
+  Synced,
+  /// The connection was closed or the local collab was dropped.
+  Closed,
+}
+
+/// Sync a [Collab] with a peer over any [Sink]/[Stream] pair, without a server.
+///
+/// Each item of the connection is a message of the Yjs sync protocol, encoded with
+/// [Encode::encode_v1]. When the sync starts, each side sends the state vector of its collab
+/// (sync step 1) and its awareness states. Then:
+/// - The sync step 1 of the peer is answered with the updates the peer is missing (sync step 2).
+/// - The updates of the local collab are sent to the peer as they happen, and the updates and the
+///   awareness states received from the peer are applied to the local collab.
+///
+/// The updates and the awareness states that were received from the peer are not sent back. The
+/// sync stops when the [PeerSync] is dropped.
+pub struct PeerSync {
+  state: watch::Receiver<PeerSyncState>,
+  tasks: Vec<JoinHandle<()>>,
+  #[allow(dead_code)]
+  update_subscription: Subscription,
+  #[allow(dead_code)]
+  awareness_subscription: Subscription,
+}
+
+impl Drop for PeerSync {
+  fn drop(&mut self) {
+    for task in &self.tasks {
+      task.abort();
+    }
+  }
+}
+
+impl PeerSync {
+  pub async fn start<Si, St>(
+    collab: &Arc<RwLock<Collab>>,
+    sink: Si,
+    stream: St,
+  ) -> Result<Self, SyncError>
+  where
+    Si: Sink<Vec<u8>> + Send + Unpin + 'static,
+    Si::Error: Display,
+    St: Stream<Item = Vec<u8>> + Send + Unpin + 'static,
+  {
+    // The origin of the updates and the awareness states received from the peer.
+    let peer_origin = Origin::from(format!(
+      "peer_sync:{}",
+      PEER_SYNC_ID.fetch_add(1, Ordering::SeqCst)
+    ));
+    let (msg_tx, msg_rx) = unbounded_channel();
+    let (state_tx, state_rx) = watch::channel(PeerSyncState::Syncing);
+
+    let lock = collab.read().await;
+    let awareness = lock.get_awareness();
+    let cloned_origin = peer_origin.clone();
+    let cloned_msg_tx = msg_tx.clone();
+    let update_subscription = awareness.doc().observe_update_v1(move |txn, event| {
+      if txn.origin() != Some(&cloned_origin) {
+        let _ = cloned_msg_tx.send(Message::Sync(SyncMessage::Update(event.update.clone())));
+      }
+    })?;
+    let cloned_origin = peer_origin.clone();
+    let cloned_msg_tx = msg_tx.clone();
+    let awareness_subscription = awareness.on_update(move |awareness, event, origin| {
+      if origin == Some(&cloned_origin) {
+        return;
+      }
+      if let Ok(update) = awareness.update_with_clients(event.all_changes()) {
+        let _ = cloned_msg_tx.send(Message::Awareness(update));
+      }
+    });
+
+    let state_vector = awareness.doc().transact().state_vector();
+    let _ = msg_tx.send(Message::Sync(SyncMessage::SyncStep1(state_vector)));
+    let awareness_update = awareness.update()?;
+    if !awareness_update.clients.is_empty() {
+      let _ = msg_tx.send(Message::Awareness(awareness_update));
+    }
+    drop(lock);
+
+    let tasks = vec![
+      spawn(send_messages(sink, msg_rx)),
+      spawn(receive_messages(
+        stream,
+        Arc::downgrade(collab),
+        peer_origin,
+        msg_tx,
+        state_tx,
+      )),
+    ];
+    Ok(Self {
+      state: state_rx,
+      tasks,
+      update_subscription,
+      awareness_subscription,
+    })
+  }
+
+  /// Sync two collabs of the same object in the same process.
+  pub async fn connect_pair(
+    collab_a: &Arc<RwLock<Collab>>,
+    collab_b: &Arc<RwLock<Collab>>,
+  ) -> Result<(Self, Self), SyncError> {
+    let (sender_a, receiver_b) = unbounded_channel();
+    let (sender_b, receiver_a) = unbounded_channel();
+    let peer_a = Self::start(
+      collab_a,
+      TokioUnboundedSink(sender_a),
+      UnboundedReceiverStream::new(receiver_a),
+    )
+    .await?;
+    let peer_b = Self::start(
+      collab_b,
+      TokioUnboundedSink(sender_b),
+      UnboundedReceiverStream::new(receiver_b),
+    )
+    .await?;
+    Ok((peer_a, peer_b))
+  }
+
+  pub fn state(&self) -> PeerSyncState {
+    *self.state.borrow()
+  }
+
+  pub fn subscribe_state(&self) -> watch::Receiver<PeerSyncState> {
+    self.state.clone()
+  }
+
+  /// Wait until the updates that were missing in the local collab are applied. Return false if
+  /// the sync was closed before.
+  pub async fn wait_until_synced(&self) -> bool {
+    let mut state = self.state.clone();
+    state
+      .wait_for(|state| *state != PeerSyncState::Syncing)
+      .await
+      .map(|state| *state == PeerSyncState::Synced)
+      .unwrap_or(false)
+  }
+}
+
+async fn send_messages<Si>(mut sink: Si, mut msg_rx: UnboundedReceiver<Message>)
+where
+  Si: Sink<Vec<u8>> + Unpin,
+  Si::Error: Display,
+{
+  while let Some(msg) = msg_rx.recv().await {
+    if let Err(err) = sink.send(msg.encode_v1()).await {
+      error!("🔴Failed to send message to the peer: {}", err);
+      break;
+    }
+  }
+}
+
+async fn receive_messages<St>(
+  mut stream: St,
+  collab: Weak<RwLock<Collab>>,
+  peer_origin: Origin,
+  msg_tx: UnboundedSender<Message>,
+  state_tx: watch::Sender<PeerSyncState>,
+) where
+  St: Stream<Item = Vec<u8>> + Unpin,
+{
+  while let Some(data) = stream.next().await {
+    let collab = match collab.upgrade() {
+      None => break,
+      Some(collab) => collab,
+    };
+    if let Err(err) = handle_data(&collab, &peer_origin, &data, &msg_tx, &state_tx).await {
+      error!("🔴Failed to handle the message of the peer: {}", err);
+    }
+  }
+  let _ = state_tx.send(PeerSyncState::Closed);
+}
+
+async fn handle_data(
+  collab: &RwLock<Collab>,
+  peer_origin: &Origin,
+  data: &[u8],
+  msg_tx: &UnboundedSender<Message>,
+  state_tx: &watch::Sender<PeerSyncState>,
+) -> Result<(), SyncError> {
+  let lock = collab.write().await;
+  let awareness = lock.get_awareness();
+  let mut decoder = DecoderV1::new(Cursor::new(data));
+  for message in MessageReader::new(&mut decoder) {
+    match message? {
+      Message::Sync(SyncMessage::SyncStep1(state_vector)) => {
+        let update = awareness
+          .doc()
+          .transact()
+          .encode_state_as_update_v1(&state_vector);
+        let _ = msg_tx.send(Message::Sync(SyncMessage::SyncStep2(update)));
+      },
+      Message::Sync(SyncMessage::SyncStep2(update)) => {
+        let update = Update::decode_v1(&update)?;
+        awareness
+          .doc()
+          .transact_mut_with(peer_origin.clone())
+          .apply_update(update)?;
+        let _ = state_tx.send(PeerSyncState::Synced);
+      },
+      Message::Sync(SyncMessage::Update(update)) => {
+        let update = Update::decode_v1(&update)?;
+        awareness
+          .doc()
+          .transact_mut_with(peer_origin.clone())
+          .apply_update(update)?;
+      },
+      Message::Awareness(update) => awareness.apply_update_with(update, peer_origin.clone())?,
+      Message::AwarenessQuery => {
+        let _ = msg_tx.send(Message::Awareness(awareness.update()?));
+      },
+      Message::Auth(reason) => warn!("🟡The peer denied the sync: {:?}", reason),
+      Message::Custom(tag, _) => trace!("skip custom message:{}", tag),
+    }
+  }
+  Ok(())
+}
@@ -1,3 +1,7 @@
 pub use plugin::*;
+#[cfg(feature = "postgres_storage")]
+pub use storage::{PostgresCollabStorage, PostgresConfig};
 
 mod plugin;
+#[cfg(feature = "postgres_storage")]
+mod storage;
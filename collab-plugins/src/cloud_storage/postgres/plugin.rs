@@ -10,6 +10,7 @@ use tokio_retry::{Action, Retry};
 use tokio_stream::wrappers::WatchStream;
 use tokio_stream::StreamExt;
 
+use collab::core::awareness::{AwarenessUpdate, Event};
 use collab::core::collab_plugin::CollabPluginType;
 use collab::core::origin::CollabOrigin;
 use collab::preclude::{Collab, CollabPlugin};
@@ -47,11 +48,12 @@ impl SupabaseDBPlugin {
       .with_strategy(SinkStrategy::FixInterval(Duration::from_secs(
         sync_per_secs,
       )));
-    let remote_collab = Arc::new(RemoteCollab::new(
+    let remote_collab = Arc::new(RemoteCollab::new_with_outbox(
       object.clone(),
       remote_collab_storage.clone(),
       config,
       local_collab.clone(),
+      local_collab_storage.clone(),
     ));
 
     // Subscribe the sync state from the remote collab
@@ -80,7 +82,7 @@ impl SupabaseDBPlugin {
 }
 
 impl CollabPlugin for SupabaseDBPlugin {
-  fn did_init(&self, _collab: &Collab, _object_id: &str) {
+  fn did_init(&self, _collab: &Collab, _object_id: &str) -> Result<(), anyhow::Error> {
     // TODO(nathan): retry action might take a long time even if the network is ready or enable of
     // the [RemoteCollabStorage] is true
     let retry_strategy = FibonacciBackoff::from_millis(2000);
@@ -98,21 +100,39 @@ impl CollabPlugin for SupabaseDBPlugin {
     tokio::spawn(async move {
       let _ = Retry::spawn(retry_strategy, action).await;
     });
+    Ok(())
   }
 
-  fn receive_local_update(&self, origin: &CollabOrigin, object_id: &str, update: &[u8]) {
+  fn receive_local_update(
+    &self,
+    origin: &CollabOrigin,
+    object_id: &str,
+    update: &[u8],
+  ) -> Result<(), anyhow::Error> {
     if self.is_first_sync_done.load(Ordering::SeqCst) {
-      if let Err(e) = self.remote_collab.push_update(update) {
-        tracing::error!(
+      self.remote_collab.push_update(update).map_err(|err| {
+        anyhow::anyhow!(
           "Collab {} failed to apply update from {}: {}",
           object_id,
           origin,
-          e
-        );
-      };
+          err
+        )
+      })?;
     } else {
       self.pending_updates.blocking_write().push(update.to_vec());
     }
+    Ok(())
+  }
+
+  fn receive_local_state(
+    &self,
+    _origin: &CollabOrigin,
+    _object_id: &str,
+    _event: &Event,
+    update: &AwarenessUpdate,
+  ) -> Result<(), anyhow::Error> {
+    self.remote_collab.push_awareness_update(update);
+    Ok(())
   }
 
   fn plugin_type(&self) -> CollabPluginType {
@@ -139,6 +159,7 @@ impl Action for InitSyncAction {
 
   fn run(&mut self) -> Self::Future {
     let weak_remote_collab = self.remote_collab.clone();
+    let weak_local_collab = self.local_collab.clone();
     let weak_pending_updates = self.pending_updates.clone();
     let weak_is_first_sync_done = self.is_first_sync_done.clone();
 
@@ -148,11 +169,12 @@ impl Action for InitSyncAction {
         weak_pending_updates.upgrade(),
         weak_is_first_sync_done.upgrade(),
       ) {
-        for update in &*pending_updates.read().await {
-          remote_collab.push_update(update)?;
-        }
-
+        // The pending updates were already applied to the local collab, so the init sync sends
+        // them to the remote together with any other update that is missing in the remote. The
+        // updates that arrive while syncing are pushed to the remote directly.
         is_first_sync_done.store(true, Ordering::SeqCst);
+        pending_updates.write().await.clear();
+        remote_collab.sync(weak_local_collab).await?;
         Ok(())
       } else {
         Ok(())
@@ -0,0 +1,143 @@
+use collab_entity::proto::collab::{
+  collab_origin, ClientOrigin, CollabOrigin, InitSync, ServerInit,
+};
+use collab_entity::CollabObject;
+use yrs::encoding::read::Cursor;
+use yrs::sync::{Message, MessageReader, SyncMessage};
+use yrs::updates::decoder::{Decode, DecoderV1};
+use yrs::updates::encoder::{Encode, Encoder, EncoderV1};
+use yrs::{diff_updates_v1, merge_updates_v1, ReadTxn, StateVector, Update};
+
+use crate::cloud_storage::error::SyncError;
+use crate::cloud_storage::msg::MsgId;
+
+/// Create the [InitSync] that starts the init sync handshake. Its payload is the sync step 1 of
+/// the Yjs sync protocol: the state vector of the local collab.
+pub fn make_init_sync(
+  object: &CollabObject,
+  msg_id: MsgId,
+  state_vector: &StateVector,
+) -> InitSync {
+  let origin = CollabOrigin {
+    origin: Some(collab_origin::Origin::Client(ClientOrigin {
+      uid: object.uid,
+      device_id: object.device_id.clone(),
+    })),
+  };
+  InitSync {
+    origin: Some(origin),
+    object_id: object.object_id.clone(),
+    collab_type: object.collab_type.to_proto() as i32,
+    workspace_id: object.workspace_id.clone(),
+    msg_id,
+    payload: Message::Sync(SyncMessage::SyncStep1(state_vector.clone())).encode_v1(),
+  }
+}
+
+/// Answer the [InitSync] with the state of the remote collab. The payload of the [ServerInit] is
+/// the sync step 2, the updates that are missing in the client, followed by the sync step 1, the
+/// state vector of the remote collab, so the client only sends back the updates that are missing
+/// in the remote.
+pub fn handle_init_sync<T: ReadTxn>(
+  txn: &T,
+  init_sync: &InitSync,
+) -> Result<ServerInit, SyncError> {
+  let client_state_vector = decode_state_vector(&init_sync.payload)?;
+  let payload = ServerInitPayload {
+    update: txn.encode_state_as_update_v1(&client_state_vector),
+    state_vector: txn.state_vector(),
+  };
+  Ok(ServerInit {
+    origin: None,
+    object_id: init_sync.object_id.clone(),
+    msg_id: init_sync.msg_id,
+    payload: payload.encode(),
+  })
+}
+
+/// Answer the [InitSync] like [handle_init_sync], from the encoded v1 updates of the remote
+/// collab instead of a transaction. The updates are diffed without being integrated into a
+/// document, so the remote storage only needs the updates that the client might miss.
+/// `state_vector` is the state vector of the remote collab. A lower bound of it is valid too, the
+/// client then sends back some updates that the remote already has.
+pub fn handle_init_sync_with_updates(
+  init_sync: &InitSync,
+  updates: &[Vec<u8>],
+  state_vector: StateVector,
+) -> Result<ServerInit, SyncError> {
+  let client_state_vector = decode_state_vector(&init_sync.payload)?;
+  let update = if updates.is_empty() {
+    Update::new().encode_v1()
+  } else {
+    diff_updates_v1(
+      &merge_updates_v1(updates)?,
+      &client_state_vector.encode_v1(),
+    )?
+  };
+  let payload = ServerInitPayload {
+    update,
+    state_vector,
+  };
+  Ok(ServerInit {
+    origin: None,
+    object_id: init_sync.object_id.clone(),
+    msg_id: init_sync.msg_id,
+    payload: payload.encode(),
+  })
+}
+
+/// The content of the [ServerInit] payload.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ServerInitPayload {
+  /// The encoded v1 update that contains the updates missing in the client.
+  pub update: Vec<u8>,
+  /// The state vector of the remote collab.
+  pub state_vector: StateVector,
+}
+
+impl ServerInitPayload {
+  pub fn encode(&self) -> Vec<u8> {
+    let mut encoder = EncoderV1::new();
+    Message::Sync(SyncMessage::SyncStep2(self.update.clone())).encode(&mut encoder);
+    Message::Sync(SyncMessage::SyncStep1(self.state_vector.clone())).encode(&mut encoder);
+    encoder.to_vec()
+  }
+
+  pub fn decode(payload: &[u8]) -> Result<Self, SyncError> {
+    let mut update = None;
+    let mut state_vector = None;
+    let mut decoder = DecoderV1::new(Cursor::new(payload));
+    for message in MessageReader::new(&mut decoder) {
+      match message? {
+        Message::Sync(SyncMessage::SyncStep2(value)) => update = Some(value),
+        Message::Sync(SyncMessage::SyncStep1(value)) => state_vector = Some(value),
+        message => {
+          return Err(SyncError::UnexpectedMessage(format!(
+            "{:?} in server init",
+            message
+          )))
+        },
+      }
+    }
+    match (update, state_vector) {
+      (Some(update), Some(state_vector)) => Ok(Self {
+        update,
+        state_vector,
+      }),
+      _ => Err(SyncError::UnexpectedMessage(
+        "server init without sync step 1 or sync step 2".to_string(),
+      )),
+    }
+  }
+}
+
+/// Decode the state vector of the client from the payload of an [InitSync].
+pub(crate) fn decode_state_vector(payload: &[u8]) -> Result<StateVector, SyncError> {
+  match Message::decode_v1(payload)? {
+    Message::Sync(SyncMessage::SyncStep1(state_vector)) => Ok(state_vector),
+    message => Err(SyncError::UnexpectedMessage(format!(
+      "{:?} in init sync",
+      message
+    ))),
+  }
+}
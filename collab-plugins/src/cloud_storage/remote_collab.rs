@@ -6,11 +6,12 @@ use std::time::{Duration, SystemTime};
 
 use anyhow::{anyhow, Error};
 use async_trait::async_trait;
-use collab::core::collab::{DataSource, TransactionMutExt};
+use collab::core::awareness::AwarenessUpdate;
+use collab::core::collab::DataSource;
 use collab::core::collab_state::SyncState;
-use collab::core::origin::CollabOrigin;
 use collab::lock::RwLock;
 use collab::preclude::Collab;
+use collab_entity::proto::collab::{InitSync, ServerInit};
 use collab_entity::CollabObject;
 use rand::random;
 use serde::Deserialize;
@@ -19,25 +20,33 @@ use tokio::sync::mpsc::unbounded_channel;
 use tokio::sync::watch;
 use tokio_stream::wrappers::WatchStream;
 use tokio_stream::StreamExt;
-use tracing::trace;
+use tracing::{trace, warn};
 use yrs::updates::decoder::Decode;
-use yrs::{merge_updates_v1, ReadTxn, Transact, Update};
+use yrs::{merge_updates_v1, Doc, ReadTxn, Transact, Update};
 
+use crate::cloud_storage::awareness::RemoteAwareness;
 use crate::cloud_storage::channel::TokioUnboundedSink;
+use crate::cloud_storage::error::SyncError;
 use crate::cloud_storage::msg::{CollabSinkMessage, MsgId};
+use crate::cloud_storage::outbox::{CollabOutbox, SinkOutbox};
+use crate::cloud_storage::protocol::{handle_init_sync, make_init_sync, ServerInitPayload};
+use crate::cloud_storage::retry::RetryPolicy;
+use crate::cloud_storage::seq::{RemoteBroadcast, SeqNumCheck, SeqNumTracker};
 use crate::cloud_storage::sink::{
   CollabSink, CollabSinkRunner, MsgIdCounter, SinkConfig, SinkState,
 };
+use crate::local_storage::kv::outbox::OutboxMsg;
+use crate::local_storage::kv::KVTransactionDB;
 
 /// The [RemoteCollab] is used to sync the local collab to the remote.
 pub struct RemoteCollab {
   object: CollabObject,
-  collab: Arc<RwLock<Collab>>,
   storage: Arc<dyn RemoteCollabStorage>,
   /// The [CollabSink] is used to queue the [Message] and continuously try to send them
   /// to the remote via the [RemoteCollabStorage].
   sink: Arc<CollabSink<TokioUnboundedSink<Message>, Message>>,
   sync_state: Arc<watch::Sender<SyncState>>,
+  awareness: RemoteAwareness,
   #[allow(dead_code)]
   is_init_sync_finish: Arc<AtomicBool>,
 }
@@ -57,27 +66,95 @@ impl RemoteCollab {
     storage: Arc<dyn RemoteCollabStorage>,
     config: SinkConfig,
     local_collab: Weak<RwLock<Collab>>,
+  ) -> Self {
+    Self::create(object, storage, config, local_collab, None)
+  }
+
+  /// Same as [RemoteCollab::new], but the queued updates are persisted in the `collab_db` until
+  /// they are acked by the remote. The updates that were not acked before, for example because
+  /// the app exited while offline, are sent again.
+  pub fn new_with_outbox<DB: KVTransactionDB>(
+    object: CollabObject,
+    storage: Arc<dyn RemoteCollabStorage>,
+    config: SinkConfig,
+    local_collab: Weak<RwLock<Collab>>,
+    collab_db: Weak<DB>,
+  ) -> Self {
+    let outbox = Arc::new(CollabOutbox::new(object.clone(), collab_db));
+    Self::create(object, storage, config, local_collab, Some(outbox))
+  }
+
+  fn create(
+    object: CollabObject,
+    storage: Arc<dyn RemoteCollabStorage>,
+    config: SinkConfig,
+    local_collab: Weak<RwLock<Collab>>,
+    outbox: Option<Arc<dyn SinkOutbox<Message>>>,
   ) -> Self {
     let is_init_sync_finish = Arc::new(AtomicBool::new(false));
     let sync_state = Arc::new(watch::channel(SyncState::InitSyncBegin).0);
-    let collab = Arc::new(RwLock::from(Collab::new_with_origin(
-      CollabOrigin::Server,
-      &object.object_id,
-      vec![],
-      true,
-    )));
     let (sink, mut stream) = unbounded_channel::<Message>();
     let weak_storage = Arc::downgrade(&storage);
     let (notifier, notifier_rx) = watch::channel(false);
     let (sync_state_tx, sink_state_rx) = watch::channel(SinkState::Init);
-    let collab_sink = Arc::new(CollabSink::new(
+    let awareness_timeout = config.awareness_timeout;
+    let retry_policy = config.retry_policy.clone();
+    let retry_interval = config.timeout;
+    let mut collab_sink = CollabSink::new(
       object.uid,
       TokioUnboundedSink(sink),
       notifier,
       sync_state_tx,
       RngMsgIdCounter::new(),
       config,
-    ));
+    );
+    if let Some(outbox) = outbox {
+      collab_sink = collab_sink.with_outbox(outbox);
+    }
+    let (resync_tx, mut resync_rx) = unbounded_channel::<()>();
+    let collab_sink = Arc::new(collab_sink.with_resync(resync_tx));
+
+    // Spawn a task to run the init sync again when the sink gives up a message. The task stops
+    // when the sink is dropped.
+    let resync_runner = ResyncRunner {
+      object: object.clone(),
+      storage: Arc::downgrade(&storage),
+      sink: Arc::downgrade(&collab_sink),
+      sync_state: sync_state.clone(),
+      local_collab: local_collab.clone(),
+      retry_policy,
+      retry_interval,
+    };
+    spawn(async move {
+      while resync_rx.recv().await.is_some() {
+        resync_runner.resync().await;
+        // The succeeded resync covers the messages that were given up while it was retrying.
+        while resync_rx.try_recv().is_ok() {}
+      }
+    });
+
+    // Spawn a task to apply the broadcasts of the remote in order of their seq_num, and sync
+    // again if some of them were missed.
+    if let Some(broadcast_stream) = storage.subscribe_remote_broadcasts(&object) {
+      let runner = BroadcastRunner {
+        object: object.clone(),
+        storage: Arc::downgrade(&storage),
+        sink: Arc::downgrade(&collab_sink),
+        sync_state: sync_state.clone(),
+        local_collab: local_collab.clone(),
+        is_init_sync_finish: is_init_sync_finish.clone(),
+      };
+      spawn(runner.run(broadcast_stream));
+    }
+
+    // Spawn a task to send the local awareness states and apply the remote ones.
+    let awareness = RemoteAwareness::new(
+      object.clone(),
+      Arc::downgrade(&storage),
+      local_collab.clone(),
+      storage.subscribe_remote_awareness(&object),
+      awareness_timeout,
+    );
 
     // spawns an asynchronous task to continuously listen to the updates stream
     // and process them as they come in.
@@ -92,8 +169,7 @@ impl RemoteCollab {
             match Update::decode_v1(&update) {
               Ok(update) => {
                 let mut collab = local_collab.write().await;
-                let mut txn = collab.transact_mut();
-                if let Err(e) = txn.try_apply_update(update) {
+                if let Err(e) = collab.apply_update(update) {
                   tracing::error!("apply remote update failed: {:?}", e);
                 }
               },
@@ -192,10 +268,10 @@ impl RemoteCollab {
     ));
     Self {
       object,
-      collab,
       storage,
       sink: collab_sink,
       sync_state,
+      awareness,
       is_init_sync_finish,
     }
   }
@@ -204,123 +280,29 @@ impl RemoteCollab {
     self.sync_state.subscribe()
   }
 
-  /// Return the update of the remote collab.
-  /// If the remote collab contains any updates, it will return None.
-  /// Otherwise, it will merge the updates into one and return the merged update.
-  #[allow(dead_code)]
+  /// Run the init sync handshake with the remote collab. The local collab sends its state vector
+  /// and receives the updates it is missing together with the state vector of the remote. Then
+  /// only the updates that are missing in the remote are queued. So the size of the transfer
+  /// depends on how far the local and the remote diverged instead of the size of the document.
+  ///
+  /// Return the update that was applied to the local collab.
   pub async fn sync(&self, local_collab: Weak<RwLock<Collab>>) -> Result<Vec<u8>, Error> {
-    let mut remote_update = vec![];
-    // It would be better if creating a edge function that calculate the diff between the local and remote.
-    // The local only need to send its state vector to the remote. In this way, the local does not need to
-    // get all the updates from remote.
-    // TODO(nathan): create a edge function to calculate the diff between the local and remote.
     tracing::trace!("Try init sync:{}", self.object);
-    let collab_doc_state = self.storage.get_doc_state(&self.object).await?;
-    if !collab_doc_state.is_empty() {
-      {
-        let mut remote_collab = self.collab.write().await;
-        let mut txn = remote_collab.transact_mut();
-
-        match collab_doc_state {
-          DataSource::Disk(_) => {},
-          DataSource::DocStateV1(doc_state) => {
-            if let Ok(update) = Update::decode_v1(&doc_state) {
-              if let Err(e) = txn.try_apply_update(update) {
-                tracing::error!("apply update failed: {:?}", e);
-              }
-            } else {
-              tracing::error!("🔴decode update failed");
-            }
-            remote_update = doc_state;
-          },
-          DataSource::DocStateV2(doc_state) => {
-            if let Ok(update) = Update::decode_v2(&doc_state) {
-              if let Err(e) = txn.try_apply_update(update) {
-                tracing::error!("apply update failed: {:?}", e);
-              }
-            } else {
-              tracing::error!("🔴decode update failed");
-            }
-            remote_update = doc_state;
-          },
-        }
-      }
-
-      let _ = self.sync_state.send(SyncState::InitSyncBegin);
-      // Encode the remote collab state as update for local collab.
-      let local_collab = local_collab
-        .upgrade()
-        .ok_or(anyhow!("local collab is dropped"))?;
-      let mut local_lock = local_collab.write().await;
-      let encode_update = self
-        .collab
-        .read()
-        .await
-        .transact()
-        .encode_state_as_update_v1(&local_lock.transact().state_vector());
-      if let Ok(update) = Update::decode_v1(&encode_update) {
-        {
-          // Don't use the with_transact_mut here, because it carries the origin information. So
-          // the update will consider as a local update. But here is apply the remote update.
-          // TODO: nathan define a sync protocol for cloud storage.
-          tracing::trace!(
-            "{}: apply remote update with diff len:{}",
-            self.object,
-            encode_update.len()
-          );
-          local_lock
-            .get_mut_awareness()
-            .doc_mut()
-            .transact_mut()
-            .apply_update(update)?;
-          drop(local_lock);
-
-          if let Err(e) = self.sync_state.send(SyncState::InitSyncEnd) {
-            tracing::error!("🔴Failed to send sync state: {:?}", e);
-          }
-        }
-      }
-    }
-
-    // Encode the local collab state as update for remote collab.
-    let mut remote_lock = self.collab.write().await;
-    let remote_state_vector = remote_lock.transact().state_vector();
-    let encode_update = local_collab
+    let local_collab = local_collab
       .upgrade()
-      .ok_or(anyhow!("local collab is dropped"))?
-      .read()
-      .await
-      .transact()
-      .encode_state_as_update_v1(&remote_state_vector);
-
-    if let Ok(decode_update) = Update::decode_v1(&encode_update) {
-      tracing::trace!(
-        "{}: sync updates to remote:{}",
-        self.object,
-        encode_update.len()
-      );
-
-      // Apply the update to the remote collab and send the update to the remote.
-      remote_lock.transact_mut().apply_update(decode_update)?;
-      drop(remote_lock);
-
-      self.sink.queue_msg(|msg_id| Message {
-        object: self.object.clone(),
-        payloads: vec![encode_update],
-        meta: MessageMeta::Init { msg_id },
-      });
-    }
-    Ok(remote_update)
+      .ok_or(anyhow!("local collab is dropped"))?;
+    init_sync_with_remote(
+      &self.object,
+      self.storage.as_ref(),
+      &self.sink,
+      &self.sync_state,
+      &local_collab,
+    )
+    .await
   }
 
   pub fn push_update(&self, update: &[u8]) -> Result<(), Error> {
-    if let Ok(decode_update) = Update::decode_v1(update) {
-      self
-        .collab
-        .blocking_write()
-        .transact_mut()
-        .apply_update(decode_update)?;
-
+    if Update::decode_v1(update).is_ok() {
       self.sink.queue_msg(|msg_id| Message {
         object: self.object.clone(),
         payloads: vec![update.to_vec()],
@@ -331,6 +313,17 @@ impl RemoteCollab {
     Ok(())
   }
 
+  /// Send the awareness update of the local collab to the remote. Unlike the document updates,
+  /// only the latest awareness state is kept until it's sent, and it's not persisted.
+  pub fn push_awareness_update(&self, update: &AwarenessUpdate) {
+    self.awareness.push_update(update);
+  }
+
+  /// Return the number of local changes that are not synced to the remote yet.
+  pub async fn number_of_unsynced_changes(&self) -> u64 {
+    self.sink.number_of_unsynced_changes().await
+  }
+
   #[allow(dead_code)]
   pub fn clear(&self) {
     self.sink.remove_all_pending_msgs();
@@ -402,12 +395,73 @@ pub trait RemoteCollabStorage: Send + Sync + 'static {
     init_update: Vec<u8>,
   ) -> Result<(), anyhow::Error>;
 
+  /// Answer the [InitSync] of the local collab with a [ServerInit] that carries the updates
+  /// missing in the local collab and the state vector of the remote collab.
+  /// The default implementation downloads the whole document with
+  /// [RemoteCollabStorage::get_doc_state] and calculates the diff locally. Storages that are able
+  /// to calculate the diff on the remote side should override it to avoid the download. The
+  /// storages that only need some of their updates can answer with
+  /// [crate::cloud_storage::handle_init_sync_with_updates].
+  async fn init_sync(
+    &self,
+    object: &CollabObject,
+    init_sync: InitSync,
+  ) -> Result<ServerInit, anyhow::Error> {
+    let doc = Doc::new();
+    let doc_state = self.get_doc_state(object).await?;
+    let mut txn = doc.transact_mut();
+    match doc_state {
+      DataSource::Disk(_) => {},
+      DataSource::DocStateV1(doc_state) => {
+        if !doc_state.is_empty() {
+          txn.apply_update(Update::decode_v1(&doc_state)?)?;
+        }
+      },
+      DataSource::DocStateV2(doc_state) => {
+        if !doc_state.is_empty() {
+          txn.apply_update(Update::decode_v2(&doc_state)?)?;
+        }
+      },
+    }
+    Ok(handle_init_sync(&txn, &init_sync)?)
+  }
+
   /// Subscribe the remote updates.
   fn subscribe_remote_updates(&self, object: &CollabObject) -> Option<RemoteUpdateReceiver>;
+
+  /// Subscribe the broadcasts of the remote collab. Unlike the updates of
+  /// [RemoteCollabStorage::subscribe_remote_updates], the broadcasts and the acks carry the
+  /// `seq_num` of the remote collab, so the [RemoteCollab] detects the missed updates and syncs
+  /// again. Return `None` if the remote storage doesn't number its updates.
+  fn subscribe_remote_broadcasts(&self, _object: &CollabObject) -> Option<RemoteBroadcastReceiver> {
+    None
+  }
+
+  /// Send the awareness update of the local collab, encoded with
+  /// [yrs::updates::encoder::Encode::encode_v1], to the
+  /// other clients of the remote collab. The awareness states are ephemeral, so the remote only
+  /// relays them. The default implementation drops the update.
+  async fn send_awareness_update(
+    &self,
+    _object: &CollabObject,
+    _update: Vec<u8>,
+  ) -> Result<(), anyhow::Error> {
+    Ok(())
+  }
+
+  /// Subscribe the awareness updates of the other clients of the remote collab. Return `None` if
+  /// the remote storage doesn't relay the awareness updates.
+  fn subscribe_remote_awareness(&self, _object: &CollabObject) -> Option<RemoteAwarenessReceiver> {
+    None
+  }
 }
 
 pub type RemoteUpdateSender = tokio::sync::mpsc::UnboundedSender<Vec<u8>>;
 pub type RemoteUpdateReceiver = tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>;
+pub type RemoteBroadcastSender = tokio::sync::mpsc::UnboundedSender<RemoteBroadcast>;
+pub type RemoteBroadcastReceiver = tokio::sync::mpsc::UnboundedReceiver<RemoteBroadcast>;
+pub type RemoteAwarenessSender = tokio::sync::mpsc::UnboundedSender<Vec<u8>>;
+pub type RemoteAwarenessReceiver = tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>;
 
 #[async_trait]
 impl<T> RemoteCollabStorage for Arc<T>
@@ -452,9 +506,245 @@ where
     (**self).send_init_sync(object, id, init_update).await
   }
 
+  async fn init_sync(
+    &self,
+    object: &CollabObject,
+    init_sync: InitSync,
+  ) -> Result<ServerInit, Error> {
+    (**self).init_sync(object, init_sync).await
+  }
+
   fn subscribe_remote_updates(&self, object: &CollabObject) -> Option<RemoteUpdateReceiver> {
     (**self).subscribe_remote_updates(object)
   }
+
+  fn subscribe_remote_broadcasts(&self, object: &CollabObject) -> Option<RemoteBroadcastReceiver> {
+    (**self).subscribe_remote_broadcasts(object)
+  }
+
+  async fn send_awareness_update(
+    &self,
+    object: &CollabObject,
+    update: Vec<u8>,
+  ) -> Result<(), Error> {
+    (**self).send_awareness_update(object, update).await
+  }
+
+  fn subscribe_remote_awareness(&self, object: &CollabObject) -> Option<RemoteAwarenessReceiver> {
+    (**self).subscribe_remote_awareness(object)
+  }
+}
+
+type RemoteCollabSink = CollabSink<TokioUnboundedSink<Message>, Message>;
+
+/// Runs the init sync again after the [RemoteCollabSink] gave up a message.
+struct ResyncRunner {
+  object: CollabObject,
+  storage: Weak<dyn RemoteCollabStorage>,
+  sink: Weak<RemoteCollabSink>,
+  sync_state: Arc<watch::Sender<SyncState>>,
+  local_collab: Weak<RwLock<Collab>>,
+  /// The [RetryPolicy] of the sink, which spaces the attempts of the resync.
+  retry_policy: RetryPolicy,
+  /// The minimum delay between two attempts, that is the timeout of the sink.
+  retry_interval: Duration,
+}
+
+impl ResyncRunner {
+  /// Run the init sync until it succeeds, because the given up message is not sent again
+  /// otherwise. Stop if the remote collab or the local collab is dropped.
+  async fn resync(&self) {
+    let mut attempts = 0;
+    loop {
+      // Don't keep the remote collab and the local collab alive while waiting for the next
+      // attempt.
+      let result = {
+        let (storage, sink, local_collab) = match (
+          self.storage.upgrade(),
+          self.sink.upgrade(),
+          self.local_collab.upgrade(),
+        ) {
+          (Some(storage), Some(sink), Some(local_collab)) => (storage, sink, local_collab),
+          _ => return,
+        };
+        trace!("{}: resync after a message was given up", self.object);
+        init_sync_with_remote(
+          &self.object,
+          storage.as_ref(),
+          &sink,
+          &self.sync_state,
+          &local_collab,
+        )
+        .await
+      };
+
+      attempts += 1;
+      match result {
+        Ok(_) => return,
+        Err(e) => tracing::error!(
+          "🔴Failed to resync {} after {} attempts: {:?}",
+          self.object,
+          attempts,
+          e
+        ),
+      }
+      let delay = self
+        .retry_policy
+        .backoff_delay(attempts)
+        .max(self.retry_interval);
+      tokio::time::sleep(delay).await;
+    }
+  }
+}
+
+/// Run the init sync handshake of [RemoteCollab::sync].
+async fn init_sync_with_remote(
+  object: &CollabObject,
+  storage: &dyn RemoteCollabStorage,
+  sink: &RemoteCollabSink,
+  sync_state: &watch::Sender<SyncState>,
+  local_collab: &RwLock<Collab>,
+) -> Result<Vec<u8>, Error> {
+  let state_vector = local_collab.read().await.transact().state_vector();
+  let init_sync = make_init_sync(object, sink.next_msg_id(), &state_vector);
+  let server_init = storage.init_sync(object, init_sync).await?;
+  let ServerInitPayload {
+    update: remote_update,
+    state_vector: remote_state_vector,
+  } = ServerInitPayload::decode(&server_init.payload)?;
+
+  let _ = sync_state.send(SyncState::InitSyncBegin);
+  let mut local_lock = local_collab.write().await;
+  tracing::trace!(
+    "{}: apply remote update with diff len:{}",
+    object,
+    remote_update.len()
+  );
+  // Don't use the with_transact_mut here, because it carries the origin information. So
+  // the update will consider as a local update. But here is apply the remote update.
+  local_lock
+    .get_mut_awareness()
+    .doc_mut()
+    .transact_mut()
+    .apply_update(Update::decode_v1(&remote_update)?)?;
+
+  // Encode the updates that are missing in the remote collab.
+  let local_update = local_lock
+    .transact()
+    .encode_state_as_update_v1(&remote_state_vector);
+  drop(local_lock);
+
+  if let Err(e) = sync_state.send(SyncState::InitSyncEnd) {
+    tracing::error!("🔴Failed to send sync state: {:?}", e);
+  }
+
+  tracing::trace!("{}: sync updates to remote:{}", object, local_update.len());
+  sink
+    .async_queue_msg(|msg_id| Message {
+      object: object.clone(),
+      payloads: vec![local_update],
+      meta: MessageMeta::Init { msg_id },
+    })
+    .await;
+  Ok(remote_update)
+}
+
+/// Apply the [RemoteBroadcast]s to the local collab and track their `seq_num`. When some
+/// broadcasts were missed, the local collab runs the init sync handshake again, which only
+/// transfers the missing updates. The [SyncState] goes back to [SyncState::InitSyncBegin] until
+/// the handshake is done.
+struct BroadcastRunner {
+  object: CollabObject,
+  storage: Weak<dyn RemoteCollabStorage>,
+  sink: Weak<RemoteCollabSink>,
+  sync_state: Arc<watch::Sender<SyncState>>,
+  local_collab: Weak<RwLock<Collab>>,
+  is_init_sync_finish: Arc<AtomicBool>,
+}
+
+impl BroadcastRunner {
+  async fn run(self, mut stream: RemoteBroadcastReceiver) {
+    let mut tracker = SeqNumTracker::new();
+    let mut is_resync_pending = false;
+    while let Some(broadcast) = stream.recv().await {
+      if !self
+        .is_init_sync_finish
+        .load(std::sync::atomic::Ordering::SeqCst)
+      {
+        continue;
+      }
+
+      let check = tracker.check(broadcast.seq_num());
+      if let RemoteBroadcast::Update(broadcast) = &broadcast {
+        // The update that follows a gap is applied too. The yrs keeps it as pending until the
+        // missing updates are applied by the resync.
+        if check != SeqNumCheck::Duplicate && !self.apply_update(&broadcast.payload).await {
+          break;
+        }
+      }
+      if let SeqNumCheck::Gap { expected, received } = check {
+        warn!(
+          "🟡{} missed the updates from seq_num:{} to {}",
+          self.object,
+          expected,
+          received - 1
+        );
+        is_resync_pending = true;
+      }
+
+      // If the resync fails, try again with the next broadcast.
+      if is_resync_pending && self.resync().await {
+        is_resync_pending = false;
+        tracker.reset();
+      }
+    }
+  }
+
+  /// Return false if the local collab was dropped.
+  async fn apply_update(&self, payload: &[u8]) -> bool {
+    let local_collab = match self.local_collab.upgrade() {
+      None => return false,
+      Some(local_collab) => local_collab,
+    };
+    match Update::decode_v1(payload) {
+      Ok(update) => {
+        let mut collab = local_collab.write().await;
+        if let Err(e) = collab.apply_update(update) {
+          tracing::error!("apply remote update failed: {:?}", e);
+        }
+      },
+      Err(e) => tracing::error!("🔴Failed to decode remote update: {:?}", e),
+    }
+    true
+  }
+
+  async fn resync(&self) -> bool {
+    let (storage, sink, local_collab) = match (
+      self.storage.upgrade(),
+      self.sink.upgrade(),
+      self.local_collab.upgrade(),
+    ) {
+      (Some(storage), Some(sink), Some(local_collab)) => (storage, sink, local_collab),
+      _ => return false,
+    };
+    trace!("{}: resync after missing updates", self.object);
+    let _ = self.sync_state.send(SyncState::InitSyncBegin);
+    match init_sync_with_remote(
+      &self.object,
+      storage.as_ref(),
+      &sink,
+      &self.sync_state,
+      &local_collab,
+    )
+    .await
+    {
+      Ok(_) => true,
+      Err(e) => {
+        tracing::error!("🔴Failed to resync {}: {:?}", self.object, e);
+        false
+      },
+    }
+  }
 }
 
 #[derive(Clone, Debug)]
@@ -586,6 +876,55 @@ impl Display for Message {
   }
 }
 
+impl<DB> SinkOutbox<Message> for CollabOutbox<DB>
+where
+  DB: KVTransactionDB,
+{
+  fn load(&self) -> Result<Vec<(MsgId, Message)>, SyncError> {
+    let msgs = self
+      .load_msgs()
+      .into_iter()
+      .map(|msg| {
+        let message = Message {
+          object: self.object.clone(),
+          meta: MessageMeta::Update { msg_id: msg.msg_id },
+          payloads: msg.payloads,
+        };
+        (msg.msg_id, message)
+      })
+      .collect();
+    Ok(msgs)
+  }
+
+  fn push(&self, msg_id: MsgId, msg: &Message) -> Result<(), SyncError> {
+    // The init message is created by the init sync every time the collab is opened. So only the
+    // updates are persisted.
+    if msg.is_init_msg() {
+      return Ok(());
+    }
+    self.push_msg(&OutboxMsg::new(msg_id, msg.payloads.clone()))
+  }
+
+  fn merge(&self, msg_id: MsgId, msg: &Message, merged_msg_ids: &[MsgId]) -> Result<(), SyncError> {
+    self.merge_msgs(
+      &OutboxMsg::new(msg_id, msg.payloads.clone()),
+      merged_msg_ids,
+    )
+  }
+
+  fn remove(&self, msg_id: MsgId) -> Result<(), SyncError> {
+    self.remove_msg(msg_id)
+  }
+
+  fn clear(&self) -> Result<(), SyncError> {
+    self.clear_msgs()
+  }
+
+  fn number_of_unsynced_changes(&self) -> u64 {
+    self.unsynced_changes()
+  }
+}
+
 #[derive(Debug, thiserror::Error)]
 enum CollabError {
   #[error("Internal error")]
@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// The [RetryPolicy] determines when the `CollabSink` sends a message again after it was not
+/// acked in time.
+///
+/// The delay between two attempts of a message grows exponentially from `initial_delay` up to
+/// `max_delay`, and is shortened by a random `jitter`, so the sinks of the open collabs don't
+/// retry at the same time. After `circuit_breaker_threshold` consecutive failures, the circuit
+/// breaker opens: the remote is marked as unreachable and the sinks only probe it once every
+/// `cool_down` until a message is acked again.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+  /// The delay before the second attempt of a message.
+  pub initial_delay: Duration,
+  /// The maximum delay between two attempts of a message.
+  pub max_delay: Duration,
+  /// The ratio of the delay that is randomized, between 0 and 1. For example, with 0.5 the delay
+  /// is a random value between the half and the whole of the computed delay.
+  pub jitter: f64,
+  /// The maximum number of attempts of a message. After that the message is removed from the
+  /// sink and its outbox, and its changes are sent again by a new init sync, which is retried
+  /// with this policy until it succeeds. `None` means no limit.
+  pub max_attempts: Option<u32>,
+  /// The number of consecutive failures that opens the circuit breaker.
+  pub circuit_breaker_threshold: u32,
+  /// The delay between two probes of the remote while the circuit breaker is open.
+  pub cool_down: Duration,
+}
+
+impl RetryPolicy {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Retry right after the timeout, without jitter, limit nor circuit breaker.
+  pub fn immediate() -> Self {
+    Self {
+      initial_delay: Duration::ZERO,
+      max_delay: Duration::ZERO,
+      jitter: 0.0,
+      max_attempts: None,
+      circuit_breaker_threshold: u32::MAX,
+      cool_down: Duration::ZERO,
+    }
+  }
+
+  pub fn with_backoff(mut self, initial_delay: Duration, max_delay: Duration) -> Self {
+    self.initial_delay = initial_delay;
+    self.max_delay = max_delay;
+    self
+  }
+
+  pub fn with_jitter(mut self, jitter: f64) -> Self {
+    self.jitter = jitter.clamp(0.0, 1.0);
+    self
+  }
+
+  pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+    self.max_attempts = Some(max_attempts);
+    self
+  }
+
+  pub fn with_circuit_breaker(mut self, threshold: u32, cool_down: Duration) -> Self {
+    self.circuit_breaker_threshold = threshold.max(1);
+    self.cool_down = cool_down;
+    self
+  }
+
+  /// Return the delay before the next attempt of a message that failed `attempts` times.
+  pub fn backoff_delay(&self, attempts: u32) -> Duration {
+    let exponent = attempts.saturating_sub(1).min(31);
+    let delay = self
+      .initial_delay
+      .saturating_mul(1 << exponent)
+      .min(self.max_delay);
+    self.apply_jitter(delay)
+  }
+
+  /// Return the delay before the next probe of the remote while the circuit breaker is open.
+  pub fn cool_down_delay(&self) -> Duration {
+    self.apply_jitter(self.cool_down)
+  }
+
+  pub fn is_exhausted(&self, attempts: u32) -> bool {
+    self
+      .max_attempts
+      .map(|max_attempts| attempts >= max_attempts)
+      .unwrap_or(false)
+  }
+
+  fn apply_jitter(&self, delay: Duration) -> Duration {
+    if self.jitter <= 0.0 {
+      return delay;
+    }
+    delay.mul_f64(1.0 - self.jitter.min(1.0) * rand::random::<f64>())
+  }
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    Self {
+      initial_delay: Duration::from_secs(1),
+      max_delay: Duration::from_secs(60),
+      jitter: 0.5,
+      max_attempts: None,
+      circuit_breaker_threshold: 5,
+      cool_down: Duration::from_secs(30),
+    }
+  }
+}
+
+/// The retry state of a `CollabSink`.
+#[derive(Debug, Default)]
+pub(crate) struct RetryState {
+  consecutive_failures: u32,
+  next_attempt_at: Option<Instant>,
+  /// The deadline of the timer that notifies the sink to send the next message, if any.
+  timer_deadline: Option<Instant>,
+}
+
+impl RetryState {
+  /// Return the instant until which the sink has to wait before sending the next message, or
+  /// `None` if it can send now.
+  pub(crate) fn blocked_until(
+    &mut self,
+    now: Instant,
+    is_reachable: bool,
+    policy: &RetryPolicy,
+  ) -> Option<Instant> {
+    if !is_reachable && self.next_attempt_at.is_none() {
+      // The remote was marked as unreachable by another sink or by the app. So this sink waits
+      // for the cool down before probing the remote.
+      self.next_attempt_at = Some(now + policy.cool_down_delay());
+    }
+    self
+      .next_attempt_at
+      .filter(|next_attempt_at| *next_attempt_at > now)
+  }
+
+  /// Record the failure of the message that was sent `attempts` times. Return true if the
+  /// circuit breaker is open.
+  pub(crate) fn on_failure(
+    &mut self,
+    now: Instant,
+    attempts: u32,
+    is_reachable: bool,
+    policy: &RetryPolicy,
+  ) -> bool {
+    self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+    let is_open = self.consecutive_failures >= policy.circuit_breaker_threshold;
+    let backoff_delay = policy.backoff_delay(attempts);
+    let delay = if is_open || !is_reachable {
+      policy.cool_down_delay().max(backoff_delay)
+    } else {
+      backoff_delay
+    };
+    self.next_attempt_at = Some(now + delay);
+    is_open
+  }
+
+  pub(crate) fn on_success(&mut self) {
+    self.consecutive_failures = 0;
+    self.next_attempt_at = None;
+  }
+
+  /// The remote was marked as reachable again. A waiting sink sends its next message after a
+  /// short random delay instead of the cool down, so the sinks don't send at the same time.
+  pub(crate) fn on_reachable(&mut self, now: Instant, policy: &RetryPolicy) {
+    self.consecutive_failures = 0;
+    if self.next_attempt_at.is_some() {
+      self.next_attempt_at = Some(now + policy.backoff_delay(1));
+    }
+  }
+
+  /// Return true if a timer should be scheduled at the given deadline, that is, there is no
+  /// timer scheduled before it.
+  pub(crate) fn schedule_timer(&mut self, deadline: Instant) -> bool {
+    match self.timer_deadline {
+      Some(timer_deadline) if timer_deadline <= deadline => false,
+      _ => {
+        self.timer_deadline = Some(deadline);
+        true
+      },
+    }
+  }
+
+  pub(crate) fn on_timer_fired(&mut self, deadline: Instant) {
+    if self.timer_deadline == Some(deadline) {
+      self.timer_deadline = None;
+    }
+  }
+}
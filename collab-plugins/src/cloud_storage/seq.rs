@@ -0,0 +1,75 @@
+use collab_entity::proto::collab::{BroadcastSync, CollabAck};
+
+/// A message of the remote that carries the `seq_num` of the collab.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RemoteBroadcast {
+  /// The update of another client.
+  Update(BroadcastSync),
+  /// The ack of an update of this client.
+  Ack(CollabAck),
+}
+
+impl RemoteBroadcast {
+  pub fn seq_num(&self) -> u32 {
+    match self {
+      RemoteBroadcast::Update(broadcast) => broadcast.seq_num,
+      RemoteBroadcast::Ack(ack) => ack.seq_num,
+    }
+  }
+}
+
+/// The result of [SeqNumTracker::check].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SeqNumCheck {
+  /// The `seq_num` follows the last one. It's also returned for the first `seq_num` after the
+  /// tracker was created or reset.
+  InOrder,
+  /// The `seq_num` was received before.
+  Duplicate,
+  /// The `seq_num`s between the last one and the received one were missed.
+  Gap { expected: u32, received: u32 },
+}
+
+/// Track the `seq_num` of the [BroadcastSync]s and [CollabAck]s of a collab.
+///
+/// The remote increases the `seq_num` of a collab by one for each update it applies. The update
+/// of another client is received as a [BroadcastSync], and the update of this client is acked
+/// with a [CollabAck]. Both carry the `seq_num` of the update, so a gap between two `seq_num`s
+/// means some updates of the remote were missed and the collab has to be synced again.
+#[derive(Clone, Debug, Default)]
+pub struct SeqNumTracker {
+  last_seq_num: Option<u32>,
+}
+
+impl SeqNumTracker {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Return the last `seq_num` that was received in order.
+  pub fn last_seq_num(&self) -> Option<u32> {
+    self.last_seq_num
+  }
+
+  /// Check the received `seq_num` against the last one. The received `seq_num` becomes the last
+  /// one unless it's a duplicate.
+  pub fn check(&mut self, seq_num: u32) -> SeqNumCheck {
+    let check = match self.last_seq_num {
+      None => SeqNumCheck::InOrder,
+      Some(last_seq_num) if seq_num <= last_seq_num => return SeqNumCheck::Duplicate,
+      Some(last_seq_num) if seq_num == last_seq_num + 1 => SeqNumCheck::InOrder,
+      Some(last_seq_num) => SeqNumCheck::Gap {
+        expected: last_seq_num + 1,
+        received: seq_num,
+      },
+    };
+    self.last_seq_num = Some(seq_num);
+    check
+  }
+
+  /// Forget the last `seq_num`. Call it after the collab was synced again, so the next
+  /// `seq_num` is accepted as is.
+  pub fn reset(&mut self) {
+    self.last_seq_num = None;
+  }
+}
@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use collab_entity::proto::collab::collab_message::Message;
+use collab_entity::proto::collab::{
+  collab_origin, BroadcastSync, CollabAck, CollabMessage, CollabOrigin, ServerInit, ServerOrigin,
+};
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use tokio::net::TcpListener;
+use tokio::spawn;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::{error, trace, warn};
+use yrs::updates::decoder::Decode;
+use yrs::{Doc, ReadTxn, StateVector, Transact, Update};
+
+use crate::cloud_storage::channel::CollabConnect;
+use crate::cloud_storage::error::SyncError;
+use crate::cloud_storage::protocol::handle_init_sync;
+use crate::cloud_storage::ws::WebSocketConnect;
+
+/// The [CollabAck::code] of a message that was handled.
+pub const ACK_CODE_SUCCESS: u32 = 0;
+/// The [CollabAck::code] of a message whose payload can't be decoded or applied.
+pub const ACK_CODE_INVALID_MESSAGE: u32 = 1;
+
+type ConnId = u64;
+
+/// A collab sync server that runs in the process. It's the reference implementation of the server
+/// side of the collab sync, used to test the clients without any external infrastructure.
+///
+/// The server keeps a room for each object id. A connection joins the room of an object when it
+/// sends a message of the object. Then:
+/// - The [Message::ClientInitSync] is answered with the [Message::ServerInitSync] that contains
+///   the updates missing in the client and the state vector of the room.
+/// - The update of a [Message::ClientUpdateSync] is applied to the room, acked with a
+///   [Message::ClientAck] that carries the `seq_num` of the room, and broadcast to the other
+///   connections of the room with the same `seq_num`. The `seq_num` is increased by one for each
+///   update.
+/// - The [Message::AwarenessSync] is relayed to the other connections of the room.
+#[derive(Clone, Default)]
+pub struct LocalSyncServer {
+  inner: Arc<ServerInner>,
+}
+
+impl LocalSyncServer {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Connect to the server within the process.
+  pub fn connect(&self) -> LoopbackConnect {
+    let (client_side, server_side) = LoopbackConnect::pair();
+    spawn(serve_connection(self.inner.clone(), server_side));
+    client_side
+  }
+
+  /// Accept the WebSocket connections on the given address, for example `127.0.0.1:0`. Return
+  /// the address the server listens on. The server stops accepting the connections after all
+  /// the [LocalSyncServer]s are dropped.
+  pub async fn listen(&self, addr: &str) -> Result<SocketAddr, SyncError> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    let weak_inner = Arc::downgrade(&self.inner);
+    spawn(async move {
+      while let Ok((stream, _)) = listener.accept().await {
+        let inner = match weak_inner.upgrade() {
+          None => break,
+          Some(inner) => inner,
+        };
+        spawn(async move {
+          match tokio_tungstenite::accept_async(stream).await {
+            Ok(stream) => serve_connection(inner, WebSocketConnect::new(stream)).await,
+            Err(err) => error!("🔴Failed to accept WebSocket connection: {}", err),
+          }
+        });
+      }
+    });
+    Ok(local_addr)
+  }
+
+  /// Return the `seq_num` of the last update of the room of the given object id.
+  pub fn seq_num(&self, object_id: &str) -> u32 {
+    self
+      .inner
+      .rooms
+      .lock()
+      .unwrap()
+      .get(object_id)
+      .map(|room| room.seq_num)
+      .unwrap_or(0)
+  }
+
+  /// Return the state of the room of the given object id, encoded as a v1 update.
+  pub fn doc_state(&self, object_id: &str) -> Option<Vec<u8>> {
+    let rooms = self.inner.rooms.lock().unwrap();
+    let room = rooms.get(object_id)?;
+    let doc_state = room
+      .doc
+      .transact()
+      .encode_state_as_update_v1(&StateVector::default());
+    Some(doc_state)
+  }
+
+  /// Return the number of connections in the room of the given object id.
+  pub fn number_of_connections(&self, object_id: &str) -> usize {
+    self
+      .inner
+      .rooms
+      .lock()
+      .unwrap()
+      .get(object_id)
+      .map(|room| room.subscribers.len())
+      .unwrap_or(0)
+  }
+}
+
+#[derive(Default)]
+struct ServerInner {
+  rooms: Mutex<HashMap<String, Room>>,
+  conn_id_counter: AtomicU64,
+}
+
+impl ServerInner {
+  fn handle_message(&self, conn_id: ConnId, sender: &UnboundedSender<CollabMessage>, msg: Message) {
+    let mut rooms = self.rooms.lock().unwrap();
+    match msg {
+      Message::ClientInitSync(init_sync) => {
+        let room = rooms.entry(init_sync.object_id.clone()).or_default();
+        room.subscribers.insert(conn_id, sender.clone());
+        let reply = match handle_init_sync(&room.doc.transact(), &init_sync) {
+          Ok(server_init) => Message::ServerInitSync(ServerInit {
+            origin: Some(server_origin()),
+            ..server_init
+          }),
+          Err(err) => {
+            warn!("🟡invalid init sync of {}: {}", init_sync.object_id, err);
+            make_ack(
+              &init_sync.object_id,
+              init_sync.msg_id,
+              ACK_CODE_INVALID_MESSAGE,
+              room.seq_num,
+            )
+          },
+        };
+        let _ = sender.send(CollabMessage {
+          message: Some(reply),
+        });
+      },
+      Message::ClientUpdateSync(update_sync) => {
+        let room = rooms.entry(update_sync.object_id.clone()).or_default();
+        room.subscribers.insert(conn_id, sender.clone());
+        let code = match room.apply_update(&update_sync.payload) {
+          Ok(_) => {
+            room.seq_num += 1;
+            let broadcast = Message::ServerBroadcast(BroadcastSync {
+              origin: update_sync.origin.clone(),
+              object_id: update_sync.object_id.clone(),
+              payload: update_sync.payload.clone(),
+              seq_num: room.seq_num,
+            });
+            room.broadcast(conn_id, broadcast);
+            ACK_CODE_SUCCESS
+          },
+          Err(err) => {
+            warn!("🟡invalid update of {}: {}", update_sync.object_id, err);
+            ACK_CODE_INVALID_MESSAGE
+          },
+        };
+        let ack = make_ack(
+          &update_sync.object_id,
+          update_sync.msg_id,
+          code,
+          room.seq_num,
+        );
+        let _ = sender.send(CollabMessage { message: Some(ack) });
+      },
+      Message::AwarenessSync(awareness_sync) => {
+        let room = rooms.entry(awareness_sync.object_id.clone()).or_default();
+        room.subscribers.insert(conn_id, sender.clone());
+        room.broadcast(conn_id, Message::AwarenessSync(awareness_sync));
+      },
+      msg => trace!("ignore message from client: {:?}", msg),
+    }
+  }
+
+  fn disconnect(&self, conn_id: ConnId) {
+    for room in self.rooms.lock().unwrap().values_mut() {
+      room.subscribers.remove(&conn_id);
+    }
+  }
+}
+
+struct Room {
+  doc: Doc,
+  seq_num: u32,
+  subscribers: HashMap<ConnId, UnboundedSender<CollabMessage>>,
+}
+
+impl Default for Room {
+  fn default() -> Self {
+    Self {
+      doc: Doc::new(),
+      seq_num: 0,
+      subscribers: HashMap::new(),
+    }
+  }
+}
+
+impl Room {
+  fn apply_update(&mut self, payload: &[u8]) -> Result<(), SyncError> {
+    let update = Update::decode_v1(payload)?;
+    self.doc.transact_mut().apply_update(update)?;
+    Ok(())
+  }
+
+  /// Send the message to all the connections of the room except the given one. The connections
+  /// that were closed are removed.
+  fn broadcast(&mut self, from: ConnId, msg: Message) {
+    let msg = CollabMessage { message: Some(msg) };
+    self
+      .subscribers
+      .retain(|conn_id, subscriber| *conn_id == from || subscriber.send(msg.clone()).is_ok());
+  }
+}
+
+async fn serve_connection<C>(inner: Arc<ServerInner>, connect: C)
+where
+  C: CollabConnect<CollabMessage>
+    + Sink<CollabMessage, Error = SyncError>
+    + Stream<Item = Result<CollabMessage, SyncError>>
+    + Send
+    + 'static,
+{
+  let conn_id = inner.conn_id_counter.fetch_add(1, Ordering::SeqCst);
+  let (mut sink, mut stream) = connect.split();
+  let (sender, mut receiver) = unbounded_channel::<CollabMessage>();
+  spawn(async move {
+    while let Some(msg) = receiver.recv().await {
+      if let Err(err) = sink.send(msg).await {
+        trace!("connection:{} is closed: {}", conn_id, err);
+        break;
+      }
+    }
+  });
+
+  while let Some(msg) = stream.next().await {
+    match msg {
+      Ok(CollabMessage { message: Some(msg) }) => inner.handle_message(conn_id, &sender, msg),
+      Ok(_) => {},
+      Err(SyncError::ProtobufDecoding(err)) => warn!("🟡skip invalid message: {}", err),
+      Err(err) => {
+        error!("🔴connection:{} failed: {}", conn_id, err);
+        break;
+      },
+    }
+  }
+  inner.disconnect(conn_id);
+}
+
+fn server_origin() -> CollabOrigin {
+  CollabOrigin {
+    origin: Some(collab_origin::Origin::Server(ServerOrigin {})),
+  }
+}
+
+fn make_ack(object_id: &str, msg_id: u64, code: u32, seq_num: u32) -> Message {
+  Message::ClientAck(CollabAck {
+    origin: Some(server_origin()),
+    object_id: object_id.to_string(),
+    meta: None,
+    payload: vec![],
+    code,
+    msg_id,
+    seq_num,
+  })
+}
+
+/// A [CollabConnect] between a client and the [LocalSyncServer] in the same process.
+pub struct LoopbackConnect {
+  sender: UnboundedSender<CollabMessage>,
+  receiver: UnboundedReceiver<CollabMessage>,
+}
+
+impl LoopbackConnect {
+  fn new(
+    sender: UnboundedSender<CollabMessage>,
+    receiver: UnboundedReceiver<CollabMessage>,
+  ) -> Self {
+    Self { sender, receiver }
+  }
+
+  /// Return two [LoopbackConnect]s that are connected to each other. The messages sent by one
+  /// are received by the other.
+  pub fn pair() -> (Self, Self) {
+    let (sender_a, receiver_b) = unbounded_channel();
+    let (sender_b, receiver_a) = unbounded_channel();
+    (
+      Self::new(sender_a, receiver_a),
+      Self::new(sender_b, receiver_b),
+    )
+  }
+}
+
+impl Sink<CollabMessage> for LoopbackConnect {
+  type Error = SyncError;
+
+  fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn start_send(self: Pin<&mut Self>, item: CollabMessage) -> Result<(), Self::Error> {
+    self.sender.send(item).map_err(|_| {
+      SyncError::IO(std::io::Error::new(
+        std::io::ErrorKind::BrokenPipe,
+        "loopback connection is closed",
+      ))
+    })
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl Stream for LoopbackConnect {
+  type Item = Result<CollabMessage, SyncError>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    self.receiver.poll_recv(cx).map(|msg| msg.map(Ok))
+  }
+}
+
+impl CollabConnect<CollabMessage> for LoopbackConnect {}
@@ -1,3 +1,4 @@
+use std::collections::binary_heap::PeekMut;
 use std::marker::PhantomData;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Weak};
@@ -6,14 +7,19 @@ use std::time::Duration;
 use collab::lock::Mutex;
 use futures_util::SinkExt;
 use tokio::spawn;
-use tokio::sync::{mpsc, oneshot, watch};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
 use tokio::time::{Instant, Interval};
-use tracing::{debug, trace};
+use tracing::{debug, trace, warn};
 
 use crate::cloud_storage::error::SyncError;
 use crate::cloud_storage::msg::{CollabSinkMessage, MessageState, PendingMsgQueue};
+use crate::cloud_storage::outbox::SinkOutbox;
+use crate::cloud_storage::retry::{RetryPolicy, RetryState};
+use crate::connect_state::{CollabConnectReachability, CollabConnectState};
 
 pub const DEFAULT_SYNC_TIMEOUT: u64 = 2;
+pub const DEFAULT_AWARENESS_TIMEOUT: u64 = 30;
 #[derive(Clone, Debug)]
 pub enum SinkState {
   Init,
@@ -42,6 +48,10 @@ pub struct CollabSink<Sink, Msg> {
   pending_msg_queue: Arc<Mutex<PendingMsgQueue<Msg>>>, //FIXME: this should be a channel
   msg_id_counter: Arc<dyn MsgIdCounter>,
 
+  /// The [SinkOutbox] is used to persist the messages of the [PendingMsgQueue] until they are
+  /// acked by the remote.
+  outbox: Option<Arc<dyn SinkOutbox<Msg>>>,
+
   /// The [watch::Sender] is used to notify the [CollabSinkRunner] to process the pending messages.
   /// Sending `false` will stop the [CollabSinkRunner].
   notifier: Arc<watch::Sender<bool>>,
@@ -55,6 +65,14 @@ pub struct CollabSink<Sink, Msg> {
   /// is [SinkStrategy::FixInterval].
   instant: Mutex<Instant>,
   state_notifier: Arc<watch::Sender<SinkState>>,
+
+  /// The [CollabConnectReachability] of the remote. It's shared by the sinks that use the same
+  /// [SinkConfig::reachability], so the circuit breaker of one sink holds back the others.
+  reachability: Arc<CollabConnectReachability>,
+  /// Determine when the next message can be sent after the remote failed to ack a message.
+  retry_state: Arc<Mutex<RetryState>>,
+  /// Notified when a message is given up after [RetryPolicy::max_attempts].
+  resync_tx: Option<mpsc::UnboundedSender<()>>,
 }
 
 impl<Sink, Msg> Drop for CollabSink<Sink, Msg> {
@@ -95,16 +113,67 @@ where
       interval_runner_stop_tx = Some(tx);
       spawn(IntervalRunner::new(*duration).run(weak_notifier, rx));
     }
+
+    let reachability = config.reachability.clone().unwrap_or_default();
+    let retry_state = Arc::new(Mutex::from(RetryState::default()));
+    spawn(ReachabilityRunner::run(
+      reachability.subscribe(),
+      notifier.subscribe(),
+      Arc::downgrade(&retry_state),
+      Arc::downgrade(&notifier),
+      config.retry_policy.clone(),
+    ));
     Self {
       uid,
       sender,
       pending_msg_queue,
       msg_id_counter,
+      outbox: None,
       notifier,
       state_notifier,
       config,
       instant,
       interval_runner_stop_tx,
+      reachability,
+      retry_state,
+      resync_tx: None,
+    }
+  }
+
+  /// Persist the queued messages with the given [SinkOutbox] until they are acked, and queue the
+  /// messages that were persisted but not acked before, so they are sent again in order of
+  /// their msg id. The [MsgIdCounter] of the sink should generate msg ids that are greater than
+  /// the persisted ones.
+  pub fn with_outbox(mut self, outbox: Arc<dyn SinkOutbox<Msg>>) -> Self {
+    match outbox.load() {
+      Ok(msgs) => {
+        if let Ok(mut pending_msgs) = self.pending_msg_queue.try_lock() {
+          trace!("replay {} messages from the outbox", msgs.len());
+          for (msg_id, msg) in msgs {
+            pending_msgs.push_msg(msg_id, msg);
+          }
+        }
+      },
+      Err(err) => tracing::error!("🔴Failed to load the outbox: {}", err),
+    }
+    self.outbox = Some(outbox);
+    self
+  }
+
+  /// Send to the `resync_tx` when a message is given up after [RetryPolicy::max_attempts]. The
+  /// changes of the message never reach the remote, so the owner of the sink must run an init
+  /// sync again when it receives the notification.
+  pub fn with_resync(mut self, resync_tx: mpsc::UnboundedSender<()>) -> Self {
+    self.resync_tx = Some(resync_tx);
+    self
+  }
+
+  /// Return the number of local changes that are not synced yet. Without [SinkOutbox], it's the
+  /// number of pending messages.
+  pub async fn number_of_unsynced_changes(&self) -> u64 {
+    match &self.outbox {
+      Some(outbox) => outbox.number_of_unsynced_changes(),
+      None => self.pending_msg_queue.lock().await.len() as u64,
     }
   }
 
@@ -118,6 +187,7 @@ where
       let mut pending_msgs = self.pending_msg_queue.blocking_lock();
       let msg_id = self.msg_id_counter.next();
       let msg = f(msg_id);
+      self.persist_msg(msg_id, &msg);
       pending_msgs.push_msg(msg_id, msg);
       drop(pending_msgs);
     }
@@ -125,8 +195,29 @@ where
     self.notify();
   }
 
+  /// Same as [CollabSink::queue_msg], but can be called in the async context.
+  pub async fn async_queue_msg(&self, f: impl FnOnce(MsgId) -> Msg) {
+    {
+      let mut pending_msgs = self.pending_msg_queue.lock().await;
+      let msg_id = self.msg_id_counter.next();
+      let msg = f(msg_id);
+      self.persist_msg(msg_id, &msg);
+      pending_msgs.push_msg(msg_id, msg);
+    }
+
+    self.notify();
+  }
+
+  /// Return a new message id that is unique among the messages of the sink.
+  pub fn next_msg_id(&self) -> MsgId {
+    self.msg_id_counter.next()
+  }
+
   pub fn remove_all_pending_msgs(&self) {
     self.pending_msg_queue.blocking_lock().clear();
+    if let Some(Err(err)) = self.outbox.as_ref().map(|outbox| outbox.clear()) {
+      tracing::error!("🔴Failed to clear the outbox: {}", err);
+    }
   }
 
   /// Notify the sink to process the next message and mark the current message as done.
@@ -146,6 +237,14 @@ where
       );
       if pending_msg.msg_id() == msg_id {
         debug!("{} message:{} was sent", object_id, msg_id);
+        if let Some(Err(err)) = self.outbox.as_ref().map(|outbox| outbox.remove(msg_id)) {
+          tracing::error!(
+            "🔴Failed to remove {} message:{} from the outbox: {}",
+            object_id,
+            msg_id,
+            err
+          );
+        }
         pending_msg.set_state(MessageState::Done);
         self.notify();
       }
@@ -195,6 +294,10 @@ where
   }
 
   async fn try_send_msg_immediately(&self) -> Option<()> {
+    if self.pending_msg_queue.lock().await.is_empty() || self.is_waiting_for_retry().await {
+      return None;
+    }
+
     let (tx, rx) = oneshot::channel();
     let collab_msg = {
       let mut pending_msg_queue = self.pending_msg_queue.lock().await;
@@ -213,6 +316,7 @@ where
       // If the message can merge other messages, try to merge the next message until the
       // message is not mergeable.
       if sending_msg.is_mergeable() {
+        let mut merged_msg_ids = vec![];
         while let Some(pending_msg) = pending_msg_queue.pop() {
           debug!("Try merge collab message: {}", pending_msg.get_msg());
 
@@ -220,6 +324,17 @@ where
             pending_msg_queue.push(pending_msg);
             break;
           }
+          merged_msg_ids.push(pending_msg.msg_id());
+        }
+
+        if !merged_msg_ids.is_empty() {
+          if let Some(outbox) = &self.outbox {
+            if let Err(err) =
+              outbox.merge(sending_msg.msg_id(), sending_msg.get_msg(), &merged_msg_ids)
+            {
+              tracing::error!("🔴Failed to persist the merged message: {}", err);
+            }
+          }
         }
       }
 
@@ -235,11 +350,19 @@ where
 
     let mut sender = self.sender.lock().await;
     tracing::debug!("[Client {}]: {}", self.uid, collab_msg);
-    sender.send(collab_msg).await.ok()?;
+    if let Err(err) = sender.send(collab_msg).await {
+      warn!("🟡Failed to send message: {}", err);
+      drop(sender);
+      self.on_send_failed().await;
+      return None;
+    }
     // Wait for the message to be acked.
-    // If the message is not acked within the timeout, resend the message.
+    // If the message is not acked within the timeout, resend the message according to the
+    // [RetryPolicy].
     match tokio::time::timeout(self.config.timeout, rx).await {
       Ok(_) => {
+        self.retry_state.lock().await.on_success();
+        self.reachability.set_state(CollabConnectState::Connected);
         if let Ok(mut pending_msgs) = self.pending_msg_queue.try_lock() {
           let pending_msg = pending_msgs.pop();
           trace!(
@@ -257,15 +380,104 @@ where
         }
         self.notify()
       },
-      Err(_) => {
-        let mut lock = self.pending_msg_queue.lock().await;
-        if let Some(mut pending_msg) = lock.peek_mut() {
-          pending_msg.set_state(MessageState::Timeout);
+      Err(_) => self.on_send_failed().await,
+    }
+    None
+  }
+
+  /// Return true if the sink has to wait before sending the next message. In that case, a timer
+  /// is scheduled to notify the sink when the wait is over.
+  async fn is_waiting_for_retry(&self) -> bool {
+    let is_reachable = self.reachability.state() == CollabConnectState::Connected;
+    let mut retry_state = self.retry_state.lock().await;
+    let deadline =
+      match retry_state.blocked_until(Instant::now(), is_reachable, &self.config.retry_policy) {
+        None => return false,
+        Some(deadline) => deadline,
+      };
+
+    if retry_state.schedule_timer(deadline) {
+      trace!("wait until {:?} to send the next message", deadline);
+      let weak_retry_state = Arc::downgrade(&self.retry_state);
+      let weak_notifier = Arc::downgrade(&self.notifier);
+      spawn(async move {
+        tokio::time::sleep_until(deadline).await;
+        if let Some(retry_state) = weak_retry_state.upgrade() {
+          retry_state.lock().await.on_timer_fired(deadline);
+        }
+        if let Some(notifier) = weak_notifier.upgrade() {
+          let _ = notifier.send(false);
         }
+      });
+    }
+    true
+  }
+
+  /// Mark the sending message as timeout and schedule the next attempt. The message is removed
+  /// from the queue and the outbox if it reaches the [RetryPolicy::max_attempts], and a resync is
+  /// requested, see [CollabSink::with_resync], so the changes of the message reach the remote
+  /// anyway. The remote is marked as unreachable if the circuit breaker opens.
+  async fn on_send_failed(&self) {
+    let attempts = {
+      let mut lock = self.pending_msg_queue.lock().await;
+      let mut pending_msg = match lock.peek_mut() {
+        Some(pending_msg) => pending_msg,
+        None => return,
+      };
+      if pending_msg.state().is_done() {
+        // The message was acked right after the timeout.
+        drop(pending_msg);
+        drop(lock);
         self.notify();
-      },
+        return;
+      }
+
+      pending_msg.set_state(MessageState::Timeout);
+      pending_msg.increase_attempts();
+      let attempts = pending_msg.attempts();
+      if self.config.retry_policy.is_exhausted(attempts) {
+        let pending_msg = PeekMut::pop(pending_msg);
+        tracing::error!(
+          "🔴Give up sending message:{} after {} attempts, resync",
+          pending_msg.msg_id(),
+          attempts
+        );
+        // The resync sends the changes of the message, so it must not be replayed from the outbox.
+        let msg_id = pending_msg.msg_id();
+        if let Some(Err(err)) = self.outbox.as_ref().map(|outbox| outbox.remove(msg_id)) {
+          tracing::error!(
+            "🔴Failed to remove message:{} from the outbox: {}",
+            msg_id,
+            err
+          );
+        }
+        if let Some(resync_tx) = &self.resync_tx {
+          let _ = resync_tx.send(());
+        }
+      }
+      attempts
+    };
+
+    let is_reachable = self.reachability.state() == CollabConnectState::Connected;
+    let is_open = self.retry_state.lock().await.on_failure(
+      Instant::now(),
+      attempts,
+      is_reachable,
+      &self.config.retry_policy,
+    );
+    if is_open && is_reachable {
+      warn!("🟡The remote is unreachable, wait for the cool down to send the next message");
+      self
+        .reachability
+        .set_state(CollabConnectState::Disconnected);
+    }
+    self.notify();
+  }
+
+  fn persist_msg(&self, msg_id: MsgId, msg: &Msg) {
+    if let Some(Err(err)) = self.outbox.as_ref().map(|outbox| outbox.push(msg_id, msg)) {
+      tracing::error!("🔴Failed to persist message:{}: {}", msg_id, err);
     }
-    None
   }
 
   /// Notify the sink to process the next message.
@@ -323,6 +535,17 @@ pub struct SinkConfig {
   pub max_merge_size: usize,
   /// `strategy` is the strategy to send the messages.
   pub strategy: SinkStrategy,
+  /// `retry_policy` determines when a message is sent again after it was not acked in time.
+  /// Default is [RetryPolicy::immediate], which sends the message again right after the timeout.
+  /// Use [RetryPolicy::default] for the exponential backoff and the circuit breaker.
+  pub retry_policy: RetryPolicy,
+  /// `reachability` is shared by the sinks that sync to the same remote. If it's `None`, the
+  /// sink uses its own reachability.
+  pub reachability: Option<Arc<CollabConnectReachability>>,
+  /// `awareness_timeout` is the time after which the awareness state of a remote client is
+  /// removed if it's not received again. The local awareness state is sent again every half of
+  /// it.
+  pub awareness_timeout: Duration,
 }
 
 impl SinkConfig {
@@ -356,6 +579,25 @@ impl SinkConfig {
     self.strategy = strategy;
     self
   }
+
+  pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+    self.retry_policy = retry_policy;
+    self
+  }
+
+  /// Share the [CollabConnectReachability] with other sinks. When the circuit breaker of a sink
+  /// opens, the remote is marked as [CollabConnectState::Disconnected] and the other sinks wait
+  /// for the cool down too. Marking the remote as [CollabConnectState::Connected] makes the
+  /// waiting sinks send their next message shortly.
+  pub fn with_reachability(mut self, reachability: Arc<CollabConnectReachability>) -> Self {
+    self.reachability = Some(reachability);
+    self
+  }
+
+  pub fn with_awareness_timeout(mut self, awareness_timeout: Duration) -> Self {
+    self.awareness_timeout = awareness_timeout;
+    self
+  }
 }
 
 impl Default for SinkConfig {
@@ -364,6 +606,9 @@ impl Default for SinkConfig {
       timeout: Duration::from_secs(DEFAULT_SYNC_TIMEOUT),
       max_merge_size: 4096,
       strategy: SinkStrategy::Asap,
+      retry_policy: RetryPolicy::immediate(),
+      reachability: None,
+      awareness_timeout: Duration::from_secs(DEFAULT_AWARENESS_TIMEOUT),
     }
   }
 }
@@ -434,3 +679,42 @@ impl IntervalRunner {
     }
   }
 }
+
+struct ReachabilityRunner;
+
+impl ReachabilityRunner {
+  /// Notify the sink to send the next message when the remote becomes reachable. The runner
+  /// stops when the sink is stopped or dropped.
+  async fn run(
+    mut state_rx: broadcast::Receiver<CollabConnectState>,
+    mut stop_rx: watch::Receiver<bool>,
+    retry_state: Weak<Mutex<RetryState>>,
+    notifier: Weak<watch::Sender<bool>>,
+    retry_policy: RetryPolicy,
+  ) {
+    loop {
+      tokio::select! {
+        result = stop_rx.changed() => {
+          if result.is_err() || *stop_rx.borrow() {
+            break;
+          }
+        },
+        state = state_rx.recv() => {
+          match state {
+            Ok(CollabConnectState::Connected) => {
+              match (retry_state.upgrade(), notifier.upgrade()) {
+                (Some(retry_state), Some(notifier)) => {
+                  retry_state.lock().await.on_reachable(Instant::now(), &retry_policy);
+                  let _ = notifier.send(false);
+                },
+                _ => break,
+              }
+            },
+            Ok(CollabConnectState::Disconnected) | Err(RecvError::Lagged(_)) => {},
+            Err(RecvError::Closed) => break,
+          }
+        }
+      }
+    }
+  }
+}
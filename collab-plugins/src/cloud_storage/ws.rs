@@ -0,0 +1,90 @@
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use collab_entity::proto::collab::CollabMessage;
+use futures_util::{Sink, Stream};
+use prost::Message;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::cloud_storage::channel::CollabConnect;
+use crate::cloud_storage::error::SyncError;
+
+/// A [CollabConnect] that sends and receives the [CollabMessage]s over a WebSocket. Each
+/// [CollabMessage] is encoded with protobuf into one binary frame.
+pub struct WebSocketConnect<S = MaybeTlsStream<TcpStream>> {
+  inner: WebSocketStream<S>,
+}
+
+impl WebSocketConnect {
+  /// Connect to the WebSocket server at the given url, for example `ws://127.0.0.1:8000`.
+  pub async fn connect(url: &str) -> Result<Self, SyncError> {
+    let (inner, _) = tokio_tungstenite::connect_async(url).await?;
+    Ok(Self { inner })
+  }
+}
+
+impl<S> WebSocketConnect<S>
+where
+  S: AsyncRead + AsyncWrite + Unpin,
+{
+  pub fn new(inner: WebSocketStream<S>) -> Self {
+    Self { inner }
+  }
+}
+
+impl<S> Sink<CollabMessage> for WebSocketConnect<S>
+where
+  S: AsyncRead + AsyncWrite + Unpin,
+{
+  type Error = SyncError;
+
+  fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    Pin::new(&mut self.inner)
+      .poll_ready(cx)
+      .map_err(SyncError::from)
+  }
+
+  fn start_send(mut self: Pin<&mut Self>, item: CollabMessage) -> Result<(), Self::Error> {
+    Pin::new(&mut self.inner).start_send(WsMessage::Binary(item.encode_to_vec()))?;
+    Ok(())
+  }
+
+  fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    Pin::new(&mut self.inner)
+      .poll_flush(cx)
+      .map_err(SyncError::from)
+  }
+
+  fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    Pin::new(&mut self.inner)
+      .poll_close(cx)
+      .map_err(SyncError::from)
+  }
+}
+
+impl<S> Stream for WebSocketConnect<S>
+where
+  S: AsyncRead + AsyncWrite + Unpin,
+{
+  type Item = Result<CollabMessage, SyncError>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    loop {
+      match ready!(Pin::new(&mut self.inner).poll_next(cx)) {
+        None | Some(Ok(WsMessage::Close(_))) => return Poll::Ready(None),
+        Some(Err(err)) => return Poll::Ready(Some(Err(err.into()))),
+        Some(Ok(WsMessage::Binary(data))) => {
+          let msg = CollabMessage::decode(data.as_slice()).map_err(SyncError::from);
+          return Poll::Ready(Some(msg));
+        },
+        // The ping and pong frames are answered by the WebSocket itself.
+        Some(Ok(_)) => continue,
+      }
+    }
+  }
+}
+
+impl<S> CollabConnect<CollabMessage> for WebSocketConnect<S> where S: AsyncRead + AsyncWrite + Unpin {}
@@ -64,7 +64,7 @@ impl IndexeddbDiskPlugin {
 }
 
 impl CollabPlugin for IndexeddbDiskPlugin {
-  fn init(&self, object_id: &str, _origin: &CollabOrigin, doc: &Doc) {
+  fn init(&self, object_id: &str, _origin: &CollabOrigin, doc: &Doc) -> Result<(), anyhow::Error> {
     if let Some(db) = self.collab_db.upgrade() {
       let object_id = object_id.to_string();
       let doc = doc.clone();
@@ -93,19 +93,27 @@ impl CollabPlugin for IndexeddbDiskPlugin {
           },
         }
       });
+      Ok(())
     } else {
-      tracing::warn!("collab_db is dropped");
+      Err(anyhow::anyhow!("collab_db is dropped"))
     }
   }
 
-  fn receive_update(&self, _object_id: &str, _txn: &TransactionMut, update: &[u8]) {
+  fn receive_update(
+    &self,
+    _object_id: &str,
+    _txn: &TransactionMut,
+    update: &[u8],
+  ) -> Result<(), anyhow::Error> {
     // Only push update if the doc is loaded
     if !self.did_load.load(SeqCst) {
-      return;
-    }
-    if let Err(err) = self.edit_sender.send(DocUpdate::Update(update.to_vec())) {
-      error!("failed to send update: {}", err);
+      return Ok(());
     }
+    self
+      .edit_sender
+      .send(DocUpdate::Update(update.to_vec()))
+      .map_err(|err| anyhow::anyhow!("failed to send update: {}", err))?;
+    Ok(())
   }
 
   fn did_init(&self, _collab: &Collab, _object_id: &str, _last_sync_at: i64) {
@@ -0,0 +1,118 @@
+use std::collections::{btree_map, BTreeMap};
+use std::ops::{Bound, RangeBounds};
+
+/// A range of keys of the [KVChanges] and the committed data.
+pub(crate) type KeyRange<'a> = (Bound<&'a [u8]>, Bound<&'a [u8]>);
+
+/// Same as the RocksDB implementation, the lower bound is always included and the upper bound is
+/// always excluded.
+pub(crate) fn key_range<'a, K, R>(range: &'a R) -> KeyRange<'a>
+where
+  K: AsRef<[u8]> + 'a,
+  R: RangeBounds<K>,
+{
+  let start = match range.start_bound() {
+    Bound::Included(start) | Bound::Excluded(start) => Bound::Included(start.as_ref()),
+    Bound::Unbounded => Bound::Unbounded,
+  };
+  let end = match range.end_bound() {
+    Bound::Included(end) | Bound::Excluded(end) => Bound::Excluded(end.as_ref()),
+    Bound::Unbounded => Bound::Unbounded,
+  };
+  (start, end)
+}
+
+/// Return true if the range can't contain any key. [BTreeMap::range] panics if its start is
+/// greater than its end.
+pub(crate) fn is_empty_range(range: KeyRange) -> bool {
+  matches!(range, (Bound::Included(start), Bound::Excluded(end)) if start >= end)
+}
+
+/// The uncommitted changes of a transaction of the [KVTransactionDB](super::KVTransactionDB)s
+/// that keep the writes in memory until the transaction is committed. The reads of the
+/// transaction overlay the changes on the committed data.
+///
+/// The implementations lock their committed data before the changes when they need both.
+#[derive(Default)]
+pub(crate) struct KVChanges {
+  /// None means the key was removed.
+  changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl KVChanges {
+  /// Return None if the key isn't changed, otherwise the changed value, which is None if the key
+  /// was removed.
+  pub fn get(&self, key: &[u8]) -> Option<Option<Vec<u8>>> {
+    self.changes.get(key).cloned()
+  }
+
+  pub fn contains_key(&self, key: &[u8]) -> bool {
+    self.changes.contains_key(key)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.changes.is_empty()
+  }
+
+  pub fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) {
+    self.changes.insert(key, Some(value));
+  }
+
+  pub fn remove(&mut self, key: Vec<u8>) {
+    self.changes.insert(key, None);
+  }
+
+  /// Return the committed entries of the range, overlaid with the changes in the range.
+  pub fn overlay(
+    &self,
+    committed: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    range: KeyRange,
+  ) -> Vec<(Vec<u8>, Vec<u8>)> {
+    if is_empty_range(range) {
+      return vec![];
+    }
+    let mut merged: BTreeMap<Vec<u8>, Vec<u8>> = committed.into_iter().collect();
+    for (key, value) in self.changes.range::<[u8], _>(range) {
+      match value {
+        None => merged.remove(key),
+        Some(value) => merged.insert(key.clone(), value.clone()),
+      };
+    }
+    merged.into_iter().collect()
+  }
+
+  /// Return the last entry of the range. `committed` is the last committed entry of the range
+  /// that isn't changed by the transaction.
+  pub fn next_back(
+    &self,
+    committed: Option<(Vec<u8>, Vec<u8>)>,
+    range: KeyRange,
+  ) -> Option<(Vec<u8>, Vec<u8>)> {
+    let changed = if is_empty_range(range) {
+      None
+    } else {
+      self
+        .changes
+        .range::<[u8], _>(range)
+        .rev()
+        .find_map(|(key, value)| value.as_ref().map(|value| (key.clone(), value.clone())))
+    };
+    match (committed, changed) {
+      (Some(committed), Some(changed)) => Some(if committed.0 > changed.0 {
+        committed
+      } else {
+        changed
+      }),
+      (committed, changed) => committed.or(changed),
+    }
+  }
+}
+
+impl IntoIterator for KVChanges {
+  type Item = (Vec<u8>, Option<Vec<u8>>);
+  type IntoIter = btree_map::IntoIter<Vec<u8>, Option<Vec<u8>>>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.changes.into_iter()
+  }
+}
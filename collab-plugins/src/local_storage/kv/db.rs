@@ -5,13 +5,15 @@ use std::sync::Arc;
 
 use crate::local_storage::kv::keys::*;
 use crate::local_storage::kv::oid::{DocIDGen, OID};
-use crate::local_storage::kv::snapshot::CollabSnapshot;
+use crate::local_storage::kv::snapshot::{CollabSnapshot, SnapshotMeta};
 use crate::local_storage::kv::PersistenceError;
 use smallvec::SmallVec;
 use yrs::{TransactionMut, Update};
 
 pub trait KVTransactionDB: Send + Sync + 'static {
-  type TransactionAction<'a>;
+  type TransactionAction<'a>: KVStore<'a, Error = PersistenceError>
+  where
+    Self: 'a;
 
   fn read_txn<'a, 'b>(&'b self) -> Self::TransactionAction<'a>
   where
@@ -28,6 +30,9 @@ pub trait KVTransactionDB: Send + Sync + 'static {
   where
     'b: 'a;
 
+  /// Commit the changes of a transaction created by [KVTransactionDB::write_txn].
+  fn commit_transaction(txn: Self::TransactionAction<'_>) -> Result<(), PersistenceError>;
+
   fn flush(&self) -> Result<(), PersistenceError>;
 }
 
@@ -55,6 +60,13 @@ pub trait KVStore<'a> {
 
   /// Return the entry prior to the given key
   fn next_back_entry(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error>;
+
+  /// Read the value of the key and lock it until the transaction is committed, so the other
+  /// transactions can't write it in the meantime. The implementations that can't lock a key
+  /// read it without locking.
+  fn get_for_update<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, Self::Error> {
+    Ok(self.get(key)?.map(|value| value.as_ref().to_vec()))
+  }
 }
 
 impl<T> KVStore<'static> for Arc<T>
@@ -89,6 +101,10 @@ where
   fn next_back_entry(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
     (**self).next_back_entry(key)
   }
+
+  fn get_for_update<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, Self::Error> {
+    (**self).get_for_update(key)
+  }
 }
 
 pub fn insert_snapshot_update<'a, K, S>(
@@ -102,12 +118,38 @@ where
   S: KVStore<'a>,
   PersistenceError: From<<S as KVStore<'a>>::Error>,
 {
-  let snapshot = CollabSnapshot::new(data).to_vec();
-  let update_key = create_update_key(snapshot_id, store, object_id, make_snapshot_update_key)?;
-  store.insert(update_key, snapshot)?;
+  insert_snapshot_update_with_meta(store, snapshot_id, object_id, data, SnapshotMeta::default())?;
   Ok(())
 }
 
+/// Insert a snapshot together with its metadata. The clock, the creation time, the size and the
+/// state vector of the given metadata are filled in from the snapshot.
+pub fn insert_snapshot_update_with_meta<'a, K, S>(
+  store: &S,
+  snapshot_id: SnapshotID,
+  object_id: &K,
+  data: Vec<u8>,
+  mut meta: SnapshotMeta,
+) -> Result<SnapshotMeta, PersistenceError>
+where
+  K: AsRef<[u8]> + ?Sized + Debug,
+  S: KVStore<'a>,
+  PersistenceError: From<<S as KVStore<'a>>::Error>,
+{
+  let snapshot = CollabSnapshot::new(data);
+  let update_key = create_update_key(snapshot_id, store, object_id, make_snapshot_update_key)?;
+  meta.clock = Clock::from_be_bytes(clock_from_key(update_key.as_ref()).try_into().unwrap());
+  meta.created_at = snapshot.created_at;
+  meta.size = snapshot.data.len() as u64;
+  meta.state_vector = SnapshotMeta::state_vector_of(&snapshot.data);
+  store.insert(update_key, snapshot.to_vec())?;
+  store.insert(
+    make_snapshot_meta_key(snapshot_id, meta.clock),
+    meta.to_vec(),
+  )?;
+  Ok(meta)
+}
+
 pub fn insert_doc_update<'a, K, S>(
   db: &S,
   doc_id: DocID,
@@ -3,6 +3,7 @@ use std::fmt::Debug;
 use crate::local_storage::kv::keys::*;
 use crate::local_storage::kv::snapshot::SnapshotAction;
 use crate::local_storage::kv::*;
+use collab::entity::{calculate_checksum, verify_checksum};
 use yrs::updates::decoder::Decode;
 use yrs::updates::encoder::Encode;
 use yrs::{Doc, ReadTxn, StateVector, Transact, TransactionMut, Update};
@@ -34,7 +35,9 @@ where
     let sv = txn.state_vector().encode_v1();
     let doc_state_key = make_doc_state_key(doc_id);
     let sv_key = make_state_vector_key(doc_id);
+    let checksum_key = make_doc_state_checksum_key(doc_id);
 
+    self.insert(checksum_key, calculate_checksum(&doc_state).to_be_bytes())?;
     self.insert(doc_state_key, doc_state)?;
     self.insert(sv_key, sv)?;
 
@@ -66,7 +69,9 @@ where
 
     let doc_state_key = make_doc_state_key(doc_id);
     let sv_key = make_state_vector_key(doc_id);
-    // Insert new doc state and state vector
+    let checksum_key = make_doc_state_checksum_key(doc_id);
+    // Insert new doc state, its checksum and state vector
+    self.insert(checksum_key, calculate_checksum(&doc_state).to_be_bytes())?;
     self.insert(doc_state_key, doc_state)?;
     self.insert(sv_key, state_vector)?;
     Ok(())
@@ -93,6 +98,15 @@ where
     if let Some(doc_id) = get_doc_id(uid, self, object_id) {
       let doc_state_key = make_doc_state_key(doc_id);
       if let Some(doc_state) = self.get(doc_state_key.as_ref())? {
+        // Verify the doc state before applying it. The doc state written before the checksum
+        // was introduced doesn't have a checksum, so it's skipped.
+        if let Some(expected) = self.get_doc_state_checksum(doc_id)? {
+          if let Err(err) = verify_checksum(doc_state.as_ref(), expected) {
+            tracing::error!("🔴{:?} doc state is corrupted: {}", object_id, err);
+            return Err(err.into());
+          }
+        }
+
         // Load the doc state
         if let Err(e) = Update::decode_v1(doc_state.as_ref())
           .map_err(PersistenceError::Yrs)
@@ -214,8 +228,40 @@ where
 
     let doc_state_key = make_doc_state_key(doc_id);
     let sv_key = make_state_vector_key(doc_id);
+    let checksum_key = make_doc_state_checksum_key(doc_id);
+
+    // Insert new doc state, its checksum and state vector
+    self.insert(checksum_key, calculate_checksum(doc_state).to_be_bytes())?;
+    self.insert(doc_state_key, doc_state)?;
+    self.insert(sv_key, sv)?;
+    Ok(())
+  }
+
+  /// Replace the document state with the given one and remove the updates up to and including
+  /// `last_update_key`. The updates that were pushed after `last_update_key` are kept, so the
+  /// given document state must contain all the updates up to `last_update_key`.
+  fn compact_doc_with<K: AsRef<[u8]> + ?Sized + Debug>(
+    &self,
+    uid: i64,
+    object_id: &K,
+    doc_state: &[u8],
+    sv: &[u8],
+    last_update_key: &[u8],
+  ) -> Result<(), PersistenceError> {
+    let doc_id = get_doc_id(uid, self, object_id).ok_or_else(|| {
+      PersistenceError::RecordNotFound(format!(
+        "doc with given object id: {:?} is not found",
+        object_id
+      ))
+    })?;
+    let start = make_doc_update_key(doc_id, 0);
+    self.remove_range(start.as_ref(), last_update_key)?;
+    self.remove(last_update_key)?;
 
-    // Insert new doc state and state vector
+    let doc_state_key = make_doc_state_key(doc_id);
+    let sv_key = make_state_vector_key(doc_id);
+    let checksum_key = make_doc_state_checksum_key(doc_id);
+    self.insert(checksum_key, calculate_checksum(doc_state).to_be_bytes())?;
     self.insert(doc_state_key, doc_state)?;
     self.insert(sv_key, sv)?;
     Ok(())
@@ -260,8 +306,10 @@ where
       // Delete the document state and the state vector
       let doc_state_key = make_doc_state_key(did);
       let sv_key = make_state_vector_key(did);
+      let checksum_key = make_doc_state_checksum_key(did);
       let _ = self.remove(doc_state_key.as_ref());
       let _ = self.remove(sv_key.as_ref());
+      let _ = self.remove(checksum_key.as_ref());
 
       // Delete the snapshot
       self.delete_all_snapshots(uid, object_id)?;
@@ -313,6 +361,22 @@ where
     get_last_update_key(self, doc_id, make_doc_update_key).ok()
   }
 
+  /// Return the checksum of the document state. Return None if the document state was written
+  /// without a checksum.
+  fn get_doc_state_checksum(&self, doc_id: DocID) -> Result<Option<u32>, PersistenceError> {
+    let checksum_key = make_doc_state_checksum_key(doc_id);
+    match self.get(checksum_key.as_ref())? {
+      None => Ok(None),
+      Some(value) => {
+        let bytes: [u8; 4] = value
+          .as_ref()
+          .try_into()
+          .map_err(|_| PersistenceError::InvalidData("invalid doc state checksum".to_string()))?;
+        Ok(Some(u32::from_be_bytes(bytes)))
+      },
+    }
+  }
+
   /// Return the number of updates for the given document
   fn number_of_updates<K: AsRef<[u8]> + ?Sized>(&self, uid: i64, object_id: &K) -> usize {
     if let Some(doc_id) = get_doc_id(uid, self, object_id) {
@@ -355,7 +419,7 @@ where
   }
 }
 
-fn get_doc_id<'a, K, S>(uid: i64, store: &S, object_id: &K) -> Option<DocID>
+pub(crate) fn get_doc_id<'a, K, S>(uid: i64, store: &S, object_id: &K) -> Option<DocID>
 where
   S: KVStore<'a>,
   K: AsRef<[u8]> + ?Sized,
@@ -365,6 +429,76 @@ where
   get_id_for_key(store, key)
 }
 
+/// Merge the update log of the document into its document state. Return the number of the
+/// merged updates.
+///
+/// The document is loaded and merged outside of the write transaction, only the write of the
+/// new document state happens in a write transaction, so the writers that push updates
+/// concurrently are not blocked. The updates pushed after the last update seen by the
+/// compaction are kept in the update log.
+///
+/// The write transaction reads the document state and the state vector by
+/// [KVStore::get_for_update] and checks that they are still the ones the merge started from. If
+/// another writer replaced them in the meantime, for example, a flush or another compaction, the
+/// compaction is aborted and 0 is returned. The check only excludes the concurrent writers on
+/// the databases that lock the keys read by [KVStore::get_for_update], like RocksDB.
+pub fn compact_doc<DB>(db: &DB, uid: i64, object_id: &str) -> Result<u32, PersistenceError>
+where
+  DB: KVTransactionDB,
+{
+  let doc = Doc::with_options(yrs::Options {
+    skip_gc: true,
+    ..yrs::Options::default()
+  });
+  let (doc_id, last_update_key, update_count, old_doc_state, old_sv) = {
+    let read_txn = db.read_txn();
+    let doc_id = match get_doc_id(uid, &read_txn, object_id) {
+      None => return Ok(0),
+      Some(doc_id) => doc_id,
+    };
+    let last_update_key = match read_txn.get_doc_last_update_key(uid, object_id) {
+      None => return Ok(0),
+      Some(key) => key,
+    };
+    let old_doc_state = read_txn
+      .get(make_doc_state_key(doc_id))?
+      .map(|value| value.as_ref().to_vec());
+    let old_sv = read_txn
+      .get(make_state_vector_key(doc_id))?
+      .map(|value| value.as_ref().to_vec());
+    let update_count = read_txn.load_doc_with_txn(uid, object_id, &mut doc.transact_mut())?;
+    (doc_id, last_update_key, update_count, old_doc_state, old_sv)
+  };
+  if update_count == 0 {
+    return Ok(0);
+  }
+
+  let txn = doc.transact();
+  let doc_state = txn.encode_state_as_update_v1(&StateVector::default());
+  let sv = txn.state_vector().encode_v1();
+  let compacted = db.with_write_txn(|w_txn| {
+    let current_doc_state = w_txn.get_for_update(make_doc_state_key(doc_id))?;
+    let current_sv = w_txn.get_for_update(make_state_vector_key(doc_id))?;
+    if get_doc_id(uid, w_txn, object_id) != Some(doc_id)
+      || current_doc_state != old_doc_state
+      || current_sv != old_sv
+    {
+      return Ok(false);
+    }
+    w_txn.compact_doc_with(uid, object_id, &doc_state, &sv, last_update_key.as_ref())?;
+    Ok(true)
+  })?;
+  if !compacted {
+    tracing::debug!(
+      "Collab {} was changed during the compaction, skip the compaction",
+      object_id
+    );
+    return Ok(0);
+  }
+  tracing::debug!("Collab {} compacted {} updates", object_id, update_count);
+  Ok(update_count)
+}
+
 pub struct OIDIter<I, E>
 where
   I: Iterator<Item = E>,
@@ -0,0 +1,504 @@
+use std::fmt::{Debug, Formatter};
+use std::ops::RangeBounds;
+use std::sync::Arc;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+use crate::local_storage::kv::keys::*;
+use crate::local_storage::kv::{
+  get_id_for_key, KVEntry, KVStore, KVTransactionDB, PersistenceError,
+};
+
+pub const ENCRYPTION_KEY_LEN: usize = 32;
+
+/// It's increased when the format of the encrypted value changes.
+const ENCRYPTED_VALUE_VERSION: u8 = 2;
+/// The first bytes of an encrypted value.
+const ENCRYPTED_VALUE_HEADER: [u8; 4] = [b'E', b'N', b'C', ENCRYPTED_VALUE_VERSION];
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+
+/// A 256-bit key that encrypts the documents of a workspace.
+#[derive(Clone, Eq, PartialEq)]
+pub struct EncryptionKey([u8; ENCRYPTION_KEY_LEN]);
+
+impl EncryptionKey {
+  pub fn new(key: [u8; ENCRYPTION_KEY_LEN]) -> Self {
+    Self(key)
+  }
+
+  /// Generate a random key.
+  pub fn generate() -> Self {
+    Self(XChaCha20Poly1305::generate_key(&mut OsRng).into())
+  }
+
+  pub fn as_bytes(&self) -> &[u8; ENCRYPTION_KEY_LEN] {
+    &self.0
+  }
+}
+
+impl Debug for EncryptionKey {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.write_str("EncryptionKey(***)")
+  }
+}
+
+/// Provides the encryption key of each workspace, for example, from the keychain of the OS.
+pub trait EncryptionKeyProvider: Send + Sync {
+  /// Return the key of the workspace. The same key must be returned for the same workspace every
+  /// time, otherwise the documents written with the previous key can't be read anymore.
+  fn workspace_key(&self, workspace_id: &str) -> Result<EncryptionKey, PersistenceError>;
+}
+
+/// Return true if the value of the key is encrypted by the [EncryptedKVTransactionDB]: the doc
+/// states, the state vectors, the updates, the snapshots, the outbox messages and the key check
+/// values of the workspaces. The doc id index isn't encrypted.
+pub fn is_encrypted_key(key: &[u8]) -> bool {
+  matches!(
+    key,
+    [DOC_SPACE, DOC_SPACE_OBJECT_KEY, ..]
+      | [SNAPSHOT_SPACE, ..]
+      | [OUTBOX_SPACE, ..]
+      | [META_SPACE, META_SPACE_ENCRYPTED_WORKSPACE, ..]
+  )
+}
+
+/// A [KVTransactionDB] that encrypts the values of the documents of a workspace with
+/// XChaCha20-Poly1305 before writing them into the underlying database.
+///
+/// Only the values are encrypted, the keys are left as they are, so the range scans of the
+/// [CollabKVAction](crate::local_storage::kv::doc::CollabKVAction) work the same way. Each value
+/// is encrypted with a random nonce and authenticated together with its key, so a value moved to
+/// another key fails to decrypt.
+///
+/// A database can store the documents of several workspaces, each one encrypted with the key of
+/// its workspace. Every encrypted value records its workspace, and each workspace is opened with
+/// its own [EncryptedKVTransactionDB] that only sees the values of the workspace: the range scans
+/// skip the values of the other workspaces and reading one of them by key fails. A key check
+/// value is recorded the first time a workspace is opened, and opening it with another key fails.
+///
+/// The values written before the database was encrypted can't be read until they are migrated
+/// with [EncryptedKVTransactionDB::migrate_plaintext_doc].
+#[derive(Clone)]
+pub struct EncryptedKVTransactionDB<DB> {
+  db: DB,
+  workspace_id: Arc<str>,
+  cipher: Arc<XChaCha20Poly1305>,
+}
+
+impl<DB> EncryptedKVTransactionDB<DB>
+where
+  DB: KVTransactionDB,
+{
+  /// Open the documents of the workspace. It fails with [PersistenceError::Encryption] if the
+  /// workspace was encrypted with another key.
+  pub fn new(db: DB, workspace_id: &str, key: &EncryptionKey) -> Result<Self, PersistenceError> {
+    if workspace_id.len() > u8::MAX as usize {
+      return Err(PersistenceError::Encryption(format!(
+        "the workspace id {} is too long",
+        workspace_id
+      )));
+    }
+    let encrypted_db = Self {
+      db,
+      workspace_id: Arc::from(workspace_id),
+      cipher: Arc::new(XChaCha20Poly1305::new(key.as_bytes().into())),
+    };
+    encrypted_db.check_key()?;
+    Ok(encrypted_db)
+  }
+
+  pub fn with_provider(
+    db: DB,
+    workspace_id: &str,
+    provider: &dyn EncryptionKeyProvider,
+  ) -> Result<Self, PersistenceError> {
+    let key = provider.workspace_key(workspace_id)?;
+    Self::new(db, workspace_id, &key)
+  }
+
+  pub fn workspace_id(&self) -> &str {
+    &self.workspace_id
+  }
+
+  /// Record the key check value of the workspace when it's opened for the first time, otherwise
+  /// check that the key decrypts it.
+  fn check_key(&self) -> Result<(), PersistenceError> {
+    let key = make_encrypted_workspace_key(self.workspace_id.as_bytes());
+    self.with_write_txn(|store| match store.get(key.as_ref()) {
+      Ok(None) => store.insert(key.as_ref(), self.workspace_id.as_bytes()),
+      Ok(Some(_)) => Ok(()),
+      Err(PersistenceError::Encryption(_)) => Err(PersistenceError::Encryption(format!(
+        "the workspace {} was encrypted with another key",
+        self.workspace_id
+      ))),
+      Err(err) => Err(err),
+    })
+  }
+
+  /// Encrypt the values of the document that were written in plain text before the database was
+  /// encrypted: its doc state, updates, snapshots and outbox messages. The values that are
+  /// already encrypted are left as they are. Return the number of values that were encrypted.
+  pub fn migrate_plaintext_doc(
+    &self,
+    uid: i64,
+    object_id: &str,
+  ) -> Result<usize, PersistenceError> {
+    let uid = uid.to_be_bytes();
+    let object_id = object_id.as_bytes();
+    self.with_write_txn(|store| {
+      let mut migrated = 0;
+      // The doc id index isn't encrypted
+      if let Some(doc_id) = get_id_for_key(&store.inner, make_doc_id_key(&uid, object_id)) {
+        let start = make_doc_start_key(doc_id);
+        let end = make_doc_end_key(doc_id);
+        migrated += store.migrate_range(start.as_ref()..end.as_ref())?;
+      }
+
+      let snapshot_id_key = make_snapshot_id_key(&uid, object_id);
+      migrated += store.migrate_value(snapshot_id_key.as_ref())?;
+      if let Some(snapshot_id) = get_id_for_key(store, snapshot_id_key) {
+        let start = make_snapshot_start_key(snapshot_id);
+        let end = make_snapshot_end_key(snapshot_id);
+        migrated += store.migrate_range(start.as_ref()..end.as_ref())?;
+      }
+
+      let start = make_outbox_msg_key(&uid, object_id, 0);
+      let end = make_outbox_msg_key(&uid, object_id, u64::MAX);
+      migrated += store.migrate_range(start.as_ref()..=end.as_ref())?;
+      Ok(migrated)
+    })
+  }
+
+  /// Encrypt all the values that were written in plain text before the database was encrypted
+  /// with the key of the workspace. Only use it when the database only stores the documents of
+  /// this workspace, otherwise migrate each document with
+  /// [EncryptedKVTransactionDB::migrate_plaintext_doc]. Return the number of values that were
+  /// encrypted.
+  pub fn migrate_plaintext(&self) -> Result<usize, PersistenceError> {
+    self.with_write_txn(|store| {
+      let mut migrated = 0;
+      let ranges: [(&[u8], &[u8]); 3] = [
+        (
+          &[DOC_SPACE, DOC_SPACE_OBJECT_KEY],
+          &[DOC_SPACE, DOC_SPACE_OBJECT_KEY + 1],
+        ),
+        (&[SNAPSHOT_SPACE], &[SNAPSHOT_SPACE + 1]),
+        (&[OUTBOX_SPACE], &[OUTBOX_SPACE + 1]),
+      ];
+      for (start, end) in ranges {
+        migrated += store.migrate_range(start..end)?;
+      }
+      Ok(migrated)
+    })
+  }
+
+  /// Return the underlying database, which reads and writes the encrypted values.
+  pub fn inner(&self) -> &DB {
+    &self.db
+  }
+}
+
+impl<DB> KVTransactionDB for EncryptedKVTransactionDB<DB>
+where
+  DB: KVTransactionDB,
+{
+  type TransactionAction<'a> = EncryptedKVStore<DB::TransactionAction<'a>>;
+
+  fn read_txn<'a, 'b>(&'b self) -> Self::TransactionAction<'a>
+  where
+    'b: 'a,
+  {
+    EncryptedKVStore::new(
+      self.db.read_txn(),
+      self.workspace_id.clone(),
+      self.cipher.clone(),
+    )
+  }
+
+  fn write_txn<'a, 'b>(&'b self) -> Self::TransactionAction<'a>
+  where
+    'b: 'a,
+  {
+    EncryptedKVStore::new(
+      self.db.write_txn(),
+      self.workspace_id.clone(),
+      self.cipher.clone(),
+    )
+  }
+
+  fn with_write_txn<'a, 'b, Output>(
+    &'b self,
+    f: impl FnOnce(&Self::TransactionAction<'a>) -> Result<Output, PersistenceError>,
+  ) -> Result<Output, PersistenceError>
+  where
+    'b: 'a,
+  {
+    let store = self.write_txn();
+    let result = f(&store)?;
+    Self::commit_transaction(store)?;
+    Ok(result)
+  }
+
+  fn commit_transaction(txn: Self::TransactionAction<'_>) -> Result<(), PersistenceError> {
+    DB::commit_transaction(txn.inner)
+  }
+
+  fn flush(&self) -> Result<(), PersistenceError> {
+    self.db.flush()
+  }
+}
+
+/// Implementation of [KVStore] for [EncryptedKVTransactionDB].
+pub struct EncryptedKVStore<S> {
+  inner: S,
+  workspace_id: Arc<str>,
+  cipher: Arc<XChaCha20Poly1305>,
+}
+
+impl<S> EncryptedKVStore<S> {
+  fn new(inner: S, workspace_id: Arc<str>, cipher: Arc<XChaCha20Poly1305>) -> Self {
+    Self {
+      inner,
+      workspace_id,
+      cipher,
+    }
+  }
+
+  /// Encrypted value: [header, workspace id length, workspace id, nonce, ciphertext with the tag]
+  fn encrypt(&self, key: &[u8], value: &[u8]) -> Result<Vec<u8>, PersistenceError> {
+    if !is_encrypted_key(key) {
+      return Ok(value.to_vec());
+    }
+    let workspace_id = self.workspace_id.as_bytes();
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = self
+      .cipher
+      .encrypt(
+        &nonce,
+        Payload {
+          msg: value,
+          aad: &aad(key, workspace_id),
+        },
+      )
+      .map_err(|_| PersistenceError::Encryption("failed to encrypt the value".to_string()))?;
+    let mut encrypted = Vec::with_capacity(
+      ENCRYPTED_VALUE_HEADER.len() + 1 + workspace_id.len() + NONCE_LEN + ciphertext.len(),
+    );
+    encrypted.extend_from_slice(&ENCRYPTED_VALUE_HEADER);
+    encrypted.push(workspace_id.len() as u8);
+    encrypted.extend_from_slice(workspace_id);
+    encrypted.extend_from_slice(&nonce);
+    encrypted.extend_from_slice(&ciphertext);
+    Ok(encrypted)
+  }
+
+  /// Return None if the value belongs to another workspace.
+  fn decrypt(&self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>, PersistenceError> {
+    if !is_encrypted_key(key) {
+      return Ok(Some(value.to_vec()));
+    }
+    let encrypted = EncryptedValue::parse(value).ok_or_else(|| {
+      PersistenceError::Encryption(format!("the value of {:?} is not encrypted", key))
+    })?;
+    if encrypted.workspace_id != self.workspace_id.as_bytes() {
+      return Ok(None);
+    }
+    self.open(key, &encrypted).map(Some)
+  }
+
+  fn open(&self, key: &[u8], encrypted: &EncryptedValue) -> Result<Vec<u8>, PersistenceError> {
+    self
+      .cipher
+      .decrypt(
+        XNonce::from_slice(encrypted.nonce),
+        Payload {
+          msg: encrypted.ciphertext,
+          aad: &aad(key, encrypted.workspace_id),
+        },
+      )
+      .map_err(|_| {
+        PersistenceError::Encryption(format!(
+          "failed to decrypt the value of {:?}, the key is wrong or the data is corrupted",
+          key
+        ))
+      })
+  }
+
+  /// Decrypt the value read by key. Unlike the range scans, the value of another workspace is an
+  /// error.
+  fn decrypt_value(&self, key: &[u8], value: &[u8]) -> Result<Vec<u8>, PersistenceError> {
+    self.decrypt(key, value)?.ok_or_else(|| {
+      PersistenceError::Encryption(format!(
+        "the value of {:?} belongs to another workspace",
+        key
+      ))
+    })
+  }
+
+  fn decrypt_entry<E: KVEntry>(
+    &self,
+    entry: E,
+  ) -> Result<Option<EncryptedEntry>, PersistenceError> {
+    let value = self.decrypt(entry.key(), entry.value())?;
+    Ok(value.map(|value| EncryptedEntry::new(entry.key().to_vec(), value)))
+  }
+
+  /// Return true if the value was written before the database was encrypted. A value that looks
+  /// like an encrypted value of this workspace but fails to decrypt is plain text too.
+  fn is_plaintext(&self, key: &[u8], value: &[u8]) -> bool {
+    match EncryptedValue::parse(value) {
+      None => true,
+      Some(encrypted) => {
+        encrypted.workspace_id == self.workspace_id.as_bytes()
+          && self.open(key, &encrypted).is_err()
+      },
+    }
+  }
+}
+
+impl<'a, S> EncryptedKVStore<S>
+where
+  S: KVStore<'a, Error = PersistenceError>,
+{
+  /// Encrypt the value of the key if it's stored in plain text. Return the number of values that
+  /// were encrypted.
+  fn migrate_value(&self, key: &[u8]) -> Result<usize, PersistenceError> {
+    match self.inner.get(key)? {
+      Some(value) if self.is_plaintext(key, value.as_ref()) => {
+        let encrypted = self.encrypt(key, value.as_ref())?;
+        self.inner.insert(key, encrypted)?;
+        Ok(1)
+      },
+      _ => Ok(0),
+    }
+  }
+
+  /// Encrypt the plain text values of the range. Return the number of values that were encrypted.
+  fn migrate_range<K: AsRef<[u8]>, R: RangeBounds<K>>(
+    &self,
+    range: R,
+  ) -> Result<usize, PersistenceError> {
+    let plaintext = self
+      .inner
+      .range(range)?
+      .filter(|entry| {
+        is_encrypted_key(entry.key()) && self.is_plaintext(entry.key(), entry.value())
+      })
+      .map(|entry| (entry.key().to_vec(), entry.value().to_vec()))
+      .collect::<Vec<_>>();
+    for (key, value) in &plaintext {
+      let encrypted = self.encrypt(key, value)?;
+      self.inner.insert(key, encrypted)?;
+    }
+    Ok(plaintext.len())
+  }
+}
+
+impl<'a, S> KVStore<'a> for EncryptedKVStore<S>
+where
+  S: KVStore<'a, Error = PersistenceError>,
+{
+  type Range = std::vec::IntoIter<EncryptedEntry>;
+  type Entry = EncryptedEntry;
+  type Value = Vec<u8>;
+  type Error = PersistenceError;
+
+  fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Self::Value>, Self::Error> {
+    match self.inner.get(key.as_ref())? {
+      None => Ok(None),
+      Some(value) => Ok(Some(self.decrypt_value(key.as_ref(), value.as_ref())?)),
+    }
+  }
+
+  fn insert<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<(), Self::Error> {
+    let value = self.encrypt(key.as_ref(), value.as_ref())?;
+    self.inner.insert(key, value)
+  }
+
+  fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+    self.inner.remove(key)
+  }
+
+  fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<(), Self::Error> {
+    self.inner.remove_range(from, to)
+  }
+
+  /// The values of the other workspaces are skipped.
+  fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> Result<Self::Range, Self::Error> {
+    let entries = self
+      .inner
+      .range(range)?
+      .filter_map(|entry| self.decrypt_entry(entry).transpose())
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok(entries.into_iter())
+  }
+
+  fn next_back_entry(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+    match self.inner.next_back_entry(key)? {
+      None => Ok(None),
+      Some(entry) => {
+        let value = self.decrypt_value(entry.key(), entry.value())?;
+        Ok(Some(EncryptedEntry::new(entry.key().to_vec(), value)))
+      },
+    }
+  }
+
+  fn get_for_update<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, Self::Error> {
+    match self.inner.get_for_update(key.as_ref())? {
+      None => Ok(None),
+      Some(value) => Ok(Some(self.decrypt_value(key.as_ref(), &value)?)),
+    }
+  }
+}
+
+/// The additional data authenticated together with the value: its key and its workspace.
+fn aad(key: &[u8], workspace_id: &[u8]) -> Vec<u8> {
+  [key, workspace_id].concat()
+}
+
+struct EncryptedValue<'a> {
+  workspace_id: &'a [u8],
+  nonce: &'a [u8],
+  ciphertext: &'a [u8],
+}
+
+impl<'a> EncryptedValue<'a> {
+  fn parse(value: &'a [u8]) -> Option<Self> {
+    let value = value.strip_prefix(ENCRYPTED_VALUE_HEADER.as_slice())?;
+    let (&workspace_id_len, value) = value.split_first()?;
+    let workspace_id_len = workspace_id_len as usize;
+    if value.len() < workspace_id_len + NONCE_LEN + TAG_LEN {
+      return None;
+    }
+    let (workspace_id, value) = value.split_at(workspace_id_len);
+    let (nonce, ciphertext) = value.split_at(NONCE_LEN);
+    Some(Self {
+      workspace_id,
+      nonce,
+      ciphertext,
+    })
+  }
+}
+
+pub struct EncryptedEntry {
+  key: Vec<u8>,
+  value: Vec<u8>,
+}
+
+impl EncryptedEntry {
+  pub fn new(key: Vec<u8>, value: Vec<u8>) -> Self {
+    Self { key, value }
+  }
+}
+
+impl KVEntry for EncryptedEntry {
+  fn key(&self) -> &[u8] {
+    self.key.as_ref()
+  }
+
+  fn value(&self) -> &[u8] {
+    self.value.as_ref()
+  }
+}
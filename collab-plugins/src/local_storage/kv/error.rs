@@ -18,6 +18,10 @@ pub enum PersistenceError {
   #[error("{0}")]
   RocksdbIOError(String),
 
+  #[cfg(all(feature = "sqlite", not(target_arch = "wasm32")))]
+  #[error(transparent)]
+  Sqlite(#[from] rusqlite::Error),
+
   #[error(transparent)]
   Bincode(#[from] bincode::Error),
 
@@ -36,6 +40,9 @@ pub enum PersistenceError {
   #[error("Failed to apply update from persistent store: {0}")]
   Update(#[from] yrs::error::UpdateError),
 
+  #[error("encryption: {0}")]
+  Encryption(String),
+
   #[error("invalid data: {0}")]
   InvalidData(String),
 
@@ -17,10 +17,18 @@ use smallvec::{smallvec, SmallVec};
 //     DOC_SPACE_OBJECT_KEY     doc_id      TERMINATOR_HI_WATERMARK (state end)
 //     DOC_SPACE_OBJECT_KEY     doc_id      DOC_STATE_VEC (state vector)
 //     DOC_SPACE_OBJECT_KEY     doc_id      DOC_UPDATE clock TERMINATOR (update)
+//     DOC_SPACE_OBJECT_KEY     doc_id      DOC_STATE_CHECKSUM (state checksum)
 //
 // SNAPSHOT_SPACE
 //     SNAPSHOT_SPACE_OBJECT        object_id       TERMINATOR
 //     SNAPSHOT_SPACE_OBJECT_KEY    snapshot_id     SNAPSHOT_UPDATE(snapshot)
+//     SNAPSHOT_SPACE_OBJECT_KEY    snapshot_id     SNAPSHOT_META(snapshot metadata)
+//
+// OUTBOX_SPACE
+//     OUTBOX_SPACE_OBJECT    uid   object_id   TERMINATOR   msg_id (unsynced message)
+//
+// META_SPACE
+//     META_SPACE_ENCRYPTED_WORKSPACE    workspace_id (key check of an encrypted workspace)
 
 /// Prefix byte used for all of the yrs object entries.
 pub const DOC_SPACE: u8 = 1;
@@ -45,6 +53,9 @@ pub const REMOTE_DOC_STATE_VEC: u8 = 2;
 /// Tag byte within [DOC_SPACE_OBJECT_KEY] used to identify object's update entries.
 pub const DOC_UPDATE: u8 = 2;
 
+/// Tag byte within [DOC_SPACE_OBJECT_KEY] used to identify object's state checksum entry.
+pub const DOC_STATE_CHECKSUM: u8 = 3;
+
 /// Prefix byte used for snapshot id -> [SnapshotID] mapping index key space.
 pub const SNAPSHOT_SPACE: u8 = 2;
 
@@ -54,9 +65,25 @@ pub const SNAPSHOT_SPACE_OBJECT: u8 = 0;
 /// Tag byte within [SNAPSHOT_SPACE_OBJECT] used to identify object's snapshot entries.
 pub const SNAPSHOT_UPDATE: u8 = 1;
 
+/// Tag byte within [SNAPSHOT_SPACE_OBJECT] used to identify the metadata of object's snapshot
+/// entries. The metadata of a snapshot has the same clock as the snapshot.
+pub const SNAPSHOT_META: u8 = 2;
+
 pub const COLLAB_SPACE: u8 = 3;
 pub const COLLAB_SPACE_OBJECT: u8 = 0;
 
+/// Prefix byte used for the messages that are waiting to be synced to the remote.
+pub const OUTBOX_SPACE: u8 = 4;
+pub const OUTBOX_SPACE_OBJECT: u8 = 0;
+pub const MSG_ID_LEN: usize = 8;
+
+/// Prefix byte used for the metadata of the database. It's the last key space, so the range scans
+/// and the [next_back_entry](crate::local_storage::kv::KVStore::next_back_entry) lookups of the
+/// other key spaces never reach it.
+pub const META_SPACE: u8 = TERMINATOR_HI_WATERMARK;
+/// Tag byte within [META_SPACE] used for the key check values of the encrypted workspaces.
+pub const META_SPACE_ENCRYPTED_WORKSPACE: u8 = 0;
+
 pub type DocID = u64;
 pub const DOC_ID_LEN: usize = 8;
 pub const DOC_STATE_KEY_LEN: usize = DOC_ID_LEN + 4;
@@ -87,6 +114,13 @@ pub fn oid_from_key(key: &[u8]) -> &[u8] {
   &key[10..(key.len() - 1)]
 }
 
+pub fn uid_from_key(key: &[u8]) -> i64 {
+  // [DOC_SPACE, DOC_SPACE_OBJECT] = 2
+  let mut uid = [0; 8];
+  uid.copy_from_slice(&key[2..10]);
+  i64::from_be_bytes(uid)
+}
+
 // [1,1,  0,0,0,0,0,0,0,0,  0]
 pub fn make_doc_state_key(doc_id: DocID) -> Key<DOC_STATE_KEY_LEN> {
   let mut v: SmallVec<[u8; DOC_STATE_KEY_LEN]> = smallvec![DOC_SPACE, DOC_SPACE_OBJECT_KEY];
@@ -123,6 +157,14 @@ pub fn make_remote_state_vector_key(doc_id: DocID) -> Key<DOC_STATE_KEY_LEN> {
   Key(v)
 }
 
+// [1,1,  0,0,0,0,0,0,0,0,  3]
+pub fn make_doc_state_checksum_key(doc_id: DocID) -> Key<DOC_STATE_KEY_LEN> {
+  let mut v: SmallVec<[u8; DOC_STATE_KEY_LEN]> = smallvec![DOC_SPACE, DOC_SPACE_OBJECT_KEY];
+  v.write_all(&doc_id.to_be_bytes()).unwrap();
+  v.push(DOC_STATE_CHECKSUM);
+  Key(v)
+}
+
 // [1,1,  0,0,0,0,0,0,0,0,  2   0,0,0,0,  0]
 pub fn make_doc_update_key(doc_id: DocID, clock: Clock) -> Key<DOC_UPDATE_KEY_LEN> {
   let mut v: SmallVec<[u8; DOC_UPDATE_KEY_LEN]> = smallvec![DOC_SPACE, DOC_SPACE_OBJECT_KEY];
@@ -170,6 +212,30 @@ pub fn make_snapshot_update_key(
   Key(v)
 }
 
+// [10,0,  0,0,0,0,0,0,0,0,  2   [0,0,0,0],  0]
+pub fn make_snapshot_meta_key(
+  snapshot_id: SnapshotID,
+  clock: Clock,
+) -> Key<SNAPSHOT_UPDATE_KEY_LEN> {
+  let mut v: SmallVec<[u8; SNAPSHOT_UPDATE_KEY_LEN]> =
+    smallvec![SNAPSHOT_SPACE, SNAPSHOT_SPACE_OBJECT];
+  v.write_all(&snapshot_id.to_be_bytes()).unwrap();
+  v.push(SNAPSHOT_META);
+  v.write_all(&clock.to_be_bytes()).unwrap();
+  v.push(TERMINATOR);
+  Key(v)
+}
+
+// The snapshots and their metadata are stored within bounds
+// [10,0,..snapshot_id,1,0,0,0,0,0]..[10,0,..snapshot_id,2,255,255,255,255,0]
+pub fn make_snapshot_start_key(snapshot_id: SnapshotID) -> Key<SNAPSHOT_UPDATE_KEY_LEN> {
+  make_snapshot_update_key(snapshot_id, 0)
+}
+
+pub fn make_snapshot_end_key(snapshot_id: SnapshotID) -> Key<SNAPSHOT_UPDATE_KEY_LEN> {
+  make_snapshot_meta_key(snapshot_id, Clock::MAX)
+}
+
 pub fn make_snapshot_update_key_prefix(
   snapshot_id: SnapshotID,
 ) -> Key<SNAPSHOT_UPDATE_KEY_PREFIX_LEN> {
@@ -180,6 +246,13 @@ pub fn make_snapshot_update_key_prefix(
   Key(v)
 }
 
+// [255,0,  workspace_id]
+pub fn make_encrypted_workspace_key(workspace_id: &[u8]) -> Key<40> {
+  let mut v: SmallVec<[u8; 40]> = smallvec![META_SPACE, META_SPACE_ENCRYPTED_WORKSPACE];
+  v.write_all(workspace_id).unwrap();
+  Key(v)
+}
+
 pub fn make_collab_id_key(object_id: &[u8]) -> Key<20> {
   let mut v: SmallVec<[u8; 20]> = smallvec![COLLAB_SPACE, COLLAB_SPACE_OBJECT];
   v.write_all(object_id).unwrap();
@@ -187,6 +260,45 @@ pub fn make_collab_id_key(object_id: &[u8]) -> Key<20> {
   Key(v)
 }
 
+// [4,0, uid,  object_id,  0]
+pub fn make_outbox_key_prefix(uid: &[u8], object_id: &[u8]) -> Key<20> {
+  let mut v: SmallVec<[u8; 20]> = smallvec![OUTBOX_SPACE, OUTBOX_SPACE_OBJECT];
+  v.write_all(uid).unwrap();
+  v.write_all(object_id).unwrap();
+  v.push(TERMINATOR);
+  Key(v)
+}
+
+// [4,0, uid,  object_id,  0,  0,0,0,0,0,0,0,0]
+pub fn make_outbox_msg_key(uid: &[u8], object_id: &[u8], msg_id: u64) -> Key<28> {
+  let mut v: SmallVec<[u8; 28]> =
+    SmallVec::from_slice(make_outbox_key_prefix(uid, object_id).as_ref());
+  v.write_all(&msg_id.to_be_bytes()).unwrap();
+  Key(v)
+}
+
+// The messages of a user are stored within bounds [4,0,..uid]..[4,0,..uid,255]
+pub fn make_outbox_uid_start_key(uid: &[u8]) -> Key<10> {
+  let mut v: SmallVec<[u8; 10]> = smallvec![OUTBOX_SPACE, OUTBOX_SPACE_OBJECT];
+  v.write_all(uid).unwrap();
+  Key(v)
+}
+
+pub fn make_outbox_uid_end_key(uid: &[u8]) -> Key<11> {
+  let mut v: SmallVec<[u8; 11]> = smallvec![OUTBOX_SPACE, OUTBOX_SPACE_OBJECT];
+  v.write_all(uid).unwrap();
+  v.push(TERMINATOR_HI_WATERMARK);
+  Key(v)
+}
+
+// [4,0, uid,  [object_id],  0,  msg_id]
+pub fn oid_from_outbox_msg_key(key: &[u8]) -> &[u8] {
+  // [OUTBOX_SPACE, OUTBOX_SPACE_OBJECT] = 2
+  // uid = 8
+  // TERMINATOR = 1
+  &key[10..(key.len() - MSG_ID_LEN - 1)]
+}
+
 #[repr(transparent)]
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Key<const N: usize>(pub SmallVec<[u8; N]>);
@@ -0,0 +1,192 @@
+use yrs::{Doc, ReadTxn, StateVector, Transact};
+
+use crate::local_storage::kv::doc::CollabKVAction;
+use crate::local_storage::kv::keys::*;
+use crate::local_storage::kv::snapshot::get_snapshot_id;
+use crate::local_storage::kv::*;
+
+type KVPair = (Vec<u8>, Vec<u8>);
+
+/// The result of [migrate_kv_db].
+#[derive(Debug, Default)]
+pub struct KVMigrationReport {
+  /// The number of documents that were copied and verified.
+  pub migrated_docs: usize,
+  /// The number of keys written to the target, including the [COLLAB_SPACE] entries.
+  pub copied_keys: usize,
+  /// The documents that were not migrated.
+  pub failed_docs: Vec<KVMigrationFailure>,
+}
+
+impl KVMigrationReport {
+  pub fn is_complete(&self) -> bool {
+    self.failed_docs.is_empty()
+  }
+}
+
+#[derive(Debug)]
+pub struct KVMigrationFailure {
+  pub uid: i64,
+  pub object_id: String,
+  pub error: PersistenceError,
+}
+
+/// Copy all the documents from the `source` to the `target`.
+///
+/// The documents are copied one by one, each in its own write transaction of the target: the
+/// doc id index, the doc state, the state vector, the checksum, the updates and the snapshots.
+/// The keys are copied as they are, so the target ends up with the same layout as the source.
+/// After a document is copied, it's loaded from both databases and the states are compared. A
+/// document that can't be loaded from the source, already exists in the target, or is loaded
+/// differently from the target is removed from the target and reported in
+/// [KVMigrationReport::failed_docs]; the migration goes on with the next document.
+///
+/// The [COLLAB_SPACE] entries are copied at the end, and the target is flushed.
+pub fn migrate_kv_db<S, T>(source: &S, target: &T) -> Result<KVMigrationReport, PersistenceError>
+where
+  S: KVTransactionDB,
+  T: KVTransactionDB,
+{
+  let mut report = KVMigrationReport::default();
+  let read_txn = source.read_txn();
+  let from = Key::from_const([DOC_SPACE, DOC_SPACE_OBJECT]);
+  let to = Key::from_const([DOC_SPACE, DOC_SPACE_OBJECT_KEY]);
+  for entry in read_txn.range(from.as_ref()..to.as_ref())? {
+    let (uid, object_id) = match parse_doc_id_key(entry.key()) {
+      Some(value) => value,
+      None => {
+        tracing::warn!("🟡skip invalid doc id key: {:?}", entry.key());
+        continue;
+      },
+    };
+    match migrate_doc(&read_txn, target, uid, &object_id) {
+      Ok(copied_keys) => {
+        report.migrated_docs += 1;
+        report.copied_keys += copied_keys;
+      },
+      Err(error) => {
+        tracing::error!("🔴migrate doc {} failed: {}", object_id, error);
+        report.failed_docs.push(KVMigrationFailure {
+          uid,
+          object_id,
+          error,
+        });
+      },
+    }
+  }
+
+  let from = Key::from_const([COLLAB_SPACE]);
+  let to = Key::from_const([COLLAB_SPACE + 1]);
+  let entries = read_txn
+    .range(from.as_ref()..to.as_ref())?
+    .map(|entry| (entry.key().to_vec(), entry.value().to_vec()))
+    .collect::<Vec<_>>();
+  report.copied_keys += entries.len();
+  target.with_write_txn(|store| {
+    for (key, value) in entries {
+      store.insert(key, value)?;
+    }
+    Ok(())
+  })?;
+  target.flush()?;
+  Ok(report)
+}
+
+/// Copy a document and verify it. Return the number of the copied keys.
+fn migrate_doc<'a, S, T>(
+  source: &S,
+  target: &T,
+  uid: i64,
+  object_id: &str,
+) -> Result<usize, PersistenceError>
+where
+  S: KVStore<'a, Error = PersistenceError> + 'a,
+  T: KVTransactionDB,
+{
+  if target.read_txn().is_exist(uid, object_id) {
+    return Err(PersistenceError::DocumentAlreadyExist);
+  }
+  let expected = encode_doc(source, uid, object_id)?;
+
+  let entries = doc_entries(source, uid, object_id)?;
+  let copied_keys = entries.len();
+  target.with_write_txn(|store| {
+    for (key, value) in entries {
+      store.insert(key, value)?;
+    }
+    Ok(())
+  })?;
+
+  let result = encode_doc(&target.read_txn(), uid, object_id).and_then(|actual| {
+    if actual == expected {
+      Ok(())
+    } else {
+      Err(PersistenceError::InvalidData(format!(
+        "{} is loaded differently after the migration",
+        object_id
+      )))
+    }
+  });
+  if let Err(err) = result {
+    target.with_write_txn(|store| store.delete_doc(uid, object_id))?;
+    return Err(err);
+  }
+  Ok(copied_keys)
+}
+
+/// Return all the entries of the document, including the ones of its snapshots.
+fn doc_entries<'a, S>(store: &S, uid: i64, object_id: &str) -> Result<Vec<KVPair>, PersistenceError>
+where
+  S: KVStore<'a, Error = PersistenceError>,
+{
+  let mut entries = vec![];
+  let mut copy_range = |from: &[u8], to: &[u8]| -> Result<(), PersistenceError> {
+    for entry in store.range(from..to)? {
+      entries.push((entry.key().to_vec(), entry.value().to_vec()));
+    }
+    Ok(())
+  };
+
+  let doc_id_key = make_doc_id_key(&uid.to_be_bytes(), object_id.as_bytes());
+  let doc_id = get_id_for_key(store, doc_id_key.clone()).ok_or_else(|| {
+    PersistenceError::RecordNotFound(format!("doc id of {} is not found", object_id))
+  })?;
+  copy_range(
+    make_doc_start_key(doc_id).as_ref(),
+    make_doc_end_key(doc_id).as_ref(),
+  )?;
+
+  if let Some(snapshot_id) = get_snapshot_id(uid, store, object_id) {
+    copy_range(
+      make_snapshot_start_key(snapshot_id).as_ref(),
+      make_snapshot_end_key(snapshot_id).as_ref(),
+    )?;
+    entries.push((
+      make_snapshot_id_key(&uid.to_be_bytes(), object_id.as_bytes()).to_vec(),
+      snapshot_id.to_be_bytes().to_vec(),
+    ));
+  }
+
+  entries.push((doc_id_key.to_vec(), doc_id.to_be_bytes().to_vec()));
+  Ok(entries)
+}
+
+fn encode_doc<'a, S>(store: &S, uid: i64, object_id: &str) -> Result<Vec<u8>, PersistenceError>
+where
+  S: KVStore<'a, Error = PersistenceError> + 'a,
+{
+  let doc = Doc::new();
+  store.load_doc(uid, object_id, doc.clone())?;
+  let txn = doc.transact();
+  Ok(txn.encode_state_as_update_v1(&StateVector::default()))
+}
+
+/// Parse the uid and the object id from a key created by [make_doc_id_key].
+pub(crate) fn parse_doc_id_key(key: &[u8]) -> Option<(i64, String)> {
+  if key.len() < 11 {
+    return None;
+  }
+  let uid = uid_from_key(key);
+  let object_id = String::from_utf8(oid_from_key(key).to_vec()).ok()?;
+  Some((uid, object_id))
+}
@@ -2,10 +2,16 @@ pub use db::*;
 pub use error::*;
 pub use range::*;
 
+pub(crate) mod changes;
 mod db;
 pub mod doc;
+#[cfg(feature = "encryption")]
+pub mod encryption;
 pub mod error;
 pub mod keys;
+pub mod migration;
 pub mod oid;
+pub mod outbox;
 mod range;
 pub mod snapshot;
+pub mod usage;
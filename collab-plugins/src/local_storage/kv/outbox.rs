@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use serde::{Deserialize, Serialize};
+
+use crate::local_storage::kv::keys::*;
+use crate::local_storage::kv::*;
+
+impl<'a, T> OutboxAction<'a> for T
+where
+  T: KVStore<'a>,
+  PersistenceError: From<<Self as KVStore<'a>>::Error>,
+{
+}
+
+/// A message that was queued to be synced to the remote but not acked yet.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct OutboxMsg {
+  pub msg_id: u64,
+  /// The updates of the message. There is more than one update if other messages were merged
+  /// into this message.
+  pub payloads: Vec<Vec<u8>>,
+}
+
+impl OutboxMsg {
+  pub fn new(msg_id: u64, payloads: Vec<Vec<u8>>) -> Self {
+    Self { msg_id, payloads }
+  }
+
+  /// Return the number of local changes in the message.
+  pub fn changes(&self) -> u64 {
+    self.payloads.len() as u64
+  }
+
+  pub fn to_vec(&self) -> Vec<u8> {
+    bincode::serialize(self).unwrap()
+  }
+}
+
+impl TryFrom<&[u8]> for OutboxMsg {
+  type Error = PersistenceError;
+
+  fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+    Ok(bincode::deserialize(value)?)
+  }
+}
+
+/// The outbox keeps the messages that are waiting to be synced to the remote, so they survive a
+/// restart of the application. The messages of a collab are ordered by their msg id.
+pub trait OutboxAction<'a>: KVStore<'a> + Sized
+where
+  PersistenceError: From<<Self as KVStore<'a>>::Error>,
+{
+  /// Put the message into the outbox. A message with the same msg id is replaced.
+  fn push_outbox_msg<K: AsRef<[u8]> + ?Sized + Debug>(
+    &self,
+    uid: i64,
+    object_id: &K,
+    msg: &OutboxMsg,
+  ) -> Result<(), PersistenceError> {
+    let key = make_outbox_msg_key(&uid.to_be_bytes(), object_id.as_ref(), msg.msg_id);
+    self.insert(key, msg.to_vec())?;
+    Ok(())
+  }
+
+  /// Replace the message with the given one that the messages of `merged_msg_ids` were merged
+  /// into, and remove the merged messages.
+  fn merge_outbox_msgs<K: AsRef<[u8]> + ?Sized + Debug>(
+    &self,
+    uid: i64,
+    object_id: &K,
+    msg: &OutboxMsg,
+    merged_msg_ids: &[u64],
+  ) -> Result<(), PersistenceError> {
+    for msg_id in merged_msg_ids {
+      self.remove_outbox_msg(uid, object_id, *msg_id)?;
+    }
+    self.push_outbox_msg(uid, object_id, msg)
+  }
+
+  /// Remove the message after it was acked by the remote.
+  fn remove_outbox_msg<K: AsRef<[u8]> + ?Sized + Debug>(
+    &self,
+    uid: i64,
+    object_id: &K,
+    msg_id: u64,
+  ) -> Result<(), PersistenceError> {
+    let key = make_outbox_msg_key(&uid.to_be_bytes(), object_id.as_ref(), msg_id);
+    self.remove(key.as_ref())?;
+    Ok(())
+  }
+
+  /// Return the messages of the given object id ordered by their msg id.
+  fn get_outbox_msgs<K: AsRef<[u8]> + ?Sized>(&self, uid: i64, object_id: &K) -> Vec<OutboxMsg> {
+    let mut msgs = vec![];
+    let uid = uid.to_be_bytes();
+    let start = make_outbox_msg_key(&uid, object_id.as_ref(), 0);
+    let end = make_outbox_msg_key(&uid, object_id.as_ref(), u64::MAX);
+    if let Ok(entries) = self.range(start.as_ref()..=end.as_ref()) {
+      for entry in entries {
+        match OutboxMsg::try_from(entry.value()) {
+          Ok(msg) => msgs.push(msg),
+          Err(err) => tracing::warn!("🟡skip invalid outbox message: {}", err),
+        }
+      }
+    }
+    msgs
+  }
+
+  /// Return the number of local changes of the given object id that are not synced yet.
+  fn number_of_unsynced_changes<K: AsRef<[u8]> + ?Sized>(&self, uid: i64, object_id: &K) -> u64 {
+    self
+      .get_outbox_msgs(uid, object_id)
+      .iter()
+      .map(|msg| msg.changes())
+      .sum()
+  }
+
+  /// Return the number of local changes that are not synced yet for each object of the user.
+  /// The objects without unsynced changes are not listed.
+  fn unsynced_changes(&self, uid: i64) -> Result<BTreeMap<String, u64>, PersistenceError> {
+    let mut changes = BTreeMap::new();
+    let uid = uid.to_be_bytes();
+    let start = make_outbox_uid_start_key(&uid);
+    let end = make_outbox_uid_end_key(&uid);
+    for entry in self.range(start.as_ref()..end.as_ref())? {
+      let msg = match OutboxMsg::try_from(entry.value()) {
+        Ok(msg) => msg,
+        Err(err) => {
+          tracing::warn!("🟡skip invalid outbox message: {}", err);
+          continue;
+        },
+      };
+      let object_id = String::from_utf8_lossy(oid_from_outbox_msg_key(entry.key())).to_string();
+      *changes.entry(object_id).or_insert(0) += msg.changes();
+    }
+    Ok(changes)
+  }
+
+  /// Remove all the messages of the given object id.
+  fn clear_outbox<K: AsRef<[u8]> + ?Sized>(
+    &self,
+    uid: i64,
+    object_id: &K,
+  ) -> Result<(), PersistenceError> {
+    let uid = uid.to_be_bytes();
+    let start = make_outbox_msg_key(&uid, object_id.as_ref(), 0);
+    let end = make_outbox_msg_key(&uid, object_id.as_ref(), u64::MAX);
+    self.remove_range(start.as_ref(), end.as_ref())?;
+    self.remove(end.as_ref())?;
+    Ok(())
+  }
+}
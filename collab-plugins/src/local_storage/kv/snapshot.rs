@@ -4,10 +4,14 @@ use std::panic::AssertUnwindSafe;
 
 use crate::local_storage::kv::keys::*;
 use crate::local_storage::kv::*;
+use crate::local_storage::SnapshotRetention;
+use collab::entity::{calculate_checksum, verify_checksum};
+use collab::preclude::Collab;
 use collab_entity::CollabType;
 use serde::{Deserialize, Serialize};
-use yrs::updates::encoder::{Encoder, EncoderV1};
-use yrs::{ReadTxn, Snapshot};
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::{Encode, Encoder, EncoderV1};
+use yrs::{Doc, Out, ReadTxn, Snapshot, StateVector, Transact, UndoManager, Update};
 
 impl<'a, T> SnapshotAction<'a> for T
 where
@@ -70,6 +74,106 @@ where
     insert_snapshot_update(self, snapshot_id, object_id, snapshot_data)?;
     Ok(())
   }
+  /// Create a snapshot with the given data and metadata. The clock, the creation time, the size
+  /// and the state vector of the metadata are filled in from the snapshot.
+  fn create_snapshot_with_meta<K>(
+    &self,
+    uid: i64,
+    object_id: &K,
+    snapshot_data: Vec<u8>,
+    meta: SnapshotMeta,
+  ) -> Result<SnapshotMeta, PersistenceError>
+  where
+    K: AsRef<[u8]> + ?Sized + Debug,
+  {
+    tracing::trace!("New snapshot for object:{:?}", object_id);
+    let snapshot_id = self.create_snapshot_id(uid, object_id.as_ref())?;
+    insert_snapshot_update_with_meta(self, snapshot_id, object_id, snapshot_data, meta)
+  }
+
+  /// Create a snapshot of the current state of the document on demand, named by the given label.
+  fn create_named_snapshot<K, T>(
+    &self,
+    uid: i64,
+    object_id: &K,
+    txn: &T,
+    label: &str,
+    author_uid: i64,
+    collab_type: CollabType,
+  ) -> Result<SnapshotMeta, PersistenceError>
+  where
+    K: AsRef<[u8]> + ?Sized + Debug,
+    T: ReadTxn,
+  {
+    let data = txn.encode_state_as_update_v1(&StateVector::default());
+    let meta = SnapshotMeta {
+      label: Some(label.to_string()),
+      author_uid: Some(author_uid),
+      collab_type: Some(collab_type),
+      ..Default::default()
+    };
+    self.create_snapshot_with_meta(uid, object_id, data, meta)
+  }
+
+  /// Return the metadata of the snapshots for the given object id, from the oldest to the newest,
+  /// without loading the snapshots. The snapshots created before the metadata was introduced are
+  /// not listed.
+  fn get_snapshot_metas<K: AsRef<[u8]> + ?Sized>(
+    &self,
+    uid: i64,
+    object_id: &K,
+  ) -> Vec<SnapshotMeta> {
+    let mut metas = vec![];
+    if let Some(snapshot_id) = get_snapshot_id(uid, self, object_id) {
+      let start = make_snapshot_meta_key(snapshot_id, 0);
+      let end = make_snapshot_end_key(snapshot_id);
+      if let Ok(encoded_metas) = self.range(start.as_ref()..end.as_ref()) {
+        for encoded_meta in encoded_metas {
+          match SnapshotMeta::try_from(encoded_meta.value()) {
+            Ok(meta) => metas.push(meta),
+            Err(err) => tracing::warn!("🟡skip invalid snapshot meta: {}", err),
+          }
+        }
+      }
+    }
+    metas
+  }
+
+  /// Return the snapshot identified by the [SnapshotMeta::clock].
+  fn get_snapshot<K: AsRef<[u8]> + ?Sized>(
+    &self,
+    uid: i64,
+    object_id: &K,
+    clock: Clock,
+  ) -> Option<CollabSnapshot> {
+    let snapshot_id = get_snapshot_id(uid, self, object_id)?;
+    let value = self
+      .get(make_snapshot_update_key(snapshot_id, clock).as_ref())
+      .ok()??;
+    CollabSnapshot::try_from(value.as_ref()).ok()
+  }
+
+  /// Restore the collab to the snapshot identified by the [SnapshotMeta::clock]. The snapshot is
+  /// applied as a new update on top of the current state, so the history is kept and the
+  /// restoration is persisted and synced like any other change.
+  fn restore_snapshot<K: AsRef<[u8]> + ?Sized + Debug>(
+    &self,
+    uid: i64,
+    object_id: &K,
+    clock: Clock,
+    collab: &mut Collab,
+  ) -> Result<(), PersistenceError> {
+    let snapshot = self.get_snapshot(uid, object_id, clock).ok_or_else(|| {
+      PersistenceError::RecordNotFound(format!(
+        "snapshot {} of {:?} is not found",
+        clock, object_id
+      ))
+    })?;
+    let update = encode_restore_update(&collab.transact(), &snapshot.data)?;
+    collab.apply_update(Update::decode_v1(&update)?)?;
+    Ok(())
+  }
+
   /// Return list of snapshots for the given object id.
   fn get_snapshots<K: AsRef<[u8]> + ?Sized>(&self, uid: i64, object_id: &K) -> Vec<CollabSnapshot> {
     let mut snapshots = vec![];
@@ -79,8 +183,9 @@ where
 
       if let Ok(encoded_updates) = self.range(start.as_ref()..=end.as_ref()) {
         for encoded_snapshot in encoded_updates {
-          if let Ok(snapshot) = CollabSnapshot::try_from(encoded_snapshot.value()) {
-            snapshots.push(snapshot);
+          match CollabSnapshot::try_from(encoded_snapshot.value()) {
+            Ok(snapshot) => snapshots.push(snapshot),
+            Err(err) => tracing::warn!("🟡skip invalid snapshot: {}", err),
           }
         }
       }
@@ -110,11 +215,13 @@ where
 
   fn delete_last_snapshot_by_snapshot_id(&self, snapshot_id: SnapshotID) {
     if let Some(last_update_key) = self.get_snapshot_last_update_key(snapshot_id) {
-      match self.remove(last_update_key.as_ref()) {
-        Ok(_) => {},
-        Err(e) => {
-          tracing::error!("🔴delete last snapshot failed: {:?}", e);
-        },
+      let clock =
+        Clock::from_be_bytes(clock_from_key(last_update_key.as_ref()).try_into().unwrap());
+      let result = self
+        .remove(last_update_key.as_ref())
+        .and_then(|_| self.remove(make_snapshot_meta_key(snapshot_id, clock).as_ref()));
+      if let Err(e) = result {
+        tracing::error!("🔴delete last snapshot failed: {:?}", e);
       }
     }
   }
@@ -126,13 +233,52 @@ where
     object_id: &K,
   ) -> Result<(), PersistenceError> {
     if let Some(snapshot_id) = get_snapshot_id(uid, self, object_id) {
-      let start = make_snapshot_update_key(snapshot_id, 0);
-      let end = make_snapshot_update_key(snapshot_id, Clock::MAX);
+      let start = make_snapshot_start_key(snapshot_id);
+      let end = make_snapshot_end_key(snapshot_id);
       self.remove_range(start.as_ref(), end.as_ref())?;
     }
     Ok(())
   }
 
+  /// Remove the snapshots of the given object id that the retention policy doesn't keep. Return
+  /// the number of the removed snapshots. The snapshots that can't be decoded are left as they are.
+  fn apply_snapshot_retention<K: AsRef<[u8]> + ?Sized>(
+    &self,
+    uid: i64,
+    object_id: &K,
+    retention: &SnapshotRetention,
+  ) -> Result<usize, PersistenceError> {
+    if !retention.is_enabled() {
+      return Ok(0);
+    }
+    let snapshot_id = match get_snapshot_id(uid, self, object_id) {
+      None => return Ok(0),
+      Some(snapshot_id) => snapshot_id,
+    };
+    let start = make_snapshot_update_key(snapshot_id, 0);
+    let end = make_snapshot_update_key(snapshot_id, Clock::MAX);
+    let mut clocks = vec![];
+    let mut snapshots = vec![];
+    for entry in self.range(start.as_ref()..end.as_ref())? {
+      match CollabSnapshot::try_from(entry.value()) {
+        Ok(snapshot) => {
+          clocks.push(Clock::from_be_bytes(
+            clock_from_key(entry.key()).try_into().unwrap(),
+          ));
+          snapshots.push((snapshot.created_at, entry.value().len() as u64));
+        },
+        Err(err) => tracing::warn!("🟡skip invalid snapshot: {}", err),
+      }
+    }
+
+    let expired = retention.expired_snapshots(&snapshots);
+    for index in &expired {
+      self.remove(make_snapshot_update_key(snapshot_id, clocks[*index]).as_ref())?;
+      self.remove(make_snapshot_meta_key(snapshot_id, clocks[*index]).as_ref())?;
+    }
+    Ok(expired.len())
+  }
+
   /// Create a snapshot id for the given object id.
   fn create_snapshot_id<K: AsRef<[u8]> + ?Sized>(
     &self,
@@ -184,6 +330,53 @@ pub fn try_encode_snapshot<T: ReadTxn>(
   }
 }
 
+/// Encode the update that restores the current state of a document to the snapshot. The update
+/// removes the content added since the snapshot and adds back the content removed since the
+/// snapshot, so applying it on top of the current state makes the document equal to the snapshot
+/// without rewriting its history.
+pub fn encode_restore_update<T: ReadTxn>(
+  txn: &T,
+  snapshot_data: &[u8],
+) -> Result<Vec<u8>, PersistenceError> {
+  const RESTORE_ORIGIN: &str = "restore_snapshot";
+
+  // The deleted content must be kept to be brought back.
+  let snapshot_doc = Doc::with_options(yrs::Options {
+    skip_gc: true,
+    ..Default::default()
+  });
+  snapshot_doc
+    .transact_mut()
+    .apply_update(Update::decode_v1(snapshot_data)?)?;
+  let snapshot_state_vector = snapshot_doc.transact().state_vector();
+
+  let mut undo_manager = UndoManager::with_options(&snapshot_doc, Default::default());
+  undo_manager.include_origin(RESTORE_ORIGIN);
+  for (name, value) in txn.root_refs() {
+    match value {
+      Out::YText(_) => undo_manager.expand_scope(&snapshot_doc.get_or_insert_text(name)),
+      Out::YArray(_) => undo_manager.expand_scope(&snapshot_doc.get_or_insert_array(name)),
+      Out::YXmlFragment(_) => {
+        undo_manager.expand_scope(&snapshot_doc.get_or_insert_xml_fragment(name))
+      },
+      _ => undo_manager.expand_scope(&snapshot_doc.get_or_insert_map(name)),
+    }
+  }
+
+  // Apply the changes made since the snapshot, then undo them.
+  let changes = txn.encode_state_as_update_v1(&snapshot_state_vector);
+  snapshot_doc
+    .transact_mut_with(RESTORE_ORIGIN)
+    .apply_update(Update::decode_v1(&changes)?)?;
+  undo_manager.undo_blocking();
+  drop(undo_manager);
+
+  let update = snapshot_doc
+    .transact()
+    .encode_state_as_update_v1(&txn.state_vector());
+  Ok(update)
+}
+
 pub trait SnapshotPersistence: Send + Sync {
   fn create_snapshot(
     &self,
@@ -198,23 +391,99 @@ pub trait SnapshotPersistence: Send + Sync {
 pub struct CollabSnapshot {
   pub data: Vec<u8>,
   pub created_at: i64,
+  /// CRC32 checksum of the [CollabSnapshot::data]. It's `None` for the snapshots that were
+  /// created before the checksum was introduced.
+  #[serde(default)]
+  pub checksum: Option<u32>,
 }
 
 impl CollabSnapshot {
   pub fn new(data: Vec<u8>) -> CollabSnapshot {
     let created_at = chrono::Utc::now().timestamp();
-    Self { data, created_at }
+    let checksum = Some(calculate_checksum(&data));
+    Self {
+      data,
+      created_at,
+      checksum,
+    }
   }
 
   pub fn to_vec(&self) -> Vec<u8> {
     bincode::serialize(&self).unwrap()
   }
+
+  /// Verify the [CollabSnapshot::data] against the stored checksum. The snapshot without checksum
+  /// is considered valid.
+  pub fn verify_checksum(&self) -> Result<(), PersistenceError> {
+    if let Some(expected) = self.checksum {
+      verify_checksum(&self.data, expected)?;
+    }
+    Ok(())
+  }
 }
 
-impl TryFrom<&[u8]> for CollabSnapshot {
+/// The metadata of a snapshot. It's stored next to the snapshot, so the snapshots can be listed
+/// without loading them.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotMeta {
+  /// Identifies the snapshot among the snapshots of the object.
+  pub clock: Clock,
+  /// The user supplied label of a named snapshot.
+  pub label: Option<String>,
+  /// The uid of the user who created the snapshot.
+  pub author_uid: Option<i64>,
+  pub collab_type: Option<CollabType>,
+  pub created_at: i64,
+  /// The size of the snapshot data in bytes.
+  pub size: u64,
+  /// The encoded v1 state vector of the snapshot. It's empty if the snapshot data is not a yrs
+  /// update.
+  pub state_vector: Vec<u8>,
+}
+
+impl SnapshotMeta {
+  pub fn to_vec(&self) -> Vec<u8> {
+    bincode::serialize(&self).unwrap()
+  }
+
+  pub(crate) fn state_vector_of(data: &[u8]) -> Vec<u8> {
+    Update::decode_v1(data)
+      .map(|update| update.state_vector().encode_v1())
+      .unwrap_or_default()
+  }
+}
+
+impl TryFrom<&[u8]> for SnapshotMeta {
   type Error = PersistenceError;
 
   fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
     Ok(bincode::deserialize(value)?)
   }
 }
+
+/// The snapshot format before the checksum was introduced.
+#[derive(Serialize, Deserialize)]
+struct CollabSnapshotV0 {
+  data: Vec<u8>,
+  created_at: i64,
+}
+
+impl TryFrom<&[u8]> for CollabSnapshot {
+  type Error = PersistenceError;
+
+  fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+    let snapshot = match bincode::deserialize::<CollabSnapshot>(value) {
+      Ok(snapshot) => snapshot,
+      Err(_) => {
+        let old_snapshot: CollabSnapshotV0 = bincode::deserialize(value)?;
+        CollabSnapshot {
+          data: old_snapshot.data,
+          created_at: old_snapshot.created_at,
+          checksum: None,
+        }
+      },
+    };
+    snapshot.verify_checksum()?;
+    Ok(snapshot)
+  }
+}
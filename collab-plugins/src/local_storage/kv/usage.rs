@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+
+use crate::local_storage::kv::keys::*;
+use crate::local_storage::kv::migration::parse_doc_id_key;
+use crate::local_storage::kv::snapshot::get_snapshot_id;
+use crate::local_storage::kv::*;
+
+impl<'a, T> StorageUsageAction<'a> for T
+where
+  T: KVStore<'a>,
+  PersistenceError: From<<Self as KVStore<'a>>::Error>,
+{
+}
+
+/// The storage used by a document. The sizes include the keys and the values.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct DocUsage {
+  pub uid: i64,
+  pub object_id: String,
+  pub doc_id: DocID,
+  pub doc_state_bytes: u64,
+  /// The local and the remote state vectors and the checksum of the doc state.
+  pub metadata_bytes: u64,
+  pub number_of_updates: u64,
+  pub update_bytes: u64,
+  pub number_of_snapshots: u64,
+  pub snapshot_bytes: u64,
+}
+
+impl DocUsage {
+  pub fn total_bytes(&self) -> u64 {
+    self.doc_state_bytes + self.metadata_bytes + self.update_bytes + self.snapshot_bytes
+  }
+}
+
+/// The storage used by all the documents of a user.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct UidUsage {
+  pub number_of_docs: u64,
+  pub doc_state_bytes: u64,
+  pub metadata_bytes: u64,
+  pub number_of_updates: u64,
+  pub update_bytes: u64,
+  pub number_of_snapshots: u64,
+  pub snapshot_bytes: u64,
+}
+
+impl UidUsage {
+  pub fn total_bytes(&self) -> u64 {
+    self.doc_state_bytes + self.metadata_bytes + self.update_bytes + self.snapshot_bytes
+  }
+
+  fn add(&mut self, doc: &DocUsage) {
+    self.number_of_docs += 1;
+    self.doc_state_bytes += doc.doc_state_bytes;
+    self.metadata_bytes += doc.metadata_bytes;
+    self.number_of_updates += doc.number_of_updates;
+    self.update_bytes += doc.update_bytes;
+    self.number_of_snapshots += doc.number_of_snapshots;
+    self.snapshot_bytes += doc.snapshot_bytes;
+  }
+}
+
+/// A doc id mapping whose document has no data at all: no doc state, no update and no metadata.
+/// A document with updates but without doc state isn't an orphan, its updates are its data.
+/// A document that still has snapshots is reported as recoverable instead, see
+/// [StorageReport::recoverable_mappings].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OrphanMapping {
+  pub uid: i64,
+  pub object_id: String,
+  pub doc_id: DocID,
+}
+
+/// The data of a doc id that no doc id mapping points to.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OrphanData {
+  pub doc_id: DocID,
+  pub number_of_keys: u64,
+  pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct StorageReport {
+  /// The documents ordered by their total size, the largest first.
+  pub docs: Vec<DocUsage>,
+  pub uids: BTreeMap<i64, UidUsage>,
+  pub orphan_mappings: Vec<OrphanMapping>,
+  /// The doc id mappings without document data but with snapshots. The document can be restored
+  /// from its snapshots, so they are not removed by [StorageUsageAction::remove_orphans].
+  pub recoverable_mappings: Vec<OrphanMapping>,
+  pub orphan_data: Vec<OrphanData>,
+}
+
+impl StorageReport {
+  pub fn has_orphans(&self) -> bool {
+    !self.orphan_mappings.is_empty() || !self.orphan_data.is_empty()
+  }
+}
+
+pub trait StorageUsageAction<'a>: KVStore<'a> + Sized
+where
+  PersistenceError: From<<Self as KVStore<'a>>::Error>,
+{
+  /// Scan the doc id mappings and the document key space, and report the storage used by each
+  /// document and each user, and the orphans.
+  fn storage_report(&self) -> Result<StorageReport, PersistenceError> {
+    let mut report = StorageReport::default();
+    let mut docs = BTreeMap::new();
+    let from = Key::from_const([DOC_SPACE, DOC_SPACE_OBJECT]);
+    let to = Key::from_const([DOC_SPACE, DOC_SPACE_OBJECT_KEY]);
+    for entry in self.range(from.as_ref()..to.as_ref())? {
+      let (uid, object_id) = match parse_doc_id_key(entry.key()) {
+        Some(value) => value,
+        None => continue,
+      };
+      let doc_id = match entry.value().try_into() {
+        Ok(doc_id) => DocID::from_be_bytes(doc_id),
+        Err(_) => continue,
+      };
+      docs.insert(
+        doc_id,
+        DocUsage {
+          uid,
+          object_id,
+          doc_id,
+          ..Default::default()
+        },
+      );
+    }
+
+    let mut orphan_data = BTreeMap::new();
+    let from = Key::from_const([DOC_SPACE, DOC_SPACE_OBJECT_KEY]);
+    let to = Key::from_const([DOC_SPACE, DOC_SPACE_OBJECT_KEY + 1]);
+    for entry in self.range(from.as_ref()..to.as_ref())? {
+      let key = entry.key();
+      if key.len() < 2 + DOC_ID_LEN + 1 {
+        continue;
+      }
+      let mut doc_id = [0; DOC_ID_LEN];
+      doc_id.copy_from_slice(&key[2..2 + DOC_ID_LEN]);
+      let doc_id = DocID::from_be_bytes(doc_id);
+      let bytes = (key.len() + entry.value().len()) as u64;
+      match docs.get_mut(&doc_id) {
+        None => {
+          let orphan = orphan_data.entry(doc_id).or_insert(OrphanData {
+            doc_id,
+            number_of_keys: 0,
+            bytes: 0,
+          });
+          orphan.number_of_keys += 1;
+          orphan.bytes += bytes;
+        },
+        Some(doc) => match key[2 + DOC_ID_LEN] {
+          DOC_STATE => doc.doc_state_bytes += bytes,
+          DOC_UPDATE if key.len() == 2 + DOC_ID_LEN + 1 + CLOCK_LEN + 1 => {
+            doc.number_of_updates += 1;
+            doc.update_bytes += bytes;
+          },
+          _ => doc.metadata_bytes += bytes,
+        },
+      }
+    }
+
+    for doc in docs.values_mut() {
+      if let Some(snapshot_id) = get_snapshot_id(doc.uid, self, &doc.object_id) {
+        let start = make_snapshot_start_key(snapshot_id);
+        let end = make_snapshot_end_key(snapshot_id);
+        for entry in self.range(start.as_ref()..end.as_ref())? {
+          if entry.key()[2 + SNAPSHOT_ID_LEN] == SNAPSHOT_UPDATE {
+            doc.number_of_snapshots += 1;
+          }
+          doc.snapshot_bytes += (entry.key().len() + entry.value().len()) as u64;
+        }
+      }
+    }
+
+    for doc in docs.into_values() {
+      // The whole key range of the document is empty
+      if doc.doc_state_bytes == 0 && doc.update_bytes == 0 && doc.metadata_bytes == 0 {
+        let mapping = OrphanMapping {
+          uid: doc.uid,
+          object_id: doc.object_id.clone(),
+          doc_id: doc.doc_id,
+        };
+        if doc.snapshot_bytes == 0 {
+          report.orphan_mappings.push(mapping);
+        } else {
+          report.recoverable_mappings.push(mapping);
+        }
+      }
+      report.uids.entry(doc.uid).or_default().add(&doc);
+      report.docs.push(doc);
+    }
+    report
+      .docs
+      .sort_by_key(|doc| std::cmp::Reverse(doc.total_bytes()));
+    report.orphan_data = orphan_data.into_values().collect();
+    Ok(report)
+  }
+
+  /// Remove the orphans found by [StorageUsageAction::storage_report]: the doc id mappings
+  /// without any document data nor snapshot, together with their snapshot id mapping, and the
+  /// data without doc id mapping. The recoverable mappings and their snapshots are kept. Return
+  /// the report the orphans were found in.
+  fn remove_orphans(&self) -> Result<StorageReport, PersistenceError> {
+    let report = self.storage_report()?;
+    for orphan in &report.orphan_mappings {
+      tracing::info!(
+        "remove orphan doc id mapping: {}:{}",
+        orphan.uid,
+        orphan.object_id
+      );
+      let key = make_doc_id_key(&orphan.uid.to_be_bytes(), orphan.object_id.as_bytes());
+      self.remove(key.as_ref())?;
+      self.remove_range(
+        make_doc_start_key(orphan.doc_id).as_ref(),
+        make_doc_end_key(orphan.doc_id).as_ref(),
+      )?;
+      let key = make_snapshot_id_key(&orphan.uid.to_be_bytes(), orphan.object_id.as_bytes());
+      self.remove(key.as_ref())?;
+    }
+    for orphan in &report.orphan_data {
+      tracing::info!("remove orphan data of doc id: {}", orphan.doc_id);
+      self.remove_range(
+        make_doc_start_key(orphan.doc_id).as_ref(),
+        make_doc_end_key(orphan.doc_id).as_ref(),
+      )?;
+    }
+    Ok(report)
+  }
+}
@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, Mutex, RwLock};
+
+use anyhow::anyhow;
+
+use crate::local_storage::kv::changes::{is_empty_range, key_range, KVChanges, KeyRange};
+use crate::local_storage::kv::{KVEntry, KVStore, KVTransactionDB, PersistenceError};
+
+type KVMap = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// A [KVTransactionDB] that keeps all the data in memory. It's used by the tests and by the
+/// sessions that must not touch the disk, for example, the guest sessions.
+///
+/// It has the same transactional semantics as the RocksDB implementation: the changes of a
+/// transaction are only visible to the transaction itself until they are committed, and a commit
+/// applies all the changes of the transaction atomically. The transactions read the latest
+/// committed data.
+///
+/// Cloning the [KVTransactionDBMemoryImpl] shares the underlying data.
+#[derive(Clone, Default)]
+pub struct KVTransactionDBMemoryImpl {
+  data: Arc<RwLock<KVMap>>,
+}
+
+impl KVTransactionDBMemoryImpl {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Return the number of the committed keys.
+  pub fn len(&self) -> usize {
+    self.data.read().map(|data| data.len()).unwrap_or(0)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+impl KVTransactionDB for KVTransactionDBMemoryImpl {
+  type TransactionAction<'a> = MemoryKVStoreImpl;
+
+  fn read_txn<'a, 'b>(&'b self) -> Self::TransactionAction<'a>
+  where
+    'b: 'a,
+  {
+    MemoryKVStoreImpl::new(self.data.clone())
+  }
+
+  fn write_txn<'a, 'b>(&'b self) -> Self::TransactionAction<'a>
+  where
+    'b: 'a,
+  {
+    MemoryKVStoreImpl::new(self.data.clone())
+  }
+
+  fn with_write_txn<'a, 'b, Output>(
+    &'b self,
+    f: impl FnOnce(&Self::TransactionAction<'a>) -> Result<Output, PersistenceError>,
+  ) -> Result<Output, PersistenceError>
+  where
+    'b: 'a,
+  {
+    let store = MemoryKVStoreImpl::new(self.data.clone());
+    let result = f(&store)?;
+    store.commit_transaction()?;
+    Ok(result)
+  }
+
+  fn commit_transaction(txn: Self::TransactionAction<'_>) -> Result<(), PersistenceError> {
+    txn.commit_transaction()
+  }
+
+  fn flush(&self) -> Result<(), PersistenceError> {
+    Ok(())
+  }
+}
+
+/// Implementation of [KVStore] for [KVTransactionDBMemoryImpl]. The changes are discarded if the
+/// transaction is dropped without calling [MemoryKVStoreImpl::commit_transaction].
+pub struct MemoryKVStoreImpl {
+  data: Arc<RwLock<KVMap>>,
+  changes: Mutex<KVChanges>,
+}
+
+impl MemoryKVStoreImpl {
+  fn new(data: Arc<RwLock<KVMap>>) -> Self {
+    Self {
+      data,
+      changes: Mutex::new(KVChanges::default()),
+    }
+  }
+
+  pub fn commit_transaction(self) -> Result<(), PersistenceError> {
+    let changes = self.changes.into_inner().map_err(|_| poisoned())?;
+    if changes.is_empty() {
+      return Ok(());
+    }
+    let mut data = self.data.write().map_err(|_| poisoned())?;
+    for (key, value) in changes {
+      match value {
+        None => data.remove(&key),
+        Some(value) => data.insert(key, value),
+      };
+    }
+    Ok(())
+  }
+
+  /// Return the committed entries in the range, overlaid with the changes of this transaction.
+  fn merged_range(&self, range: KeyRange) -> Result<Vec<MemoryEntry>, PersistenceError> {
+    if is_empty_range(range) {
+      return Ok(vec![]);
+    }
+    let data = self.data.read().map_err(|_| poisoned())?;
+    let changes = self.changes.lock().map_err(|_| poisoned())?;
+    let committed = data
+      .range::<[u8], _>(range)
+      .map(|(key, value)| (key.clone(), value.clone()));
+    Ok(
+      changes
+        .overlay(committed, range)
+        .into_iter()
+        .map(|(key, value)| MemoryEntry::new(key, value))
+        .collect(),
+    )
+  }
+}
+
+fn poisoned() -> PersistenceError {
+  PersistenceError::Internal(anyhow!("the memory kv store is poisoned"))
+}
+
+impl<'a> KVStore<'a> for MemoryKVStoreImpl {
+  type Range = std::vec::IntoIter<MemoryEntry>;
+  type Entry = MemoryEntry;
+  type Value = Vec<u8>;
+  type Error = PersistenceError;
+
+  fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Self::Value>, Self::Error> {
+    let key = key.as_ref();
+    if let Some(value) = self.changes.lock().map_err(|_| poisoned())?.get(key) {
+      return Ok(value);
+    }
+    let data = self.data.read().map_err(|_| poisoned())?;
+    Ok(data.get(key).cloned())
+  }
+
+  fn insert<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<(), Self::Error> {
+    self
+      .changes
+      .lock()
+      .map_err(|_| poisoned())?
+      .insert(key.as_ref().to_vec(), value.as_ref().to_vec());
+    Ok(())
+  }
+
+  fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+    self
+      .changes
+      .lock()
+      .map_err(|_| poisoned())?
+      .remove(key.to_vec());
+    Ok(())
+  }
+
+  fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<(), Self::Error> {
+    let entries = self.merged_range((Bound::Included(from), Bound::Excluded(to)))?;
+    let mut changes = self.changes.lock().map_err(|_| poisoned())?;
+    for entry in entries {
+      changes.remove(entry.key);
+    }
+    Ok(())
+  }
+
+  /// Same as the RocksDB implementation, the lower bound is always included and the upper bound
+  /// is always excluded.
+  fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> Result<Self::Range, Self::Error> {
+    Ok(self.merged_range(key_range(&range))?.into_iter())
+  }
+
+  fn next_back_entry(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+    let range = (Bound::Unbounded, Bound::Included(key));
+    let data = self.data.read().map_err(|_| poisoned())?;
+    let changes = self.changes.lock().map_err(|_| poisoned())?;
+    // The last committed entry that isn't removed or overwritten by this transaction
+    let committed = data
+      .range::<[u8], _>(range)
+      .rev()
+      .find(|(key, _)| !changes.contains_key(key.as_slice()))
+      .map(|(key, value)| (key.clone(), value.clone()));
+    let entry = changes.next_back(committed, range);
+    Ok(entry.map(|(key, value)| MemoryEntry::new(key, value)))
+  }
+}
+
+pub struct MemoryEntry {
+  key: Vec<u8>,
+  value: Vec<u8>,
+}
+
+impl MemoryEntry {
+  pub fn new(key: Vec<u8>, value: Vec<u8>) -> Self {
+    Self { key, value }
+  }
+}
+
+impl KVEntry for MemoryEntry {
+  fn key(&self) -> &[u8] {
+    self.key.as_ref()
+  }
+
+  fn value(&self) -> &[u8] {
+    self.value.as_ref()
+  }
+}
@@ -1,8 +1,12 @@
 pub mod kv;
+pub mod memory;
 
 #[cfg(not(target_arch = "wasm32"))]
 pub mod rocksdb;
 
+#[cfg(all(feature = "sqlite", not(target_arch = "wasm32")))]
+pub mod sqlite;
+
 #[cfg(target_arch = "wasm32")]
 pub mod indexeddb;
 
@@ -0,0 +1,305 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use collab::entity::{EncodedCollab, EncoderVersion};
+use serde::{Deserialize, Serialize};
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::Encode;
+use yrs::{Doc, ReadTxn, StateVector, Transact, Update};
+
+use crate::local_storage::kv::doc::CollabKVAction;
+use crate::local_storage::kv::keys::*;
+use crate::local_storage::kv::migration::parse_doc_id_key;
+use crate::local_storage::kv::*;
+use crate::local_storage::rocksdb::kv_impl::KVTransactionDBRocksdbImpl;
+use crate::local_storage::rocksdb::rocksdb_plugin::RocksdbBackup;
+
+/// Describes the documents of a backup. It's returned by [backup_collab_db] and is required by
+/// [restore_collab_db] and by the next incremental backup.
+///
+/// Each backup is a generation identified by its `created_at`. A backup only saves the documents
+/// that changed since its base into its own generation, and references the documents of the
+/// older generations for the others. So the older manifests stay restorable as long as their
+/// generations are kept.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct BackupManifest {
+  pub uid: i64,
+  /// The timestamp in milliseconds when the backup was created. It's greater than the one of the
+  /// base backup.
+  pub created_at: i64,
+  pub docs: BTreeMap<String, BackupDocEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct BackupDocEntry {
+  /// The encoded state vector of the document when it was backed up.
+  pub state_vector: Vec<u8>,
+  /// The checksum of the doc state. Deleting content doesn't change the state vector, so the
+  /// checksum is compared as well to find out if a document changed.
+  pub checksum: Option<u32>,
+  /// The `created_at` of the backup generation that saved the document.
+  pub created_at: i64,
+}
+
+impl BackupDocEntry {
+  fn from_encoded_collab(encoded_collab: &EncodedCollab, created_at: i64) -> Self {
+    Self {
+      state_vector: encoded_collab.state_vector.to_vec(),
+      checksum: encoded_collab.checksum,
+      created_at,
+    }
+  }
+
+  fn is_same_state(&self, other: &Self) -> bool {
+    self.state_vector == other.state_vector && self.checksum == other.checksum
+  }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BackupProgress {
+  pub object_id: String,
+  /// The number of documents that were processed, including this one.
+  pub processed: usize,
+  pub total: usize,
+  /// False if the document was skipped because it didn't change since the base backup.
+  pub saved: bool,
+}
+
+/// Back up all the documents of the user to a new generation of the `backup`.
+///
+/// The documents are read from the snapshot of a single read transaction, so the backup is a
+/// consistent view of the database at the time the backup started, and the database stays
+/// usable while the backup is running. When a `base` manifest is given, only the documents whose
+/// state changed since the base backup are saved, and the documents that were not changed are
+/// referenced from the generations of the base backup, which are expected to be in the `backup`.
+pub fn backup_collab_db(
+  db: &KVTransactionDBRocksdbImpl,
+  uid: i64,
+  backup: &dyn RocksdbBackup,
+  base: Option<&BackupManifest>,
+  mut progress: impl FnMut(&BackupProgress),
+) -> Result<BackupManifest, PersistenceError> {
+  // The read transaction is created with a snapshot, see [KVTransactionDBRocksdbImpl::read_txn].
+  let read_txn = db.read_txn();
+  let from = Key::from_const([DOC_SPACE, DOC_SPACE_OBJECT]);
+  let to = Key::from_const([DOC_SPACE, DOC_SPACE_OBJECT_KEY]);
+  let object_ids = read_txn
+    .range(from.as_ref()..to.as_ref())?
+    .filter_map(|entry| parse_doc_id_key(entry.key()))
+    .filter(|(doc_uid, _)| *doc_uid == uid)
+    .map(|(_, object_id)| object_id)
+    .collect::<Vec<_>>();
+
+  let mut created_at = chrono::Utc::now().timestamp_millis();
+  if let Some(base) = base {
+    // Never save into the generation of the base backup.
+    created_at = created_at.max(base.created_at + 1);
+  }
+  let mut manifest = BackupManifest {
+    uid,
+    created_at,
+    docs: BTreeMap::new(),
+  };
+  let total = object_ids.len();
+  for (index, object_id) in object_ids.into_iter().enumerate() {
+    let encoded_collab = encode_collab(&read_txn, uid, &object_id)?;
+    let entry = BackupDocEntry::from_encoded_collab(&encoded_collab, created_at);
+    let base_entry = base
+      .and_then(|base| base.docs.get(&object_id))
+      .filter(|base_entry| base_entry.is_same_state(&entry));
+    let unchanged = base_entry.is_some();
+    let entry = match base_entry {
+      Some(base_entry) => base_entry.clone(),
+      None => {
+        backup.save_doc(uid, created_at, &object_id, encoded_collab)?;
+        entry
+      },
+    };
+    manifest.docs.insert(object_id.clone(), entry);
+    progress(&BackupProgress {
+      object_id,
+      processed: index + 1,
+      total,
+      saved: !unchanged,
+    });
+  }
+  Ok(manifest)
+}
+
+/// Restore the documents of the `manifest` from the `backup` into the `db`. Return the number of
+/// restored documents.
+///
+/// Each restored document replaces the doc state and the updates of the document in the `db`,
+/// its snapshots are kept. The other documents of the user, the documents of the other users and
+/// the other data of the `db` are left untouched. The documents are restored in a single
+/// transaction, so a failed restore leaves the `db` untouched. Each document is verified against
+/// its checksum and the state vector recorded in the manifest before it's written. The restored
+/// documents must not be opened during the restore.
+pub fn restore_collab_db(
+  db: &KVTransactionDBRocksdbImpl,
+  backup: &dyn RocksdbBackup,
+  manifest: &BackupManifest,
+  mut progress: impl FnMut(&BackupProgress),
+) -> Result<usize, PersistenceError> {
+  let uid = manifest.uid;
+  let total = manifest.docs.len();
+  db.with_write_txn(|store| {
+    for (index, (object_id, entry)) in manifest.docs.iter().enumerate() {
+      let encoded_collab = backup.get_doc(uid, entry.created_at, object_id)?;
+      encoded_collab.verify_checksum()?;
+      if !BackupDocEntry::from_encoded_collab(&encoded_collab, entry.created_at)
+        .is_same_state(entry)
+      {
+        return Err(PersistenceError::InvalidData(format!(
+          "the backup of {} doesn't match the manifest",
+          object_id
+        )));
+      }
+
+      let doc = Doc::new();
+      let mut txn = doc.transact_mut();
+      let update = match encoded_collab.version {
+        EncoderVersion::V1 => Update::decode_v1(&encoded_collab.doc_state)?,
+        EncoderVersion::V2 => Update::decode_v2(&encoded_collab.doc_state)?,
+      };
+      txn.try_apply_update(update)?;
+      store.flush_doc(
+        uid,
+        object_id,
+        txn.state_vector().encode_v1(),
+        txn.encode_state_as_update_v1(&StateVector::default()),
+      )?;
+      progress(&BackupProgress {
+        object_id: object_id.clone(),
+        processed: index + 1,
+        total,
+        saved: true,
+      });
+    }
+    Ok(())
+  })?;
+  db.flush()?;
+  Ok(total)
+}
+
+fn encode_collab<'a, S>(
+  store: &S,
+  uid: i64,
+  object_id: &str,
+) -> Result<EncodedCollab, PersistenceError>
+where
+  S: KVStore<'a, Error = PersistenceError> + 'a,
+{
+  let doc = Doc::new();
+  store.load_doc(uid, object_id, doc.clone())?;
+  let txn = doc.transact();
+  Ok(EncodedCollab::new_v1(
+    txn.state_vector().encode_v1(),
+    txn.encode_state_as_update_v1(&StateVector::default()),
+  ))
+}
+
+/// A [RocksdbBackup] that stores each document in a file of the directory of its generation:
+/// `<dir>/<uid>/<created_at>/<hex encoded object id>.collab`. The manifest of each backup is
+/// stored in `<dir>/<uid>/<created_at>/manifest.json`, and the manifest of the last backup of a
+/// user in `<dir>/<uid>/manifest.json` too.
+pub struct FileBackup {
+  dir: PathBuf,
+}
+
+impl FileBackup {
+  pub fn new(dir: impl Into<PathBuf>) -> Self {
+    Self { dir: dir.into() }
+  }
+
+  /// Save the manifest in its generation and as the last backup of the user.
+  pub fn save_manifest(&self, manifest: &BackupManifest) -> Result<(), anyhow::Error> {
+    let data = serde_json::to_vec(manifest)?;
+    write_file(
+      &self
+        .generation_dir(manifest.uid, manifest.created_at)
+        .join("manifest.json"),
+      &data,
+    )?;
+    write_file(&self.user_dir(manifest.uid).join("manifest.json"), &data)
+  }
+
+  /// Return the manifest of the last backup of the user, or None if the user has no backup.
+  pub fn load_manifest(&self, uid: i64) -> Result<Option<BackupManifest>, anyhow::Error> {
+    read_manifest(&self.user_dir(uid).join("manifest.json"))
+  }
+
+  /// Return the manifest of the backup created at `created_at`, or None if there is no such
+  /// backup.
+  pub fn load_generation_manifest(
+    &self,
+    uid: i64,
+    created_at: i64,
+  ) -> Result<Option<BackupManifest>, anyhow::Error> {
+    read_manifest(&self.generation_dir(uid, created_at).join("manifest.json"))
+  }
+
+  fn user_dir(&self, uid: i64) -> PathBuf {
+    self.dir.join(uid.to_string())
+  }
+
+  fn generation_dir(&self, uid: i64, created_at: i64) -> PathBuf {
+    self.user_dir(uid).join(created_at.to_string())
+  }
+
+  fn doc_path(&self, uid: i64, created_at: i64, object_id: &str) -> PathBuf {
+    let mut file_name = String::with_capacity(object_id.len() * 2 + 7);
+    for b in object_id.as_bytes() {
+      let _ = write!(file_name, "{:02x}", b);
+    }
+    file_name.push_str(".collab");
+    self.generation_dir(uid, created_at).join(file_name)
+  }
+}
+
+impl RocksdbBackup for FileBackup {
+  fn save_doc(
+    &self,
+    uid: i64,
+    created_at: i64,
+    object_id: &str,
+    data: EncodedCollab,
+  ) -> Result<(), anyhow::Error> {
+    write_file(
+      &self.doc_path(uid, created_at, object_id),
+      &data.encode_to_bytes()?,
+    )
+  }
+
+  fn get_doc(
+    &self,
+    uid: i64,
+    created_at: i64,
+    object_id: &str,
+  ) -> Result<EncodedCollab, anyhow::Error> {
+    let path = self.doc_path(uid, created_at, object_id);
+    let data = fs::read(&path).map_err(|err| anyhow!("read {:?} failed: {}", path, err))?;
+    Ok(EncodedCollab::decode_and_verify(&data)?)
+  }
+}
+
+fn read_manifest(path: &Path) -> Result<Option<BackupManifest>, anyhow::Error> {
+  if !path.exists() {
+    return Ok(None);
+  }
+  Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+}
+
+/// Write the file atomically, so a crash never leaves a partially written file behind.
+fn write_file(path: &Path, data: &[u8]) -> Result<(), anyhow::Error> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  let tmp_path = path.with_extension("tmp");
+  fs::write(&tmp_path, data)?;
+  fs::rename(&tmp_path, path)?;
+  Ok(())
+}
@@ -3,7 +3,7 @@ use std::ops::RangeBounds;
 use std::path::Path;
 use std::sync::Arc;
 
-use crate::local_storage::kv::doc::CollabKVAction;
+use crate::local_storage::kv::doc::{compact_doc, CollabKVAction};
 
 use crate::local_storage::kv::{KVEntry, KVStore, KVTransactionDB, PersistenceError};
 use rocksdb::Direction::Forward;
@@ -121,6 +121,11 @@ impl KVTransactionDBRocksdbImpl {
     self.with_write_txn(|txn| txn.delete_doc(uid, doc_id))?;
     Ok(())
   }
+
+  /// Merge the update log of the document into its document state. See [compact_doc].
+  pub fn compact_doc(&self, uid: i64, object_id: &str) -> Result<u32, PersistenceError> {
+    compact_doc(self, uid, object_id)
+  }
 }
 
 impl KVTransactionDB for KVTransactionDBRocksdbImpl {
@@ -131,10 +136,10 @@ impl KVTransactionDB for KVTransactionDBRocksdbImpl {
     'b: 'a,
   {
     let mut txn_options = TransactionOptions::default();
-    // Use snapshot to provides a consistent view of the data. This snapshot can then be used
-    // to perform read operations, and the returned data will be consistent with the database
-    // state at the time the snapshot was created, regardless of any subsequent modifications
-    // made by other transactions.
+    // Use snapshot to provides a consistent view of the data. The reads of the transaction use
+    // this snapshot (see [RocksdbKVStoreImpl::read_options]), so the returned data is consistent
+    // with the database state at the time the snapshot was created, regardless of any
+    // subsequent modifications made by other transactions.
     txn_options.set_snapshot(true);
     let txn = self
       .db
@@ -170,6 +175,10 @@ impl KVTransactionDB for KVTransactionDBRocksdbImpl {
     Ok(result)
   }
 
+  fn commit_transaction(txn: Self::TransactionAction<'_>) -> Result<(), PersistenceError> {
+    txn.commit_transaction()
+  }
+
   fn flush(&self) -> Result<(), PersistenceError> {
     Ok(())
   }
@@ -190,6 +199,17 @@ impl<'a, DB: Send + Sync> RocksdbKVStoreImpl<'a, DB> {
     self.0.commit()?;
     Ok(())
   }
+
+  /// Return the read options of the reads of the transaction. If the transaction was created with
+  /// a snapshot, the reads see the data at the time the snapshot was created. Otherwise, they see
+  /// the latest committed data.
+  fn read_options(&self) -> ReadOptions {
+    let mut opt = ReadOptions::default();
+    // The read options keep the snapshot of the transaction itself, which lives as long as the
+    // transaction, so the wrapper returned by `snapshot` can be dropped.
+    opt.set_snapshot(&self.0.snapshot());
+    opt
+  }
 }
 
 impl<'a, DB: Send + Sync> KVStore<'a> for RocksdbKVStoreImpl<'a, DB> {
@@ -199,7 +219,7 @@ impl<'a, DB: Send + Sync> KVStore<'a> for RocksdbKVStoreImpl<'a, DB> {
   type Error = PersistenceError;
 
   fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Self::Value>, Self::Error> {
-    if let Some(value) = self.0.get(key)? {
+    if let Some(value) = self.0.get_opt(key, &self.read_options())? {
       Ok(Some(value))
     } else {
       Ok(None)
@@ -217,7 +237,7 @@ impl<'a, DB: Send + Sync> KVStore<'a> for RocksdbKVStoreImpl<'a, DB> {
   }
 
   fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<(), Self::Error> {
-    let mut opt = ReadOptions::default();
+    let mut opt = self.read_options();
     opt.set_iterate_lower_bound(from);
     opt.set_iterate_upper_bound(to);
     let i = self
@@ -231,7 +251,7 @@ impl<'a, DB: Send + Sync> KVStore<'a> for RocksdbKVStoreImpl<'a, DB> {
   }
 
   fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> Result<Self::Range, Self::Error> {
-    let mut opt = ReadOptions::default();
+    let mut opt = self.read_options();
     let mut from: &[u8] = &[];
     let mut to: &[u8] = &[];
     match range.start_bound() {
@@ -273,7 +293,7 @@ impl<'a, DB: Send + Sync> KVStore<'a> for RocksdbKVStoreImpl<'a, DB> {
   }
 
   fn next_back_entry(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
-    let opt = ReadOptions::default();
+    let opt = self.read_options();
     let mut raw = self.0.raw_iterator_opt(opt);
     raw.seek_for_prev(key);
     if let Some((key, value)) = raw.item() {
@@ -282,6 +302,10 @@ impl<'a, DB: Send + Sync> KVStore<'a> for RocksdbKVStoreImpl<'a, DB> {
       Ok(None)
     }
   }
+
+  fn get_for_update<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, Self::Error> {
+    Ok(self.0.get_for_update(key, true)?)
+  }
 }
 
 impl<'a, DB: Send + Sync> From<Transaction<'a, DB>> for RocksdbKVStoreImpl<'a, DB> {
@@ -1,3 +1,4 @@
+pub mod backup;
 pub mod kv_impl;
 pub mod rocksdb_plugin;
 pub mod snapshot_plugin;
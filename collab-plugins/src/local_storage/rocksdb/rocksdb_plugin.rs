@@ -1,56 +1,133 @@
 use std::ops::Deref;
 use std::sync::atomic::Ordering::SeqCst;
-use std::sync::atomic::{AtomicBool, AtomicU32};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64};
 use std::sync::{Arc, Weak};
+use std::time::Duration;
 
-use crate::local_storage::kv::doc::CollabKVAction;
+use crate::local_storage::kv::doc::{compact_doc, CollabKVAction};
 use crate::local_storage::kv::KVTransactionDB;
+use crate::local_storage::rocksdb::snapshot_plugin::{
+  CollabKVDBSnapshotPersistence, CollabSnapshot,
+};
 use crate::local_storage::CollabPersistenceConfig;
 use crate::CollabKVDB;
 
+use anyhow::anyhow;
+use collab::core::collab_plugin::PluginFuture;
 use collab::entity::EncodedCollab;
 use collab::preclude::{Collab, CollabPlugin};
 use collab_entity::CollabType;
-use tracing::error;
+use tokio::sync::Mutex;
 
 use yrs::TransactionMut;
 
+/// Stores the documents of the backups, see [crate::local_storage::rocksdb::backup]. `created_at`
+/// is the generation of the backup that saves the document. The generations must be stored
+/// separately, because the newer backups reference the documents of the older generations.
 pub trait RocksdbBackup: Send + Sync {
-  fn save_doc(&self, uid: i64, object_id: &str, data: EncodedCollab) -> Result<(), anyhow::Error>;
-  fn get_doc(&self, uid: i64, object_id: &str) -> Result<EncodedCollab, anyhow::Error>;
+  fn save_doc(
+    &self,
+    uid: i64,
+    created_at: i64,
+    object_id: &str,
+    data: EncodedCollab,
+  ) -> Result<(), anyhow::Error>;
+  fn get_doc(
+    &self,
+    uid: i64,
+    created_at: i64,
+    object_id: &str,
+  ) -> Result<EncodedCollab, anyhow::Error>;
 }
 
-#[derive(Clone)]
-pub struct RocksdbDiskPlugin {
+/// Persists the updates of a [Collab] into a [KVTransactionDB], and compacts and snapshots the
+/// document according to the [CollabPersistenceConfig]. Defaults to the [CollabKVDB], but any
+/// [KVTransactionDB] works, for example, the in-memory or the encrypted one.
+pub struct RocksdbDiskPlugin<DB = CollabKVDB> {
   uid: i64,
-  #[allow(dead_code)]
   object_id: String,
   collab_type: CollabType,
-  collab_db: Weak<CollabKVDB>,
+  collab_db: Weak<DB>,
   did_init: Arc<AtomicBool>,
+  /// The number of updates since the last compaction
   update_count: Arc<AtomicU32>,
-  #[allow(dead_code)]
+  /// The total size of the updates since the last compaction
+  update_bytes: Arc<AtomicU64>,
+  compaction: Arc<CompactionState>,
+  /// Creates the snapshots when [CollabPersistenceConfig::enable_snapshot] is true
+  snapshot: Option<CollabSnapshot>,
+  /// The number of updates since the last snapshot
+  snapshot_update_count: Arc<AtomicU32>,
   config: CollabPersistenceConfig,
 }
 
-impl Deref for RocksdbDiskPlugin {
-  type Target = Weak<CollabKVDB>;
+#[derive(Default)]
+struct CompactionState {
+  /// Held by the running compaction
+  running: Arc<Mutex<()>>,
+  /// Increased by each update. Used to detect the idle time.
+  update_generation: AtomicU64,
+  idle_task_running: AtomicBool,
+  compaction_count: AtomicU64,
+  compacted_updates: AtomicU64,
+}
+
+/// The statistics of the automatic update-log compaction of a [RocksdbDiskPlugin].
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CompactionStats {
+  /// The number of compactions that merged at least one update.
+  pub compaction_count: u64,
+  /// The total number of updates merged into the document state.
+  pub compacted_updates: u64,
+  /// The number of updates currently in the update log of the document.
+  pub number_of_updates: usize,
+}
+
+impl<DB> Clone for RocksdbDiskPlugin<DB> {
+  fn clone(&self) -> Self {
+    Self {
+      uid: self.uid,
+      object_id: self.object_id.clone(),
+      collab_type: self.collab_type.clone(),
+      collab_db: self.collab_db.clone(),
+      did_init: self.did_init.clone(),
+      update_count: self.update_count.clone(),
+      update_bytes: self.update_bytes.clone(),
+      compaction: self.compaction.clone(),
+      snapshot: self.snapshot.clone(),
+      snapshot_update_count: self.snapshot_update_count.clone(),
+      config: self.config.clone(),
+    }
+  }
+}
+
+impl<DB> Deref for RocksdbDiskPlugin<DB> {
+  type Target = Weak<DB>;
 
   fn deref(&self) -> &Self::Target {
     &self.collab_db
   }
 }
 
-impl RocksdbDiskPlugin {
+impl<DB> RocksdbDiskPlugin<DB>
+where
+  DB: KVTransactionDB,
+{
   pub fn new_with_config(
     uid: i64,
     object_id: String,
     collab_type: CollabType,
-    collab_db: Weak<CollabKVDB>,
+    collab_db: Weak<DB>,
     config: CollabPersistenceConfig,
   ) -> Self {
     let update_count = Arc::new(AtomicU32::new(0));
     let did_init = Arc::new(AtomicBool::new(false));
+    let snapshot = config.enable_snapshot.then(|| {
+      CollabSnapshot::new(Arc::new(CollabKVDBSnapshotPersistence::new(
+        collab_db.clone(),
+        config.snapshot_retention.clone(),
+      )))
+    });
     Self {
       object_id,
       collab_type,
@@ -58,16 +135,15 @@ impl RocksdbDiskPlugin {
       uid,
       did_init,
       update_count,
+      update_bytes: Default::default(),
+      compaction: Default::default(),
+      snapshot,
+      snapshot_update_count: Default::default(),
       config,
     }
   }
 
-  pub fn new(
-    uid: i64,
-    object_id: String,
-    collab_type: CollabType,
-    collab_db: Weak<CollabKVDB>,
-  ) -> Self {
+  pub fn new(uid: i64, object_id: String, collab_type: CollabType, collab_db: Weak<DB>) -> Self {
     Self::new_with_config(
       uid,
       object_id,
@@ -77,36 +153,155 @@ impl RocksdbDiskPlugin {
     )
   }
 
-  fn increase_count(&self) {
-    let _update_count = self.update_count.fetch_add(1, SeqCst);
+  fn increase_count(&self, update_len: usize) -> (u32, u64) {
+    let update_count = self.update_count.fetch_add(1, SeqCst) + 1;
+    let update_bytes = self.update_bytes.fetch_add(update_len as u64, SeqCst) + update_len as u64;
+    self.compaction.update_generation.fetch_add(1, SeqCst);
+    (update_count, update_bytes)
+  }
+
+  /// Return the statistics of the update-log compaction of the document.
+  pub fn compaction_stats(&self) -> CompactionStats {
+    let number_of_updates = self
+      .collab_db
+      .upgrade()
+      .map(|db| db.read_txn().number_of_updates(self.uid, &self.object_id))
+      .unwrap_or(0);
+    CompactionStats {
+      compaction_count: self.compaction.compaction_count.load(SeqCst),
+      compacted_updates: self.compaction.compacted_updates.load(SeqCst),
+      number_of_updates,
+    }
+  }
+
+  /// Merge the update log into the document state in the background. Do nothing if a
+  /// compaction is already running.
+  fn schedule_compaction(&self, object_id: &str) {
+    let guard = match self.compaction.running.clone().try_lock_owned() {
+      Ok(guard) => guard,
+      Err(_) => return,
+    };
+    let db = match self.collab_db.upgrade() {
+      None => return,
+      Some(db) => db,
+    };
+    // The updates received from now on are counted for the next compaction.
+    self.update_count.store(0, SeqCst);
+    self.update_bytes.store(0, SeqCst);
+
+    let uid = self.uid;
+    let object_id = object_id.to_string();
+    let compaction = self.compaction.clone();
+    let task = move || {
+      let _guard = guard;
+      match compact_doc(&*db, uid, &object_id) {
+        Ok(0) => {},
+        Ok(compacted) => {
+          compaction.compaction_count.fetch_add(1, SeqCst);
+          compaction
+            .compacted_updates
+            .fetch_add(compacted as u64, SeqCst);
+        },
+        Err(err) => tracing::error!("🔴compact {} failed: {}", object_id, err),
+      }
+    };
+    match tokio::runtime::Handle::try_current() {
+      Ok(handle) => {
+        handle.spawn_blocking(task);
+      },
+      Err(_) => task(),
+    }
+  }
+
+  /// Create a snapshot every [CollabPersistenceConfig::snapshot_per_update] updates.
+  fn schedule_snapshot(&self, object_id: &str) {
+    let snapshot = match &self.snapshot {
+      None => return,
+      Some(snapshot) => snapshot,
+    };
+    let count = self.snapshot_update_count.fetch_add(1, SeqCst) + 1;
+    if count >= self.config.snapshot_per_update && snapshot.should_create_snapshot() {
+      self.snapshot_update_count.store(0, SeqCst);
+      snapshot.create_snapshot(
+        self.collab_db.clone(),
+        self.uid,
+        object_id,
+        &self.collab_type,
+      );
+    }
+  }
+
+  /// Compact the update log once no update was received for the given duration.
+  fn schedule_idle_compaction(&self, object_id: &str, idle_timeout: Duration) {
+    if self.compaction.idle_task_running.swap(true, SeqCst) {
+      return;
+    }
+    let handle = match tokio::runtime::Handle::try_current() {
+      Ok(handle) => handle,
+      Err(_) => {
+        self.compaction.idle_task_running.store(false, SeqCst);
+        return;
+      },
+    };
+    let plugin = self.clone();
+    let object_id = object_id.to_string();
+    handle.spawn(async move {
+      let mut generation = plugin.compaction.update_generation.load(SeqCst);
+      loop {
+        tokio::time::sleep(idle_timeout).await;
+        let current = plugin.compaction.update_generation.load(SeqCst);
+        if current == generation {
+          break;
+        }
+        generation = current;
+      }
+      plugin.compaction.idle_task_running.store(false, SeqCst);
+      plugin.schedule_compaction(&object_id);
+    });
   }
 }
 
-impl CollabPlugin for RocksdbDiskPlugin {
-  fn did_init(&self, collab: &Collab, object_id: &str) {
+impl<DB> CollabPlugin for RocksdbDiskPlugin<DB>
+where
+  DB: KVTransactionDB,
+{
+  fn did_init(&self, collab: &Collab, object_id: &str) -> Result<(), anyhow::Error> {
     self.did_init.store(true, SeqCst);
     if let Some(collab_db) = self.collab_db.upgrade() {
       let rocksdb_read = collab_db.read_txn();
+      if self.config.compaction.is_enabled() {
+        let number_of_updates = rocksdb_read.number_of_updates(self.uid, object_id) as u32;
+        self.update_count.store(number_of_updates, SeqCst);
+        if self.config.compaction.should_compact(number_of_updates, 0) {
+          self.schedule_compaction(object_id);
+        }
+      }
       if !rocksdb_read.is_exist(self.uid, object_id) {
         let txn = collab.transact();
-        if let Err(err) = collab_db.with_write_txn(|w_db_txn| {
-          w_db_txn.create_new_doc(self.uid, &object_id, &txn)?;
-          tracing::trace!("Created new doc {}", object_id);
-          Ok(())
-        }) {
-          error!("create doc for {:?} failed: {}", object_id, err);
-        }
+        collab_db
+          .with_write_txn(|w_db_txn| {
+            w_db_txn.create_new_doc(self.uid, &object_id, &txn)?;
+            tracing::trace!("Created new doc {}", object_id);
+            Ok(())
+          })
+          .map_err(|err| anyhow!("create doc for {:?} failed: {}", object_id, err))?;
       }
     }
+    Ok(())
   }
 
-  fn receive_update(&self, object_id: &str, _txn: &TransactionMut, update: &[u8]) {
+  fn receive_update(
+    &self,
+    object_id: &str,
+    _txn: &TransactionMut,
+    update: &[u8],
+  ) -> Result<(), anyhow::Error> {
     // Only push update if the doc is loaded
     if !self.did_init.load(SeqCst) {
-      return;
+      return Ok(());
     }
     if let Some(db) = self.collab_db.upgrade() {
-      self.increase_count();
+      let (update_count, update_bytes) = self.increase_count(update.len());
       //Acquire a write transaction to ensure consistency
       let result = db.with_write_txn(|w_db_txn| {
         let _ = w_db_txn.push_update(self.uid, object_id, update)?;
@@ -130,14 +325,40 @@ impl CollabPlugin for RocksdbDiskPlugin {
         Ok(())
       });
 
-      if let Err(e) = result {
-        error!(
+      result.map_err(|err| {
+        anyhow!(
           "{}:{} save update failed: {:?}",
-          object_id, self.collab_type, e
-        );
+          object_id,
+          self.collab_type,
+          err
+        )
+      })?;
+
+      self.schedule_snapshot(object_id);
+      let policy = &self.config.compaction;
+      if policy.should_compact(update_count, update_bytes) {
+        self.schedule_compaction(object_id);
+      } else if let Some(idle_timeout) = policy.idle_timeout {
+        self.schedule_idle_compaction(object_id, idle_timeout);
       }
     } else {
       tracing::warn!("collab_db is dropped");
     };
+    Ok(())
+  }
+
+  /// Wait for the running compaction and snapshot
+  fn flush(&self, _object_id: &str) -> Option<PluginFuture> {
+    if !self.config.compaction.is_enabled() && self.snapshot.is_none() {
+      return None;
+    }
+    let running = self.compaction.running.clone();
+    let snapshot = self.snapshot.clone();
+    Some(Box::pin(async move {
+      let _ = running.lock().await;
+      if let Some(snapshot) = snapshot {
+        snapshot.wait().await;
+      }
+    }))
   }
 }
@@ -2,11 +2,14 @@ use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::{Arc, Weak};
 
 use crate::local_storage::kv::doc::CollabKVAction;
-use crate::local_storage::kv::snapshot::SnapshotPersistence;
+use crate::local_storage::kv::snapshot::{SnapshotAction, SnapshotMeta, SnapshotPersistence};
 use crate::local_storage::kv::{KVTransactionDB, PersistenceError};
+use crate::local_storage::SnapshotRetention;
 use crate::CollabKVDB;
+use anyhow::anyhow;
 use collab::preclude::Collab;
 use collab_entity::CollabType;
+use tokio::sync::Mutex;
 
 use yrs::{ReadTxn, StateVector};
 
@@ -38,20 +41,21 @@ impl TryFrom<u8> for SnapshotState {
   }
 }
 
-#[allow(dead_code)]
 #[derive(Clone)]
 pub struct CollabSnapshot {
   state: Arc<AtomicU8>,
+  /// Held by the running snapshot
+  running: Arc<Mutex<()>>,
   snapshot_persistence: Arc<dyn SnapshotPersistence>,
 }
 
-#[allow(dead_code)]
 impl CollabSnapshot {
   pub fn new(snapshot_persistence: Arc<dyn SnapshotPersistence>) -> Self {
     let state = Arc::new(AtomicU8::new(SnapshotState::IDLE));
     Self {
       snapshot_persistence,
       state,
+      running: Default::default(),
     }
   }
 
@@ -66,60 +70,114 @@ impl CollabSnapshot {
     old != SnapshotState::Processing
   }
 
-  pub(crate) fn create_snapshot(
+  /// Create the snapshot in the background, or in the current thread if there is no tokio
+  /// runtime.
+  pub(crate) fn create_snapshot<DB: KVTransactionDB>(
     &self,
-    weak_collab_db: Weak<CollabKVDB>,
+    weak_collab_db: Weak<DB>,
     uid: i64,
     object_id: &str,
     collab_type: &CollabType,
   ) {
-    let weak_snapshot_persistence = Arc::downgrade(&self.snapshot_persistence);
+    let db = match weak_collab_db.upgrade() {
+      Some(db) => db,
+      None => {
+        self.state.store(SnapshotState::IDLE, Ordering::Release);
+        return;
+      },
+    };
+    let persistence = self.snapshot_persistence.clone();
+    let guard = self.running.clone().try_lock_owned().ok();
     let object_id = object_id.to_string();
     let collab_type = collab_type.clone();
     let state = self.state.clone();
-
-    if let (Some(db), Some(persistence)) = (
-      weak_collab_db.upgrade(),
-      weak_snapshot_persistence.upgrade(),
-    ) {
-      tokio::spawn(async move {
-        if let Err(err) =
-          Self::try_snapshot(db, persistence, uid, object_id, collab_type, state).await
-        {
+    let task = move || {
+      let _guard = guard;
+      let result = Self::try_snapshot(db, persistence, uid, &object_id, collab_type);
+      let next_state = match result {
+        Ok(_) => SnapshotState::Idle,
+        Err(err) => {
           tracing::error!("failed to create snapshot: {}", err);
-        }
-      });
+          SnapshotState::Fail
+        },
+      };
+      state.store(next_state as u8, Ordering::Release);
+    };
+    match tokio::runtime::Handle::try_current() {
+      Ok(handle) => {
+        handle.spawn_blocking(task);
+      },
+      Err(_) => task(),
     }
   }
 
-  async fn try_snapshot(
-    db: Arc<CollabKVDB>,
+  /// Wait for the running snapshot
+  pub(crate) async fn wait(&self) {
+    let _ = self.running.lock().await;
+  }
+
+  fn try_snapshot<DB: KVTransactionDB>(
+    db: Arc<DB>,
     persistence: Arc<dyn SnapshotPersistence>,
     uid: i64,
-    object_id: String,
+    object_id: &str,
     collab_type: CollabType,
-    state: Arc<AtomicU8>,
   ) -> Result<(), PersistenceError> {
-    let result: Result<(), PersistenceError> = tokio::task::spawn_blocking(move || {
-      let mut collab = Collab::new(uid, object_id.clone(), "1", vec![], false);
-      db.read_txn()
-        .load_doc_with_txn(uid, &object_id, &mut collab.transact_mut())?;
-
-      // Generate the snapshot
-      let txn = collab.transact();
-      let encoded_v1 = txn.encode_state_as_update_v1(&StateVector::default());
-      persistence.create_snapshot(uid, &object_id, &collab_type, encoded_v1)?;
+    let mut collab = Collab::new(uid, object_id, "1", vec![], false);
+    db.read_txn()
+      .load_doc_with_txn(uid, object_id, &mut collab.transact_mut())?;
+
+    // Generate the snapshot
+    let txn = collab.transact();
+    let encoded_v1 = txn.encode_state_as_update_v1(&StateVector::default());
+    persistence.create_snapshot(uid, object_id, &collab_type, encoded_v1)?;
+    Ok(())
+  }
+}
+
+/// A [SnapshotPersistence] that stores the snapshots in the [KVTransactionDB] next to the
+/// documents. The [SnapshotRetention] is applied in the same transaction as each new snapshot.
+pub struct CollabKVDBSnapshotPersistence<DB = CollabKVDB> {
+  collab_db: Weak<DB>,
+  retention: SnapshotRetention,
+}
+
+impl<DB> CollabKVDBSnapshotPersistence<DB> {
+  pub fn new(collab_db: Weak<DB>, retention: SnapshotRetention) -> Self {
+    Self {
+      collab_db,
+      retention,
+    }
+  }
+}
+
+impl<DB> SnapshotPersistence for CollabKVDBSnapshotPersistence<DB>
+where
+  DB: KVTransactionDB,
+{
+  fn create_snapshot(
+    &self,
+    uid: i64,
+    object_id: &str,
+    collab_type: &CollabType,
+    encoded_v1: Vec<u8>,
+  ) -> Result<(), PersistenceError> {
+    let collab_db = self
+      .collab_db
+      .upgrade()
+      .ok_or_else(|| PersistenceError::Internal(anyhow!("collab_db is dropped")))?;
+    collab_db.with_write_txn(|txn| {
+      let meta = SnapshotMeta {
+        author_uid: Some(uid),
+        collab_type: Some(collab_type.clone()),
+        ..Default::default()
+      };
+      txn.create_snapshot_with_meta(uid, object_id, encoded_v1, meta)?;
+      let removed = txn.apply_snapshot_retention(uid, object_id, &self.retention)?;
+      if removed > 0 {
+        tracing::trace!("Removed {} expired snapshots of {}", removed, object_id);
+      }
       Ok(())
     })
-    .await
-    .unwrap();
-
-    let next_state = if result.is_ok() {
-      SnapshotState::Idle
-    } else {
-      SnapshotState::Fail
-    };
-    state.store(next_state as u8, Ordering::Release);
-    result
   }
 }
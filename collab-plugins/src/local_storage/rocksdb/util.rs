@@ -7,13 +7,17 @@ use collab::preclude::Collab;
 use std::sync::Weak;
 use tracing::{error, warn};
 
-pub struct KVDBCollabPersistenceImpl {
-  pub db: Weak<CollabKVDB>,
+/// Loads the [Collab] from a [KVTransactionDB]. Defaults to the [CollabKVDB].
+pub struct KVDBCollabPersistenceImpl<DB = CollabKVDB> {
+  pub db: Weak<DB>,
   pub uid: i64,
 }
 
-impl KVDBCollabPersistenceImpl {
-  pub fn new(db: Weak<CollabKVDB>, uid: i64) -> Self {
+impl<DB> KVDBCollabPersistenceImpl<DB>
+where
+  DB: KVTransactionDB,
+{
+  pub fn new(db: Weak<DB>, uid: i64) -> Self {
     Self { db, uid }
   }
 
@@ -22,13 +26,19 @@ impl KVDBCollabPersistenceImpl {
   }
 }
 
-impl From<KVDBCollabPersistenceImpl> for DataSource {
-  fn from(persistence: KVDBCollabPersistenceImpl) -> Self {
+impl<DB> From<KVDBCollabPersistenceImpl<DB>> for DataSource
+where
+  DB: KVTransactionDB,
+{
+  fn from(persistence: KVDBCollabPersistenceImpl<DB>) -> Self {
     persistence.into_data_source()
   }
 }
 
-impl CollabPersistence for KVDBCollabPersistenceImpl {
+impl<DB> CollabPersistence for KVDBCollabPersistenceImpl<DB>
+where
+  DB: KVTransactionDB,
+{
   fn load_collab_from_disk(&self, collab: &mut Collab) {
     if let Some(collab_db) = self.db.upgrade() {
       let object_id = collab.object_id().to_string();
@@ -0,0 +1,290 @@
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use anyhow::anyhow;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+
+use crate::local_storage::kv::changes::{is_empty_range, key_range, KVChanges, KeyRange};
+use crate::local_storage::kv::{KVEntry, KVStore, KVTransactionDB, PersistenceError};
+
+/// A [KVTransactionDB] that stores the data in a single ordered key/value table of a SQLite
+/// database. The keys are BLOBs, which SQLite compares with memcmp, so the order of the keys is
+/// the same as the RocksDB implementation.
+///
+/// It has the same transactional semantics as the RocksDB implementation: the changes of a
+/// transaction are only visible to the transaction itself until they are committed, and a commit
+/// applies all the changes of the transaction atomically in a SQLite transaction. The
+/// transactions read the latest committed data.
+#[derive(Clone)]
+pub struct KVTransactionDBSqliteImpl {
+  conn: Arc<Mutex<Connection>>,
+}
+
+impl KVTransactionDBSqliteImpl {
+  /// Open the SQLite database file at the given path. The file is created if it doesn't exist.
+  pub fn open(path: impl AsRef<Path>) -> Result<Self, PersistenceError> {
+    let conn = Connection::open(path)?;
+    // The WAL mode allows the readers to read while a transaction is being committed.
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    Self::with_connection(conn)
+  }
+
+  /// Open a SQLite database that lives in memory.
+  pub fn open_in_memory() -> Result<Self, PersistenceError> {
+    Self::with_connection(Connection::open_in_memory()?)
+  }
+
+  fn with_connection(conn: Connection) -> Result<Self, PersistenceError> {
+    conn.execute(
+      "CREATE TABLE IF NOT EXISTS collab_kv (key BLOB PRIMARY KEY NOT NULL, value BLOB NOT NULL) WITHOUT ROWID",
+      [],
+    )?;
+    Ok(Self {
+      conn: Arc::new(Mutex::new(conn)),
+    })
+  }
+}
+
+impl KVTransactionDB for KVTransactionDBSqliteImpl {
+  type TransactionAction<'a> = SqliteKVStoreImpl;
+
+  fn read_txn<'a, 'b>(&'b self) -> Self::TransactionAction<'a>
+  where
+    'b: 'a,
+  {
+    SqliteKVStoreImpl::new(self.conn.clone())
+  }
+
+  fn write_txn<'a, 'b>(&'b self) -> Self::TransactionAction<'a>
+  where
+    'b: 'a,
+  {
+    SqliteKVStoreImpl::new(self.conn.clone())
+  }
+
+  fn with_write_txn<'a, 'b, Output>(
+    &'b self,
+    f: impl FnOnce(&Self::TransactionAction<'a>) -> Result<Output, PersistenceError>,
+  ) -> Result<Output, PersistenceError>
+  where
+    'b: 'a,
+  {
+    let store = SqliteKVStoreImpl::new(self.conn.clone());
+    let result = f(&store)?;
+    store.commit_transaction()?;
+    Ok(result)
+  }
+
+  fn commit_transaction(txn: Self::TransactionAction<'_>) -> Result<(), PersistenceError> {
+    txn.commit_transaction()
+  }
+
+  fn flush(&self) -> Result<(), PersistenceError> {
+    let conn = lock(&self.conn)?;
+    conn.pragma_update(None, "wal_checkpoint", "PASSIVE")?;
+    Ok(())
+  }
+}
+
+/// Implementation of [KVStore] for [KVTransactionDBSqliteImpl]. The changes are discarded if the
+/// transaction is dropped without calling [SqliteKVStoreImpl::commit_transaction].
+pub struct SqliteKVStoreImpl {
+  conn: Arc<Mutex<Connection>>,
+  changes: Mutex<KVChanges>,
+}
+
+impl SqliteKVStoreImpl {
+  fn new(conn: Arc<Mutex<Connection>>) -> Self {
+    Self {
+      conn,
+      changes: Mutex::new(KVChanges::default()),
+    }
+  }
+
+  pub fn commit_transaction(self) -> Result<(), PersistenceError> {
+    let changes = self.changes.into_inner().map_err(|_| poisoned())?;
+    if changes.is_empty() {
+      return Ok(());
+    }
+    let mut conn = lock(&self.conn)?;
+    let txn = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    {
+      let mut insert =
+        txn.prepare_cached("INSERT OR REPLACE INTO collab_kv (key, value) VALUES (?1, ?2)")?;
+      let mut delete = txn.prepare_cached("DELETE FROM collab_kv WHERE key = ?1")?;
+      for (key, value) in changes {
+        match value {
+          None => delete.execute(params![key])?,
+          Some(value) => insert.execute(params![key, value])?,
+        };
+      }
+    }
+    txn.commit()?;
+    Ok(())
+  }
+
+  /// Return the committed entries in the range, overlaid with the changes of this transaction.
+  fn merged_range(&self, range: KeyRange) -> Result<Vec<SqliteEntry>, PersistenceError> {
+    if is_empty_range(range) {
+      return Ok(vec![]);
+    }
+    let conn = lock(&self.conn)?;
+    let changes = self.changes.lock().map_err(|_| poisoned())?;
+    let (sql, args) = range_query(range, "ASC");
+    let mut stmt = conn.prepare_cached(&sql)?;
+    let committed = stmt
+      .query_map(rusqlite::params_from_iter(args), |row| {
+        Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?))
+      })?
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok(
+      changes
+        .overlay(committed, range)
+        .into_iter()
+        .map(|(key, value)| SqliteEntry::new(key, value))
+        .collect(),
+    )
+  }
+}
+
+/// Build the query of the entries in the range, ordered by the key.
+fn range_query<'a>(range: KeyRange<'a>, order: &str) -> (String, Vec<&'a [u8]>) {
+  let mut conditions = vec![];
+  let mut args = vec![];
+  match range.0 {
+    Bound::Included(start) => {
+      args.push(start);
+      conditions.push(format!("key >= ?{}", args.len()));
+    },
+    Bound::Excluded(start) => {
+      args.push(start);
+      conditions.push(format!("key > ?{}", args.len()));
+    },
+    Bound::Unbounded => {},
+  }
+  match range.1 {
+    Bound::Included(end) => {
+      args.push(end);
+      conditions.push(format!("key <= ?{}", args.len()));
+    },
+    Bound::Excluded(end) => {
+      args.push(end);
+      conditions.push(format!("key < ?{}", args.len()));
+    },
+    Bound::Unbounded => {},
+  }
+  let mut sql = "SELECT key, value FROM collab_kv".to_string();
+  if !conditions.is_empty() {
+    sql.push_str(" WHERE ");
+    sql.push_str(&conditions.join(" AND "));
+  }
+  sql.push_str(&format!(" ORDER BY key {}", order));
+  (sql, args)
+}
+
+fn lock(conn: &Mutex<Connection>) -> Result<MutexGuard<'_, Connection>, PersistenceError> {
+  conn.lock().map_err(|_| poisoned())
+}
+
+fn poisoned() -> PersistenceError {
+  PersistenceError::Internal(anyhow!("the sqlite kv store is poisoned"))
+}
+
+impl<'a> KVStore<'a> for SqliteKVStoreImpl {
+  type Range = std::vec::IntoIter<SqliteEntry>;
+  type Entry = SqliteEntry;
+  type Value = Vec<u8>;
+  type Error = PersistenceError;
+
+  fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Self::Value>, Self::Error> {
+    let key = key.as_ref();
+    if let Some(value) = self.changes.lock().map_err(|_| poisoned())?.get(key) {
+      return Ok(value);
+    }
+    let conn = lock(&self.conn)?;
+    let value = conn
+      .prepare_cached("SELECT value FROM collab_kv WHERE key = ?1")?
+      .query_row(params![key], |row| row.get::<_, Vec<u8>>(0))
+      .optional()?;
+    Ok(value)
+  }
+
+  fn insert<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<(), Self::Error> {
+    self
+      .changes
+      .lock()
+      .map_err(|_| poisoned())?
+      .insert(key.as_ref().to_vec(), value.as_ref().to_vec());
+    Ok(())
+  }
+
+  fn remove(&self, key: &[u8]) -> Result<(), Self::Error> {
+    self
+      .changes
+      .lock()
+      .map_err(|_| poisoned())?
+      .remove(key.to_vec());
+    Ok(())
+  }
+
+  fn remove_range(&self, from: &[u8], to: &[u8]) -> Result<(), Self::Error> {
+    let entries = self.merged_range((Bound::Included(from), Bound::Excluded(to)))?;
+    let mut changes = self.changes.lock().map_err(|_| poisoned())?;
+    for entry in entries {
+      changes.remove(entry.key);
+    }
+    Ok(())
+  }
+
+  /// Same as the RocksDB implementation, the lower bound is always included and the upper bound
+  /// is always excluded.
+  fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> Result<Self::Range, Self::Error> {
+    Ok(self.merged_range(key_range(&range))?.into_iter())
+  }
+
+  fn next_back_entry(&self, key: &[u8]) -> Result<Option<Self::Entry>, Self::Error> {
+    let range = (Bound::Unbounded, Bound::Included(key));
+    let conn = lock(&self.conn)?;
+    let changes = self.changes.lock().map_err(|_| poisoned())?;
+    // The last committed entry that isn't removed or overwritten by this transaction
+    let committed = {
+      let (sql, args) = range_query(range, "DESC");
+      let mut stmt = conn.prepare_cached(&sql)?;
+      let mut rows = stmt.query(rusqlite::params_from_iter(args))?;
+      let mut committed = None;
+      while let Some(row) = rows.next()? {
+        let key: Vec<u8> = row.get(0)?;
+        if !changes.contains_key(&key) {
+          committed = Some((key, row.get::<_, Vec<u8>>(1)?));
+          break;
+        }
+      }
+      committed
+    };
+    let entry = changes.next_back(committed, range);
+    Ok(entry.map(|(key, value)| SqliteEntry::new(key, value)))
+  }
+}
+
+pub struct SqliteEntry {
+  key: Vec<u8>,
+  value: Vec<u8>,
+}
+
+impl SqliteEntry {
+  pub fn new(key: Vec<u8>, value: Vec<u8>) -> Self {
+    Self { key, value }
+  }
+}
+
+impl KVEntry for SqliteEntry {
+  fn key(&self) -> &[u8] {
+    self.key.as_ref()
+  }
+
+  fn value(&self) -> &[u8] {
+    self.value.as_ref()
+  }
+}
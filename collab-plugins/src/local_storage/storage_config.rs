@@ -1,10 +1,22 @@
+use std::time::Duration;
+
+/// The number of snapshots of a document kept by the default [CollabPersistenceConfig].
+pub const DEFAULT_SNAPSHOT_KEEP_LAST: usize = 10;
+
 #[derive(Clone)]
 pub struct CollabPersistenceConfig {
-  /// Enable snapshot. Default is [false].
+  /// Enable snapshot. Default is [true].
   pub enable_snapshot: bool,
   /// Generate a snapshot every N updates
   /// Default is 100. The value must be greater than 0.
   pub snapshot_per_update: u32,
+  /// Which snapshots to keep after a new snapshot is created.
+  /// Default keeps the [DEFAULT_SNAPSHOT_KEEP_LAST] newest snapshots. Use
+  /// [SnapshotRetention::default] to keep all the snapshots.
+  pub snapshot_retention: SnapshotRetention,
+  /// When to merge the update log of a document into its document state.
+  /// Default is [CompactionPolicy::default], which never compacts.
+  pub compaction: CompactionPolicy,
 }
 
 impl CollabPersistenceConfig {
@@ -22,6 +34,16 @@ impl CollabPersistenceConfig {
     self.snapshot_per_update = snapshot_per_update;
     self
   }
+
+  pub fn snapshot_retention(mut self, snapshot_retention: SnapshotRetention) -> Self {
+    self.snapshot_retention = snapshot_retention;
+    self
+  }
+
+  pub fn compaction(mut self, compaction: CompactionPolicy) -> Self {
+    self.compaction = compaction;
+    self
+  }
 }
 
 impl Default for CollabPersistenceConfig {
@@ -29,6 +51,191 @@ impl Default for CollabPersistenceConfig {
     Self {
       enable_snapshot: true,
       snapshot_per_update: 100,
+      snapshot_retention: SnapshotRetention::new().keep_last(DEFAULT_SNAPSHOT_KEEP_LAST),
+      compaction: CompactionPolicy::default(),
+    }
+  }
+}
+
+/// The policy of the automatic update-log compaction. The update log of a document is merged into
+/// its document state in the background as soon as any of the thresholds is reached. The
+/// thresholds are counted from the last compaction. None disables the threshold.
+#[derive(Clone, Debug, Default)]
+pub struct CompactionPolicy {
+  /// Compact after N updates. The value must be greater than 0.
+  pub max_updates: Option<u32>,
+  /// Compact when the total size of the updates exceeds the given bytes.
+  pub max_update_bytes: Option<u64>,
+  /// Compact when no update was received for the given duration.
+  pub idle_timeout: Option<Duration>,
+}
+
+impl CompactionPolicy {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn max_updates(mut self, max_updates: u32) -> Self {
+    debug_assert!(max_updates > 0);
+    self.max_updates = Some(max_updates);
+    self
+  }
+
+  pub fn max_update_bytes(mut self, max_update_bytes: u64) -> Self {
+    self.max_update_bytes = Some(max_update_bytes);
+    self
+  }
+
+  pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+    self.idle_timeout = Some(idle_timeout);
+    self
+  }
+
+  pub fn is_enabled(&self) -> bool {
+    self.max_updates.is_some() || self.max_update_bytes.is_some() || self.idle_timeout.is_some()
+  }
+
+  /// Return true if the update log should be compacted after the given number of updates and bytes.
+  pub fn should_compact(&self, update_count: u32, update_bytes: u64) -> bool {
+    self.max_updates.map_or(false, |max| update_count >= max)
+      || self
+        .max_update_bytes
+        .map_or(false, |max| update_bytes >= max)
+  }
+}
+
+const SECONDS_PER_HOUR: i64 = 60 * 60;
+const SECONDS_PER_DAY: i64 = 24 * SECONDS_PER_HOUR;
+const SECONDS_PER_WEEK: i64 = 7 * SECONDS_PER_DAY;
+
+/// The retention policy of the snapshots of a document, applied each time a snapshot is created.
+///
+/// A snapshot is kept if any of the `keep_*` rules keeps it. The hourly, daily and weekly rules
+/// keep the newest snapshot of each of the N most recent periods that have a snapshot. The
+/// periods are fixed hours, days and weeks since the Unix epoch, in UTC. Then the oldest kept
+/// snapshots are removed until the total size is below [SnapshotRetention::max_total_bytes].
+/// The newest snapshot is always kept.
+///
+/// Without any `keep_*` rule, all the snapshots are kept, subject to the size limit.
+#[derive(Clone, Debug, Default)]
+pub struct SnapshotRetention {
+  /// Keep the N newest snapshots.
+  pub keep_last: Option<usize>,
+  /// Keep the newest snapshot of each of the N most recent hours.
+  pub keep_hourly: Option<usize>,
+  /// Keep the newest snapshot of each of the N most recent days.
+  pub keep_daily: Option<usize>,
+  /// Keep the newest snapshot of each of the N most recent weeks.
+  pub keep_weekly: Option<usize>,
+  /// The maximum total size of the encoded snapshots of a document.
+  pub max_total_bytes: Option<u64>,
+}
+
+impl SnapshotRetention {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn keep_last(mut self, keep_last: usize) -> Self {
+    self.keep_last = Some(keep_last);
+    self
+  }
+
+  pub fn keep_hourly(mut self, keep_hourly: usize) -> Self {
+    self.keep_hourly = Some(keep_hourly);
+    self
+  }
+
+  pub fn keep_daily(mut self, keep_daily: usize) -> Self {
+    self.keep_daily = Some(keep_daily);
+    self
+  }
+
+  pub fn keep_weekly(mut self, keep_weekly: usize) -> Self {
+    self.keep_weekly = Some(keep_weekly);
+    self
+  }
+
+  pub fn max_total_bytes(mut self, max_total_bytes: u64) -> Self {
+    self.max_total_bytes = Some(max_total_bytes);
+    self
+  }
+
+  pub fn is_enabled(&self) -> bool {
+    self.has_keep_rule() || self.max_total_bytes.is_some()
+  }
+
+  fn has_keep_rule(&self) -> bool {
+    self.keep_last.is_some()
+      || self.keep_hourly.is_some()
+      || self.keep_daily.is_some()
+      || self.keep_weekly.is_some()
+  }
+
+  /// Return the indexes of the snapshots to remove. The snapshots are given as their creation
+  /// time in seconds and their size in bytes, ordered from the oldest to the newest.
+  pub fn expired_snapshots(&self, snapshots: &[(i64, u64)]) -> Vec<usize> {
+    if !self.is_enabled() || snapshots.is_empty() {
+      return vec![];
+    }
+
+    let mut keep = vec![!self.has_keep_rule(); snapshots.len()];
+    if let Some(keep_last) = self.keep_last {
+      keep
+        .iter_mut()
+        .rev()
+        .take(keep_last)
+        .for_each(|keep| *keep = true);
     }
+    for (count, period) in [
+      (self.keep_hourly, SECONDS_PER_HOUR),
+      (self.keep_daily, SECONDS_PER_DAY),
+      (self.keep_weekly, SECONDS_PER_WEEK),
+    ] {
+      let count = match count {
+        None => continue,
+        Some(count) => count,
+      };
+      let mut buckets = 0;
+      let mut last_bucket = None;
+      for (index, (created_at, _)) in snapshots.iter().enumerate().rev() {
+        if buckets >= count {
+          break;
+        }
+        let bucket = created_at.div_euclid(period);
+        if last_bucket != Some(bucket) {
+          keep[index] = true;
+          buckets += 1;
+          last_bucket = Some(bucket);
+        }
+      }
+    }
+    let newest = snapshots.len() - 1;
+    keep[newest] = true;
+
+    if let Some(max_total_bytes) = self.max_total_bytes {
+      let mut total_bytes: u64 = snapshots
+        .iter()
+        .zip(keep.iter())
+        .filter(|(_, keep)| **keep)
+        .map(|((_, bytes), _)| bytes)
+        .sum();
+      for (index, (_, bytes)) in snapshots.iter().enumerate().take(newest) {
+        if total_bytes <= max_total_bytes {
+          break;
+        }
+        if keep[index] {
+          keep[index] = false;
+          total_bytes -= bytes;
+        }
+      }
+    }
+
+    keep
+      .iter()
+      .enumerate()
+      .filter(|(_, keep)| !**keep)
+      .map(|(index, _)| index)
+      .collect()
   }
 }
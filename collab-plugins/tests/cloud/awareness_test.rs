@@ -0,0 +1,254 @@
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Error;
+use async_trait::async_trait;
+use collab::core::awareness::Event;
+use collab::core::collab::DataSource;
+use collab::lock::RwLock;
+use collab::preclude::Collab;
+use collab_entity::CollabObject;
+use collab_plugins::cloud_storage::{
+  RemoteAwarenessReceiver, RemoteAwarenessSender, RemoteCollab, RemoteCollabSnapshot,
+  RemoteCollabState, RemoteCollabStorage, RemoteUpdateReceiver, SinkConfig, SinkStrategy,
+};
+use tokio::sync::mpsc::unbounded_channel;
+use yrs::Subscription;
+
+use crate::cloud::util::{test_object, MemoryRemoteStorage};
+
+/// Relay the awareness updates between the [AwarenessStorage]s of different devices.
+#[derive(Default)]
+struct AwarenessHub {
+  subscribers: Mutex<Vec<(String, Arc<MemoryRemoteStorage>, RemoteAwarenessSender)>>,
+  /// The device ids of the sent awareness updates.
+  sent: Mutex<Vec<String>>,
+}
+
+impl AwarenessHub {
+  fn sent_by(&self, device_id: &str) -> usize {
+    let sent = self.sent.lock().unwrap();
+    sent.iter().filter(|sender| *sender == device_id).count()
+  }
+}
+
+/// A [RemoteCollabStorage] of a device whose awareness updates are relayed by an [AwarenessHub].
+struct AwarenessStorage {
+  inner: Arc<MemoryRemoteStorage>,
+  device_id: String,
+  hub: Arc<AwarenessHub>,
+}
+
+#[async_trait]
+impl RemoteCollabStorage for AwarenessStorage {
+  fn is_enable(&self) -> bool {
+    self.inner.is_enable()
+  }
+
+  async fn get_doc_state(&self, object: &CollabObject) -> Result<DataSource, Error> {
+    self.inner.get_doc_state(object).await
+  }
+
+  async fn get_snapshots(&self, object_id: &str, limit: usize) -> Vec<RemoteCollabSnapshot> {
+    self.inner.get_snapshots(object_id, limit).await
+  }
+
+  async fn get_collab_state(&self, object_id: &str) -> Result<Option<RemoteCollabState>, Error> {
+    self.inner.get_collab_state(object_id).await
+  }
+
+  async fn create_snapshot(&self, object: &CollabObject, snapshot: Vec<u8>) -> Result<i64, Error> {
+    self.inner.create_snapshot(object, snapshot).await
+  }
+
+  async fn send_update(
+    &self,
+    object: &CollabObject,
+    id: u64,
+    update: Vec<u8>,
+  ) -> Result<(), Error> {
+    self.inner.send_update(object, id, update).await
+  }
+
+  async fn send_init_sync(
+    &self,
+    object: &CollabObject,
+    id: u64,
+    init_update: Vec<u8>,
+  ) -> Result<(), Error> {
+    self.inner.send_init_sync(object, id, init_update).await
+  }
+
+  fn subscribe_remote_updates(&self, _object: &CollabObject) -> Option<RemoteUpdateReceiver> {
+    None
+  }
+
+  async fn send_awareness_update(
+    &self,
+    _object: &CollabObject,
+    update: Vec<u8>,
+  ) -> Result<(), Error> {
+    self.hub.sent.lock().unwrap().push(self.device_id.clone());
+    let subscribers = self.hub.subscribers.lock().unwrap();
+    for (device_id, storage, tx) in subscribers.iter() {
+      // A disabled storage is offline, so it doesn't receive the update.
+      if *device_id != self.device_id && storage.is_enable() {
+        let _ = tx.send(update.clone());
+      }
+    }
+    Ok(())
+  }
+
+  fn subscribe_remote_awareness(&self, _object: &CollabObject) -> Option<RemoteAwarenessReceiver> {
+    let (tx, rx) = unbounded_channel();
+    self
+      .hub
+      .subscribers
+      .lock()
+      .unwrap()
+      .push((self.device_id.clone(), self.inner.clone(), tx));
+    Some(rx)
+  }
+}
+
+struct Device {
+  memory: Arc<MemoryRemoteStorage>,
+  remote: Option<Arc<RemoteCollab>>,
+  collab: Arc<RwLock<Collab>>,
+  _subscription: Subscription,
+}
+
+impl Device {
+  async fn new(hub: &Arc<AwarenessHub>, device_id: &str, awareness_timeout: Duration) -> Self {
+    let memory = Arc::new(MemoryRemoteStorage::new(Collab::new(
+      1,
+      "1",
+      "server",
+      vec![],
+      false,
+    )));
+    let storage = AwarenessStorage {
+      inner: memory.clone(),
+      device_id: device_id.to_string(),
+      hub: hub.clone(),
+    };
+    let collab = Arc::new(RwLock::from(Collab::new(1, "1", device_id, vec![], false)));
+    let remote = Arc::new(RemoteCollab::new(
+      test_object("1"),
+      Arc::new(storage),
+      SinkConfig::new()
+        .with_strategy(SinkStrategy::Asap)
+        .with_awareness_timeout(awareness_timeout),
+      Arc::downgrade(&collab),
+    ));
+    remote.sync(Arc::downgrade(&collab)).await.unwrap();
+
+    // Push the local awareness updates like the cloud storage plugin does.
+    let weak_remote = Arc::downgrade(&remote);
+    let subscription =
+      collab
+        .read()
+        .await
+        .get_awareness()
+        .on_update(move |awareness, event: &Event, _| {
+          if let Some(remote) = weak_remote.upgrade() {
+            if let Ok(update) = awareness.update_with_clients(event.all_changes()) {
+              remote.push_awareness_update(&update);
+            }
+          }
+        });
+    Self {
+      memory,
+      remote: Some(remote),
+      collab,
+      _subscription: subscription,
+    }
+  }
+
+  async fn client_id(&self) -> u64 {
+    self.collab.read().await.get_awareness().client_id()
+  }
+
+  async fn set_local_state(&self, state: serde_json::Value) {
+    let lock = self.collab.read().await;
+    lock.get_awareness().set_local_state(state).unwrap();
+  }
+
+  async fn state_of(&self, client_id: u64) -> Option<serde_json::Value> {
+    let lock = self.collab.read().await;
+    lock.get_awareness().state(client_id)
+  }
+
+  async fn wait_for_state(&self, client_id: u64, is_some: bool) {
+    for _ in 0..100 {
+      if self.state_of(client_id).await.is_some() == is_some {
+        return;
+      }
+      tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!(
+      "the awareness state of {} was expected to be present: {}",
+      client_id, is_some
+    );
+  }
+}
+
+#[tokio::test]
+async fn awareness_is_relayed_without_echo_test() {
+  let hub = Arc::new(AwarenessHub::default());
+  let device_1 = Device::new(&hub, "d1", Duration::from_secs(30)).await;
+  let device_2 = Device::new(&hub, "d2", Duration::from_secs(30)).await;
+  let client_id_1 = device_1.client_id().await;
+
+  device_1
+    .set_local_state(serde_json::json!({"cursor": 1}))
+    .await;
+  device_2.wait_for_state(client_id_1, true).await;
+  assert_eq!(
+    device_2.state_of(client_id_1).await.unwrap(),
+    serde_json::json!({"cursor": 1})
+  );
+
+  device_1
+    .set_local_state(serde_json::json!({"cursor": 2}))
+    .await;
+  for _ in 0..100 {
+    if device_2.state_of(client_id_1).await.unwrap() == serde_json::json!({"cursor": 2}) {
+      break;
+    }
+    tokio::time::sleep(Duration::from_millis(20)).await;
+  }
+  assert_eq!(
+    device_2.state_of(client_id_1).await.unwrap(),
+    serde_json::json!({"cursor": 2})
+  );
+  // The state of the first device is not sent back by the second device.
+  assert_eq!(hub.sent_by("d2"), 0);
+  assert!(hub.sent_by("d1") >= 1);
+}
+
+#[tokio::test]
+async fn stale_awareness_is_removed_test() {
+  let hub = Arc::new(AwarenessHub::default());
+  let mut device_1 = Device::new(&hub, "d1", Duration::from_millis(400)).await;
+  let device_2 = Device::new(&hub, "d2", Duration::from_millis(400)).await;
+  let client_id_1 = device_1.client_id().await;
+
+  device_1
+    .set_local_state(serde_json::json!({"cursor": 1}))
+    .await;
+  device_2.wait_for_state(client_id_1, true).await;
+
+  // The states of the other clients are removed when the remote is disconnected.
+  device_2.memory.enable.store(false, Ordering::SeqCst);
+  device_2.wait_for_state(client_id_1, false).await;
+
+  // The renewed state of the first device is received again after reconnecting.
+  device_2.memory.enable.store(true, Ordering::SeqCst);
+  device_2.wait_for_state(client_id_1, true).await;
+
+  // The state of the first device times out once it stops renewing it.
+  device_1.remote.take();
+  device_2.wait_for_state(client_id_1, false).await;
+}
@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use collab::lock::RwLock;
+use collab::preclude::Collab;
+use collab_entity::proto::collab::InitSync;
+use collab_plugins::cloud_storage::{
+  handle_init_sync, handle_init_sync_with_updates, make_init_sync, RemoteCollab,
+  RemoteCollabStorage, ServerInitPayload, SinkConfig, SinkStrategy, SyncError,
+};
+use yrs::updates::decoder::Decode;
+use yrs::{ReadTxn, StateVector, Update};
+
+use crate::cloud::util::{test_object, wait_for_key, MemoryRemoteStorage, RemoteDiffStorage};
+
+fn large_collab() -> Collab {
+  let mut collab = Collab::new(1, "1", "server", vec![], false);
+  for i in 0..2000 {
+    collab.insert(&format!("key_{}", i), "a".repeat(64));
+  }
+  collab
+}
+
+fn full_update(collab: &Collab) -> Vec<u8> {
+  collab
+    .transact()
+    .encode_state_as_update_v1(&StateVector::default())
+}
+
+fn remote_collab(storage: Arc<dyn RemoteCollabStorage>) -> RemoteCollab {
+  RemoteCollab::new(
+    test_object("1"),
+    storage,
+    SinkConfig::new().with_strategy(SinkStrategy::Asap),
+    Default::default(),
+  )
+}
+
+#[tokio::test]
+async fn init_sync_only_transfers_missing_updates_test() {
+  let server_collab = large_collab();
+  let full_len = full_update(&server_collab).len();
+  let mut local_collab = Collab::new(1, "1", "local", vec![], false);
+  local_collab
+    .transact_mut()
+    .apply_update(Update::decode_v1(&full_update(&server_collab)).unwrap())
+    .unwrap();
+
+  let memory = Arc::new(MemoryRemoteStorage::new(server_collab));
+  memory.with_collab(|collab| collab.insert("server", "1"));
+  local_collab.insert("local", "2");
+
+  let storage = Arc::new(RemoteDiffStorage::new(memory.clone()));
+  let remote = remote_collab(storage.clone());
+  let local_collab = Arc::new(RwLock::from(local_collab));
+  let remote_update = remote.sync(Arc::downgrade(&local_collab)).await.unwrap();
+  assert!(remote_update.len() < 1024);
+  assert_eq!(local_collab.read().await.to_json_value()["server"], "1");
+
+  wait_for_key(&memory, "local").await;
+  assert_eq!(*memory.doc_state_downloads.lock().unwrap(), 0);
+  let server_init_payloads = storage.server_init_payloads.lock().unwrap().clone();
+  assert_eq!(server_init_payloads.len(), 1);
+  assert!(server_init_payloads[0] < 1024);
+  let received_payloads = memory.received_payloads.lock().unwrap().clone();
+  assert_eq!(received_payloads.len(), 1);
+  assert!(received_payloads[0] < 1024);
+  assert!(full_len > 100 * 1024);
+
+  assert_eq!(
+    local_collab.read().await.to_json_value(),
+    memory.with_collab(|collab| collab.to_json_value())
+  );
+}
+
+#[tokio::test]
+async fn init_sync_with_default_storage_diff_test() {
+  let memory = Arc::new(MemoryRemoteStorage::new(large_collab()));
+  let mut local_collab = Collab::new(1, "1", "local", vec![], false);
+  local_collab.insert("local", "2");
+
+  let remote = remote_collab(memory.clone());
+  let local_collab = Arc::new(RwLock::from(local_collab));
+  remote.sync(Arc::downgrade(&local_collab)).await.unwrap();
+  assert_eq!(*memory.doc_state_downloads.lock().unwrap(), 1);
+  assert_eq!(
+    local_collab.read().await.to_json_value()["key_1999"],
+    "a".repeat(64)
+  );
+
+  wait_for_key(&memory, "local").await;
+  assert_eq!(
+    local_collab.read().await.to_json_value(),
+    memory.with_collab(|collab| collab.to_json_value())
+  );
+}
+
+#[test]
+fn server_init_payload_test() {
+  let server_collab = large_collab();
+  let init_sync = make_init_sync(
+    &test_object("1"),
+    1,
+    &server_collab.transact().state_vector(),
+  );
+  let server_init = handle_init_sync(&server_collab.transact(), &init_sync).unwrap();
+  assert_eq!(server_init.msg_id, 1);
+  assert_eq!(server_init.object_id, "1");
+
+  let payload = ServerInitPayload::decode(&server_init.payload).unwrap();
+  assert_eq!(
+    payload.state_vector,
+    server_collab.transact().state_vector()
+  );
+  let update = Update::decode_v1(&payload.update).unwrap();
+  assert!(update.state_vector().is_empty());
+
+  let invalid = InitSync {
+    payload: payload.update.clone(),
+    ..init_sync
+  };
+  assert!(handle_init_sync(&server_collab.transact(), &invalid).is_err());
+  assert!(matches!(
+    ServerInitPayload::decode(&invalid.payload),
+    Err(SyncError::UnexpectedMessage(_) | SyncError::DecodingError(_))
+  ));
+}
+
+#[test]
+fn init_sync_with_updates_test() {
+  let mut server_collab = large_collab();
+  let mut local_collab = Collab::new(1, "1", "local", vec![], false);
+  local_collab
+    .transact_mut()
+    .apply_update(Update::decode_v1(&full_update(&server_collab)).unwrap())
+    .unwrap();
+
+  // The removal doesn't change the state vector, so it's only carried by the delete set.
+  server_collab.remove("key_0");
+  let base_state_vector = server_collab.transact().state_vector();
+  let delete_set = server_collab
+    .transact()
+    .encode_state_as_update_v1(&base_state_vector);
+  server_collab.insert("server", "1");
+  let update = server_collab
+    .transact()
+    .encode_state_as_update_v1(&base_state_vector);
+
+  let init_sync = make_init_sync(
+    &test_object("1"),
+    1,
+    &local_collab.transact().state_vector(),
+  );
+  let server_init =
+    handle_init_sync_with_updates(&init_sync, &[update, delete_set], base_state_vector.clone())
+      .unwrap();
+  let payload = ServerInitPayload::decode(&server_init.payload).unwrap();
+  assert_eq!(payload.state_vector, base_state_vector);
+  assert!(payload.update.len() < 1024);
+
+  local_collab
+    .transact_mut()
+    .apply_update(Update::decode_v1(&payload.update).unwrap())
+    .unwrap();
+  assert_eq!(local_collab.to_json_value(), server_collab.to_json_value());
+  assert!(local_collab.to_json_value()["key_0"].is_null());
+}
@@ -0,0 +1,13 @@
+mod awareness_test;
+mod init_sync_test;
+#[cfg(feature = "sync_server")]
+mod mux_test;
+mod outbox_test;
+mod peer_test;
+#[cfg(feature = "postgres_storage")]
+mod postgres_test;
+mod retry_test;
+mod seq_test;
+#[cfg(feature = "sync_server")]
+mod sync_server_test;
+mod util;
@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use collab_entity::proto::collab::collab_message::Message;
+use collab_entity::proto::collab::ServerInit;
+use collab_plugins::cloud_storage::{
+  CollabMultiplexer, LocalSyncServer, LoopbackConnect, MultiplexerConfig,
+};
+use futures::StreamExt;
+
+use crate::cloud::sync_server_test::{next_msg, send};
+use crate::cloud::util::{init_sync, update_sync};
+
+fn server_init(object_id: &str, msg_id: u64) -> Message {
+  Message::ServerInitSync(ServerInit {
+    origin: None,
+    object_id: object_id.to_string(),
+    msg_id,
+    payload: vec![],
+  })
+}
+
+/// Return the object id and the type of the message, for example `("1", "init")`.
+fn describe(msg: &Message) -> (String, &'static str) {
+  match msg {
+    Message::ClientInitSync(init_sync) => (init_sync.object_id.clone(), "init"),
+    Message::ClientUpdateSync(update_sync) => (update_sync.object_id.clone(), "update"),
+    msg => panic!("unexpected message: {:?}", msg),
+  }
+}
+
+async fn assert_no_msg(connect: &mut LoopbackConnect) {
+  assert!(
+    tokio::time::timeout(Duration::from_millis(200), connect.next())
+      .await
+      .is_err()
+  );
+}
+
+#[tokio::test]
+async fn init_sync_many_objects_over_one_connection_test() {
+  let server = LocalSyncServer::new();
+  let mux = CollabMultiplexer::new(
+    server.connect(),
+    MultiplexerConfig::new().with_max_pending_init_syncs(10),
+  );
+  let mut rows = (0..200)
+    .map(|i| mux.connect(&format!("row_{}", i)))
+    .collect::<Vec<_>>();
+  for (i, row) in rows.iter_mut().enumerate() {
+    send(row, init_sync(&format!("row_{}", i), 1)).await;
+  }
+
+  for (i, row) in rows.iter_mut().enumerate() {
+    match next_msg(row).await {
+      Message::ServerInitSync(server_init) => {
+        assert_eq!(server_init.object_id, format!("row_{}", i))
+      },
+      msg => panic!("unexpected message: {:?}", msg),
+    }
+  }
+  assert_eq!(server.number_of_connections("row_0"), 1);
+  assert_eq!(server.number_of_connections("row_199"), 1);
+  assert_eq!(mux.number_of_pending_init_syncs(), 0);
+  assert_eq!(mux.number_of_queued_messages(), 0);
+}
+
+#[tokio::test]
+async fn foreground_object_and_updates_go_before_init_syncs_test() {
+  let (client, mut server) = LoopbackConnect::pair();
+  let mux = CollabMultiplexer::new(
+    client,
+    MultiplexerConfig::new().with_max_pending_init_syncs(2),
+  );
+  let mut rows = (0..5)
+    .map(|i| mux.connect(&format!("row_{}", i)))
+    .collect::<Vec<_>>();
+  let mut document = mux.connect("document");
+  for (i, row) in rows.iter_mut().enumerate() {
+    send(row, init_sync(&format!("row_{}", i), 1)).await;
+  }
+  mux.set_foreground(Some("row_4"));
+  send(&mut document, update_sync("document", 2, vec![])).await;
+
+  let mut written = vec![];
+  for _ in 0..3 {
+    written.push(describe(&next_msg(&mut server).await));
+  }
+  assert_eq!(
+    written,
+    vec![
+      ("row_4".to_string(), "init"),
+      ("document".to_string(), "update"),
+      ("row_0".to_string(), "init"),
+    ]
+  );
+  // Two init syncs are waiting for the answer of the remote.
+  assert_no_msg(&mut server).await;
+  assert_eq!(mux.number_of_pending_init_syncs(), 2);
+
+  send(&mut server, server_init("row_0", 1)).await;
+  assert_eq!(
+    describe(&next_msg(&mut server).await),
+    ("row_1".to_string(), "init")
+  );
+  assert_eq!(next_msg(&mut rows[0]).await, server_init("row_0", 1));
+}
+
+#[tokio::test]
+async fn updates_wait_for_the_queued_init_sync_of_their_object_test() {
+  let (client, mut server) = LoopbackConnect::pair();
+  let mux = CollabMultiplexer::new(
+    client,
+    MultiplexerConfig::new().with_max_pending_init_syncs(1),
+  );
+  let mut rows = ["a", "b"]
+    .iter()
+    .map(|object_id| mux.connect(object_id))
+    .collect::<Vec<_>>();
+  send(&mut rows[0], init_sync("a", 1)).await;
+  send(&mut rows[1], init_sync("b", 1)).await;
+  send(&mut rows[1], update_sync("b", 2, vec![])).await;
+  assert_eq!(
+    describe(&next_msg(&mut server).await),
+    ("a".to_string(), "init")
+  );
+
+  // The update of b is held back until the init sync of b is written.
+  assert_no_msg(&mut server).await;
+  send(&mut server, server_init("a", 1)).await;
+  assert_eq!(
+    describe(&next_msg(&mut server).await),
+    ("b".to_string(), "init")
+  );
+  assert_eq!(
+    describe(&next_msg(&mut server).await),
+    ("b".to_string(), "update")
+  );
+}
+
+#[tokio::test]
+async fn unanswered_init_sync_expires_test() {
+  let (client, mut server) = LoopbackConnect::pair();
+  let mux = CollabMultiplexer::new(
+    client,
+    MultiplexerConfig::new()
+      .with_max_pending_init_syncs(1)
+      .with_init_sync_timeout(Duration::from_millis(300)),
+  );
+  let mut rows = ["a", "b"]
+    .iter()
+    .map(|object_id| mux.connect(object_id))
+    .collect::<Vec<_>>();
+  send(&mut rows[0], init_sync("a", 1)).await;
+  send(&mut rows[1], init_sync("b", 1)).await;
+  assert_eq!(
+    describe(&next_msg(&mut server).await),
+    ("a".to_string(), "init")
+  );
+
+  // The remote never answers the init sync of a, so b is written once it expires.
+  assert_eq!(
+    describe(&next_msg(&mut server).await),
+    ("b".to_string(), "init")
+  );
+  assert_eq!(mux.number_of_pending_init_syncs(), 1);
+}
+
+#[tokio::test]
+async fn objects_share_the_backpressure_of_the_connection_test() {
+  let (client, mut server) = LoopbackConnect::pair();
+  let mux = CollabMultiplexer::new(
+    client,
+    MultiplexerConfig::new()
+      .with_max_queued_messages(2)
+      .with_max_pending_init_syncs(1),
+  );
+  let mut rows = ["a", "b", "c", "d"]
+    .iter()
+    .map(|object_id| mux.connect(object_id))
+    .collect::<Vec<_>>();
+  for (row, object_id) in rows.iter_mut().zip(["a", "b", "c"]) {
+    send(row, init_sync(object_id, 1)).await;
+  }
+  assert_eq!(
+    describe(&next_msg(&mut server).await),
+    ("a".to_string(), "init")
+  );
+
+  // The init syncs of b and c fill the queue, so d has to wait.
+  let send_update = send(&mut rows[3], update_sync("d", 1, vec![]));
+  tokio::pin!(send_update);
+  assert!(
+    tokio::time::timeout(Duration::from_millis(200), &mut send_update)
+      .await
+      .is_err()
+  );
+
+  send(&mut server, server_init("a", 1)).await;
+  send_update.await;
+  assert_eq!(
+    describe(&next_msg(&mut server).await),
+    ("b".to_string(), "init")
+  );
+  assert_eq!(
+    describe(&next_msg(&mut server).await),
+    ("d".to_string(), "update")
+  );
+  assert_no_msg(&mut server).await;
+}
@@ -0,0 +1,154 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use collab::lock::RwLock;
+use collab::preclude::Collab;
+use collab_plugins::cloud_storage::{RemoteCollab, RetryPolicy, SinkConfig, SinkStrategy};
+use collab_plugins::local_storage::kv::outbox::OutboxAction;
+use collab_plugins::local_storage::kv::KVTransactionDB;
+use collab_plugins::local_storage::memory::kv_impl::KVTransactionDBMemoryImpl;
+use yrs::ReadTxn;
+
+use crate::cloud::util::{test_object, wait_for_key, MemoryRemoteStorage};
+
+fn remote_collab(
+  storage: Arc<MemoryRemoteStorage>,
+  db: &Arc<KVTransactionDBMemoryImpl>,
+) -> Arc<RemoteCollab> {
+  Arc::new(RemoteCollab::new_with_outbox(
+    test_object("1"),
+    storage,
+    SinkConfig::new()
+      .with_timeout(1)
+      .with_strategy(SinkStrategy::Asap),
+    Default::default(),
+    Arc::downgrade(db),
+  ))
+}
+
+fn insert(collab: &mut Collab, key: &str) -> Vec<u8> {
+  let state_vector = collab.transact().state_vector();
+  collab.insert(key, "value");
+  collab.transact().encode_state_as_update_v1(&state_vector)
+}
+
+async fn push_update(remote: &Arc<RemoteCollab>, update: Vec<u8>) {
+  let remote = remote.clone();
+  tokio::task::spawn_blocking(move || remote.push_update(&update))
+    .await
+    .unwrap()
+    .unwrap();
+}
+
+#[tokio::test]
+async fn unsynced_updates_are_replayed_after_restart_test() {
+  let db = Arc::new(KVTransactionDBMemoryImpl::new());
+  let storage = Arc::new(MemoryRemoteStorage::new(Collab::new(
+    1,
+    "1",
+    "server",
+    vec![],
+    false,
+  )));
+  storage.enable.store(false, Ordering::SeqCst);
+
+  let mut local_collab = Collab::new(1, "1", "local", vec![], false);
+  let remote = remote_collab(storage.clone(), &db);
+  for key in ["a", "b", "c"] {
+    push_update(&remote, insert(&mut local_collab, key)).await;
+  }
+  assert_eq!(remote.number_of_unsynced_changes().await, 3);
+  assert_eq!(db.read_txn().unsynced_changes(1).unwrap()["1"], 3);
+
+  // The app exits while offline.
+  tokio::time::sleep(Duration::from_millis(1500)).await;
+  drop(remote);
+  assert_eq!(db.read_txn().number_of_unsynced_changes(1, "1"), 3);
+
+  storage.enable.store(true, Ordering::SeqCst);
+  let remote = remote_collab(storage.clone(), &db);
+  for key in ["a", "b", "c"] {
+    wait_for_key(&storage, key).await;
+  }
+  for _ in 0..100 {
+    if remote.number_of_unsynced_changes().await == 0 {
+      break;
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+  }
+  assert_eq!(remote.number_of_unsynced_changes().await, 0);
+  assert!(db.read_txn().unsynced_changes(1).unwrap().is_empty());
+  assert_eq!(
+    storage.with_collab(|collab| collab.to_json_value()),
+    local_collab.to_json_value()
+  );
+}
+
+#[tokio::test]
+async fn acked_updates_are_removed_from_outbox_test() {
+  let db = Arc::new(KVTransactionDBMemoryImpl::new());
+  let storage = Arc::new(MemoryRemoteStorage::new(Collab::new(
+    1,
+    "1",
+    "server",
+    vec![],
+    false,
+  )));
+  let mut local_collab = Collab::new(1, "1", "local", vec![], false);
+  let remote = remote_collab(storage.clone(), &db);
+  push_update(&remote, insert(&mut local_collab, "a")).await;
+  wait_for_key(&storage, "a").await;
+
+  for _ in 0..100 {
+    if db.read_txn().get_outbox_msgs(1, "1").is_empty() {
+      break;
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+  }
+  assert!(db.read_txn().get_outbox_msgs(1, "1").is_empty());
+  assert_eq!(remote.number_of_unsynced_changes().await, 0);
+}
+
+#[tokio::test]
+async fn given_up_updates_are_removed_from_outbox_test() {
+  let db = Arc::new(KVTransactionDBMemoryImpl::new());
+  let storage = Arc::new(MemoryRemoteStorage::new(Collab::new(
+    1,
+    "1",
+    "server",
+    vec![],
+    false,
+  )));
+  storage.enable.store(false, Ordering::SeqCst);
+  let mut local_collab = Collab::new(1, "1", "local", vec![], false);
+  let update = insert(&mut local_collab, "a");
+  let local_collab = Arc::new(RwLock::from(local_collab));
+  let remote = Arc::new(RemoteCollab::new_with_outbox(
+    test_object("1"),
+    storage.clone(),
+    SinkConfig::new()
+      .with_timeout(1)
+      .with_strategy(SinkStrategy::Asap)
+      .with_retry_policy(RetryPolicy::immediate().with_max_attempts(2)),
+    Arc::downgrade(&local_collab),
+    Arc::downgrade(&db),
+  ));
+  push_update(&remote, update).await;
+
+  // The update is given up after two timeouts, and the resync fails until the remote is back.
+  tokio::time::sleep(Duration::from_millis(4500)).await;
+  assert!(storage.with_collab(|collab| collab.to_json_value()["a"].is_null()));
+  storage.enable.store(true, Ordering::SeqCst);
+  wait_for_key(&storage, "a").await;
+
+  // Only the message of the resync was in the outbox, and it was acked.
+  for _ in 0..100 {
+    if db.read_txn().get_outbox_msgs(1, "1").is_empty() {
+      break;
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+  }
+  assert!(db.read_txn().get_outbox_msgs(1, "1").is_empty());
+  assert_eq!(remote.number_of_unsynced_changes().await, 0);
+}
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use collab::lock::RwLock;
+use collab::preclude::Collab;
+use collab_plugins::cloud_storage::{PeerSync, PeerSyncState};
+use serde_json::{json, Value};
+
+fn collab(device_id: &str) -> Arc<RwLock<Collab>> {
+  Arc::new(RwLock::from(Collab::new(1, "1", device_id, vec![], false)))
+}
+
+async fn wait_until_equal(collab_a: &RwLock<Collab>, collab_b: &RwLock<Collab>) {
+  for _ in 0..100 {
+    if collab_a.read().await.to_json_value() == collab_b.read().await.to_json_value() {
+      return;
+    }
+    tokio::time::sleep(Duration::from_millis(20)).await;
+  }
+  panic!("the collabs didn't converge");
+}
+
+#[tokio::test]
+async fn peers_exchange_missing_and_new_updates_test() {
+  let collab_a = collab("a");
+  let collab_b = collab("b");
+  collab_a.write().await.insert("a", "1");
+  collab_b.write().await.insert("b", "2");
+
+  let (peer_a, peer_b) = PeerSync::connect_pair(&collab_a, &collab_b).await.unwrap();
+  assert!(peer_a.wait_until_synced().await);
+  assert!(peer_b.wait_until_synced().await);
+  wait_until_equal(&collab_a, &collab_b).await;
+  assert_eq!(
+    collab_b.read().await.to_json_value(),
+    json!({"a": "1", "b": "2"})
+  );
+
+  collab_b.write().await.insert("c", "3");
+  wait_until_equal(&collab_a, &collab_b).await;
+  assert_eq!(collab_a.read().await.to_json_value()["c"], "3");
+}
+
+#[tokio::test]
+async fn concurrent_edits_converge_test() {
+  let collab_a = collab("a");
+  let collab_b = collab("b");
+  let (_peer_a, _peer_b) = PeerSync::connect_pair(&collab_a, &collab_b).await.unwrap();
+  {
+    let mut lock_a = collab_a.write().await;
+    let mut lock_b = collab_b.write().await;
+    lock_a.insert("title", "from a");
+    lock_b.insert("title", "from b");
+  }
+  wait_until_equal(&collab_a, &collab_b).await;
+  let title = collab_a.read().await.to_json_value()["title"].clone();
+  assert!(title == "from a" || title == "from b");
+}
+
+#[tokio::test]
+async fn awareness_is_synced_test() {
+  let collab_a = collab("a");
+  let collab_b = collab("b");
+  let (_peer_a, peer_b) = PeerSync::connect_pair(&collab_a, &collab_b).await.unwrap();
+  assert!(peer_b.wait_until_synced().await);
+
+  let client_id = {
+    let lock = collab_a.read().await;
+    lock
+      .get_awareness()
+      .set_local_state(json!({"cursor": 1}))
+      .unwrap();
+    lock.client_id()
+  };
+  for _ in 0..100 {
+    let state = collab_b
+      .read()
+      .await
+      .get_awareness()
+      .state::<Value>(client_id);
+    if state == Some(json!({"cursor": 1})) {
+      return;
+    }
+    tokio::time::sleep(Duration::from_millis(20)).await;
+  }
+  panic!("the awareness state wasn't synced");
+}
+
+#[tokio::test]
+async fn dropped_collab_closes_the_peer_test() {
+  let collab_a = collab("a");
+  let collab_b = collab("b");
+  let (peer_a, _peer_b) = PeerSync::connect_pair(&collab_a, &collab_b).await.unwrap();
+  assert!(peer_a.wait_until_synced().await);
+
+  drop(collab_a);
+  collab_b.write().await.insert("b", "2");
+  let mut state = peer_a.subscribe_state();
+  tokio::time::timeout(
+    Duration::from_secs(5),
+    state.wait_for(|state| *state == PeerSyncState::Closed),
+  )
+  .await
+  .unwrap()
+  .unwrap();
+}
@@ -0,0 +1,129 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use collab::lock::RwLock;
+use collab::preclude::Collab;
+use collab_plugins::cloud_storage::{RemoteCollab, RetryPolicy, SinkConfig, SinkStrategy};
+use collab_plugins::connect_state::{CollabConnectReachability, CollabConnectState};
+use yrs::ReadTxn;
+
+use crate::cloud::util::{test_object, wait_for_key, MemoryRemoteStorage};
+
+#[test]
+fn backoff_delay_grows_exponentially_test() {
+  let policy = RetryPolicy::new()
+    .with_backoff(Duration::from_millis(100), Duration::from_millis(500))
+    .with_jitter(0.0);
+  let delays = (1..=5)
+    .map(|attempts| policy.backoff_delay(attempts))
+    .collect::<Vec<_>>();
+  assert_eq!(
+    delays,
+    vec![100, 200, 400, 500, 500]
+      .into_iter()
+      .map(Duration::from_millis)
+      .collect::<Vec<_>>()
+  );
+
+  let policy = policy.with_jitter(0.5);
+  for _ in 0..100 {
+    let delay = policy.backoff_delay(2);
+    assert!(delay > Duration::from_millis(100) && delay <= Duration::from_millis(200));
+  }
+}
+
+#[tokio::test]
+async fn circuit_breaker_holds_back_retries_until_reachable_test() {
+  let storage = Arc::new(MemoryRemoteStorage::new(Collab::new(
+    1,
+    "1",
+    "server",
+    vec![],
+    false,
+  )));
+  storage.enable.store(false, Ordering::SeqCst);
+  let reachability = Arc::new(CollabConnectReachability::new());
+  let config = SinkConfig::new()
+    .with_timeout(1)
+    .with_strategy(SinkStrategy::Asap)
+    .with_retry_policy(
+      RetryPolicy::new()
+        .with_backoff(Duration::from_millis(100), Duration::from_millis(100))
+        .with_jitter(0.0)
+        .with_circuit_breaker(2, Duration::from_secs(60)),
+    )
+    .with_reachability(reachability.clone());
+  let remote = Arc::new(RemoteCollab::new(
+    test_object("1"),
+    storage.clone(),
+    config,
+    Default::default(),
+  ));
+
+  let mut local_collab = Collab::new(1, "1", "local", vec![], false);
+  local_collab.insert("a", "value");
+  let update = local_collab
+    .transact()
+    .encode_state_as_update_v1(&Default::default());
+  let cloned_remote = remote.clone();
+  tokio::task::spawn_blocking(move || cloned_remote.push_update(&update))
+    .await
+    .unwrap()
+    .unwrap();
+
+  // The circuit breaker opens after two timeouts.
+  for _ in 0..100 {
+    if reachability.state() == CollabConnectState::Disconnected {
+      break;
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+  }
+  assert_eq!(reachability.state(), CollabConnectState::Disconnected);
+
+  // The sink waits for the cool down, even though the remote is back.
+  storage.enable.store(true, Ordering::SeqCst);
+  tokio::time::sleep(Duration::from_millis(1500)).await;
+  assert!(storage.with_collab(|collab| collab.to_json_value()["a"].is_null()));
+
+  reachability.set_state(CollabConnectState::Connected);
+  wait_for_key(&storage, "a").await;
+}
+
+#[tokio::test]
+async fn resync_after_max_attempts_test() {
+  let storage = Arc::new(MemoryRemoteStorage::new(Collab::new(
+    1,
+    "1",
+    "server",
+    vec![],
+    false,
+  )));
+  storage.enable.store(false, Ordering::SeqCst);
+  let config = SinkConfig::new()
+    .with_timeout(1)
+    .with_strategy(SinkStrategy::Asap)
+    .with_retry_policy(RetryPolicy::immediate().with_max_attempts(2));
+  let mut local_collab = Collab::new(1, "1", "local", vec![], false);
+  local_collab.insert("a", "value");
+  let update = local_collab
+    .transact()
+    .encode_state_as_update_v1(&Default::default());
+  let local_collab = Arc::new(RwLock::from(local_collab));
+  let remote = Arc::new(RemoteCollab::new(
+    test_object("1"),
+    storage.clone(),
+    config,
+    Arc::downgrade(&local_collab),
+  ));
+  let cloned_remote = remote.clone();
+  tokio::task::spawn_blocking(move || cloned_remote.push_update(&update))
+    .await
+    .unwrap()
+    .unwrap();
+
+  // The update is given up after two timeouts, then the resync sends the change again.
+  tokio::time::sleep(Duration::from_millis(2500)).await;
+  storage.enable.store(true, Ordering::SeqCst);
+  wait_for_key(&storage, "a").await;
+}
@@ -0,0 +1,225 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Error;
+use async_trait::async_trait;
+use collab::core::collab::DataSource;
+use collab::core::collab_state::SyncState;
+use collab::lock::RwLock;
+use collab::preclude::Collab;
+use collab_entity::proto::collab::BroadcastSync;
+use collab_entity::CollabObject;
+use collab_plugins::cloud_storage::{
+  RemoteBroadcast, RemoteBroadcastReceiver, RemoteBroadcastSender, RemoteCollab,
+  RemoteCollabSnapshot, RemoteCollabState, RemoteCollabStorage, RemoteUpdateReceiver, SeqNumCheck,
+  SeqNumTracker, SinkConfig, SinkStrategy,
+};
+use tokio::sync::mpsc::unbounded_channel;
+use yrs::updates::decoder::Decode;
+use yrs::{ReadTxn, Update};
+
+use crate::cloud::util::{test_object, MemoryRemoteStorage};
+
+/// A [RemoteCollabStorage] whose broadcasts are sent by the test.
+struct BroadcastStorage {
+  inner: Arc<MemoryRemoteStorage>,
+  broadcast_tx: Mutex<Option<RemoteBroadcastSender>>,
+}
+
+impl BroadcastStorage {
+  fn new(inner: Arc<MemoryRemoteStorage>) -> Self {
+    Self {
+      inner,
+      broadcast_tx: Default::default(),
+    }
+  }
+
+  /// Apply the update to the remote collab. The update is broadcast only if `is_broadcast` is
+  /// true, to simulate a broadcast that is lost.
+  fn push_update(&self, seq_num: u32, update: Vec<u8>, is_broadcast: bool) {
+    self.inner.with_collab(|collab| {
+      collab
+        .transact_mut()
+        .apply_update(Update::decode_v1(&update).unwrap())
+        .unwrap()
+    });
+    if is_broadcast {
+      let broadcast = RemoteBroadcast::Update(BroadcastSync {
+        origin: None,
+        object_id: "1".to_string(),
+        payload: update,
+        seq_num,
+      });
+      let broadcast_tx = self.broadcast_tx.lock().unwrap();
+      broadcast_tx.as_ref().unwrap().send(broadcast).unwrap();
+    }
+  }
+}
+
+#[async_trait]
+impl RemoteCollabStorage for BroadcastStorage {
+  fn is_enable(&self) -> bool {
+    self.inner.is_enable()
+  }
+
+  async fn get_doc_state(&self, object: &CollabObject) -> Result<DataSource, Error> {
+    self.inner.get_doc_state(object).await
+  }
+
+  async fn get_snapshots(&self, object_id: &str, limit: usize) -> Vec<RemoteCollabSnapshot> {
+    self.inner.get_snapshots(object_id, limit).await
+  }
+
+  async fn get_collab_state(&self, object_id: &str) -> Result<Option<RemoteCollabState>, Error> {
+    self.inner.get_collab_state(object_id).await
+  }
+
+  async fn create_snapshot(&self, object: &CollabObject, snapshot: Vec<u8>) -> Result<i64, Error> {
+    self.inner.create_snapshot(object, snapshot).await
+  }
+
+  async fn send_update(
+    &self,
+    object: &CollabObject,
+    id: u64,
+    update: Vec<u8>,
+  ) -> Result<(), Error> {
+    self.inner.send_update(object, id, update).await
+  }
+
+  async fn send_init_sync(
+    &self,
+    object: &CollabObject,
+    id: u64,
+    init_update: Vec<u8>,
+  ) -> Result<(), Error> {
+    self.inner.send_init_sync(object, id, init_update).await
+  }
+
+  fn subscribe_remote_updates(&self, _object: &CollabObject) -> Option<RemoteUpdateReceiver> {
+    None
+  }
+
+  fn subscribe_remote_broadcasts(&self, _object: &CollabObject) -> Option<RemoteBroadcastReceiver> {
+    let (tx, rx) = unbounded_channel();
+    *self.broadcast_tx.lock().unwrap() = Some(tx);
+    Some(rx)
+  }
+}
+
+fn peer_updates(keys: &[&str]) -> Vec<Vec<u8>> {
+  let mut collab = Collab::new(2, "1", "peer", vec![], false);
+  keys
+    .iter()
+    .map(|key| {
+      let state_vector = collab.transact().state_vector();
+      collab.insert(key, "value");
+      collab.transact().encode_state_as_update_v1(&state_vector)
+    })
+    .collect()
+}
+
+async fn synced_remote_collab(
+  storage: Arc<BroadcastStorage>,
+) -> (RemoteCollab, Arc<RwLock<Collab>>) {
+  let local_collab = Arc::new(RwLock::from(Collab::new(1, "1", "local", vec![], false)));
+  let remote = RemoteCollab::new(
+    test_object("1"),
+    storage,
+    SinkConfig::new().with_strategy(SinkStrategy::Asap),
+    Arc::downgrade(&local_collab),
+  );
+  remote.sync(Arc::downgrade(&local_collab)).await.unwrap();
+  wait_for_sync_finished(&remote).await;
+  (remote, local_collab)
+}
+
+async fn wait_for_sync_finished(remote: &RemoteCollab) {
+  let mut sync_state = remote.subscribe_sync_state();
+  tokio::time::timeout(
+    Duration::from_secs(5),
+    sync_state.wait_for(|state| *state == SyncState::SyncFinished),
+  )
+  .await
+  .expect("the init sync didn't finish")
+  .unwrap();
+}
+
+async fn wait_for_local_key(local_collab: &RwLock<Collab>, key: &str) {
+  for _ in 0..100 {
+    if !local_collab.read().await.to_json_value()[key].is_null() {
+      return;
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+  }
+  panic!("the local collab didn't receive {}", key);
+}
+
+#[test]
+fn seq_num_tracker_test() {
+  let mut tracker = SeqNumTracker::new();
+  assert_eq!(tracker.check(5), SeqNumCheck::InOrder);
+  assert_eq!(tracker.check(6), SeqNumCheck::InOrder);
+  assert_eq!(tracker.check(6), SeqNumCheck::Duplicate);
+  assert_eq!(tracker.check(4), SeqNumCheck::Duplicate);
+  assert_eq!(
+    tracker.check(9),
+    SeqNumCheck::Gap {
+      expected: 7,
+      received: 9
+    }
+  );
+  assert_eq!(tracker.last_seq_num(), Some(9));
+
+  tracker.reset();
+  assert_eq!(tracker.check(2), SeqNumCheck::InOrder);
+}
+
+#[tokio::test]
+async fn broadcasts_in_order_are_applied_without_resync_test() {
+  let memory = Arc::new(MemoryRemoteStorage::new(Collab::new(
+    1,
+    "1",
+    "server",
+    vec![],
+    false,
+  )));
+  let storage = Arc::new(BroadcastStorage::new(memory.clone()));
+  let (_remote, local_collab) = synced_remote_collab(storage.clone()).await;
+  assert_eq!(*memory.doc_state_downloads.lock().unwrap(), 1);
+
+  let updates = peer_updates(&["a", "b"]);
+  for (i, update) in updates.into_iter().enumerate() {
+    storage.push_update(i as u32 + 1, update, true);
+  }
+  wait_for_local_key(&local_collab, "b").await;
+  assert_eq!(*memory.doc_state_downloads.lock().unwrap(), 1);
+}
+
+#[tokio::test]
+async fn missed_broadcast_triggers_resync_test() {
+  let memory = Arc::new(MemoryRemoteStorage::new(Collab::new(
+    1,
+    "1",
+    "server",
+    vec![],
+    false,
+  )));
+  let storage = Arc::new(BroadcastStorage::new(memory.clone()));
+  let (remote, local_collab) = synced_remote_collab(storage.clone()).await;
+
+  let updates = peer_updates(&["a", "b", "c"]);
+  storage.push_update(1, updates[0].clone(), true);
+  // The broadcast of the second update is lost.
+  storage.push_update(2, updates[1].clone(), false);
+  storage.push_update(3, updates[2].clone(), true);
+
+  wait_for_local_key(&local_collab, "b").await;
+  wait_for_local_key(&local_collab, "c").await;
+  assert_eq!(*memory.doc_state_downloads.lock().unwrap(), 2);
+  wait_for_sync_finished(&remote).await;
+  assert_eq!(
+    local_collab.read().await.to_json_value(),
+    memory.with_collab(|collab| collab.to_json_value())
+  );
+}
@@ -0,0 +1,172 @@
+use std::time::Duration;
+
+use collab_entity::proto::collab::collab_message::Message;
+use collab_entity::proto::collab::{AwarenessSync, CollabMessage};
+use collab_plugins::cloud_storage::{
+  LocalSyncServer, ServerInitPayload, SyncError, WebSocketConnect, ACK_CODE_INVALID_MESSAGE,
+  ACK_CODE_SUCCESS,
+};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+
+use crate::cloud::util::{init_sync, update_of, update_sync};
+
+pub(crate) async fn send<C>(connect: &mut C, msg: Message)
+where
+  C: Sink<CollabMessage, Error = SyncError> + Unpin,
+{
+  connect
+    .send(CollabMessage { message: Some(msg) })
+    .await
+    .unwrap();
+}
+
+pub(crate) async fn next_msg<C>(connect: &mut C) -> Message
+where
+  C: Stream<Item = Result<CollabMessage, SyncError>> + Unpin,
+{
+  tokio::time::timeout(Duration::from_secs(5), connect.next())
+    .await
+    .expect("no message from the server")
+    .unwrap()
+    .unwrap()
+    .message
+    .unwrap()
+}
+
+fn collab_update(key: &str) -> Vec<u8> {
+  update_of(|collab| {
+    collab.insert(key, "value");
+  })
+}
+
+async fn update_is_acked_and_broadcast<A, B>(client_a: &mut A, client_b: &mut B)
+where
+  A: Sink<CollabMessage, Error = SyncError>
+    + Stream<Item = Result<CollabMessage, SyncError>>
+    + Unpin,
+  B: Sink<CollabMessage, Error = SyncError>
+    + Stream<Item = Result<CollabMessage, SyncError>>
+    + Unpin,
+{
+  send(client_a, init_sync("1", 1)).await;
+  assert!(matches!(
+    next_msg(client_a).await,
+    Message::ServerInitSync(_)
+  ));
+  send(client_b, init_sync("1", 1)).await;
+  assert!(matches!(
+    next_msg(client_b).await,
+    Message::ServerInitSync(_)
+  ));
+
+  let update = collab_update("a");
+  send(client_a, update_sync("1", 2, update.clone())).await;
+  match next_msg(client_a).await {
+    Message::ClientAck(ack) => {
+      assert_eq!(ack.msg_id, 2);
+      assert_eq!(ack.seq_num, 1);
+      assert_eq!(ack.code, ACK_CODE_SUCCESS);
+    },
+    msg => panic!("unexpected message: {:?}", msg),
+  }
+  match next_msg(client_b).await {
+    Message::ServerBroadcast(broadcast) => {
+      assert_eq!(broadcast.object_id, "1");
+      assert_eq!(broadcast.seq_num, 1);
+      assert_eq!(broadcast.payload, update);
+    },
+    msg => panic!("unexpected message: {:?}", msg),
+  }
+}
+
+#[tokio::test]
+async fn loopback_update_is_acked_and_broadcast_test() {
+  let server = LocalSyncServer::new();
+  let mut client_a = server.connect();
+  let mut client_b = server.connect();
+  update_is_acked_and_broadcast(&mut client_a, &mut client_b).await;
+  assert_eq!(server.seq_num("1"), 1);
+  assert_eq!(server.number_of_connections("1"), 2);
+
+  // The init sync of a new client returns the updates of the room.
+  let mut client_c = server.connect();
+  send(&mut client_c, init_sync("1", 1)).await;
+  match next_msg(&mut client_c).await {
+    Message::ServerInitSync(server_init) => {
+      let payload = ServerInitPayload::decode(&server_init.payload).unwrap();
+      assert_eq!(payload.update, server.doc_state("1").unwrap());
+    },
+    msg => panic!("unexpected message: {:?}", msg),
+  }
+}
+
+#[tokio::test]
+async fn invalid_update_is_not_broadcast_test() {
+  let server = LocalSyncServer::new();
+  let mut client_a = server.connect();
+  let mut client_b = server.connect();
+  send(&mut client_b, init_sync("1", 1)).await;
+  next_msg(&mut client_b).await;
+
+  send(&mut client_a, update_sync("1", 1, vec![1, 2, 3])).await;
+  match next_msg(&mut client_a).await {
+    Message::ClientAck(ack) => {
+      assert_eq!(ack.code, ACK_CODE_INVALID_MESSAGE);
+      assert_eq!(ack.seq_num, 0);
+    },
+    msg => panic!("unexpected message: {:?}", msg),
+  }
+
+  // The next update is the first one that is broadcast.
+  send(&mut client_a, update_sync("1", 2, collab_update("a"))).await;
+  next_msg(&mut client_a).await;
+  match next_msg(&mut client_b).await {
+    Message::ServerBroadcast(broadcast) => assert_eq!(broadcast.seq_num, 1),
+    msg => panic!("unexpected message: {:?}", msg),
+  }
+}
+
+#[tokio::test]
+async fn awareness_is_relayed_to_other_clients_test() {
+  let server = LocalSyncServer::new();
+  let mut client_a = server.connect();
+  let mut client_b = server.connect();
+  send(&mut client_b, init_sync("1", 1)).await;
+  next_msg(&mut client_b).await;
+
+  let awareness = AwarenessSync {
+    origin: None,
+    object_id: "1".to_string(),
+    payload: vec![1, 2, 3],
+  };
+  send(&mut client_a, Message::AwarenessSync(awareness.clone())).await;
+  assert_eq!(
+    next_msg(&mut client_b).await,
+    Message::AwarenessSync(awareness)
+  );
+  assert!(
+    tokio::time::timeout(Duration::from_millis(200), client_a.next())
+      .await
+      .is_err()
+  );
+}
+
+#[tokio::test]
+async fn websocket_update_is_acked_and_broadcast_test() {
+  let server = LocalSyncServer::new();
+  let addr = server.listen("127.0.0.1:0").await.unwrap();
+  let url = format!("ws://{}", addr);
+  let mut client_a = WebSocketConnect::connect(&url).await.unwrap();
+  let mut client_b = WebSocketConnect::connect(&url).await.unwrap();
+  update_is_acked_and_broadcast(&mut client_a, &mut client_b).await;
+  assert_eq!(server.seq_num("1"), 1);
+
+  drop(client_b);
+  for _ in 0..100 {
+    if server.number_of_connections("1") == 1 {
+      break;
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+  }
+  assert_eq!(server.number_of_connections("1"), 1);
+}
@@ -0,0 +1,245 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use collab::core::collab::DataSource;
+use collab::preclude::Collab;
+#[cfg(feature = "sync_server")]
+use collab_entity::proto::collab::collab_message::Message;
+#[cfg(feature = "sync_server")]
+use collab_entity::proto::collab::UpdateSync;
+use collab_entity::proto::collab::{InitSync, ServerInit};
+use collab_entity::{CollabObject, CollabType};
+use collab_plugins::cloud_storage::{
+  handle_init_sync, RemoteCollabSnapshot, RemoteCollabState, RemoteCollabStorage,
+  RemoteUpdateReceiver,
+};
+use yrs::updates::decoder::Decode;
+use yrs::{ReadTxn, StateVector, Update};
+
+pub fn test_object(object_id: &str) -> CollabObject {
+  CollabObject::new(
+    1,
+    object_id.to_string(),
+    CollabType::Document,
+    "w1".to_string(),
+    "d1".to_string(),
+  )
+}
+
+/// Returns the update of a new collab that is edited by `f`.
+#[cfg(any(feature = "sync_server", feature = "postgres_storage"))]
+pub fn update_of(f: impl FnOnce(&mut Collab)) -> Vec<u8> {
+  let mut collab = Collab::new(1, "1", "local", vec![], false);
+  f(&mut collab);
+  collab
+    .transact()
+    .encode_state_as_update_v1(&StateVector::default())
+}
+
+#[cfg(feature = "sync_server")]
+pub fn init_sync(object_id: &str, msg_id: u64) -> Message {
+  Message::ClientInitSync(collab_plugins::cloud_storage::make_init_sync(
+    &test_object(object_id),
+    msg_id,
+    &StateVector::default(),
+  ))
+}
+
+#[cfg(feature = "sync_server")]
+pub fn update_sync(object_id: &str, msg_id: u64, payload: Vec<u8>) -> Message {
+  Message::ClientUpdateSync(UpdateSync {
+    origin: None,
+    object_id: object_id.to_string(),
+    msg_id,
+    payload,
+  })
+}
+
+pub async fn wait_for_key(storage: &MemoryRemoteStorage, key: &str) {
+  for _ in 0..100 {
+    if !storage.with_collab(|collab| collab.to_json_value()[key].is_null()) {
+      return;
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+  }
+  panic!("the remote collab didn't receive {}", key);
+}
+
+/// A [RemoteCollabStorage] that keeps the remote collab in memory and records the size of the
+/// payloads that are transferred.
+pub struct MemoryRemoteStorage {
+  collab: Mutex<Collab>,
+  /// Set to false to simulate that the remote is offline.
+  pub enable: AtomicBool,
+  pub doc_state_downloads: Mutex<usize>,
+  pub received_payloads: Mutex<Vec<usize>>,
+}
+
+impl MemoryRemoteStorage {
+  pub fn new(collab: Collab) -> Self {
+    Self {
+      collab: Mutex::new(collab),
+      enable: AtomicBool::new(true),
+      doc_state_downloads: Default::default(),
+      received_payloads: Default::default(),
+    }
+  }
+
+  pub fn with_collab<F, T>(&self, f: F) -> T
+  where
+    F: FnOnce(&mut Collab) -> T,
+  {
+    f(&mut self.collab.lock().unwrap())
+  }
+
+  fn apply_update(&self, update: &[u8]) -> Result<(), Error> {
+    self.received_payloads.lock().unwrap().push(update.len());
+    let update = Update::decode_v1(update)?;
+    self
+      .collab
+      .lock()
+      .unwrap()
+      .transact_mut()
+      .apply_update(update)?;
+    Ok(())
+  }
+}
+
+#[async_trait]
+impl RemoteCollabStorage for MemoryRemoteStorage {
+  fn is_enable(&self) -> bool {
+    self.enable.load(Ordering::SeqCst)
+  }
+
+  async fn get_doc_state(&self, _object: &CollabObject) -> Result<DataSource, Error> {
+    if !self.is_enable() {
+      return Err(anyhow!("the remote is offline"));
+    }
+    *self.doc_state_downloads.lock().unwrap() += 1;
+    let doc_state = self
+      .collab
+      .lock()
+      .unwrap()
+      .transact()
+      .encode_state_as_update_v1(&StateVector::default());
+    Ok(DataSource::DocStateV1(doc_state))
+  }
+
+  async fn get_snapshots(&self, _object_id: &str, _limit: usize) -> Vec<RemoteCollabSnapshot> {
+    vec![]
+  }
+
+  async fn get_collab_state(&self, _object_id: &str) -> Result<Option<RemoteCollabState>, Error> {
+    Ok(None)
+  }
+
+  async fn create_snapshot(
+    &self,
+    _object: &CollabObject,
+    _snapshot: Vec<u8>,
+  ) -> Result<i64, Error> {
+    Ok(0)
+  }
+
+  async fn send_update(
+    &self,
+    _object: &CollabObject,
+    _id: u64,
+    update: Vec<u8>,
+  ) -> Result<(), Error> {
+    self.apply_update(&update)
+  }
+
+  async fn send_init_sync(
+    &self,
+    _object: &CollabObject,
+    _id: u64,
+    init_update: Vec<u8>,
+  ) -> Result<(), Error> {
+    self.apply_update(&init_update)
+  }
+
+  fn subscribe_remote_updates(&self, _object: &CollabObject) -> Option<RemoteUpdateReceiver> {
+    None
+  }
+}
+
+/// A [RemoteCollabStorage] that calculates the diff of the init sync on the remote side instead
+/// of using the default [RemoteCollabStorage::init_sync].
+pub struct RemoteDiffStorage {
+  pub inner: Arc<MemoryRemoteStorage>,
+  pub server_init_payloads: Mutex<Vec<usize>>,
+}
+
+impl RemoteDiffStorage {
+  pub fn new(inner: Arc<MemoryRemoteStorage>) -> Self {
+    Self {
+      inner,
+      server_init_payloads: Default::default(),
+    }
+  }
+}
+
+#[async_trait]
+impl RemoteCollabStorage for RemoteDiffStorage {
+  fn is_enable(&self) -> bool {
+    self.inner.is_enable()
+  }
+
+  async fn get_doc_state(&self, object: &CollabObject) -> Result<DataSource, Error> {
+    self.inner.get_doc_state(object).await
+  }
+
+  async fn get_snapshots(&self, object_id: &str, limit: usize) -> Vec<RemoteCollabSnapshot> {
+    self.inner.get_snapshots(object_id, limit).await
+  }
+
+  async fn get_collab_state(&self, object_id: &str) -> Result<Option<RemoteCollabState>, Error> {
+    self.inner.get_collab_state(object_id).await
+  }
+
+  async fn create_snapshot(&self, object: &CollabObject, snapshot: Vec<u8>) -> Result<i64, Error> {
+    self.inner.create_snapshot(object, snapshot).await
+  }
+
+  async fn send_update(
+    &self,
+    object: &CollabObject,
+    id: u64,
+    update: Vec<u8>,
+  ) -> Result<(), Error> {
+    self.inner.send_update(object, id, update).await
+  }
+
+  async fn send_init_sync(
+    &self,
+    object: &CollabObject,
+    id: u64,
+    init_update: Vec<u8>,
+  ) -> Result<(), Error> {
+    self.inner.send_init_sync(object, id, init_update).await
+  }
+
+  async fn init_sync(
+    &self,
+    _object: &CollabObject,
+    init_sync: InitSync,
+  ) -> Result<ServerInit, Error> {
+    let server_init = self
+      .inner
+      .with_collab(|collab| handle_init_sync(&collab.transact(), &init_sync))?;
+    self
+      .server_init_payloads
+      .lock()
+      .unwrap()
+      .push(server_init.payload.len());
+    Ok(server_init)
+  }
+
+  fn subscribe_remote_updates(&self, object: &CollabObject) -> Option<RemoteUpdateReceiver> {
+    self.inner.subscribe_remote_updates(object)
+  }
+}
@@ -0,0 +1,180 @@
+use crate::disk::util::rocks_db;
+use collab::preclude::{Doc, GetString, Text, Transact};
+use collab_plugins::local_storage::kv::doc::CollabKVAction;
+use collab_plugins::local_storage::kv::snapshot::SnapshotAction;
+use collab_plugins::local_storage::kv::{KVTransactionDB, PersistenceError};
+use collab_plugins::local_storage::rocksdb::backup::{
+  backup_collab_db, restore_collab_db, FileBackup,
+};
+use collab_plugins::CollabKVDB;
+use tempfile::TempDir;
+
+fn create_doc(db: &CollabKVDB, uid: i64, object_id: &str, content: &str) -> Doc {
+  let doc = Doc::new();
+  db.with_write_txn(|store| store.create_new_doc(uid, object_id, &doc.transact()))
+    .unwrap();
+  edit_doc(db, &doc, uid, object_id, |text, txn| {
+    text.push(txn, content)
+  });
+  doc
+}
+
+fn edit_doc(
+  db: &CollabKVDB,
+  doc: &Doc,
+  uid: i64,
+  object_id: &str,
+  f: impl FnOnce(&collab::preclude::TextRef, &mut collab::preclude::TransactionMut),
+) {
+  let text = doc.get_or_insert_text("text");
+  let mut txn = doc.transact_mut();
+  f(&text, &mut txn);
+  let update = txn.encode_update_v1();
+  db.with_write_txn(|store| store.push_update(uid, object_id, &update))
+    .unwrap();
+}
+
+fn load_text(db: &CollabKVDB, uid: i64, object_id: &str) -> String {
+  let doc = Doc::new();
+  db.read_txn().load_doc(uid, object_id, doc.clone()).unwrap();
+  let text = doc.get_or_insert_text("text");
+  let txn = doc.transact();
+  text.get_string(&txn)
+}
+
+#[test]
+fn backup_and_restore_test() {
+  let (_path, db) = rocks_db();
+  let doc_1 = create_doc(&db, 1, "1", "hello");
+  create_doc(&db, 1, "2", "world");
+  create_doc(&db, 2, "3", "other user");
+  db.with_write_txn(|store| store.create_snapshot_with_data(2, "3", vec![1, 2, 3]))
+    .unwrap();
+
+  let dir = TempDir::new().unwrap();
+  let backup = FileBackup::new(dir.path());
+  let mut progress = vec![];
+  let manifest = backup_collab_db(&db, 1, &backup, None, |p| progress.push(p.clone())).unwrap();
+  backup.save_manifest(&manifest).unwrap();
+  assert_eq!(manifest.docs.len(), 2);
+  assert_eq!(progress.len(), 2);
+  assert_eq!(progress[1].processed, 2);
+  assert_eq!(progress[1].total, 2);
+
+  // Restore into a new database
+  let (_restore_path, restored) = rocks_db();
+  let manifest = backup.load_manifest(1).unwrap().unwrap();
+  let count = restore_collab_db(&restored, &backup, &manifest, |_| {}).unwrap();
+  assert_eq!(count, 2);
+  assert_eq!(load_text(&restored, 1, "1"), "hello");
+  assert_eq!(load_text(&restored, 1, "2"), "world");
+  assert!(!restored.read_txn().is_exist(2, "3"));
+
+  // Restore into the backed up database, which was changed since the backup
+  edit_doc(&db, &doc_1, 1, "1", |text, txn| text.push(txn, " world"));
+  create_doc(&db, 1, "5", "not in the backup");
+  restore_collab_db(&db, &backup, &manifest, |_| {}).unwrap();
+  assert_eq!(load_text(&db, 1, "1"), "hello");
+  assert_eq!(load_text(&db, 1, "2"), "world");
+  assert_eq!(db.read_txn().number_of_updates(1, "1"), 0);
+  // The data that is not in the backup is left untouched
+  assert_eq!(load_text(&db, 1, "5"), "not in the backup");
+  assert_eq!(load_text(&db, 2, "3"), "other user");
+  assert_eq!(db.read_txn().get_snapshots(2, "3").len(), 1);
+}
+
+#[test]
+fn incremental_backup_test() {
+  let (_path, db) = rocks_db();
+  let doc_1 = create_doc(&db, 1, "1", "hello");
+  let doc_2 = create_doc(&db, 1, "2", "world");
+  create_doc(&db, 1, "3", "unchanged");
+
+  let dir = TempDir::new().unwrap();
+  let backup = FileBackup::new(dir.path());
+  let base = backup_collab_db(&db, 1, &backup, None, |_| {}).unwrap();
+  backup.save_manifest(&base).unwrap();
+
+  // Insert into the document 1 and only delete from the document 2, which doesn't change its
+  // state vector.
+  edit_doc(&db, &doc_1, 1, "1", |text, txn| text.push(txn, " world"));
+  edit_doc(&db, &doc_2, 1, "2", |text, txn| {
+    text.remove_range(txn, 0, 1)
+  });
+  create_doc(&db, 1, "4", "new");
+
+  let mut saved = vec![];
+  let manifest = backup_collab_db(&db, 1, &backup, Some(&base), |p| {
+    if p.saved {
+      saved.push(p.object_id.clone())
+    }
+  })
+  .unwrap();
+  backup.save_manifest(&manifest).unwrap();
+  assert_eq!(saved, vec!["1", "2", "4"]);
+  assert!(manifest.created_at > base.created_at);
+  // The unchanged document is referenced from the base generation
+  assert_eq!(manifest.docs["3"].created_at, base.created_at);
+  assert_eq!(manifest.docs["1"].created_at, manifest.created_at);
+
+  let (_restore_path, restored) = rocks_db();
+  restore_collab_db(&restored, &backup, &manifest, |_| {}).unwrap();
+  assert_eq!(load_text(&restored, 1, "1"), "hello world");
+  assert_eq!(load_text(&restored, 1, "2"), "orld");
+  assert_eq!(load_text(&restored, 1, "3"), "unchanged");
+  assert_eq!(load_text(&restored, 1, "4"), "new");
+
+  // The base backup is still restorable, the incremental backup didn't overwrite it
+  assert_eq!(backup.load_manifest(1).unwrap().unwrap(), manifest);
+  let base = backup
+    .load_generation_manifest(1, base.created_at)
+    .unwrap()
+    .unwrap();
+  restore_collab_db(&restored, &backup, &base, |_| {}).unwrap();
+  assert_eq!(load_text(&restored, 1, "1"), "hello");
+  assert_eq!(load_text(&restored, 1, "2"), "world");
+  assert_eq!(load_text(&restored, 1, "3"), "unchanged");
+  assert_eq!(load_text(&restored, 1, "4"), "new");
+}
+
+#[test]
+fn failed_restore_leaves_db_untouched_test() {
+  let (_path, db) = rocks_db();
+  let doc_1 = create_doc(&db, 1, "1", "hello");
+  create_doc(&db, 1, "2", "world");
+
+  let dir = TempDir::new().unwrap();
+  let backup = FileBackup::new(dir.path());
+  let mut manifest = backup_collab_db(&db, 1, &backup, None, |_| {}).unwrap();
+  edit_doc(&db, &doc_1, 1, "1", |text, txn| text.push(txn, " world"));
+
+  // The document 2 doesn't match the manifest anymore, so nothing is restored.
+  manifest.docs.get_mut("2").unwrap().checksum = Some(0);
+  let err = restore_collab_db(&db, &backup, &manifest, |_| {}).unwrap_err();
+  assert!(matches!(err, PersistenceError::InvalidData(_)));
+  assert_eq!(load_text(&db, 1, "1"), "hello world");
+}
+
+#[test]
+fn backup_reads_a_snapshot_test() {
+  let (_path, db) = rocks_db();
+  create_doc(&db, 1, "1", "hello");
+  create_doc(&db, 1, "2", "world");
+
+  // The document 2 is edited while the backup is running, after the document 1 was saved.
+  let dir = TempDir::new().unwrap();
+  let backup = FileBackup::new(dir.path());
+  let manifest = backup_collab_db(&db, 1, &backup, None, |p| {
+    if p.object_id == "1" {
+      let doc = Doc::new();
+      db.read_txn().load_doc(1, "2", doc.clone()).unwrap();
+      edit_doc(&db, &doc, 1, "2", |text, txn| text.push(txn, "!"));
+    }
+  })
+  .unwrap();
+  assert_eq!(load_text(&db, 1, "2"), "world!");
+
+  let (_restore_path, restored) = rocks_db();
+  restore_collab_db(&restored, &backup, &manifest, |_| {}).unwrap();
+  assert_eq!(load_text(&restored, 1, "2"), "world");
+}
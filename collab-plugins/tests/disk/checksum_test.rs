@@ -0,0 +1,55 @@
+use crate::disk::util::rocks_db;
+use collab::error::CollabError;
+use collab::preclude::{Doc, Map, Transact};
+use collab_plugins::local_storage::kv::doc::CollabKVAction;
+use collab_plugins::local_storage::kv::keys::{make_doc_id_key, make_doc_state_key};
+use collab_plugins::local_storage::kv::snapshot::CollabSnapshot;
+use collab_plugins::local_storage::kv::{
+  get_id_for_key, KVStore, KVTransactionDB, PersistenceError,
+};
+
+#[tokio::test]
+async fn load_doc_with_corrupted_doc_state_test() {
+  let (_path, db) = rocks_db();
+  let uid = 1;
+  let object_id = "1";
+
+  let doc = Doc::new();
+  let map = doc.get_or_insert_map("data");
+  map.insert(&mut doc.transact_mut(), "1", "a");
+  db.with_write_txn(|store| store.create_new_doc(uid, object_id, &doc.transact()))
+    .unwrap();
+
+  // Loading the document with valid doc state should succeed.
+  db.read_txn().load_doc(uid, object_id, Doc::new()).unwrap();
+
+  // Overwrite the doc state without updating the checksum
+  let doc_id_key = make_doc_id_key(&uid.to_be_bytes(), object_id.as_bytes());
+  let doc_id = get_id_for_key(&db.read_txn(), doc_id_key).unwrap();
+  db.with_write_txn(|store| store.insert(make_doc_state_key(doc_id), [1, 2, 3]))
+    .unwrap();
+
+  let err = db
+    .read_txn()
+    .load_doc(uid, object_id, Doc::new())
+    .unwrap_err();
+  assert!(matches!(
+    err,
+    PersistenceError::Collab(CollabError::ChecksumMismatch { .. })
+  ));
+}
+
+#[test]
+fn decode_corrupted_snapshot_test() {
+  let mut snapshot = CollabSnapshot::new(vec![1, 2, 3]);
+  assert!(CollabSnapshot::try_from(snapshot.to_vec().as_slice()).is_ok());
+
+  snapshot.data = vec![1, 2, 4];
+  let result = CollabSnapshot::try_from(snapshot.to_vec().as_slice());
+  assert!(matches!(
+    result,
+    Err(PersistenceError::Collab(
+      CollabError::ChecksumMismatch { .. }
+    ))
+  ));
+}
@@ -0,0 +1,120 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::disk::util::rocks_db;
+use collab::core::collab::DataSource;
+use collab::core::origin::CollabOrigin;
+use collab::preclude::{Collab, CollabBuilder};
+use collab_entity::CollabType;
+use collab_plugins::local_storage::kv::doc::CollabKVAction;
+use collab_plugins::local_storage::kv::KVTransactionDB;
+use collab_plugins::local_storage::rocksdb::rocksdb_plugin::RocksdbDiskPlugin;
+use collab_plugins::local_storage::{CollabPersistenceConfig, CompactionPolicy};
+use collab_plugins::CollabKVDB;
+
+fn open_collab(
+  db: &Arc<CollabKVDB>,
+  object_id: &str,
+  policy: CompactionPolicy,
+) -> (Collab, RocksdbDiskPlugin) {
+  let plugin = RocksdbDiskPlugin::new_with_config(
+    1,
+    object_id.to_string(),
+    CollabType::Document,
+    Arc::downgrade(db),
+    CollabPersistenceConfig::new().compaction(policy),
+  );
+  let mut collab = CollabBuilder::new(1, object_id, DataSource::Disk(None))
+    .with_device_id("1")
+    .with_plugin(plugin.clone())
+    .build()
+    .unwrap();
+  collab.initialize();
+  (collab, plugin)
+}
+
+fn load_collab(db: &CollabKVDB, object_id: &str) -> Collab {
+  let mut collab = Collab::new_with_origin(CollabOrigin::Empty, object_id, vec![], false);
+  db.read_txn()
+    .load_doc_with_txn(1, object_id, &mut collab.transact_mut())
+    .unwrap();
+  collab
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn compact_every_n_updates_test() {
+  let (_path, db) = rocks_db();
+  let db = Arc::new(db);
+  let (mut collab, plugin) = open_collab(&db, "1", CompactionPolicy::new().max_updates(5));
+  for i in 0..12 {
+    collab.insert(&i.to_string(), i);
+    collab.flush_plugins().await;
+  }
+
+  let stats = plugin.compaction_stats();
+  assert_eq!(stats.compaction_count, 2);
+  assert_eq!(stats.compacted_updates, 10);
+  assert_eq!(stats.number_of_updates, 2);
+
+  let restored = load_collab(&db, "1");
+  for i in 0..12 {
+    assert_eq!(restored.get::<i64>(&i.to_string()).unwrap(), i);
+  }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn compact_by_update_bytes_test() {
+  let (_path, db) = rocks_db();
+  let db = Arc::new(db);
+  let (mut collab, plugin) = open_collab(&db, "1", CompactionPolicy::new().max_update_bytes(1024));
+  let text = "a".repeat(600);
+  collab.insert("1", text.clone());
+  collab.flush_plugins().await;
+  assert_eq!(plugin.compaction_stats().compaction_count, 0);
+
+  collab.insert("2", text.clone());
+  collab.flush_plugins().await;
+  let stats = plugin.compaction_stats();
+  assert_eq!(stats.compaction_count, 1);
+  assert_eq!(stats.number_of_updates, 0);
+
+  let restored = load_collab(&db, "1");
+  assert_eq!(restored.get::<String>("2").unwrap(), text);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn compact_after_idle_timeout_test() {
+  let (_path, db) = rocks_db();
+  let db = Arc::new(db);
+  let (mut collab, plugin) = open_collab(
+    &db,
+    "1",
+    CompactionPolicy::new().idle_timeout(Duration::from_millis(50)),
+  );
+  for i in 0..3 {
+    collab.insert(&i.to_string(), i);
+  }
+  assert_eq!(plugin.compaction_stats().number_of_updates, 3);
+
+  tokio::time::sleep(Duration::from_millis(300)).await;
+  collab.flush_plugins().await;
+  let stats = plugin.compaction_stats();
+  assert_eq!(stats.compaction_count, 1);
+  assert_eq!(stats.number_of_updates, 0);
+
+  let restored = load_collab(&db, "1");
+  assert_eq!(restored.get::<i64>("2").unwrap(), 2);
+}
+
+#[tokio::test]
+async fn compaction_is_disabled_by_default_test() {
+  let (_path, db) = rocks_db();
+  let db = Arc::new(db);
+  let (mut collab, plugin) = open_collab(&db, "1", CompactionPolicy::default());
+  for i in 0..10 {
+    collab.insert(&i.to_string(), i);
+  }
+  let stats = plugin.compaction_stats();
+  assert_eq!(stats.compaction_count, 0);
+  assert_eq!(stats.number_of_updates, 10);
+}
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::disk::util::rocks_db;
+use collab::preclude::{Doc, GetString, Text, Transact};
+use collab_plugins::local_storage::kv::doc::CollabKVAction;
+use collab_plugins::local_storage::kv::encryption::{
+  EncryptedKVTransactionDB, EncryptionKey, EncryptionKeyProvider,
+};
+use collab_plugins::local_storage::kv::keys::{
+  make_doc_id_key, make_doc_state_key, make_outbox_msg_key,
+};
+use collab_plugins::local_storage::kv::outbox::{OutboxAction, OutboxMsg};
+use collab_plugins::local_storage::kv::snapshot::SnapshotAction;
+use collab_plugins::local_storage::kv::{
+  get_id_for_key, KVStore, KVTransactionDB, PersistenceError,
+};
+
+#[derive(Default)]
+struct KeyProvider {
+  keys: Mutex<HashMap<String, EncryptionKey>>,
+}
+
+impl EncryptionKeyProvider for KeyProvider {
+  fn workspace_key(&self, workspace_id: &str) -> Result<EncryptionKey, PersistenceError> {
+    let mut keys = self.keys.lock().unwrap();
+    let key = keys
+      .entry(workspace_id.to_string())
+      .or_insert_with(EncryptionKey::generate);
+    Ok(key.clone())
+  }
+}
+
+fn create_doc<DB: KVTransactionDB>(db: &DB, object_id: &str, content: &str) {
+  let doc = Doc::new();
+  let text = doc.get_or_insert_text("text");
+  db.with_write_txn(|store| store.create_new_doc(1, object_id, &doc.transact()))
+    .unwrap();
+  let mut txn = doc.transact_mut();
+  text.push(&mut txn, content);
+  let update = txn.encode_update_v1();
+  db.with_write_txn(|store| store.push_update(1, object_id, &update))
+    .unwrap();
+}
+
+fn load_text<DB: KVTransactionDB>(db: &DB, object_id: &str) -> Result<String, PersistenceError> {
+  let doc = Doc::new();
+  db.read_txn().load_doc(1, object_id, doc.clone())?;
+  let text = doc.get_or_insert_text("text");
+  let txn = doc.transact();
+  Ok(text.get_string(&txn))
+}
+
+#[test]
+fn encrypted_doc_test() {
+  let (_path, db) = rocks_db();
+  let provider = KeyProvider::default();
+  let workspace_a = EncryptedKVTransactionDB::with_provider(db.clone(), "a", &provider).unwrap();
+  create_doc(&workspace_a, "1", "secret text");
+  workspace_a
+    .with_write_txn(|store| store.create_snapshot_with_data(1, "1", b"secret snapshot".to_vec()))
+    .unwrap();
+
+  assert_eq!(load_text(&workspace_a, "1").unwrap(), "secret text");
+  assert_eq!(workspace_a.read_txn().number_of_updates(1, "1"), 1);
+  assert_eq!(
+    workspace_a
+      .read_txn()
+      .get_last_snapshot(1, "1")
+      .unwrap()
+      .data,
+    b"secret snapshot"
+  );
+
+  // The doc state is not stored in plain text
+  let doc_id_key = make_doc_id_key(&1_i64.to_be_bytes(), b"1");
+  let doc_id = get_id_for_key(&db.read_txn(), doc_id_key).unwrap();
+  let raw_doc_state = db
+    .read_txn()
+    .get(make_doc_state_key(doc_id))
+    .unwrap()
+    .unwrap();
+  let plain_doc_state = workspace_a
+    .read_txn()
+    .get(make_doc_state_key(doc_id))
+    .unwrap()
+    .unwrap();
+  assert_ne!(raw_doc_state, plain_doc_state);
+
+  // The document can't be read without the key of its workspace
+  assert!(db.read_txn().is_exist(1, "1"));
+  assert!(matches!(
+    load_text(&db, "1").unwrap_err(),
+    PersistenceError::InvalidData(_)
+  ));
+  assert!(matches!(
+    EncryptedKVTransactionDB::new(db.clone(), "a", &EncryptionKey::generate()),
+    Err(PersistenceError::Encryption(_))
+  ));
+
+  // The same workspace key reads the document again
+  let workspace_a = EncryptedKVTransactionDB::with_provider(db, "a", &provider).unwrap();
+  assert_eq!(load_text(&workspace_a, "1").unwrap(), "secret text");
+}
+
+#[test]
+fn encrypted_workspaces_in_one_db_test() {
+  let (_path, db) = rocks_db();
+  let provider = KeyProvider::default();
+  let workspace_a = EncryptedKVTransactionDB::with_provider(db.clone(), "a", &provider).unwrap();
+  let workspace_b = EncryptedKVTransactionDB::with_provider(db.clone(), "b", &provider).unwrap();
+  create_doc(&workspace_a, "1", "text of a");
+  create_doc(&workspace_b, "2", "text of b");
+  for (workspace, object_id) in [(&workspace_a, "1"), (&workspace_b, "2")] {
+    let msg = OutboxMsg::new(1, vec![object_id.as_bytes().to_vec()]);
+    workspace
+      .with_write_txn(|store| store.push_outbox_msg(1, object_id, &msg))
+      .unwrap();
+  }
+
+  // Each workspace reads its own documents with its own key
+  assert_eq!(load_text(&workspace_a, "1").unwrap(), "text of a");
+  assert_eq!(load_text(&workspace_b, "2").unwrap(), "text of b");
+  assert!(matches!(
+    load_text(&workspace_a, "2").unwrap_err(),
+    PersistenceError::Encryption(_)
+  ));
+  assert!(matches!(
+    load_text(&workspace_b, "1").unwrap_err(),
+    PersistenceError::Encryption(_)
+  ));
+
+  // The range scans only see the values of the workspace
+  let unsynced_a = workspace_a.read_txn().unsynced_changes(1).unwrap();
+  assert_eq!(unsynced_a.keys().collect::<Vec<_>>(), vec!["1"]);
+  let unsynced_b = workspace_b.read_txn().unsynced_changes(1).unwrap();
+  assert_eq!(unsynced_b.keys().collect::<Vec<_>>(), vec!["2"]);
+
+  // Each workspace checks its own key
+  assert!(matches!(
+    EncryptedKVTransactionDB::new(db.clone(), "b", &EncryptionKey::generate()),
+    Err(PersistenceError::Encryption(_))
+  ));
+  let workspace_b = EncryptedKVTransactionDB::with_provider(db, "b", &provider).unwrap();
+  assert_eq!(load_text(&workspace_b, "2").unwrap(), "text of b");
+}
+
+#[test]
+fn migrate_plaintext_doc_test() {
+  let (_path, db) = rocks_db();
+  create_doc(&db, "1", "plain text");
+  create_doc(&db, "2", "other plain text");
+  db.with_write_txn(|store| store.create_snapshot_with_data(1, "1", b"plain snapshot".to_vec()))
+    .unwrap();
+  let msg = OutboxMsg::new(1, vec![b"plain update".to_vec()]);
+  db.with_write_txn(|store| store.push_outbox_msg(1, "1", &msg))
+    .unwrap();
+
+  let encrypted =
+    EncryptedKVTransactionDB::new(db.clone(), "w1", &EncryptionKey::generate()).unwrap();
+  assert!(matches!(
+    load_text(&encrypted, "1").unwrap_err(),
+    PersistenceError::Encryption(_)
+  ));
+
+  assert!(encrypted.migrate_plaintext_doc(1, "1").unwrap() > 0);
+  assert_eq!(load_text(&encrypted, "1").unwrap(), "plain text");
+  assert_eq!(
+    encrypted.read_txn().get_last_snapshot(1, "1").unwrap().data,
+    b"plain snapshot"
+  );
+  assert_eq!(encrypted.read_txn().get_outbox_msgs(1, "1"), vec![msg]);
+  assert!(matches!(
+    load_text(&db, "1").unwrap_err(),
+    PersistenceError::InvalidData(_)
+  ));
+
+  // The migration only encrypts the values of the document once
+  assert_eq!(encrypted.migrate_plaintext_doc(1, "1").unwrap(), 0);
+  assert_eq!(load_text(&db, "2").unwrap(), "other plain text");
+
+  assert!(encrypted.migrate_plaintext().unwrap() > 0);
+  assert_eq!(load_text(&encrypted, "2").unwrap(), "other plain text");
+  assert_eq!(encrypted.migrate_plaintext().unwrap(), 0);
+}
+
+#[test]
+fn encrypted_value_bound_to_key_test() {
+  let (_path, db) = rocks_db();
+  let encrypted =
+    EncryptedKVTransactionDB::new(db.clone(), "w1", &EncryptionKey::generate()).unwrap();
+  create_doc(&encrypted, "1", "hello");
+  let doc_id_key = make_doc_id_key(&1_i64.to_be_bytes(), b"1");
+  let doc_id = get_id_for_key(&db.read_txn(), doc_id_key).unwrap();
+
+  // Move the encrypted doc state of the document to the doc state key of another document
+  let raw_doc_state = db
+    .read_txn()
+    .get(make_doc_state_key(doc_id))
+    .unwrap()
+    .unwrap();
+  db.with_write_txn(|store| store.insert(make_doc_state_key(doc_id + 1), raw_doc_state))
+    .unwrap();
+  assert!(matches!(
+    encrypted.read_txn().get(make_doc_state_key(doc_id + 1)),
+    Err(PersistenceError::Encryption(_))
+  ));
+}
+
+#[test]
+fn encrypted_outbox_msg_test() {
+  let (_path, db) = rocks_db();
+  let encrypted =
+    EncryptedKVTransactionDB::new(db.clone(), "w1", &EncryptionKey::generate()).unwrap();
+  let msg = OutboxMsg::new(1, vec![b"secret update".to_vec()]);
+  encrypted
+    .with_write_txn(|store| store.push_outbox_msg(1, "1", &msg))
+    .unwrap();
+  assert_eq!(
+    encrypted.read_txn().get_outbox_msgs(1, "1"),
+    vec![msg.clone()]
+  );
+  assert_eq!(encrypted.read_txn().number_of_unsynced_changes(1, "1"), 1);
+
+  // The outbox message is not stored in plain text
+  let raw_msg = db
+    .read_txn()
+    .get(make_outbox_msg_key(&1_i64.to_be_bytes(), b"1", 1))
+    .unwrap()
+    .unwrap();
+  assert_ne!(raw_msg, msg.to_vec());
+  assert!(!raw_msg
+    .windows(b"secret update".len())
+    .any(|window| window == b"secret update"));
+}
@@ -78,7 +78,13 @@ async fn flush_test() {
     .unwrap();
   write_txn.commit_transaction().unwrap();
 
-  let after_flush_updates = read.get_all_updates(test.uid, &doc_id).unwrap();
+  // The read transaction keeps reading the snapshot it was created with
+  assert_eq!(read.get_all_updates(test.uid, &doc_id).unwrap().len(), 100);
+  let after_flush_updates = test
+    .db
+    .read_txn()
+    .get_all_updates(test.uid, &doc_id)
+    .unwrap();
 
   let after_flush_value = collab.to_json_value();
   assert_eq!(before_flush_updates.len(), 100);
@@ -0,0 +1,484 @@
+//! The conformance suite of the [KVTransactionDB] implementations.
+//!
+//! Every backend runs the same tests, so they all behave the same way as the RocksDB
+//! implementation that the key layout in [keys] was designed for. A new backend is verified by
+//! adding one line at the bottom of this file:
+//!
+//! - `kv_conformance_tests!(name, DBType, open = |path: &Path| ...)` for the backends that persist
+//!   the data. The database is opened in a temporary directory, and the crash-consistency tests
+//!   reopen it from the same directory.
+//! - `kv_conformance_tests!(name, DBType, new = ...)` for the backends that keep the data in
+//!   memory. The crash-consistency tests are skipped.
+//!
+//! The IndexedDB storage doesn't implement [KVTransactionDB] because its API is async. Its tests
+//! live in `tests/web`.
+use std::path::Path;
+use std::sync::Arc;
+
+use collab::preclude::{Doc, GetString, ReadTxn, StateVector, Text, Transact};
+use collab_plugins::local_storage::kv::doc::CollabKVAction;
+use collab_plugins::local_storage::kv::keys::*;
+use collab_plugins::local_storage::kv::snapshot::SnapshotAction;
+use collab_plugins::local_storage::kv::{
+  get_id_for_key, KVEntry, KVStore, KVTransactionDB, PersistenceError,
+};
+use collab_plugins::local_storage::memory::kv_impl::KVTransactionDBMemoryImpl;
+use collab_plugins::CollabKVDB;
+use tempfile::TempDir;
+use yrs::updates::encoder::Encode;
+
+macro_rules! kv_conformance_tests {
+  ($backend:ident, $db:ty, open = $open:expr) => {
+    mod $backend {
+      use super::*;
+
+      type DB = $db;
+
+      fn open_db(path: &Path) -> DB {
+        let open: fn(&Path) -> DB = $open;
+        open(path)
+      }
+
+      fn make_db() -> (Option<TempDir>, DB) {
+        let dir = TempDir::new().unwrap();
+        let db = open_db(dir.path());
+        (Some(dir), db)
+      }
+
+      kv_conformance_tests!(@common);
+      kv_conformance_tests!(@crash);
+    }
+  };
+  ($backend:ident, $db:ty, new = $new:expr) => {
+    mod $backend {
+      use super::*;
+
+      type DB = $db;
+
+      fn make_db() -> (Option<TempDir>, DB) {
+        (None, $new)
+      }
+
+      kv_conformance_tests!(@common);
+    }
+  };
+  (@common) => {
+    /// Create a document with the given text and push each character as an update.
+    fn create_doc(db: &DB, object_id: &str, content: &str) -> Doc {
+      let doc = Doc::new();
+      db.with_write_txn(|store| store.create_new_doc(1, object_id, &doc.transact()))
+        .unwrap();
+      push_text(db, &doc, object_id, content);
+      doc
+    }
+
+    fn push_text(db: &DB, doc: &Doc, object_id: &str, content: &str) {
+      let text = doc.get_or_insert_text("text");
+      for c in content.chars() {
+        let mut txn = doc.transact_mut();
+        text.push(&mut txn, &c.to_string());
+        let update = txn.encode_update_v1();
+        db.with_write_txn(|store| store.push_update(1, object_id, &update))
+          .unwrap();
+      }
+    }
+
+    fn load_text(db: &DB, object_id: &str) -> String {
+      let doc = Doc::new();
+      db.read_txn().load_doc(1, object_id, doc.clone()).unwrap();
+      let text = doc.get_or_insert_text("text");
+      let txn = doc.transact();
+      text.get_string(&txn)
+    }
+
+    fn doc_id(db: &DB, object_id: &str) -> DocID {
+      let key = make_doc_id_key(&1_i64.to_be_bytes(), object_id.as_bytes());
+      get_id_for_key(&db.read_txn(), key).unwrap()
+    }
+
+    fn keys<'a, S: KVStore<'a>>(store: &S, from: &[u8], to: &[u8]) -> Vec<Vec<u8>> {
+      store
+        .range(from..to)
+        .unwrap()
+        .map(|entry| entry.key().to_vec())
+        .collect()
+    }
+
+    #[test]
+    fn create_and_load_doc_test() {
+      let (_dir, db) = make_db();
+      create_doc(&db, "1", "hello");
+      create_doc(&db, "2", "world");
+      assert!(db.read_txn().is_exist(1, "1"));
+      assert!(!db.read_txn().is_exist(2, "1"));
+      assert_eq!(db.read_txn().number_of_updates(1, "1"), 5);
+      assert_eq!(load_text(&db, "1"), "hello");
+      assert_eq!(load_text(&db, "2"), "world");
+
+      let err = db
+        .with_write_txn(|store| store.create_new_doc(1, "1", &Doc::new().transact()))
+        .unwrap_err();
+      assert!(matches!(err, PersistenceError::DocumentAlreadyExist));
+
+      let mut oids = db.read_txn().get_all_docs().unwrap().collect::<Vec<_>>();
+      oids.sort();
+      assert_eq!(oids, vec!["1", "2"]);
+    }
+
+    #[test]
+    fn flush_doc_test() {
+      let (_dir, db) = make_db();
+      let doc = create_doc(&db, "1", "hello");
+      {
+        let txn = doc.transact();
+        let doc_state = txn.encode_state_as_update_v1(&StateVector::default());
+        let sv = txn.state_vector().encode_v1();
+        db.with_write_txn(|store| store.flush_doc(1, "1", sv, doc_state))
+          .unwrap();
+      }
+      assert_eq!(db.read_txn().number_of_updates(1, "1"), 0);
+      assert_eq!(load_text(&db, "1"), "hello");
+
+      // The updates pushed after the flush are applied on top of the flushed doc state
+      push_text(&db, &doc, "1", " world");
+      assert_eq!(db.read_txn().number_of_updates(1, "1"), 6);
+      assert_eq!(load_text(&db, "1"), "hello world");
+    }
+
+    #[test]
+    fn delete_doc_test() {
+      let (_dir, db) = make_db();
+      create_doc(&db, "1", "hello");
+      create_doc(&db, "2", "world");
+      db.with_write_txn(|store| store.delete_doc(1, "1")).unwrap();
+      assert!(!db.read_txn().is_exist(1, "1"));
+      assert_eq!(db.read_txn().number_of_updates(1, "1"), 0);
+      assert!(db
+        .read_txn()
+        .load_doc(1, "1", Doc::new())
+        .unwrap_err()
+        .is_record_not_found());
+
+      // The other documents are not affected
+      assert_eq!(load_text(&db, "2"), "world");
+    }
+
+    #[test]
+    fn snapshot_test() {
+      let (_dir, db) = make_db();
+      create_doc(&db, "1", "hello");
+      for i in 0..3u8 {
+        db.with_write_txn(|store| store.create_snapshot_with_data(1, "1", vec![i; 4]))
+          .unwrap();
+      }
+      let snapshots = db.read_txn().get_snapshots(1, "1");
+      assert_eq!(snapshots.len(), 3);
+      assert_eq!(snapshots[2].data, vec![2; 4]);
+      assert_eq!(
+        db.read_txn().get_last_snapshot(1, "1").unwrap().data,
+        vec![2; 4]
+      );
+
+      db.with_write_txn(|store| store.delete_all_snapshots(1, "1"))
+        .unwrap();
+      assert!(db.read_txn().get_snapshots(1, "1").is_empty());
+    }
+
+    #[test]
+    fn uncommitted_changes_test() {
+      let (_dir, db) = make_db();
+      // The changes of a failed transaction are discarded
+      let result = db.with_write_txn(|store| {
+        store.insert([1], [1])?;
+        Err::<(), _>(PersistenceError::UnexpectedEmptyUpdates)
+      });
+      assert!(result.is_err());
+      assert_eq!(db.read_txn().get([1]).unwrap(), None);
+
+      db.with_write_txn(|store| {
+        store.insert([1], [1])?;
+        // A transaction reads its own changes
+        assert_eq!(store.get([1])?, Some(vec![1]));
+        Ok(())
+      })
+      .unwrap();
+      assert_eq!(db.read_txn().get([1]).unwrap(), Some(vec![1]));
+    }
+
+    #[test]
+    fn range_test() {
+      let (_dir, db) = make_db();
+      db.with_write_txn(|store| {
+        for i in 0..10u8 {
+          store.insert([i], [i])?;
+        }
+        store.remove_range(&[2], &[4])?;
+        Ok(())
+      })
+      .unwrap();
+
+      let txn = db.read_txn();
+      let keys = |from: u8, to: u8| {
+        txn
+          .range([from]..[to])
+          .unwrap()
+          .map(|entry| entry.key()[0])
+          .collect::<Vec<_>>()
+      };
+      assert_eq!(keys(0, 6), vec![0, 1, 4, 5]);
+      assert_eq!(keys(8, 30), vec![8, 9]);
+      assert!(keys(5, 5).is_empty());
+
+      // The upper bound is excluded even if the range is inclusive
+      let inclusive = txn
+        .range([0]..=[1])
+        .unwrap()
+        .map(|entry| entry.key()[0])
+        .collect::<Vec<_>>();
+      assert_eq!(inclusive, vec![0]);
+
+      assert_eq!(txn.next_back_entry(&[3]).unwrap().unwrap().key(), &[1]);
+      assert_eq!(txn.next_back_entry(&[7]).unwrap().unwrap().value(), &[7]);
+      assert_eq!(txn.next_back_entry(&[30]).unwrap().unwrap().key(), &[9]);
+    }
+
+    #[test]
+    fn hi_watermark_range_test() {
+      let (_dir, db) = make_db();
+      let hi = TERMINATOR_HI_WATERMARK;
+      db.with_write_txn(|store| {
+        for key in [vec![0, hi - 1], vec![0, hi], vec![0, hi, hi], vec![1, 0]] {
+          store.insert(&key, &key)?;
+        }
+        Ok(())
+      })
+      .unwrap();
+
+      let txn = db.read_txn();
+      assert_eq!(keys(&txn, &[0], &[0, hi]), vec![vec![0, hi - 1]]);
+      assert_eq!(
+        keys(&txn, &[0, hi], &[1]),
+        vec![vec![0, hi], vec![0, hi, hi]]
+      );
+      assert_eq!(
+        keys(&txn, &[0, hi, hi], &[1, 0]),
+        vec![vec![0, hi, hi]]
+      );
+      assert_eq!(
+        txn.next_back_entry(&[0, hi, hi, hi]).unwrap().unwrap().key(),
+        &[0, hi, hi]
+      );
+      assert_eq!(
+        txn.next_back_entry(&[0, hi]).unwrap().unwrap().key(),
+        &[0, hi]
+      );
+      drop(txn);
+
+      db.with_write_txn(|store| store.remove_range(&[0], &[0, hi]))
+        .unwrap();
+      assert_eq!(
+        keys(&db.read_txn(), &[0], &[2]),
+        vec![vec![0, hi], vec![0, hi, hi], vec![1, 0]]
+      );
+    }
+
+    #[test]
+    fn doc_key_space_bounds_test() {
+      let (_dir, db) = make_db();
+      create_doc(&db, "1", "abc");
+      create_doc(&db, "2", "def");
+      let did = doc_id(&db, "1");
+      let start = make_doc_start_key(did);
+      let end = make_doc_end_key(did);
+
+      // The doc state, the state vector, the checksum and the updates of the document are all
+      // within [start..end).
+      let doc_keys = keys(&db.read_txn(), start.as_ref(), end.as_ref());
+      assert_eq!(doc_keys.len(), 6);
+      assert!(doc_keys.iter().all(|key| key[..10] == start.as_ref()[..10]));
+
+      // A key at the hi watermark is outside the key space of the document
+      db.with_write_txn(|store| store.insert(end.as_ref(), [1]))
+        .unwrap();
+      assert_eq!(
+        keys(&db.read_txn(), start.as_ref(), end.as_ref()).len(),
+        6
+      );
+      assert_eq!(
+        db.read_txn()
+          .next_back_entry(end.as_ref())
+          .unwrap()
+          .unwrap()
+          .key(),
+        end.as_ref()
+      );
+
+      db.with_write_txn(|store| store.delete_doc(1, "1")).unwrap();
+      assert!(keys(&db.read_txn(), start.as_ref(), end.as_ref()).is_empty());
+      assert_eq!(db.read_txn().get(end.as_ref()).unwrap(), Some(vec![1]));
+      assert_eq!(load_text(&db, "2"), "def");
+    }
+
+    #[test]
+    fn update_clock_crosses_byte_boundary_test() {
+      let (_dir, db) = make_db();
+      let content = "a".repeat(300);
+      create_doc(&db, "1", &content);
+      assert_eq!(db.read_txn().number_of_updates(1, "1"), 300);
+      assert_eq!(load_text(&db, "1"), content);
+
+      let did = doc_id(&db, "1");
+      let clocks = db
+        .read_txn()
+        .range(make_doc_update_key(did, 0).as_ref()..make_doc_update_key(did, Clock::MAX).as_ref())
+        .unwrap()
+        .map(|entry| {
+          let mut clock = [0; CLOCK_LEN];
+          clock.copy_from_slice(clock_from_key(entry.key()));
+          Clock::from_be_bytes(clock)
+        })
+        .collect::<Vec<_>>();
+      // The lowest byte of the clock wraps at least once
+      assert_eq!(clocks.len(), 300);
+      assert!(clocks.windows(2).all(|pair| pair[1] == pair[0] + 1));
+    }
+
+    #[test]
+    fn concurrent_write_test() {
+      let (_dir, db) = make_db();
+      let db = Arc::new(db);
+      let object_ids = (0..4).map(|i| i.to_string()).collect::<Vec<_>>();
+      std::thread::scope(|scope| {
+        for object_id in &object_ids {
+          let db = db.clone();
+          scope.spawn(move || {
+            create_doc(&db, object_id, &"x".repeat(20));
+          });
+        }
+
+        // Each transaction writes the same value to all the keys of its thread. A reader never
+        // sees the changes of a transaction partially.
+        for thread in 0..4u8 {
+          let db = db.clone();
+          scope.spawn(move || {
+            for i in 0..20u8 {
+              db.with_write_txn(|store| {
+                for key in 0..4u8 {
+                  store.insert([10, thread, key], [i])?;
+                }
+                Ok(())
+              })
+              .unwrap();
+            }
+          });
+        }
+        for thread in 0..4u8 {
+          let db = db.clone();
+          scope.spawn(move || {
+            for _ in 0..20 {
+              let values = db
+                .read_txn()
+                .range([10, thread].as_ref()..[10, thread + 1].as_ref())
+                .unwrap()
+                .map(|entry| entry.value().to_vec())
+                .collect::<Vec<_>>();
+              assert!(values.windows(2).all(|pair| pair[0] == pair[1]));
+            }
+          });
+        }
+      });
+
+      for object_id in &object_ids {
+        assert_eq!(db.read_txn().number_of_updates(1, object_id), 20);
+        assert_eq!(load_text(&db, object_id), "x".repeat(20));
+      }
+      for thread in 0..4u8 {
+        for key in 0..4u8 {
+          assert_eq!(db.read_txn().get([10, thread, key]).unwrap(), Some(vec![19]));
+        }
+      }
+    }
+  };
+  (@crash) => {
+    #[test]
+    fn reopen_test() {
+      let dir = TempDir::new().unwrap();
+      {
+        let db = open_db(dir.path());
+        create_doc(&db, "1", "hello");
+        db.flush().unwrap();
+      }
+      let db = open_db(dir.path());
+      assert_eq!(db.read_txn().number_of_updates(1, "1"), 5);
+      assert_eq!(load_text(&db, "1"), "hello");
+    }
+
+    #[test]
+    fn crash_without_flush_test() {
+      let dir = TempDir::new().unwrap();
+      {
+        let db = open_db(dir.path());
+        let doc = create_doc(&db, "1", "hello");
+
+        // A transaction that is never committed
+        let txn = db.write_txn();
+        txn.insert([10], [1]).unwrap();
+        txn.push_update(1, "1", &doc.transact().encode_state_as_update_v1(&StateVector::default()))
+          .unwrap();
+        drop(txn);
+
+        // A transaction that fails after writing some of its changes
+        let result = db.with_write_txn(|store| {
+          store.insert([11], [1])?;
+          store.delete_doc(1, "1")?;
+          Err::<(), _>(PersistenceError::UnexpectedEmptyUpdates)
+        });
+        assert!(result.is_err());
+        // The database is dropped without flushing
+      }
+
+      let db = open_db(dir.path());
+      assert_eq!(db.read_txn().get([10]).unwrap(), None);
+      assert_eq!(db.read_txn().get([11]).unwrap(), None);
+      assert!(db.read_txn().is_exist(1, "1"));
+      assert_eq!(db.read_txn().number_of_updates(1, "1"), 5);
+      assert_eq!(load_text(&db, "1"), "hello");
+    }
+  };
+}
+
+kv_conformance_tests!(
+  rocksdb,
+  CollabKVDB,
+  open = |path| CollabKVDB::open(path).unwrap()
+);
+kv_conformance_tests!(
+  memory,
+  KVTransactionDBMemoryImpl,
+  new = KVTransactionDBMemoryImpl::new()
+);
+#[cfg(feature = "sqlite")]
+kv_conformance_tests!(
+  sqlite,
+  collab_plugins::local_storage::sqlite::kv_impl::KVTransactionDBSqliteImpl,
+  open = |path| {
+    collab_plugins::local_storage::sqlite::kv_impl::KVTransactionDBSqliteImpl::open(
+      path.join("collab.db"),
+    )
+    .unwrap()
+  }
+);
+#[cfg(feature = "encryption")]
+kv_conformance_tests!(
+  encrypted_rocksdb,
+  collab_plugins::local_storage::kv::encryption::EncryptedKVTransactionDB<CollabKVDB>,
+  open = |path| {
+    use collab_plugins::local_storage::kv::encryption::*;
+    EncryptedKVTransactionDB::new(
+      CollabKVDB::open(path).unwrap(),
+      "w1",
+      &EncryptionKey::new([7; 32]),
+    )
+    .unwrap()
+  }
+);
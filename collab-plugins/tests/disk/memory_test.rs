@@ -0,0 +1,147 @@
+use std::sync::Arc;
+
+use collab::core::collab::DataSource;
+use collab::preclude::{CollabBuilder, Doc, GetString, Text, Transact};
+use collab_entity::CollabType;
+use collab_plugins::local_storage::kv::doc::CollabKVAction;
+use collab_plugins::local_storage::kv::snapshot::SnapshotAction;
+use collab_plugins::local_storage::kv::{KVEntry, KVStore, KVTransactionDB};
+use collab_plugins::local_storage::memory::kv_impl::KVTransactionDBMemoryImpl;
+use collab_plugins::local_storage::rocksdb::rocksdb_plugin::RocksdbDiskPlugin;
+use collab_plugins::local_storage::rocksdb::util::KVDBCollabPersistenceImpl;
+use collab_plugins::local_storage::{CollabPersistenceConfig, CompactionPolicy};
+
+#[tokio::test]
+async fn memory_db_create_and_load_doc_test() {
+  let db = KVTransactionDBMemoryImpl::new();
+  let doc = Doc::new();
+  let text = doc.get_or_insert_text("text");
+  db.with_write_txn(|store| store.create_new_doc(1, "1", &doc.transact()))
+    .unwrap();
+  for i in 0..10 {
+    let mut txn = doc.transact_mut();
+    text.push(&mut txn, &i.to_string());
+    let update = txn.encode_update_v1();
+    db.with_write_txn(|store| store.push_update(1, "1", &update))
+      .unwrap();
+  }
+  assert_eq!(db.read_txn().number_of_updates(1, "1"), 10);
+
+  let restored = Doc::new();
+  let update_count = db.read_txn().load_doc(1, "1", restored.clone()).unwrap();
+  assert_eq!(update_count, 10);
+  let restored_text = restored.get_or_insert_text("text");
+  assert_eq!(restored_text.get_string(&restored.transact()), "0123456789");
+
+  db.with_write_txn(|store| store.delete_doc(1, "1")).unwrap();
+  assert!(!db.read_txn().is_exist(1, "1"));
+}
+
+#[test]
+fn memory_db_uncommitted_changes_are_isolated_test() {
+  let db = KVTransactionDBMemoryImpl::new();
+  let write_txn = db.write_txn();
+  write_txn.insert([1], [1]).unwrap();
+  assert_eq!(write_txn.get([1]).unwrap(), Some(vec![1]));
+  assert_eq!(db.read_txn().get([1]).unwrap(), None);
+  write_txn.commit_transaction().unwrap();
+  assert_eq!(db.read_txn().get([1]).unwrap(), Some(vec![1]));
+
+  // The changes of a dropped transaction are discarded
+  {
+    let write_txn = db.write_txn();
+    write_txn.remove(&[1]).unwrap();
+    write_txn.insert([2], [2]).unwrap();
+  }
+  assert_eq!(db.read_txn().get([1]).unwrap(), Some(vec![1]));
+  assert_eq!(db.read_txn().get([2]).unwrap(), None);
+
+  // A failed with_write_txn doesn't commit
+  let _ = db.with_write_txn(|store| {
+    store.insert([3], [3])?;
+    Err::<(), _>(anyhow::anyhow!("failed").into())
+  });
+  assert_eq!(db.read_txn().get([3]).unwrap(), None);
+}
+
+#[test]
+fn memory_db_range_test() {
+  let db = KVTransactionDBMemoryImpl::new();
+  db.with_write_txn(|store| {
+    for i in 0..10u8 {
+      store.insert([i], [i])?;
+    }
+    Ok(())
+  })
+  .unwrap();
+
+  let txn = db.write_txn();
+  txn.remove_range(&[2], &[4]).unwrap();
+  txn.insert([20], [20]).unwrap();
+  let collect = |from: u8, to: u8| {
+    txn
+      .range([from]..[to])
+      .unwrap()
+      .map(|entry| entry.key()[0])
+      .collect::<Vec<_>>()
+  };
+  assert_eq!(collect(0, 6), vec![0, 1, 4, 5]);
+  assert_eq!(collect(8, 30), vec![8, 9, 20]);
+  assert!(collect(5, 5).is_empty());
+  assert!(collect(6, 5).is_empty());
+
+  // The upper bound is excluded even if the range is inclusive
+  let inclusive = txn
+    .range([0]..=[1])
+    .unwrap()
+    .map(|entry| entry.key()[0])
+    .collect::<Vec<_>>();
+  assert_eq!(inclusive, vec![0]);
+
+  assert_eq!(txn.next_back_entry(&[3]).unwrap().unwrap().key(), &[1]);
+  assert_eq!(txn.next_back_entry(&[30]).unwrap().unwrap().key(), &[20]);
+  assert_eq!(txn.next_back_entry(&[7]).unwrap().unwrap().value(), &[7]);
+  assert_eq!(
+    db.read_txn().next_back_entry(&[30]).unwrap().unwrap().key(),
+    &[9]
+  );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn memory_db_disk_plugin_test() {
+  let db = Arc::new(KVTransactionDBMemoryImpl::new());
+  let plugin = RocksdbDiskPlugin::new_with_config(
+    1,
+    "1".to_string(),
+    CollabType::Document,
+    Arc::downgrade(&db),
+    CollabPersistenceConfig::new()
+      .enable_snapshot(true)
+      .snapshot_per_update(5)
+      .compaction(CompactionPolicy::new().max_updates(4)),
+  );
+  let mut collab = CollabBuilder::new(1, "1", DataSource::Disk(None))
+    .with_device_id("1")
+    .with_plugin(plugin.clone())
+    .build()
+    .unwrap();
+  collab.initialize();
+  for i in 0..10 {
+    collab.insert(&i.to_string(), i);
+    collab.flush_plugins().await;
+  }
+  assert_eq!(plugin.compaction_stats().compaction_count, 2);
+  assert!(!db.read_txn().get_snapshots(1, "1").is_empty());
+
+  let restored = CollabBuilder::new(
+    1,
+    "1",
+    KVDBCollabPersistenceImpl::new(Arc::downgrade(&db), 1).into(),
+  )
+  .with_device_id("1")
+  .build()
+  .unwrap();
+  for i in 0..10 {
+    assert_eq!(restored.get::<i64>(&i.to_string()).unwrap(), i);
+  }
+}
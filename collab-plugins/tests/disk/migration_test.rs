@@ -0,0 +1,108 @@
+use crate::disk::util::rocks_db;
+use collab::preclude::{Doc, GetString, Text, Transact};
+use collab_plugins::local_storage::kv::doc::CollabKVAction;
+use collab_plugins::local_storage::kv::keys::{
+  make_collab_id_key, make_doc_id_key, make_doc_state_key,
+};
+use collab_plugins::local_storage::kv::migration::migrate_kv_db;
+use collab_plugins::local_storage::kv::snapshot::SnapshotAction;
+use collab_plugins::local_storage::kv::{
+  get_id_for_key, KVStore, KVTransactionDB, PersistenceError,
+};
+use collab_plugins::local_storage::memory::kv_impl::KVTransactionDBMemoryImpl;
+
+fn create_doc<DB: KVTransactionDB>(db: &DB, uid: i64, object_id: &str, content: &str) {
+  let doc = Doc::new();
+  let text = doc.get_or_insert_text("text");
+  db.with_write_txn(|store| store.create_new_doc(uid, object_id, &doc.transact()))
+    .unwrap();
+  for c in content.chars() {
+    let mut txn = doc.transact_mut();
+    text.push(&mut txn, &c.to_string());
+    let update = txn.encode_update_v1();
+    db.with_write_txn(|store| store.push_update(uid, object_id, &update))
+      .unwrap();
+  }
+}
+
+fn load_text<DB: KVTransactionDB>(db: &DB, uid: i64, object_id: &str) -> String {
+  let doc = Doc::new();
+  db.read_txn().load_doc(uid, object_id, doc.clone()).unwrap();
+  let text = doc.get_or_insert_text("text");
+  let txn = doc.transact();
+  text.get_string(&txn)
+}
+
+#[test]
+fn migrate_rocksdb_to_memory_test() {
+  let (_path, source) = rocks_db();
+  create_doc(&source, 1, "1", "hello");
+  create_doc(&source, 1, "2", "world");
+  create_doc(&source, 2, "1", "other user");
+  source
+    .with_write_txn(|store| store.create_snapshot_with_data(1, "1", vec![1, 2, 3]))
+    .unwrap();
+  source
+    .with_write_txn(|store| store.insert(make_collab_id_key(b"1"), [7]))
+    .unwrap();
+
+  let target = KVTransactionDBMemoryImpl::new();
+  let report = migrate_kv_db(&source, &target).unwrap();
+  assert!(report.is_complete());
+  assert_eq!(report.migrated_docs, 3);
+
+  assert_eq!(load_text(&target, 1, "1"), "hello");
+  assert_eq!(load_text(&target, 1, "2"), "world");
+  assert_eq!(load_text(&target, 2, "1"), "other user");
+  assert_eq!(target.read_txn().number_of_updates(1, "1"), 5);
+  assert_eq!(
+    target.read_txn().get_last_snapshot(1, "1").unwrap().data,
+    vec![1, 2, 3]
+  );
+  assert_eq!(
+    target.read_txn().get(make_collab_id_key(b"1")).unwrap(),
+    Some(vec![7])
+  );
+
+  // The migrated database can be migrated back
+  let (_path, restored) = rocks_db();
+  let report = migrate_kv_db(&target, &restored).unwrap();
+  assert!(report.is_complete());
+  assert_eq!(load_text(&restored, 1, "2"), "world");
+}
+
+#[test]
+fn migrate_skips_failed_docs_test() {
+  let (_path, source) = rocks_db();
+  create_doc(&source, 1, "1", "hello");
+  create_doc(&source, 1, "2", "world");
+  create_doc(&source, 1, "3", "corrupted");
+
+  // Overwrite the doc state of the document 3 without updating the checksum
+  let doc_id_key = make_doc_id_key(&1_i64.to_be_bytes(), b"3");
+  let doc_id = get_id_for_key(&source.read_txn(), doc_id_key).unwrap();
+  source
+    .with_write_txn(|store| store.insert(make_doc_state_key(doc_id), [1, 2, 3]))
+    .unwrap();
+
+  // The document 2 already exists in the target
+  let target = KVTransactionDBMemoryImpl::new();
+  create_doc(&target, 1, "2", "existing");
+
+  let report = migrate_kv_db(&source, &target).unwrap();
+  assert_eq!(report.migrated_docs, 1);
+  assert_eq!(report.failed_docs.len(), 2);
+  assert!(report
+    .failed_docs
+    .iter()
+    .any(|failure| failure.object_id == "2"
+      && matches!(failure.error, PersistenceError::DocumentAlreadyExist)));
+  assert!(report
+    .failed_docs
+    .iter()
+    .any(|failure| failure.object_id == "3"));
+
+  assert_eq!(load_text(&target, 1, "1"), "hello");
+  assert_eq!(load_text(&target, 1, "2"), "existing");
+  assert!(!target.read_txn().is_exist(1, "3"));
+}
@@ -1,7 +1,21 @@
+mod backup_test;
+mod checksum_test;
+mod compaction_test;
 mod delete_test;
+#[cfg(feature = "encryption")]
+mod encryption_test;
 mod insert_test;
+mod kv_conformance_test;
+mod memory_test;
+mod migration_test;
+mod named_snapshot_test;
+mod outbox_test;
 mod range_test;
 mod restore_test;
 mod script;
+mod snapshot_retention_test;
+#[cfg(feature = "sqlite")]
+mod sqlite_test;
 mod undo_test;
+mod usage_test;
 mod util;
@@ -0,0 +1,132 @@
+use crate::disk::util::rocks_db;
+use collab::preclude::{Collab, ReadTxn};
+use collab_entity::CollabType;
+use collab_plugins::local_storage::kv::snapshot::{encode_restore_update, SnapshotAction};
+use collab_plugins::local_storage::kv::KVTransactionDB;
+use collab_plugins::local_storage::SnapshotRetention;
+use yrs::updates::decoder::Decode;
+use yrs::{StateVector, Update};
+
+#[test]
+fn create_named_snapshot_test() {
+  let (_path, db) = rocks_db();
+  let mut collab = Collab::new(1, "1", "1", vec![], false);
+  collab.insert("1", "a");
+
+  let meta = db
+    .with_write_txn(|store| {
+      store.create_named_snapshot(1, "1", &collab.transact(), "v1", 2, CollabType::Document)
+    })
+    .unwrap();
+  assert_eq!(meta.label.as_deref(), Some("v1"));
+  db.with_write_txn(|store| store.create_snapshot_with_data(1, "1", vec![1, 2, 3]))
+    .unwrap();
+
+  let metas = db.read_txn().get_snapshot_metas(1, "1");
+  assert_eq!(metas.len(), 2);
+  assert_eq!(metas[0], meta);
+  assert_eq!(metas[0].author_uid, Some(2));
+  assert_eq!(metas[0].collab_type, Some(CollabType::Document));
+  assert_eq!(
+    StateVector::decode_v1(&metas[0].state_vector).unwrap(),
+    collab.transact().state_vector()
+  );
+  assert_eq!(metas[1].label, None);
+  assert_eq!(metas[1].size, 3);
+
+  let snapshot = db.read_txn().get_snapshot(1, "1", metas[0].clock).unwrap();
+  assert_eq!(snapshot.data.len() as u64, metas[0].size);
+  assert_eq!(
+    db.read_txn()
+      .get_snapshot(1, "1", metas[1].clock)
+      .unwrap()
+      .data,
+    vec![1, 2, 3]
+  );
+
+  db.with_write_txn(|store| store.delete_all_snapshots(1, "1"))
+    .unwrap();
+  assert!(db.read_txn().get_snapshot_metas(1, "1").is_empty());
+}
+
+#[test]
+fn snapshot_retention_removes_meta_test() {
+  let (_path, db) = rocks_db();
+  for i in 0..3 {
+    db.with_write_txn(|store| store.create_snapshot_with_data(1, "1", vec![i; 4]))
+      .unwrap();
+  }
+  let retention = SnapshotRetention::new().keep_last(1);
+  db.with_write_txn(|store| store.apply_snapshot_retention(1, "1", &retention))
+    .unwrap();
+
+  let metas = db.read_txn().get_snapshot_metas(1, "1");
+  assert_eq!(metas.len(), 1);
+  assert_eq!(
+    db.read_txn()
+      .get_snapshot(1, "1", metas[0].clock)
+      .unwrap()
+      .data,
+    vec![2; 4]
+  );
+}
+
+#[test]
+fn restore_snapshot_as_forward_update_test() {
+  let (_path, db) = rocks_db();
+  let mut collab = Collab::new(1, "1", "1", vec![], false);
+  collab.insert("1", "a");
+  collab.insert("2", "b");
+  let meta = db
+    .with_write_txn(|store| {
+      store.create_named_snapshot(1, "1", &collab.transact(), "v1", 1, CollabType::Document)
+    })
+    .unwrap();
+
+  collab.insert("2", "c");
+  collab.insert("3", "d");
+  collab.remove("1");
+
+  // Another peer that has seen all the changes
+  let mut peer = Collab::new(2, "1", "2", vec![], false);
+  let state = collab
+    .transact()
+    .encode_state_as_update_v1(&StateVector::default());
+  peer
+    .apply_update(Update::decode_v1(&state).unwrap())
+    .unwrap();
+
+  let state_vector = collab.transact().state_vector();
+  db.read_txn()
+    .restore_snapshot(1, "1", meta.clock, &mut collab)
+    .unwrap();
+  assert_eq!(collab.get::<String>("1").unwrap(), "a");
+  assert_eq!(collab.get::<String>("2").unwrap(), "b");
+  assert!(collab.get::<String>("3").is_none());
+
+  // The restoration is a new update on top of the history
+  let restored_state_vector = collab.transact().state_vector();
+  assert_ne!(restored_state_vector, state_vector);
+  for (client_id, clock) in state_vector.iter() {
+    assert_eq!(restored_state_vector.get(client_id), *clock);
+  }
+
+  // The peer converges by applying the changes made by the restoration
+  let update = collab
+    .transact()
+    .encode_state_as_update_v1(&peer.transact().state_vector());
+  peer
+    .apply_update(Update::decode_v1(&update).unwrap())
+    .unwrap();
+  assert_eq!(peer.to_json_value(), collab.to_json_value());
+
+  // Restoring the current state changes nothing
+  let snapshot_data = collab
+    .transact()
+    .encode_state_as_update_v1(&StateVector::default());
+  let update = encode_restore_update(&collab.transact(), &snapshot_data).unwrap();
+  collab
+    .apply_update(Update::decode_v1(&update).unwrap())
+    .unwrap();
+  assert_eq!(peer.to_json_value(), collab.to_json_value());
+}
@@ -0,0 +1,72 @@
+use crate::disk::util::rocks_db;
+use collab_plugins::local_storage::kv::outbox::{OutboxAction, OutboxMsg};
+use collab_plugins::local_storage::kv::KVTransactionDB;
+
+#[test]
+fn outbox_msgs_are_ordered_by_msg_id_test() {
+  let (_path, db) = rocks_db();
+  for msg_id in [3, 1, 2] {
+    let msg = OutboxMsg::new(msg_id, vec![vec![msg_id as u8]]);
+    db.with_write_txn(|store| store.push_outbox_msg(1, "1", &msg))
+      .unwrap();
+  }
+  db.with_write_txn(|store| store.push_outbox_msg(1, "2", &OutboxMsg::new(1, vec![vec![1]])))
+    .unwrap();
+
+  let msgs = db.read_txn().get_outbox_msgs(1, "1");
+  assert_eq!(
+    msgs.iter().map(|msg| msg.msg_id).collect::<Vec<_>>(),
+    vec![1, 2, 3]
+  );
+  assert_eq!(msgs[2].payloads, vec![vec![3]]);
+
+  db.with_write_txn(|store| store.remove_outbox_msg(1, "1", 2))
+    .unwrap();
+  let msgs = db.read_txn().get_outbox_msgs(1, "1");
+  assert_eq!(
+    msgs.iter().map(|msg| msg.msg_id).collect::<Vec<_>>(),
+    vec![1, 3]
+  );
+  assert_eq!(db.read_txn().get_outbox_msgs(1, "2").len(), 1);
+}
+
+#[test]
+fn outbox_merged_msgs_keep_their_changes_test() {
+  let (_path, db) = rocks_db();
+  for msg_id in 1..=3 {
+    let msg = OutboxMsg::new(msg_id, vec![vec![msg_id as u8]]);
+    db.with_write_txn(|store| store.push_outbox_msg(1, "1", &msg))
+      .unwrap();
+  }
+  let merged = OutboxMsg::new(1, vec![vec![1], vec![2], vec![3]]);
+  db.with_write_txn(|store| store.merge_outbox_msgs(1, "1", &merged, &[2, 3]))
+    .unwrap();
+
+  let msgs = db.read_txn().get_outbox_msgs(1, "1");
+  assert_eq!(msgs, vec![merged]);
+  assert_eq!(db.read_txn().number_of_unsynced_changes(1, "1"), 3);
+}
+
+#[test]
+fn unsynced_changes_per_object_test() {
+  let (_path, db) = rocks_db();
+  db.with_write_txn(|store| {
+    store.push_outbox_msg(1, "1", &OutboxMsg::new(1, vec![vec![1], vec![2]]))?;
+    store.push_outbox_msg(1, "1", &OutboxMsg::new(2, vec![vec![3]]))?;
+    store.push_outbox_msg(1, "2", &OutboxMsg::new(1, vec![vec![1]]))?;
+    store.push_outbox_msg(2, "3", &OutboxMsg::new(1, vec![vec![1]]))
+  })
+  .unwrap();
+
+  let changes = db.read_txn().unsynced_changes(1).unwrap();
+  assert_eq!(changes.len(), 2);
+  assert_eq!(changes["1"], 3);
+  assert_eq!(changes["2"], 1);
+
+  db.with_write_txn(|store| store.clear_outbox(1, "1"))
+    .unwrap();
+  let changes = db.read_txn().unsynced_changes(1).unwrap();
+  assert_eq!(changes.len(), 1);
+  assert_eq!(db.read_txn().number_of_unsynced_changes(1, "1"), 0);
+  assert_eq!(db.read_txn().unsynced_changes(2).unwrap()["3"], 1);
+}
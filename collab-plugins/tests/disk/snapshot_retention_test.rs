@@ -0,0 +1,189 @@
+use std::sync::Arc;
+
+use crate::disk::util::rocks_db;
+use collab::core::collab::DataSource;
+use collab::preclude::{Collab, CollabBuilder};
+use collab_entity::CollabType;
+use collab_plugins::local_storage::kv::snapshot::SnapshotAction;
+use collab_plugins::local_storage::kv::KVTransactionDB;
+use collab_plugins::local_storage::rocksdb::rocksdb_plugin::RocksdbDiskPlugin;
+use collab_plugins::local_storage::{
+  CollabPersistenceConfig, SnapshotRetention, DEFAULT_SNAPSHOT_KEEP_LAST,
+};
+use collab_plugins::CollabKVDB;
+
+const HOUR: i64 = 60 * 60;
+const DAY: i64 = 24 * HOUR;
+
+fn open_collab(db: &Arc<CollabKVDB>, object_id: &str, config: CollabPersistenceConfig) -> Collab {
+  let plugin = RocksdbDiskPlugin::new_with_config(
+    1,
+    object_id.to_string(),
+    CollabType::Document,
+    Arc::downgrade(db),
+    config,
+  );
+  let mut collab = CollabBuilder::new(1, object_id, DataSource::Disk(None))
+    .with_device_id("1")
+    .with_plugin(plugin)
+    .build()
+    .unwrap();
+  collab.initialize();
+  collab
+}
+
+#[test]
+fn retention_policy_test() {
+  // Two snapshots per hour over two days, 10 bytes each
+  let snapshots = (0..96)
+    .map(|i| (i * HOUR / 2, 10))
+    .collect::<Vec<(i64, u64)>>();
+
+  assert!(SnapshotRetention::new()
+    .expired_snapshots(&snapshots)
+    .is_empty());
+
+  let expired = SnapshotRetention::new()
+    .keep_last(3)
+    .expired_snapshots(&snapshots);
+  assert_eq!(expired, (0..93).collect::<Vec<_>>());
+
+  // The newest snapshot of the last 4 hours
+  let expired = SnapshotRetention::new()
+    .keep_hourly(4)
+    .expired_snapshots(&snapshots);
+  assert_eq!(expired.len(), 92);
+  for kept in [89, 91, 93, 95] {
+    assert!(!expired.contains(&kept));
+  }
+
+  // The newest snapshot of each day, plus the last one
+  let expired = SnapshotRetention::new()
+    .keep_last(1)
+    .keep_daily(7)
+    .expired_snapshots(&snapshots);
+  assert_eq!(expired.len(), 94);
+  assert!(!expired.contains(&47));
+  assert!(!expired.contains(&95));
+  assert_eq!(snapshots[47].0 / DAY, 0);
+
+  // The oldest snapshots are removed to fit in the size limit
+  let expired = SnapshotRetention::new()
+    .max_total_bytes(55)
+    .expired_snapshots(&snapshots);
+  assert_eq!(expired, (0..91).collect::<Vec<_>>());
+
+  // The newest snapshot is kept even if it exceeds the size limit
+  let expired = SnapshotRetention::new()
+    .keep_last(5)
+    .max_total_bytes(1)
+    .expired_snapshots(&snapshots);
+  assert_eq!(expired, (0..95).collect::<Vec<_>>());
+}
+
+#[test]
+fn apply_snapshot_retention_test() {
+  let (_path, db) = rocks_db();
+  for i in 0..5 {
+    db.with_write_txn(|store| store.create_snapshot_with_data(1, "1", vec![i; 4]))
+      .unwrap();
+  }
+
+  let retention = SnapshotRetention::new().keep_last(2);
+  let removed = db
+    .with_write_txn(|store| store.apply_snapshot_retention(1, "1", &retention))
+    .unwrap();
+  assert_eq!(removed, 3);
+
+  let snapshots = db.read_txn().get_snapshots(1, "1");
+  assert_eq!(snapshots.len(), 2);
+  assert_eq!(snapshots[0].data, vec![3; 4]);
+  assert_eq!(snapshots[1].data, vec![4; 4]);
+
+  // Applying the same policy again removes nothing
+  let removed = db
+    .with_write_txn(|store| store.apply_snapshot_retention(1, "1", &retention))
+    .unwrap();
+  assert_eq!(removed, 0);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn disk_plugin_create_snapshot_test() {
+  let (_path, db) = rocks_db();
+  let db = Arc::new(db);
+  let config = CollabPersistenceConfig::new()
+    .enable_snapshot(true)
+    .snapshot_per_update(5);
+  let mut collab = open_collab(&db, "1", config);
+  for i in 0..12 {
+    collab.insert(&i.to_string(), i);
+    collab.flush_plugins().await;
+  }
+
+  let snapshots = db.read_txn().get_snapshots(1, "1");
+  assert_eq!(snapshots.len(), 2);
+  let mut restored = CollabBuilder::new(1, "1", DataSource::DocStateV1(snapshots[1].data.clone()))
+    .with_device_id("1")
+    .build()
+    .unwrap();
+  restored.initialize();
+  for i in 0..10 {
+    assert_eq!(restored.get::<i64>(&i.to_string()).unwrap(), i);
+  }
+  assert!(restored.get::<i64>("10").is_none());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn disk_plugin_snapshot_retention_test() {
+  let (_path, db) = rocks_db();
+  let db = Arc::new(db);
+  let config = CollabPersistenceConfig::new()
+    .enable_snapshot(true)
+    .snapshot_per_update(2)
+    .snapshot_retention(SnapshotRetention::new().keep_last(3));
+  let mut collab = open_collab(&db, "1", config);
+  for i in 0..20 {
+    collab.insert(&i.to_string(), i);
+    collab.flush_plugins().await;
+  }
+  assert_eq!(db.read_txn().get_snapshots(1, "1").len(), 3);
+}
+
+#[test]
+fn snapshot_enabled_with_bounded_retention_by_default_test() {
+  let config = CollabPersistenceConfig::default();
+  assert!(config.enable_snapshot);
+  assert_eq!(
+    config.snapshot_retention.keep_last,
+    Some(DEFAULT_SNAPSHOT_KEEP_LAST)
+  );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn disk_plugin_default_snapshot_retention_test() {
+  let (_path, db) = rocks_db();
+  let db = Arc::new(db);
+  let config = CollabPersistenceConfig::new().snapshot_per_update(2);
+  let mut collab = open_collab(&db, "1", config);
+  for i in 0..(DEFAULT_SNAPSHOT_KEEP_LAST * 2 + 6) {
+    collab.insert(&i.to_string(), i as i64);
+    collab.flush_plugins().await;
+  }
+  assert_eq!(
+    db.read_txn().get_snapshots(1, "1").len(),
+    DEFAULT_SNAPSHOT_KEEP_LAST
+  );
+}
+
+#[tokio::test]
+async fn disk_plugin_snapshot_disabled_test() {
+  let (_path, db) = rocks_db();
+  let db = Arc::new(db);
+  let config = CollabPersistenceConfig::new().enable_snapshot(false);
+  let mut collab = open_collab(&db, "1", config);
+  for i in 0..200 {
+    collab.insert(&i.to_string(), i);
+  }
+  collab.flush_plugins().await;
+  assert!(db.read_txn().get_snapshots(1, "1").is_empty());
+}
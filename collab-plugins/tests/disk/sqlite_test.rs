@@ -0,0 +1,30 @@
+use collab::preclude::{Doc, GetString, Text, Transact};
+use collab_plugins::local_storage::kv::doc::CollabKVAction;
+use collab_plugins::local_storage::kv::KVTransactionDB;
+use collab_plugins::local_storage::sqlite::kv_impl::KVTransactionDBSqliteImpl;
+use tempfile::TempDir;
+
+#[test]
+fn sqlite_reopen_database_test() {
+  let dir = TempDir::new().unwrap();
+  let path = dir.path().join("collab.db");
+  {
+    let db = KVTransactionDBSqliteImpl::open(&path).unwrap();
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("text");
+    db.with_write_txn(|store| store.create_new_doc(1, "1", &doc.transact()))
+      .unwrap();
+    let mut txn = doc.transact_mut();
+    text.push(&mut txn, "hello");
+    let update = txn.encode_update_v1();
+    db.with_write_txn(|store| store.push_update(1, "1", &update))
+      .unwrap();
+    db.flush().unwrap();
+  }
+
+  let db = KVTransactionDBSqliteImpl::open(&path).unwrap();
+  let doc = Doc::new();
+  db.read_txn().load_doc(1, "1", doc.clone()).unwrap();
+  let text = doc.get_or_insert_text("text");
+  assert_eq!(text.get_string(&doc.transact()), "hello");
+}
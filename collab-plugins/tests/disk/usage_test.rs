@@ -0,0 +1,124 @@
+use crate::disk::util::rocks_db;
+use collab::preclude::{Doc, Text, Transact};
+use collab_plugins::local_storage::kv::doc::CollabKVAction;
+use collab_plugins::local_storage::kv::keys::{
+  make_doc_id_key, make_doc_state_key, make_doc_update_key,
+};
+use collab_plugins::local_storage::kv::snapshot::SnapshotAction;
+use collab_plugins::local_storage::kv::usage::StorageUsageAction;
+use collab_plugins::local_storage::kv::{KVStore, KVTransactionDB};
+use collab_plugins::CollabKVDB;
+
+fn create_doc(db: &CollabKVDB, uid: i64, object_id: &str, updates: usize) {
+  let doc = Doc::new();
+  let text = doc.get_or_insert_text("text");
+  db.with_write_txn(|store| store.create_new_doc(uid, object_id, &doc.transact()))
+    .unwrap();
+  for _ in 0..updates {
+    let mut txn = doc.transact_mut();
+    text.push(&mut txn, "abc");
+    let update = txn.encode_update_v1();
+    db.with_write_txn(|store| store.push_update(uid, object_id, &update))
+      .unwrap();
+  }
+}
+
+#[test]
+fn storage_report_test() {
+  let (_path, db) = rocks_db();
+  create_doc(&db, 1, "1", 10);
+  create_doc(&db, 1, "2", 2);
+  create_doc(&db, 2, "3", 1);
+  db.with_write_txn(|store| store.create_snapshot_with_data(1, "2", vec![0; 4096]))
+    .unwrap();
+
+  let report = db.read_txn().storage_report().unwrap();
+  assert!(!report.has_orphans());
+  assert_eq!(report.docs.len(), 3);
+
+  let doc_1 = report.docs.iter().find(|doc| doc.object_id == "1").unwrap();
+  assert_eq!(doc_1.uid, 1);
+  assert_eq!(doc_1.number_of_updates, 10);
+  assert!(doc_1.update_bytes > 0);
+  assert!(doc_1.doc_state_bytes > 0);
+  assert!(doc_1.metadata_bytes > 0);
+  assert_eq!(doc_1.number_of_snapshots, 0);
+
+  // The document with the snapshot is the largest one
+  assert_eq!(report.docs[0].object_id, "2");
+  assert_eq!(report.docs[0].number_of_snapshots, 1);
+  assert!(report.docs[0].snapshot_bytes > 4096);
+
+  let uid_1 = &report.uids[&1];
+  assert_eq!(uid_1.number_of_docs, 2);
+  assert_eq!(uid_1.number_of_updates, 12);
+  assert_eq!(report.uids[&2].number_of_docs, 1);
+  assert_eq!(
+    report
+      .uids
+      .values()
+      .map(|uid| uid.total_bytes())
+      .sum::<u64>(),
+    report.docs.iter().map(|doc| doc.total_bytes()).sum::<u64>()
+  );
+}
+
+#[test]
+fn remove_orphans_test() {
+  let (_path, db) = rocks_db();
+  create_doc(&db, 1, "1", 3);
+  let orphan_doc_id = u64::MAX / 2;
+  db.with_write_txn(|store| {
+    // A doc id mapping without any data
+    store.insert(
+      make_doc_id_key(&1_i64.to_be_bytes(), b"2"),
+      (orphan_doc_id + 1).to_be_bytes(),
+    )?;
+    // A doc id mapping with updates but without doc state is not an orphan
+    store.insert(
+      make_doc_id_key(&1_i64.to_be_bytes(), b"3"),
+      (orphan_doc_id + 2).to_be_bytes(),
+    )?;
+    store.insert(make_doc_update_key(orphan_doc_id + 2, 0), [4, 5, 6])?;
+    // A doc id mapping without data but with a snapshot is recoverable
+    store.insert(
+      make_doc_id_key(&1_i64.to_be_bytes(), b"4"),
+      (orphan_doc_id + 3).to_be_bytes(),
+    )?;
+    store.create_snapshot_with_data(1, "4", vec![7, 8, 9])?;
+    // The data of a doc id without mapping
+    store.insert(make_doc_state_key(orphan_doc_id), [1, 2, 3])?;
+    store.insert(make_doc_update_key(orphan_doc_id, 0), [1, 2, 3])?;
+    Ok(())
+  })
+  .unwrap();
+
+  let report = db.read_txn().storage_report().unwrap();
+  assert_eq!(report.orphan_mappings.len(), 1);
+  assert_eq!(report.orphan_mappings[0].object_id, "2");
+  assert_eq!(report.recoverable_mappings.len(), 1);
+  assert_eq!(report.recoverable_mappings[0].object_id, "4");
+  assert_eq!(report.orphan_data.len(), 1);
+  assert_eq!(report.orphan_data[0].doc_id, orphan_doc_id);
+  assert_eq!(report.orphan_data[0].number_of_keys, 2);
+
+  let removed = db.with_write_txn(|store| store.remove_orphans()).unwrap();
+  assert!(removed.has_orphans());
+
+  let report = db.read_txn().storage_report().unwrap();
+  assert!(!report.has_orphans());
+  assert_eq!(report.docs.len(), 3);
+  assert_eq!(report.recoverable_mappings.len(), 1);
+  assert!(!db.read_txn().is_exist(1, "2"));
+  assert!(db.read_txn().is_exist(1, "3"));
+  assert_eq!(db.read_txn().number_of_updates(1, "3"), 1);
+  assert!(db.read_txn().is_exist(1, "4"));
+  assert_eq!(db.read_txn().get_snapshots(1, "4").len(), 1);
+  assert_eq!(
+    db.read_txn()
+      .get(make_doc_state_key(orphan_doc_id))
+      .unwrap(),
+    None
+  );
+  assert_eq!(db.read_txn().number_of_updates(1, "1"), 3);
+}
@@ -1,3 +1,5 @@
+#[cfg(all(feature = "postgres_plugin", not(target_arch = "wasm32")))]
+mod cloud;
 #[cfg(not(target_arch = "wasm32"))]
 mod disk;
 
@@ -13,11 +13,7 @@ async fn indexeddb_put_and_get_encoded_collab_test() {
       let db = CollabIndexeddb::new().await.unwrap();
       let object_id = Uuid::new_v4().to_string();
       let uid: i64 = 1;
-      let encoded_collab = EncodedCollab {
-        state_vector: vec![1, 2, 3].into(),
-        doc_state: vec![4, 5, 6].into(),
-        version: collab::entity::EncoderVersion::V1,
-      };
+      let encoded_collab = EncodedCollab::new_v1(vec![1, 2, 3], vec![4, 5, 6]);
 
       db.create_doc(uid, &object_id, &encoded_collab)
         .await
@@ -102,11 +98,7 @@ async fn indexeddb_flush_doc_test() {
       let update_4 = vec![10, 11, 12];
       db.push_update(uid, &object_id, &update_4).await.unwrap();
 
-      let encoded_collab = EncodedCollab {
-        state_vector: vec![1, 2, 3].into(),
-        doc_state: vec![4, 5, 6].into(),
-        version: collab::entity::EncoderVersion::V1,
-      };
+      let encoded_collab = EncodedCollab::new_v1(vec![1, 2, 3], vec![4, 5, 6]);
       db.flush_doc(uid, &object_id, &encoded_collab)
         .await
         .unwrap();
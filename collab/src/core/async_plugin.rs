@@ -0,0 +1,267 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::anyhow;
+use arc_swap::ArcSwapOption;
+use async_trait::async_trait;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot};
+use yrs::TransactionMut;
+
+use crate::core::collab_plugin::{CollabPlugin, PluginError, PluginErrorSender, PluginFuture};
+use crate::core::origin::CollabOrigin;
+use crate::preclude::Collab;
+
+/// The default capacity of the queue of the [AsyncPluginAdapter].
+pub const DEFAULT_ASYNC_PLUGIN_QUEUE_CAPACITY: usize = 1000;
+
+/// The async version of the [CollabPlugin]. It's used by the plugins that need to await I/O, for
+/// example, writing the updates to the disk or sending them to the server.
+///
+/// An [AsyncCollabPlugin] is added to the [Collab] by wrapping it with the [AsyncPluginAdapter].
+/// The hooks are called one by one, in the order the events happen on the [Collab], so the
+/// updates are always received in the order they were applied.
+#[async_trait]
+pub trait AsyncCollabPlugin: Send + Sync + 'static {
+  /// See [CollabPlugin::name].
+  fn name(&self) -> &str {
+    std::any::type_name::<Self>()
+  }
+
+  /// See [CollabPlugin::priority].
+  fn priority(&self) -> i32 {
+    0
+  }
+
+  /// Called after the [Collab] is initialized.
+  async fn did_init(&self, _object_id: &str) -> Result<(), anyhow::Error> {
+    Ok(())
+  }
+
+  /// Called for each update of the document, including the local ones.
+  async fn receive_update(
+    &self,
+    _object_id: &str,
+    _origin: &CollabOrigin,
+    _update: &[u8],
+  ) -> Result<(), anyhow::Error> {
+    Ok(())
+  }
+
+  /// Called for each update that was made by the local user.
+  async fn receive_local_update(
+    &self,
+    _origin: &CollabOrigin,
+    _object_id: &str,
+    _update: &[u8],
+  ) -> Result<(), anyhow::Error> {
+    Ok(())
+  }
+
+  /// Called by [Collab::flush_plugins] after all the previous events were handled.
+  async fn flush(&self, _object_id: &str) -> Result<(), anyhow::Error> {
+    Ok(())
+  }
+
+  /// Called when the plugin is removed, after all the queued events were handled.
+  async fn destroy(&self) {}
+}
+
+enum AsyncPluginEvent {
+  DidInit {
+    object_id: String,
+  },
+  Update {
+    object_id: String,
+    origin: CollabOrigin,
+    update: Vec<u8>,
+  },
+  LocalUpdate {
+    object_id: String,
+    origin: CollabOrigin,
+    update: Vec<u8>,
+  },
+  Flush {
+    object_id: String,
+    ret: oneshot::Sender<()>,
+  },
+}
+
+/// Runs an [AsyncCollabPlugin] as a [CollabPlugin].
+///
+/// The events are pushed into a bounded queue and handled by a background task, one at a time.
+/// The [Collab] never waits for the queue: the hooks are called within the yrs observers, while
+/// the transaction is held, so blocking there would stall the document. When the queue is full,
+/// the event is dropped and the error is reported through [Collab::subscribe_plugin_error]. The
+/// callers that produce updates faster than the plugin handles them should await
+/// [Collab::flush_plugins] between the batches, or create the adapter with a larger capacity by
+/// [AsyncPluginAdapter::with_capacity].
+///
+/// The errors returned by the async hooks are also reported through
+/// [Collab::subscribe_plugin_error].
+///
+/// The adapter must be created within a tokio runtime.
+pub struct AsyncPluginAdapter<T> {
+  plugin: Arc<T>,
+  sender: Mutex<Option<mpsc::Sender<AsyncPluginEvent>>>,
+  error_sender: Arc<ArcSwapOption<PluginErrorSender>>,
+}
+
+impl<T> AsyncPluginAdapter<T>
+where
+  T: AsyncCollabPlugin,
+{
+  pub fn new(plugin: T) -> Self {
+    Self::with_capacity(plugin, DEFAULT_ASYNC_PLUGIN_QUEUE_CAPACITY)
+  }
+
+  /// Create the adapter with a queue that holds up to `capacity` events.
+  pub fn with_capacity(plugin: T, capacity: usize) -> Self {
+    let plugin = Arc::new(plugin);
+    let (sender, receiver) = mpsc::channel(capacity);
+    let error_sender = Arc::new(ArcSwapOption::empty());
+    tokio::spawn(run_plugin(plugin.clone(), receiver, error_sender.clone()));
+    Self {
+      plugin,
+      sender: Mutex::new(Some(sender)),
+      error_sender,
+    }
+  }
+
+  pub fn plugin(&self) -> &Arc<T> {
+    &self.plugin
+  }
+
+  fn sender(&self) -> Option<mpsc::Sender<AsyncPluginEvent>> {
+    self.sender.lock().unwrap().clone()
+  }
+
+  /// Push the event into the queue without waiting. Return an error if the queue is full or
+  /// closed, the event is dropped in that case.
+  fn enqueue(&self, event: AsyncPluginEvent) -> Result<(), anyhow::Error> {
+    let sender = self
+      .sender()
+      .ok_or_else(|| anyhow!("the plugin was destroyed"))?;
+    sender.try_send(event).map_err(|err| match err {
+      TrySendError::Full(_) => anyhow!("the queue of the plugin is full, the event is dropped"),
+      TrySendError::Closed(_) => anyhow!("the queue of the plugin is closed"),
+    })
+  }
+}
+
+async fn run_plugin<T>(
+  plugin: Arc<T>,
+  mut receiver: mpsc::Receiver<AsyncPluginEvent>,
+  error_sender: Arc<ArcSwapOption<PluginErrorSender>>,
+) where
+  T: AsyncCollabPlugin,
+{
+  while let Some(event) = receiver.recv().await {
+    let (object_id, result) = match event {
+      AsyncPluginEvent::DidInit { object_id } => {
+        let result = plugin.did_init(&object_id).await;
+        (object_id, result)
+      },
+      AsyncPluginEvent::Update {
+        object_id,
+        origin,
+        update,
+      } => {
+        let result = plugin.receive_update(&object_id, &origin, &update).await;
+        (object_id, result)
+      },
+      AsyncPluginEvent::LocalUpdate {
+        object_id,
+        origin,
+        update,
+      } => {
+        let result = plugin
+          .receive_local_update(&origin, &object_id, &update)
+          .await;
+        (object_id, result)
+      },
+      AsyncPluginEvent::Flush { object_id, ret } => {
+        let result = plugin.flush(&object_id).await;
+        let _ = ret.send(());
+        (object_id, result)
+      },
+    };
+
+    if let Err(err) = result {
+      match error_sender.load_full() {
+        Some(error_sender) => PluginError::report(&error_sender, plugin.name(), &object_id, err),
+        None => tracing::error!("plugin {} failed on {}: {}", plugin.name(), object_id, err),
+      }
+    }
+  }
+  plugin.destroy().await;
+}
+
+impl<T> CollabPlugin for AsyncPluginAdapter<T>
+where
+  T: AsyncCollabPlugin,
+{
+  fn name(&self) -> &str {
+    self.plugin.name()
+  }
+
+  fn priority(&self) -> i32 {
+    self.plugin.priority()
+  }
+
+  fn did_init(&self, collab: &Collab, object_id: &str) -> Result<(), anyhow::Error> {
+    self
+      .error_sender
+      .store(Some(Arc::new(collab.plugin_error_sender())));
+    self.enqueue(AsyncPluginEvent::DidInit {
+      object_id: object_id.to_string(),
+    })
+  }
+
+  fn receive_update(
+    &self,
+    object_id: &str,
+    txn: &TransactionMut,
+    update: &[u8],
+  ) -> Result<(), anyhow::Error> {
+    self.enqueue(AsyncPluginEvent::Update {
+      object_id: object_id.to_string(),
+      origin: CollabOrigin::from(txn),
+      update: update.to_vec(),
+    })
+  }
+
+  fn receive_local_update(
+    &self,
+    origin: &CollabOrigin,
+    object_id: &str,
+    update: &[u8],
+  ) -> Result<(), anyhow::Error> {
+    self.enqueue(AsyncPluginEvent::LocalUpdate {
+      object_id: object_id.to_string(),
+      origin: origin.clone(),
+      update: update.to_vec(),
+    })
+  }
+
+  fn flush(&self, object_id: &str) -> Option<PluginFuture> {
+    // The flush event goes through the same queue as the other events, so it's handled after
+    // all of them. Unlike the other events, it's not called within an observer, so the returned
+    // future waits for room in the queue instead of dropping the event.
+    let sender = self.sender()?;
+    let (ret, rx) = oneshot::channel();
+    let event = AsyncPluginEvent::Flush {
+      object_id: object_id.to_string(),
+      ret,
+    };
+    Some(Box::pin(async move {
+      if sender.send(event).await.is_ok() {
+        let _ = rx.await;
+      }
+    }))
+  }
+
+  /// Close the queue. The background task destroys the plugin after the queued events are handled.
+  fn destroy(&self) {
+    self.sender.lock().unwrap().take();
+  }
+}
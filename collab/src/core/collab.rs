@@ -1,4 +1,5 @@
 pub use std::fmt::Display;
+use std::future::Future;
 use std::ops::{Deref, DerefMut};
 use std::panic;
 use std::panic::AssertUnwindSafe;
@@ -21,7 +22,11 @@ use yrs::{
 };
 
 use crate::core::awareness::Awareness;
-use crate::core::collab_plugin::{CollabPersistence, CollabPlugin, Plugins};
+use crate::core::collab_diagnostics::SyncDiagnostics;
+use crate::core::collab_metrics::{CollabMetrics, CollabMetricsPlugin};
+use crate::core::collab_plugin::{
+  CollabPersistence, CollabPlugin, PluginErrorReceiver, PluginErrorSender, Plugins,
+};
 use crate::core::collab_state::{InitState, SnapshotState, State, SyncState};
 use crate::core::origin::{CollabClient, CollabOrigin};
 use crate::core::transaction::DocTransactionExtension;
@@ -81,34 +86,52 @@ pub struct CollabContext {
   origin: CollabOrigin,
   /// The [Awareness] is used to track the awareness of the other peers.
   awareness: Awareness,
+  /// The state of the [Collab]. It's used to reject the local writes in read-only mode.
+  state: Arc<State>,
   /// The [UndoManager] is used to undo and redo changes. By default, the [UndoManager]
   /// is disabled. To enable it, call [Collab::enable_undo_manager].
   undo_manager: Option<UndoManager>,
 
   /// The current transaction that is being executed.
   current_txn: Option<TransactionMut<'static>>,
+
+  /// The metrics of the [Collab]. By default, the metrics are disabled. To enable them, call
+  /// [Collab::enable_metrics].
+  metrics: Option<Arc<CollabMetrics>>,
 }
 
 unsafe impl Send for CollabContext {}
 unsafe impl Sync for CollabContext {}
 
 impl CollabContext {
-  fn new(origin: CollabOrigin, awareness: Awareness) -> Self {
+  fn new(origin: CollabOrigin, awareness: Awareness, state: Arc<State>) -> Self {
     CollabContext {
       origin,
       awareness,
+      state,
       undo_manager: None,
       current_txn: None,
+      metrics: None,
     }
   }
 
+  /// Run `f` within the current transaction, or within a new one if there is none. Return
+  /// [CollabError::ReadOnly] without running `f` if the [Collab] is read-only.
   pub fn with_txn<F, T>(&mut self, f: F) -> Result<T, CollabError>
+  where
+    F: FnOnce(&mut TransactionMut) -> T,
+  {
+    self.check_writable()?;
+    self.with_txn_unchecked(f)
+  }
+
+  fn with_txn_unchecked<F, T>(&mut self, f: F) -> Result<T, CollabError>
   where
     F: FnOnce(&mut TransactionMut) -> T,
   {
     let mut cleanup = false;
     if self.current_txn.is_none() {
-      let txn: TransactionMut<'_> = self.transact_mut();
+      let txn: TransactionMut<'_> = self.transact_mut_unchecked();
       self.current_txn = Some(unsafe {
         std::mem::transmute::<yrs::TransactionMut<'_>, yrs::TransactionMut<'static>>(txn)
       });
@@ -124,6 +147,9 @@ impl CollabContext {
     if cleanup {
       // the call which initialized the transaction is responsible for cleaning it up
       self.current_txn = None;
+      if let (Some(metrics), Some(undo_manager)) = (&self.metrics, &self.undo_manager) {
+        metrics.record_undo_stack_depth(undo_manager.undo_stack().len());
+      }
     }
     result
   }
@@ -159,7 +185,41 @@ impl CollabContext {
     }
   }
 
+  /// Return [CollabError::ReadOnly] if the [Collab] is read-only.
+  ///
+  /// yrs can't roll back a transaction, so the local writes are rejected before the transaction is
+  /// created. Otherwise, the rejected changes would remain in the document and the later updates
+  /// would depend on them.
+  pub fn check_writable(&self) -> Result<(), CollabError> {
+    if self.state.is_read_only() {
+      return Err(CollabError::ReadOnly);
+    }
+    Ok(())
+  }
+
+  /// Create a new transaction, or return [CollabError::ReadOnly] if the [Collab] is read-only.
+  pub fn try_transact_mut(&mut self) -> Result<TransactionMut, CollabError> {
+    self.check_writable()?;
+    Ok(self.transact_mut_unchecked())
+  }
+
+  /// Create a new transaction.
+  ///
+  /// The read-only mode isn't enforced here: the transaction is created and a warning is logged
+  /// if the [Collab] is read-only. Use [CollabContext::try_transact_mut] when the write must
+  /// respect the read-only mode, and [CollabContext::apply_update] to apply the remote updates.
   pub fn transact_mut(&mut self) -> TransactionMut {
+    if self.state.is_read_only() {
+      tracing::warn!("🟡create a transaction of the read-only collab");
+    }
+    self.transact_mut_unchecked()
+  }
+
+  fn transact_mut_unchecked(&mut self) -> TransactionMut {
+    // The transaction is recorded by the [CollabMetricsPlugin] when it's committed.
+    if let Some(metrics) = &self.metrics {
+      metrics.begin_transaction();
+    }
     self.doc().transact_mut_with(self.origin.clone())
   }
 
@@ -173,8 +233,10 @@ impl CollabContext {
     Ok(undo_manager.redo_blocking())
   }
 
+  /// Apply the update to the document. It's allowed in read-only mode, because applying an update
+  /// doesn't create new local changes.
   pub fn apply_update(&mut self, update: Update) -> Result<(), CollabError> {
-    self.with_txn(|tx| tx.apply_update(update))??;
+    self.with_txn_unchecked(|tx| tx.apply_update(update))??;
     Ok(())
   }
 
@@ -246,27 +308,40 @@ impl Collab {
     skip_gc: bool,
   ) -> Result<Self, CollabError> {
     let mut collab = Self::new_with_origin(origin, object_id, plugins, skip_gc);
+    collab.load_data_source(data_source)?;
+    Ok(collab)
+  }
+
+  fn load_data_source(&mut self, data_source: DataSource) -> Result<(), CollabError> {
+    let start = self
+      .context
+      .metrics
+      .as_ref()
+      .map(|_| std::time::Instant::now());
     match data_source {
       DataSource::Disk(disk) => {
         if let Some(disk) = disk {
-          disk.load_collab_from_disk(&mut collab);
+          disk.load_collab_from_disk(self);
         }
       },
       DataSource::DocStateV1(doc_state) => {
         if !doc_state.is_empty() {
           let update = Update::decode_v1(&doc_state)?;
-          collab.context.apply_update(update)?;
+          self.context.apply_update(update)?;
         }
       },
       DataSource::DocStateV2(doc_state) => {
         if !doc_state.is_empty() {
           let update = Update::decode_v2(&doc_state)?;
-          collab.context.apply_update(update)?;
+          self.context.apply_update(update)?;
         }
       },
     }
 
-    Ok(collab)
+    if let (Some(metrics), Some(start)) = (&self.context.metrics, start) {
+      metrics.record_load_doc(start.elapsed());
+    }
+    Ok(())
   }
 
   pub fn clear_plugins(&self) {
@@ -276,6 +351,53 @@ impl Collab {
     }
   }
 
+  /// Remove the plugin with the given [CollabPlugin::name]. The removed plugin will be destroyed.
+  /// Return false if there is no such plugin.
+  pub fn remove_plugin(&self, name: &str) -> bool {
+    match self.plugins.remove(name) {
+      None => false,
+      Some(plugin) => {
+        plugin.destroy();
+        true
+      },
+    }
+  }
+
+  /// Return the names of the plugins in the order they are called.
+  pub fn plugin_names(&self) -> Vec<String> {
+    self.plugins.names()
+  }
+
+  /// Subscribe to the errors returned by the plugins' hooks.
+  pub fn subscribe_plugin_error(&self) -> PluginErrorReceiver {
+    self.plugins.subscribe_error()
+  }
+
+  pub(crate) fn plugin_error_sender(&self) -> PluginErrorSender {
+    self.plugins.error_sender()
+  }
+
+  /// Return a future that resolves once all the work queued by the plugins is done. Call it
+  /// before closing the [Collab] to make sure the pending updates of the
+  /// [AsyncPluginAdapter](crate::core::async_plugin::AsyncPluginAdapter)s
+  /// are persisted or sent.
+  ///
+  /// The returned future doesn't borrow the [Collab], so it can be awaited after the [Collab]
+  /// is dropped.
+  pub fn flush_plugins(&self) -> impl Future<Output = ()> + Send + 'static {
+    let mut futures = vec![];
+    self.plugins.each(|plugin| {
+      if let Some(fut) = plugin.flush(&self.object_id) {
+        futures.push(fut);
+      }
+    });
+    async move {
+      for fut in futures {
+        fut.await;
+      }
+    }
+  }
+
   pub fn new_with_origin<T: AsRef<str>>(
     origin: CollabOrigin,
     object_id: T,
@@ -291,7 +413,7 @@ impl Collab {
     let awareness = Awareness::new(doc);
     Self {
       object_id,
-      context: CollabContext::new(origin, awareness),
+      context: CollabContext::new(origin, awareness, state.clone()),
       state,
       data,
       meta,
@@ -321,15 +443,23 @@ impl Collab {
     let doc = self.context.doc();
     {
       let origin = self.origin();
-      self
-        .plugins
-        .each(|plugin| plugin.init(&self.object_id, origin, doc));
+      self.plugins.each(|plugin| {
+        if let Err(err) = plugin.init(&self.object_id, origin, doc) {
+          self
+            .plugins
+            .report_error(plugin.name(), &self.object_id, err);
+        }
+      });
     }
     self.observe_update();
     {
-      self
-        .plugins
-        .each(|plugin| plugin.did_init(self, &self.object_id));
+      self.plugins.each(|plugin| {
+        if let Err(err) = plugin.did_init(self, &self.object_id) {
+          self
+            .plugins
+            .report_error(plugin.name(), &self.object_id, err);
+        }
+      });
     }
   }
 
@@ -387,19 +517,21 @@ impl Collab {
     self.index_json_sender.subscribe()
   }
 
-  /// Add a plugin to the [Collab]. The plugin's callbacks will be called in the order they are added.
+  /// Add a plugin to the [Collab]. The plugin's callbacks will be called according to its
+  /// [CollabPlugin::priority]. See [Plugins] for more details.
   pub fn add_plugin(&self, plugin: Box<dyn CollabPlugin>) {
     self.add_plugins([plugin]);
   }
 
-  /// Add plugins to the [Collab]. The plugin's callbacks will be called in the order they are added.
+  /// Add plugins to the [Collab]. The plugin's callbacks will be called according to their
+  /// [CollabPlugin::priority]. See [Plugins] for more details.
   pub fn add_plugins<I>(&self, plugins: I)
   where
     I: IntoIterator<Item = Box<dyn CollabPlugin>>,
   {
     for plugin in plugins.into_iter() {
-      if !self.plugins.push_front(plugin) {
-        tracing::error!("Only one cloud storage plugin can be added to a collab instance.");
+      if let Err(err) = self.plugins.insert(plugin) {
+        tracing::error!("🔴{}", err);
       }
     }
   }
@@ -412,6 +544,20 @@ impl Collab {
     self.state.set_snapshot_state(snapshot_state);
   }
 
+  /// Switch the [Collab] into read-only mode. In read-only mode, the local writes of the fallible
+  /// APIs, [CollabContext::try_transact_mut], [CollabContext::with_txn], [Collab::try_insert] and
+  /// [Collab::try_remove], are rejected with [CollabError::ReadOnly] before they reach the
+  /// document. [Collab::insert] and [Collab::remove] log and skip the write, and
+  /// [CollabContext::transact_mut] only logs it. The remote updates applied by
+  /// [CollabContext::apply_update] still apply.
+  pub fn set_read_only(&self, read_only: bool) {
+    self.state.set_read_only(read_only);
+  }
+
+  pub fn is_read_only(&self) -> bool {
+    self.state.is_read_only()
+  }
+
   pub fn observe_data<F>(&self, f: F) -> MapSubscription
   where
     F: Fn(&TransactionMut, &MapEvent) + Send + Sync + 'static,
@@ -429,14 +575,54 @@ impl Collab {
     });
   }
 
-  pub fn insert<P>(&mut self, key: &str, value: P) -> P::Return
+  /// Check the local write to the given paths against the read-only mode and the
+  /// [CollabPlugin::check_update] of the plugins. It must be called before the write, because a
+  /// write can't be rolled back once it's applied to the document. The paths start from the root
+  /// section, for example, `["data", "name"]`.
+  ///
+  /// Only [Collab::try_insert], [Collab::try_remove], [Collab::insert] and [Collab::remove] call
+  /// it, with the path of their top-level key. The writes made through a transaction, including
+  /// the writes to the nested types, aren't checked unless the caller calls it before the write.
+  pub fn check_local_update(&self, paths: &[Path]) -> Result<(), CollabError> {
+    self.context.check_writable()?;
+    let mut vetoed = None;
+    self.plugins.each(|plugin| {
+      if vetoed.is_none() && !plugin.check_update(&self.object_id, self.origin(), paths) {
+        vetoed = Some(plugin.name().to_string());
+      }
+    });
+    match vetoed {
+      None => Ok(()),
+      Some(plugin) => {
+        tracing::warn!("🟡{} update is vetoed by {}", self.object_id, plugin);
+        Err(CollabError::UpdateVetoed(plugin))
+      },
+    }
+  }
+
+  /// Insert the value under the key of the data section. Return None and leave the document
+  /// unchanged if the write is rejected, see [Collab::try_insert].
+  pub fn insert<P>(&mut self, key: &str, value: P) -> Option<P::Return>
   where
     P: Prelim,
   {
-    self
-      .context
-      .with_txn(|tx| self.data.insert(tx, key, value))
-      .unwrap()
+    match self.try_insert(key, value) {
+      Ok(value) => Some(value),
+      Err(err) => {
+        tracing::warn!("🟡{} skip inserting {}: {}", self.object_id, key, err);
+        None
+      },
+    }
+  }
+
+  /// Insert the value under the key of the data section. Return an error without changing the
+  /// document if the write is rejected by [Collab::check_local_update].
+  pub fn try_insert<P>(&mut self, key: &str, value: P) -> Result<P::Return, CollabError>
+  where
+    P: Prelim,
+  {
+    self.check_local_update(&[Path::from(vec![DATA_SECTION, key])])?;
+    self.context.with_txn(|tx| self.data.insert(tx, key, value))
   }
 
   pub fn get<V>(&self, key: &str) -> Option<V>
@@ -448,11 +634,23 @@ impl Collab {
     V::try_from(value).ok()
   }
 
+  /// Remove the key of the data section. Return None and leave the document unchanged if the
+  /// write is rejected, see [Collab::try_remove].
   pub fn remove(&mut self, key: &str) -> Option<Out> {
-    self
-      .context
-      .with_txn(|tx| self.data.remove(tx, key))
-      .unwrap()
+    match self.try_remove(key) {
+      Ok(value) => value,
+      Err(err) => {
+        tracing::warn!("🟡{} skip removing {}: {}", self.object_id, key, err);
+        None
+      },
+    }
+  }
+
+  /// Remove the key of the data section. Return an error without changing the document if the
+  /// write is rejected by [Collab::check_local_update].
+  pub fn try_remove(&mut self, key: &str) -> Result<Option<Out>, CollabError> {
+    self.check_local_update(&[Path::from(vec![DATA_SECTION, key])])?;
+    self.context.with_txn(|tx| self.data.remove(tx, key))
   }
 
   pub fn enable_undo_redo(&mut self) {
@@ -471,6 +669,28 @@ impl Collab {
     self.context.undo_manager = Some(undo_manager);
   }
 
+  /// Enable the metrics of the [Collab]. It adds a [CollabMetricsPlugin] that records the
+  /// document and awareness updates, and starts measuring the transactions created by the
+  /// [Collab]. Calling it multiple times returns the same [CollabMetrics].
+  pub fn enable_metrics(&mut self) -> Arc<CollabMetrics> {
+    if let Some(metrics) = &self.context.metrics {
+      return metrics.clone();
+    }
+    let metrics = Arc::new(CollabMetrics::new(&self.object_id));
+    self.add_plugin(Box::new(CollabMetricsPlugin::new(
+      self.origin().clone(),
+      self.client_id(),
+      metrics.clone(),
+    )));
+    self.context.metrics = Some(metrics.clone());
+    metrics
+  }
+
+  /// Returns the [CollabMetrics] if the metrics are enabled.
+  pub fn metrics(&self) -> Option<Arc<CollabMetrics>> {
+    self.context.metrics.clone()
+  }
+
   /// Returns the doc state and the state vector.
   pub fn encode_collab_v1<F, E>(&self, validate: F) -> Result<EncodedCollab, E>
   where
@@ -487,6 +707,12 @@ impl Collab {
     tx.get_encoded_collab_v2()
   }
 
+  /// Compare the collab with the [EncodedCollab] of the remote, for example the server, to find
+  /// out why they diverge. See [SyncDiagnostics].
+  pub fn diagnose_sync(&self, remote: &EncodedCollab) -> Result<SyncDiagnostics, CollabError> {
+    SyncDiagnostics::compare(&self.object_id, &self.context.transact(), remote)
+  }
+
   pub fn to_json(&self) -> Any {
     self.data.to_json(&self.context.transact())
   }
@@ -520,7 +746,11 @@ fn observe_awareness(
 ) -> Subscription {
   awareness.on_update(move |awareness, e, _| {
     if let Ok(update) = awareness.update_with_clients(e.all_changes()) {
-      plugins.each(|plugin| plugin.receive_local_state(&origin, &oid, e, &update));
+      plugins.each(|plugin| {
+        if let Err(err) = plugin.receive_local_state(&origin, &oid, e, &update) {
+          plugins.report_error(plugin.name(), &oid, err);
+        }
+      });
     }
   })
 }
@@ -538,13 +768,17 @@ fn observe_doc(
   let cloned_plugins = plugins.clone();
   let update_sub = doc
     .observe_update_v1(move |txn, event| {
+      let remote_origin = CollabOrigin::from(txn);
       // If the origin of the txn is none, it means that the update is coming from a remote source.
       cloned_plugins.each(|plugin| {
-        plugin.receive_update(&cloned_oid, txn, &event.update);
+        if let Err(err) = plugin.receive_update(&cloned_oid, txn, &event.update) {
+          cloned_plugins.report_error(plugin.name(), &cloned_oid, err);
+        }
 
-        let remote_origin = CollabOrigin::from(txn);
         if remote_origin == local_origin {
-          plugin.receive_local_update(&local_origin, &cloned_oid, &event.update);
+          if let Err(err) = plugin.receive_local_update(&local_origin, &cloned_oid, &event.update) {
+            cloned_plugins.report_error(plugin.name(), &cloned_oid, err);
+          }
         } else {
           #[cfg(feature = "verbose_log")]
           tracing::trace!("{} did apply remote {} update", local_origin, remote_origin);
@@ -555,7 +789,11 @@ fn observe_doc(
 
   let after_txn_sub = doc
     .observe_after_transaction(move |txn| {
-      plugins.each(|plugin| plugin.after_transaction(&oid, txn))
+      plugins.each(|plugin| {
+        if let Err(err) = plugin.after_transaction(&oid, txn) {
+          plugins.report_error(plugin.name(), &oid, err);
+        }
+      })
     })
     .ok();
 
@@ -570,6 +808,8 @@ pub struct CollabBuilder {
   object_id: String,
   source: DataSource,
   skip_gc: bool,
+  enable_metrics: bool,
+  read_only: bool,
 }
 
 /// The raw data of a collab document. It is a list of updates. Each of them can be parsed by
@@ -616,6 +856,8 @@ impl CollabBuilder {
       device_id: "".to_string(),
       source: data_source,
       skip_gc: true,
+      enable_metrics: false,
+      read_only: false,
     }
   }
 
@@ -640,15 +882,27 @@ impl CollabBuilder {
     self
   }
 
+  /// Enable the metrics of the [Collab]. Unlike [Collab::enable_metrics], the time spent on
+  /// loading the document from the [DataSource] is also recorded.
+  pub fn with_metrics(mut self, enable_metrics: bool) -> Self {
+    self.enable_metrics = enable_metrics;
+    self
+  }
+
+  /// Create the [Collab] in read-only mode. See [Collab::set_read_only] for more details.
+  pub fn with_read_only(mut self, read_only: bool) -> Self {
+    self.read_only = read_only;
+    self
+  }
+
   pub fn build(self) -> Result<Collab, CollabError> {
     let origin = CollabOrigin::Client(CollabClient::new(self.uid, self.device_id));
-    let collab = Collab::new_with_source(
-      origin,
-      &self.object_id,
-      self.source,
-      self.plugins,
-      self.skip_gc,
-    )?;
+    let mut collab = Collab::new_with_origin(origin, &self.object_id, self.plugins, self.skip_gc);
+    if self.enable_metrics {
+      collab.enable_metrics();
+    }
+    collab.load_data_source(self.source)?;
+    collab.set_read_only(self.read_only);
     Ok(collab)
   }
 }
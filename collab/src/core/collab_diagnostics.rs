@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::Encode;
+use yrs::{DeleteSet, Doc, ReadTxn, StateVector, Transact, Update};
+
+use crate::entity::{EncodedCollab, EncoderVersion};
+use crate::error::CollabError;
+
+/// The result of comparing a local collab with a remote [EncodedCollab], see
+/// [Collab::diagnose_sync](crate::core::collab::Collab::diagnose_sync).
+///
+/// It's meant to be attached to support tickets when a client and the server disagree on the
+/// content of a collab, so it only contains clock values and sizes, never the content itself. Call
+/// [SyncDiagnostics::to_json_value] to get it as JSON.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct SyncDiagnostics {
+  pub object_id: String,
+  /// True if both sides have the same state vector and the same delete set, and none of them has
+  /// pending updates.
+  pub is_in_sync: bool,
+  /// The clients whose clock or deleted length differ between the two sides, ordered by client id.
+  pub diverged_clients: Vec<ClientDiagnostics>,
+  pub local: SideDiagnostics,
+  pub remote: SideDiagnostics,
+  /// False if the checksum of the remote doc state doesn't match its content.
+  pub remote_checksum_valid: bool,
+  /// False if the state vector that comes with the remote [EncodedCollab] is not the state vector
+  /// of its doc state.
+  pub remote_state_vector_consistent: bool,
+}
+
+/// The clock and the deleted length of a client on both sides.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct ClientDiagnostics {
+  pub client_id: u64,
+  /// The number of integrated clocks of the client on the local side.
+  pub local_clock: u32,
+  /// The number of integrated clocks of the client on the remote side.
+  pub remote_clock: u32,
+  /// The number of deleted clocks of the client on the local side.
+  pub local_deleted: u32,
+  /// The number of deleted clocks of the client on the remote side.
+  pub remote_deleted: u32,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize)]
+pub struct SideDiagnostics {
+  /// The number of bytes of the update that this side would send to the other side, that is, the
+  /// updates and the deletions the other side is missing.
+  pub bytes_to_send: usize,
+  /// The structs that were received but can't be integrated because some of their dependencies
+  /// are missing.
+  pub pending_update: Option<PendingUpdateDiagnostics>,
+  /// The deletions that were received but can't be applied because the deleted structs are
+  /// missing. yrs drops the deletions of the clients this side has no struct of, so they are not
+  /// reported here.
+  pub pending_delete_set: Vec<DeleteRange>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct PendingUpdateDiagnostics {
+  /// The size of the pending update, encoded with [Encode::encode_v1].
+  pub len: usize,
+  /// The dependencies that block the integration of the pending update.
+  pub missing_dependencies: Vec<MissingDependency>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct MissingDependency {
+  pub client_id: u64,
+  /// The pending update needs the structs of the client starting at this clock.
+  pub clock: u32,
+  /// True if the other side has the struct at the clock, so syncing with it unblocks the
+  /// pending update.
+  pub available_on_other_side: bool,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct DeleteRange {
+  pub client_id: u64,
+  pub start: u32,
+  pub end: u32,
+}
+
+impl SyncDiagnostics {
+  /// Compare the document of `local` with the remote [EncodedCollab].
+  pub fn compare<T: ReadTxn>(
+    object_id: &str,
+    local: &T,
+    remote: &EncodedCollab,
+  ) -> Result<Self, CollabError> {
+    let remote_doc = Doc::new();
+    {
+      let mut txn = remote_doc.transact_mut();
+      if !remote.doc_state.is_empty() {
+        let update = match remote.version {
+          EncoderVersion::V1 => Update::decode_v1(&remote.doc_state)?,
+          EncoderVersion::V2 => Update::decode_v2(&remote.doc_state)?,
+        };
+        txn.apply_update(update)?;
+      }
+    }
+    let remote_txn = remote_doc.transact();
+
+    let local_sv = local.state_vector();
+    let remote_sv = remote_txn.state_vector();
+    let local_deleted = deleted_lens(local.snapshot().delete_set);
+    let remote_deleted = deleted_lens(remote_txn.snapshot().delete_set);
+
+    let mut client_ids = local_sv
+      .iter()
+      .chain(remote_sv.iter())
+      .map(|(client_id, _)| *client_id)
+      .chain(local_deleted.keys().copied())
+      .chain(remote_deleted.keys().copied())
+      .collect::<Vec<_>>();
+    client_ids.sort_unstable();
+    client_ids.dedup();
+    let diverged_clients = client_ids
+      .into_iter()
+      .map(|client_id| ClientDiagnostics {
+        client_id,
+        local_clock: local_sv.get(&client_id),
+        remote_clock: remote_sv.get(&client_id),
+        local_deleted: local_deleted.get(&client_id).copied().unwrap_or(0),
+        remote_deleted: remote_deleted.get(&client_id).copied().unwrap_or(0),
+      })
+      .filter(|client| {
+        client.local_clock != client.remote_clock || client.local_deleted != client.remote_deleted
+      })
+      .collect::<Vec<_>>();
+
+    let local_side = SideDiagnostics {
+      bytes_to_send: local.encode_state_as_update_v1(&remote_sv).len(),
+      pending_update: pending_update(local, &remote_sv),
+      pending_delete_set: pending_delete_set(local),
+    };
+    let remote_side = SideDiagnostics {
+      bytes_to_send: remote_txn.encode_state_as_update_v1(&local_sv).len(),
+      pending_update: pending_update(&remote_txn, &local_sv),
+      pending_delete_set: pending_delete_set(&remote_txn),
+    };
+
+    let declared_sv = match remote.version {
+      EncoderVersion::V1 => StateVector::decode_v1(&remote.state_vector),
+      EncoderVersion::V2 => StateVector::decode_v2(&remote.state_vector),
+    };
+    let is_in_sync = diverged_clients.is_empty()
+      && local_side.pending_update.is_none()
+      && local_side.pending_delete_set.is_empty()
+      && remote_side.pending_update.is_none()
+      && remote_side.pending_delete_set.is_empty();
+
+    Ok(Self {
+      object_id: object_id.to_string(),
+      is_in_sync,
+      diverged_clients,
+      local: local_side,
+      remote: remote_side,
+      remote_checksum_valid: remote.verify_checksum().is_ok(),
+      remote_state_vector_consistent: declared_sv.map(|sv| sv == remote_sv).unwrap_or(false),
+    })
+  }
+
+  pub fn to_json_value(&self) -> serde_json::Value {
+    serde_json::to_value(self).unwrap_or_default()
+  }
+}
+
+/// Return the number of deleted clocks of each client.
+fn deleted_lens(mut delete_set: DeleteSet) -> BTreeMap<u64, u32> {
+  delete_set.squash();
+  delete_set
+    .iter()
+    .map(|(client_id, range)| {
+      let len = range.iter().map(|range| range.end - range.start).sum();
+      (*client_id, len)
+    })
+    .collect()
+}
+
+fn pending_update<T: ReadTxn>(txn: &T, other_sv: &StateVector) -> Option<PendingUpdateDiagnostics> {
+  let pending = txn.store().pending_update()?;
+  let mut missing_dependencies = pending
+    .missing
+    .iter()
+    .map(|(client_id, clock)| MissingDependency {
+      client_id: *client_id,
+      clock: *clock,
+      available_on_other_side: other_sv.get(client_id) > *clock,
+    })
+    .collect::<Vec<_>>();
+  missing_dependencies.sort_unstable_by_key(|dependency| dependency.client_id);
+  Some(PendingUpdateDiagnostics {
+    len: pending.update.encode_v1().len(),
+    missing_dependencies,
+  })
+}
+
+fn pending_delete_set<T: ReadTxn>(txn: &T) -> Vec<DeleteRange> {
+  let mut ranges = txn
+    .store()
+    .pending_ds()
+    .map(|delete_set| {
+      delete_set
+        .iter()
+        .flat_map(|(client_id, range)| {
+          range.iter().map(|range| DeleteRange {
+            client_id: *client_id,
+            start: range.start,
+            end: range.end,
+          })
+        })
+        .collect::<Vec<_>>()
+    })
+    .unwrap_or_default();
+  ranges.sort_unstable_by_key(|range| (range.client_id, range.start));
+  ranges
+}
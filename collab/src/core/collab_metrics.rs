@@ -0,0 +1,261 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use yrs::block::ClientID;
+use yrs::updates::encoder::Encode;
+use yrs::TransactionMut;
+
+use crate::core::awareness::{AwarenessUpdate, Event};
+use crate::core::collab_plugin::CollabPlugin;
+use crate::core::origin::CollabOrigin;
+
+/// Per-object metrics of a [Collab](crate::core::collab::Collab).
+///
+/// The metrics are collected by the [CollabMetricsPlugin] and by the [Collab](crate::core::collab::Collab)
+/// itself (transaction durations, load time and undo stack depth). Call [CollabMetrics::snapshot]
+/// to read the current values. When the `metrics` feature is enabled, every record is also
+/// forwarded to the [metrics](https://docs.rs/metrics) facade. The facade series are aggregated
+/// over all the objects, they are not labeled with the object id because the number of objects is
+/// unbounded.
+#[derive(Debug, Default)]
+pub struct CollabMetrics {
+  object_id: String,
+  local_update_count: AtomicU64,
+  local_update_bytes: AtomicU64,
+  remote_update_count: AtomicU64,
+  remote_update_bytes: AtomicU64,
+  local_awareness_update_count: AtomicU64,
+  local_awareness_update_bytes: AtomicU64,
+  remote_awareness_update_count: AtomicU64,
+  remote_awareness_update_bytes: AtomicU64,
+  /// The start of the uncommitted transaction created by
+  /// [CollabContext::transact_mut](crate::core::collab::CollabContext::transact_mut).
+  transaction_started_at: Mutex<Option<Instant>>,
+  transaction_count: AtomicU64,
+  transaction_duration_micros: AtomicU64,
+  max_transaction_duration_micros: AtomicU64,
+  load_doc_duration_micros: AtomicU64,
+  undo_stack_depth: AtomicU64,
+}
+
+/// A point-in-time copy of the [CollabMetrics].
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CollabMetricsSnapshot {
+  pub object_id: String,
+  pub local_update_count: u64,
+  pub local_update_bytes: u64,
+  pub remote_update_count: u64,
+  pub remote_update_bytes: u64,
+  pub local_awareness_update_count: u64,
+  pub local_awareness_update_bytes: u64,
+  pub remote_awareness_update_count: u64,
+  pub remote_awareness_update_bytes: u64,
+  /// The number of transactions that were created by the [Collab](crate::core::collab::Collab)
+  /// itself, via [CollabContext::transact_mut](crate::core::collab::CollabContext::transact_mut) or
+  /// [CollabContext::with_txn](crate::core::collab::CollabContext::with_txn).
+  pub transaction_count: u64,
+  pub total_transaction_duration: Duration,
+  pub max_transaction_duration: Duration,
+  pub load_doc_duration: Duration,
+  pub undo_stack_depth: u64,
+}
+
+impl CollabMetrics {
+  pub fn new(object_id: &str) -> Self {
+    Self {
+      object_id: object_id.to_string(),
+      ..Default::default()
+    }
+  }
+
+  pub fn object_id(&self) -> &str {
+    &self.object_id
+  }
+
+  pub fn record_local_update(&self, len: usize) {
+    self.local_update_count.fetch_add(1, Ordering::Relaxed);
+    self
+      .local_update_bytes
+      .fetch_add(len as u64, Ordering::Relaxed);
+    #[cfg(feature = "metrics")]
+    {
+      metrics::counter!("collab_update_count", "origin" => "local").increment(1);
+      metrics::counter!("collab_update_bytes", "origin" => "local").increment(len as u64);
+    }
+  }
+
+  pub fn record_remote_update(&self, len: usize) {
+    self.remote_update_count.fetch_add(1, Ordering::Relaxed);
+    self
+      .remote_update_bytes
+      .fetch_add(len as u64, Ordering::Relaxed);
+    #[cfg(feature = "metrics")]
+    {
+      metrics::counter!("collab_update_count", "origin" => "remote").increment(1);
+      metrics::counter!("collab_update_bytes", "origin" => "remote").increment(len as u64);
+    }
+  }
+
+  pub fn record_local_awareness_update(&self, len: usize) {
+    self
+      .local_awareness_update_count
+      .fetch_add(1, Ordering::Relaxed);
+    self
+      .local_awareness_update_bytes
+      .fetch_add(len as u64, Ordering::Relaxed);
+    #[cfg(feature = "metrics")]
+    {
+      metrics::counter!("collab_awareness_update_count", "origin" => "local").increment(1);
+      metrics::counter!("collab_awareness_update_bytes", "origin" => "local").increment(len as u64);
+    }
+  }
+
+  pub fn record_remote_awareness_update(&self, len: usize) {
+    self
+      .remote_awareness_update_count
+      .fetch_add(1, Ordering::Relaxed);
+    self
+      .remote_awareness_update_bytes
+      .fetch_add(len as u64, Ordering::Relaxed);
+    #[cfg(feature = "metrics")]
+    {
+      metrics::counter!("collab_awareness_update_count", "origin" => "remote").increment(1);
+      metrics::counter!("collab_awareness_update_bytes", "origin" => "remote")
+        .increment(len as u64);
+    }
+  }
+
+  /// Mark the start of a transaction. It's recorded by [CollabMetrics::end_transaction] once the
+  /// transaction is committed.
+  pub(crate) fn begin_transaction(&self) {
+    *self.transaction_started_at.lock().unwrap() = Some(Instant::now());
+  }
+
+  /// Record the transaction started by [CollabMetrics::begin_transaction]. The transactions that
+  /// were not created by the [Collab](crate::core::collab::Collab) are ignored.
+  pub(crate) fn end_transaction(&self) {
+    let started_at = self.transaction_started_at.lock().unwrap().take();
+    if let Some(started_at) = started_at {
+      self.record_transaction(started_at.elapsed());
+    }
+  }
+
+  pub fn record_transaction(&self, elapsed: Duration) {
+    let micros = elapsed.as_micros() as u64;
+    self.transaction_count.fetch_add(1, Ordering::Relaxed);
+    self
+      .transaction_duration_micros
+      .fetch_add(micros, Ordering::Relaxed);
+    self
+      .max_transaction_duration_micros
+      .fetch_max(micros, Ordering::Relaxed);
+    #[cfg(feature = "metrics")]
+    metrics::histogram!("collab_transaction_duration_seconds").record(elapsed.as_secs_f64());
+  }
+
+  pub fn record_load_doc(&self, elapsed: Duration) {
+    self
+      .load_doc_duration_micros
+      .store(elapsed.as_micros() as u64, Ordering::Relaxed);
+    #[cfg(feature = "metrics")]
+    metrics::histogram!("collab_load_doc_duration_seconds").record(elapsed.as_secs_f64());
+  }
+
+  pub fn record_undo_stack_depth(&self, depth: usize) {
+    self.undo_stack_depth.store(depth as u64, Ordering::Relaxed);
+    #[cfg(feature = "metrics")]
+    metrics::histogram!("collab_undo_stack_depth").record(depth as f64);
+  }
+
+  pub fn snapshot(&self) -> CollabMetricsSnapshot {
+    CollabMetricsSnapshot {
+      object_id: self.object_id.clone(),
+      local_update_count: self.local_update_count.load(Ordering::Relaxed),
+      local_update_bytes: self.local_update_bytes.load(Ordering::Relaxed),
+      remote_update_count: self.remote_update_count.load(Ordering::Relaxed),
+      remote_update_bytes: self.remote_update_bytes.load(Ordering::Relaxed),
+      local_awareness_update_count: self.local_awareness_update_count.load(Ordering::Relaxed),
+      local_awareness_update_bytes: self.local_awareness_update_bytes.load(Ordering::Relaxed),
+      remote_awareness_update_count: self.remote_awareness_update_count.load(Ordering::Relaxed),
+      remote_awareness_update_bytes: self.remote_awareness_update_bytes.load(Ordering::Relaxed),
+      transaction_count: self.transaction_count.load(Ordering::Relaxed),
+      total_transaction_duration: Duration::from_micros(
+        self.transaction_duration_micros.load(Ordering::Relaxed),
+      ),
+      max_transaction_duration: Duration::from_micros(
+        self.max_transaction_duration_micros.load(Ordering::Relaxed),
+      ),
+      load_doc_duration: Duration::from_micros(
+        self.load_doc_duration_micros.load(Ordering::Relaxed),
+      ),
+      undo_stack_depth: self.undo_stack_depth.load(Ordering::Relaxed),
+    }
+  }
+}
+
+/// A [CollabPlugin] that records the document and awareness updates and the transactions into the
+/// [CollabMetrics].
+/// It's added by [Collab::enable_metrics](crate::core::collab::Collab::enable_metrics).
+pub struct CollabMetricsPlugin {
+  local_origin: CollabOrigin,
+  local_client_id: ClientID,
+  metrics: Arc<CollabMetrics>,
+}
+
+impl CollabMetricsPlugin {
+  pub fn new(
+    local_origin: CollabOrigin,
+    local_client_id: ClientID,
+    metrics: Arc<CollabMetrics>,
+  ) -> Self {
+    Self {
+      local_origin,
+      local_client_id,
+      metrics,
+    }
+  }
+}
+
+impl CollabPlugin for CollabMetricsPlugin {
+  fn receive_update(
+    &self,
+    _object_id: &str,
+    txn: &TransactionMut,
+    update: &[u8],
+  ) -> Result<(), anyhow::Error> {
+    if CollabOrigin::from(txn) == self.local_origin {
+      self.metrics.record_local_update(update.len());
+    } else {
+      self.metrics.record_remote_update(update.len());
+    }
+    Ok(())
+  }
+
+  fn receive_local_state(
+    &self,
+    _origin: &CollabOrigin,
+    _object_id: &str,
+    _event: &Event,
+    update: &AwarenessUpdate,
+  ) -> Result<(), anyhow::Error> {
+    // The awareness observer is called for both the local changes and the applied remote
+    // updates. Only the local changes contain the state of the local client.
+    let len = update.encode_v1().len();
+    if update.clients.contains_key(&self.local_client_id) {
+      self.metrics.record_local_awareness_update(len);
+    } else {
+      self.metrics.record_remote_awareness_update(len);
+    }
+    Ok(())
+  }
+
+  fn after_transaction(
+    &self,
+    _object_id: &str,
+    _txn: &mut TransactionMut,
+  ) -> Result<(), anyhow::Error> {
+    self.metrics.end_transaction();
+    Ok(())
+  }
+}
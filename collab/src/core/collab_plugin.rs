@@ -1,11 +1,14 @@
 use crate::core::awareness::{AwarenessUpdate, Event};
 
-use arc_swap::ArcSwapOption;
-use async_trait::async_trait;
-use std::sync::atomic::AtomicBool;
+use arc_swap::ArcSwap;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
+use tokio::sync::broadcast;
 use yrs::{Doc, TransactionMut};
 
+use crate::core::collab::Path;
 use crate::core::origin::CollabOrigin;
 use crate::preclude::Collab;
 
@@ -31,33 +34,98 @@ where
 }
 
 pub trait CollabPlugin: Send + Sync + 'static {
+  /// The name of the plugin. It's used to remove the plugin by [Collab::remove_plugin] and to
+  /// identify the plugin in the [PluginError]. Defaults to the type name of the plugin, so a
+  /// plugin type that is added more than once to the same [Collab] must return a unique name:
+  /// a plugin whose name is already used is rejected by [Plugins::insert].
+  fn name(&self) -> &str {
+    std::any::type_name::<Self>()
+  }
+
+  /// The plugins with higher priority are called first. Defaults to 0.
+  fn priority(&self) -> i32 {
+    0
+  }
+
   /// Called when the plugin is initialized.
   /// The will apply the updates to the current [TransactionMut] which will restore the state of
-  /// the document.
-  fn init(&self, _object_id: &str, _origin: &CollabOrigin, _doc: &Doc) {}
+  /// the document. The returned error is reported through [Collab::subscribe_plugin_error].
+  fn init(
+    &self,
+    _object_id: &str,
+    _origin: &CollabOrigin,
+    _doc: &Doc,
+  ) -> Result<(), anyhow::Error> {
+    Ok(())
+  }
 
-  /// Called when the plugin is initialized.
-  fn did_init(&self, _collab: &Collab, _object_id: &str) {}
+  /// Called when the plugin is initialized. The returned error is reported through
+  /// [Collab::subscribe_plugin_error].
+  fn did_init(&self, _collab: &Collab, _object_id: &str) -> Result<(), anyhow::Error> {
+    Ok(())
+  }
+
+  /// Called by [Collab::check_local_update] before a local write. The `changed_paths` are the
+  /// paths of the fields the write is going to change, starting from the root section, for
+  /// example, `["data", "name"]`. Returning false vetoes the write: it's rejected before it
+  /// reaches the document.
+  ///
+  /// Only the writes to the top-level keys of the data section made by [Collab::try_insert] and
+  /// [Collab::try_remove], and their infallible variants, are checked. The writes made through a
+  /// transaction aren't, see [Collab::check_local_update].
+  fn check_update(
+    &self,
+    _object_id: &str,
+    _origin: &CollabOrigin,
+    _changed_paths: &[Path],
+  ) -> bool {
+    true
+  }
 
   /// Called when the plugin receives an update. It happens after the [TransactionMut] commit to
-  /// the Yrs document.
-  fn receive_update(&self, _object_id: &str, _txn: &TransactionMut, _update: &[u8]) {}
+  /// the Yrs document. The returned error is reported through [Collab::subscribe_plugin_error].
+  fn receive_update(
+    &self,
+    _object_id: &str,
+    _txn: &TransactionMut,
+    _update: &[u8],
+  ) -> Result<(), anyhow::Error> {
+    Ok(())
+  }
 
   /// Called when the plugin receives a local update.
   /// We use the [CollabOrigin] to know if the update comes from the local user or from a remote
-  fn receive_local_update(&self, _origin: &CollabOrigin, _object_id: &str, _update: &[u8]) {}
+  /// The returned error is reported through [Collab::subscribe_plugin_error].
+  fn receive_local_update(
+    &self,
+    _origin: &CollabOrigin,
+    _object_id: &str,
+    _update: &[u8],
+  ) -> Result<(), anyhow::Error> {
+    Ok(())
+  }
 
+  /// Called when the local awareness state changes. The returned error is reported through
+  /// [Collab::subscribe_plugin_error].
   fn receive_local_state(
     &self,
     _origin: &CollabOrigin,
     _object_id: &str,
     _event: &Event,
     _update: &AwarenessUpdate,
-  ) {
+  ) -> Result<(), anyhow::Error> {
+    Ok(())
   }
 
-  /// Called after each [TransactionMut]
-  fn after_transaction(&self, _object_id: &str, _txn: &mut TransactionMut) {}
+  /// Called after each [TransactionMut]. The returned error is reported through
+  /// [Collab::subscribe_plugin_error].
+  fn after_transaction(
+    &self,
+    _object_id: &str,
+    _txn: &mut TransactionMut,
+  ) -> Result<(), anyhow::Error> {
+    Ok(())
+  }
 
   /// Returns the type of the plugin.
   fn plugin_type(&self) -> CollabPluginType {
@@ -69,33 +137,63 @@ pub trait CollabPlugin: Send + Sync + 'static {
 
   fn start_init_sync(&self) {}
 
+  /// Return a future that resolves once all the work queued by the plugin is done, for example,
+  /// the updates that are waiting to be written to the disk. Return None if the plugin doesn't
+  /// queue any work. See [Collab::flush_plugins].
+  fn flush(&self, _object_id: &str) -> Option<PluginFuture> {
+    None
+  }
+
   /// Called when the plugin is removed
   fn destroy(&self) {}
 }
 
+pub type PluginFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
 /// Implement the [CollabPlugin] trait for Box<T> and Arc<T> where T implements CollabPlugin.
 ///
 /// A limitation of manually implementing traits for Arc<T> is that any default methods in the trait
 /// must also be explicitly implemented for Arc<T>. If not, Arc<T> will default to using the trait's
 /// default method implementations, even if the underlying type T has its own specific implementations
-#[async_trait]
 impl<T> CollabPlugin for Box<T>
 where
   T: CollabPlugin,
 {
-  fn init(&self, object_id: &str, origin: &CollabOrigin, doc: &Doc) {
-    (**self).init(object_id, origin, doc);
+  fn name(&self) -> &str {
+    (**self).name()
+  }
+
+  fn priority(&self) -> i32 {
+    (**self).priority()
+  }
+
+  fn init(&self, object_id: &str, origin: &CollabOrigin, doc: &Doc) -> Result<(), anyhow::Error> {
+    (**self).init(object_id, origin, doc)
   }
 
-  fn did_init(&self, collab: &Collab, _object_id: &str) {
+  fn did_init(&self, collab: &Collab, _object_id: &str) -> Result<(), anyhow::Error> {
     (**self).did_init(collab, _object_id)
   }
 
-  fn receive_update(&self, object_id: &str, txn: &TransactionMut, update: &[u8]) {
+  fn check_update(&self, object_id: &str, origin: &CollabOrigin, changed_paths: &[Path]) -> bool {
+    (**self).check_update(object_id, origin, changed_paths)
+  }
+
+  fn receive_update(
+    &self,
+    object_id: &str,
+    txn: &TransactionMut,
+    update: &[u8],
+  ) -> Result<(), anyhow::Error> {
     (**self).receive_update(object_id, txn, update)
   }
 
-  fn receive_local_update(&self, origin: &CollabOrigin, object_id: &str, update: &[u8]) {
+  fn receive_local_update(
+    &self,
+    origin: &CollabOrigin,
+    object_id: &str,
+    update: &[u8],
+  ) -> Result<(), anyhow::Error> {
     (**self).receive_local_update(origin, object_id, update)
   }
   fn receive_local_state(
@@ -104,11 +202,15 @@ where
     object_id: &str,
     event: &Event,
     update: &AwarenessUpdate,
-  ) {
+  ) -> Result<(), anyhow::Error> {
     (**self).receive_local_state(origin, object_id, event, update)
   }
 
-  fn after_transaction(&self, object_id: &str, txn: &mut TransactionMut) {
+  fn after_transaction(
+    &self,
+    object_id: &str,
+    txn: &mut TransactionMut,
+  ) -> Result<(), anyhow::Error> {
     (**self).after_transaction(object_id, txn)
   }
   fn plugin_type(&self) -> CollabPluginType {
@@ -119,23 +221,79 @@ where
     (**self).start_init_sync()
   }
 
+  fn flush(&self, object_id: &str) -> Option<PluginFuture> {
+    (**self).flush(object_id)
+  }
+
   fn destroy(&self) {
     (**self).destroy()
   }
 }
 
-#[derive(Clone, Default)]
+/// An error returned by one of the fallible [CollabPlugin] hooks. The errors are broadcast to the
+/// receivers returned by [Collab::subscribe_plugin_error].
+#[derive(Debug, Clone)]
+pub struct PluginError {
+  pub plugin_name: String,
+  pub object_id: String,
+  pub error: Arc<anyhow::Error>,
+}
+
+impl PluginError {
+  pub(crate) fn report(
+    sender: &PluginErrorSender,
+    plugin_name: &str,
+    object_id: &str,
+    error: anyhow::Error,
+  ) {
+    let error = PluginError {
+      plugin_name: plugin_name.to_string(),
+      object_id: object_id.to_string(),
+      error: Arc::new(error),
+    };
+    tracing::error!("{}", error);
+    let _ = sender.send(error);
+  }
+}
+
+impl Display for PluginError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "plugin {} failed on {}: {}",
+      self.plugin_name, self.object_id, self.error
+    )
+  }
+}
+
+pub type PluginErrorSender = broadcast::Sender<PluginError>;
+pub type PluginErrorReceiver = broadcast::Receiver<PluginError>;
+
+/// The reason why [Plugins::insert] skipped a plugin.
+#[derive(Debug, thiserror::Error)]
+pub enum PluginInsertError {
+  #[error("The plugin {0} is already added")]
+  DuplicateName(String),
+
+  #[error("Only one cloud storage plugin can be added to a collab instance")]
+  CloudStorageExists,
+}
+
+/// The plugins of a [Collab]. The plugins with higher [CollabPlugin::priority] are called first.
+/// The plugins with the same priority are called in reverse order of addition: the most recently
+/// added plugin is called first.
+#[derive(Clone)]
 pub struct Plugins(Arc<PluginsInner>);
 
-#[derive(Default)]
 struct PluginsInner {
-  has_cloud_storage: AtomicBool,
-  head: ArcSwapOption<Node>,
+  list: ArcSwap<Vec<Arc<dyn CollabPlugin>>>,
+  error_sender: PluginErrorSender,
 }
 
-struct Node {
-  next: ArcSwapOption<Node>,
-  value: Box<dyn CollabPlugin>,
+impl Default for Plugins {
+  fn default() -> Self {
+    Self::new(vec![])
+  }
 }
 
 impl Plugins {
@@ -144,72 +302,97 @@ impl Plugins {
     I: IntoIterator<Item = Box<dyn CollabPlugin>>,
   {
     let list = Plugins(Arc::new(PluginsInner {
-      has_cloud_storage: AtomicBool::new(false),
-      head: ArcSwapOption::new(None),
+      list: ArcSwap::new(Arc::new(vec![])),
+      error_sender: broadcast::channel(100).0,
     }));
     for plugin in plugins {
-      list.push_front(plugin);
+      if let Err(err) = list.insert(plugin) {
+        tracing::error!("🔴{}", err);
+      }
     }
     list
   }
 
-  pub fn push_front(&self, plugin: Box<dyn CollabPlugin>) -> bool {
-    let inner = &*self.0;
-    if plugin.plugin_type() == CollabPluginType::CloudStorage {
-      let already_existed = inner
-        .has_cloud_storage
-        .swap(true, std::sync::atomic::Ordering::SeqCst);
-      if already_existed {
-        return false; // skip adding the plugin
-      }
-    }
-    let new = Arc::new(Node {
-      next: ArcSwapOption::new(None),
-      value: plugin,
+  /// Insert the plugin according to its priority. The plugin is skipped if there is already a
+  /// plugin with the same [CollabPlugin::name], or if it's a [CollabPluginType::CloudStorage]
+  /// plugin and there is already one.
+  pub fn insert(&self, plugin: Box<dyn CollabPlugin>) -> Result<(), PluginInsertError> {
+    let new: Arc<dyn CollabPlugin> = Arc::from(plugin);
+    let is_cloud_storage = new.plugin_type() == CollabPluginType::CloudStorage;
+    let priority = new.priority();
+    let mut result = Ok(());
+    self.0.list.rcu(|old_list| {
+      let mut list = Vec::clone(old_list);
+      result = if list.iter().any(|plugin| plugin.name() == new.name()) {
+        Err(PluginInsertError::DuplicateName(new.name().to_string()))
+      } else if is_cloud_storage
+        && list
+          .iter()
+          .any(|plugin| plugin.plugin_type() == CollabPluginType::CloudStorage)
+      {
+        Err(PluginInsertError::CloudStorageExists)
+      } else {
+        let index = list
+          .iter()
+          .position(|plugin| plugin.priority() <= priority)
+          .unwrap_or(list.len());
+        list.insert(index, new.clone());
+        Ok(())
+      };
+      list
     });
-    inner.head.rcu(|old_head| {
-      new.next.store(old_head.clone());
-      Some(new.clone())
+    result
+  }
+
+  /// Remove the plugin with the given name. Return the removed plugin if it exists.
+  pub fn remove(&self, name: &str) -> Option<Arc<dyn CollabPlugin>> {
+    let mut removed = None;
+    self.0.list.rcu(|old_list| {
+      let mut list = Vec::clone(old_list);
+      removed = list
+        .iter()
+        .position(|plugin| plugin.name() == name)
+        .map(|index| list.remove(index));
+      list
     });
-    true
+    removed
   }
 
-  pub fn remove_all(&self) -> RemovedPluginsIter {
-    let inner = &*self.0;
-    let current = inner.head.swap(None);
-    inner
-      .has_cloud_storage
-      .store(false, std::sync::atomic::Ordering::SeqCst);
-    RemovedPluginsIter { current }
+  pub fn remove_all(&self) -> Vec<Arc<dyn CollabPlugin>> {
+    let list = self.0.list.swap(Arc::new(vec![]));
+    Vec::clone(&list)
+  }
+
+  /// Return the names of the plugins in the order they are called.
+  pub fn names(&self) -> Vec<String> {
+    let list = self.0.list.load();
+    list
+      .iter()
+      .map(|plugin| plugin.name().to_string())
+      .collect()
   }
 
   pub fn each<F>(&self, mut f: F)
   where
-    F: FnMut(&Box<dyn CollabPlugin>),
+    F: FnMut(&Arc<dyn CollabPlugin>),
   {
-    let mut curr = self.0.head.load_full();
-    while let Some(node) = curr {
-      f(&node.value);
-      curr = node.next.load_full();
+    // Iterate over a snapshot of the list, so the plugins can be added or removed in the callback.
+    let list = self.0.list.load_full();
+    for plugin in list.iter() {
+      f(plugin);
     }
   }
-}
 
-pub struct RemovedPluginsIter {
-  current: Option<Arc<Node>>,
-}
+  /// Broadcast the error returned by the plugin's hook.
+  pub fn report_error(&self, plugin_name: &str, object_id: &str, error: anyhow::Error) {
+    PluginError::report(&self.0.error_sender, plugin_name, object_id, error);
+  }
 
-impl Iterator for RemovedPluginsIter {
-  type Item = Box<dyn CollabPlugin>;
-
-  fn next(&mut self) -> Option<Self::Item> {
-    match self.current.take() {
-      None => None,
-      Some(node) => {
-        self.current = node.next.load_full();
-        let node = Arc::into_inner(node)?;
-        Some(node.value)
-      },
-    }
+  pub fn subscribe_error(&self) -> PluginErrorReceiver {
+    self.0.error_sender.subscribe()
+  }
+
+  pub(crate) fn error_sender(&self) -> PluginErrorSender {
+    self.0.error_sender.clone()
   }
 }
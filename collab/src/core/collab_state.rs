@@ -1,5 +1,5 @@
 use arc_swap::ArcSwap;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
 
 use tokio::sync::watch;
@@ -107,6 +107,7 @@ pub struct State {
   init_state: AtomicU32,
   sync_state: AtomicU32,
   snapshot_state: ArcSwap<SnapshotState>,
+  read_only: AtomicBool,
   pub(crate) sync_state_notifier: Arc<watch::Sender<SyncState>>,
   pub(crate) snapshot_state_notifier: Arc<watch::Sender<SnapshotState>>,
 }
@@ -120,6 +121,7 @@ impl State {
       init_state: AtomicU32::new(InitState::Uninitialized as u32),
       sync_state: AtomicU32::new(SyncState::InitSyncBegin as u32),
       snapshot_state: ArcSwap::new(SnapshotState::WaitingForSnapshot.into()),
+      read_only: AtomicBool::new(false),
       sync_state_notifier: Arc::new(sync_state_notifier),
       snapshot_state_notifier: Arc::new(snapshot_state_notifier),
     }
@@ -141,6 +143,14 @@ impl State {
     self.sync_state().is_sync_finished()
   }
 
+  pub fn is_read_only(&self) -> bool {
+    self.read_only.load(Ordering::Acquire)
+  }
+
+  pub fn set_read_only(&self, read_only: bool) {
+    self.read_only.store(read_only, Ordering::Release);
+  }
+
   pub fn set_init_state(&self, state: InitState) {
     self.init_state.store(state as u32, Ordering::Release);
   }
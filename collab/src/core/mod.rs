@@ -1,5 +1,8 @@
 pub use yrs::sync::awareness;
+pub mod async_plugin;
 pub mod collab;
+pub mod collab_diagnostics;
+pub mod collab_metrics;
 pub mod collab_plugin;
 mod collab_search;
 pub mod collab_state;
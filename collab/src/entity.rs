@@ -2,12 +2,18 @@ use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
+use crate::error::CollabError;
+
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub struct EncodedCollab {
   pub state_vector: Bytes,
   pub doc_state: Bytes,
   #[serde(default)]
   pub version: EncoderVersion,
+  /// CRC32 checksum of the [EncodedCollab::doc_state]. It's `None` for the data that was encoded
+  /// before the checksum was introduced.
+  #[serde(default)]
+  pub checksum: Option<u32>,
 }
 
 #[derive(Default, Serialize_repr, Deserialize_repr, Eq, PartialEq, Debug, Clone)]
@@ -20,44 +26,107 @@ pub enum EncoderVersion {
 
 impl EncodedCollab {
   pub fn new_v1<T: Into<Bytes>>(state_vector: T, doc_state: T) -> Self {
+    let doc_state = doc_state.into();
     Self {
       state_vector: state_vector.into(),
-      doc_state: doc_state.into(),
+      checksum: Some(calculate_checksum(&doc_state)),
+      doc_state,
       version: EncoderVersion::V1,
     }
   }
 
   pub fn new_v2<T: Into<Bytes>>(state_vector: T, doc_state: T) -> Self {
+    let doc_state = doc_state.into();
     Self {
       state_vector: state_vector.into(),
-      doc_state: doc_state.into(),
+      checksum: Some(calculate_checksum(&doc_state)),
+      doc_state,
       version: EncoderVersion::V2,
     }
   }
 
+  /// Verify the [EncodedCollab::doc_state] against the stored checksum. The data without checksum
+  /// is considered valid.
+  pub fn verify_checksum(&self) -> Result<(), CollabError> {
+    match self.checksum {
+      None => Ok(()),
+      Some(expected) => verify_checksum(&self.doc_state, expected),
+    }
+  }
+
   pub fn encode_to_bytes(&self) -> Result<Vec<u8>, bincode::Error> {
     bincode::serialize(self)
   }
 
+  /// Decode the [EncodedCollab] from bytes and verify its checksum if it has one. A checksum
+  /// mismatch is returned as a [bincode::ErrorKind::Custom] error, use
+  /// [EncodedCollab::decode_and_verify] to get [CollabError::ChecksumMismatch] instead.
   pub fn decode_from_bytes(encoded: &[u8]) -> Result<EncodedCollab, bincode::Error> {
+    let encoded_collab = Self::decode_unverified(encoded)?;
+    encoded_collab
+      .verify_checksum()
+      .map_err(|err| Box::new(bincode::ErrorKind::Custom(err.to_string())))?;
+    Ok(encoded_collab)
+  }
+
+  /// Decode the [EncodedCollab] from bytes and verify its checksum if it has one.
+  pub fn decode_and_verify(encoded: &[u8]) -> Result<EncodedCollab, CollabError> {
+    let encoded_collab = Self::decode_unverified(encoded)?;
+    encoded_collab.verify_checksum()?;
+    Ok(encoded_collab)
+  }
+
+  fn decode_unverified(encoded: &[u8]) -> Result<EncodedCollab, bincode::Error> {
     // The deserialize_encoded_collab function first tries to deserialize the data as EncodedCollab.
-    // If it fails (presumably because the data was serialized with EncodedCollabV0), it then tries to deserialize as EncodedCollabV0.
-    // After successfully deserializing as EncodedCollabV0, it constructs a new EncodedCollab object with the data from
-    // EncodedCollabV0 and sets the version to a default value.
-    match bincode::deserialize::<EncodedCollab>(encoded) {
-      Ok(new_collab) => Ok(new_collab),
-      Err(_) => {
-        let old_collab: EncodedCollabV0 = bincode::deserialize(encoded)?;
-        Ok(EncodedCollab {
-          state_vector: old_collab.state_vector,
-          doc_state: old_collab.doc_state,
-          version: EncoderVersion::V1,
-        })
+    // If it fails (presumably because the data was serialized with EncodedCollabV1 or EncodedCollabV0),
+    // it falls back to the older formats one by one. The old formats don't carry a checksum, so
+    // the checksum of the decoded EncodedCollab is None.
+    let encoded_collab = match bincode::deserialize::<EncodedCollab>(encoded) {
+      Ok(new_collab) => new_collab,
+      Err(_) => match bincode::deserialize::<EncodedCollabV1>(encoded) {
+        Ok(collab) => EncodedCollab {
+          state_vector: collab.state_vector,
+          doc_state: collab.doc_state,
+          version: collab.version,
+          checksum: None,
+        },
+        Err(_) => {
+          let old_collab: EncodedCollabV0 = bincode::deserialize(encoded)?;
+          EncodedCollab {
+            state_vector: old_collab.state_vector,
+            doc_state: old_collab.doc_state,
+            version: EncoderVersion::V1,
+            checksum: None,
+          }
+        },
       },
-    }
+    };
+    Ok(encoded_collab)
   }
 }
 
+/// Calculate the CRC32 checksum of the given data.
+pub fn calculate_checksum(data: &[u8]) -> u32 {
+  crc32fast::hash(data)
+}
+
+/// Return [CollabError::ChecksumMismatch] if the checksum of the given data is not equal to the
+/// expected one.
+pub fn verify_checksum(data: &[u8], expected: u32) -> Result<(), CollabError> {
+  let actual = calculate_checksum(data);
+  if actual != expected {
+    return Err(CollabError::ChecksumMismatch { expected, actual });
+  }
+  Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EncodedCollabV1 {
+  pub state_vector: Bytes,
+  pub doc_state: Bytes,
+  pub version: EncoderVersion,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct EncodedCollabV0 {
   pub state_vector: Bytes,
@@ -83,17 +152,14 @@ mod tests {
         state_vector: Bytes::from(vec![1, 2, 3]),
         doc_state: Bytes::from(vec![4, 5, 6]),
         version: EncoderVersion::V1,
+        checksum: None,
       }
     );
   }
 
   #[test]
   fn new_encoded_collab_decoded_into_old_encoded_collab() {
-    let new_encoded_collab = EncodedCollab {
-      state_vector: Bytes::from(vec![1, 2, 3]),
-      doc_state: Bytes::from(vec![4, 5, 6]),
-      version: EncoderVersion::V1,
-    };
+    let new_encoded_collab = EncodedCollab::new_v1(vec![1, 2, 3], vec![4, 5, 6]);
 
     let new_encoded_collab_bytes = new_encoded_collab.encode_to_bytes().unwrap();
     let old_encoded_collab: EncodedCollabV0 =
@@ -105,4 +171,39 @@ mod tests {
       new_encoded_collab.state_vector
     );
   }
+
+  #[test]
+  fn encoded_collab_without_checksum_decoded_into_new_encoded_collab() {
+    let encoded_collab = EncodedCollabV1 {
+      state_vector: Bytes::from(vec![1, 2, 3]),
+      doc_state: Bytes::from(vec![4, 5, 6]),
+      version: EncoderVersion::V2,
+    };
+
+    let bytes = bincode::serialize(&encoded_collab).unwrap();
+    let new_encoded_collab = EncodedCollab::decode_from_bytes(&bytes).unwrap();
+    assert_eq!(new_encoded_collab.version, EncoderVersion::V2);
+    assert_eq!(new_encoded_collab.checksum, None);
+  }
+
+  #[test]
+  fn corrupted_encoded_collab_fails_checksum_verification() {
+    let mut encoded_collab = EncodedCollab::new_v1(vec![1, 2, 3], vec![4, 5, 6]);
+    let bytes = encoded_collab.encode_to_bytes().unwrap();
+    assert!(EncodedCollab::decode_and_verify(&bytes).is_ok());
+
+    encoded_collab.doc_state = Bytes::from(vec![4, 5, 7]);
+    let bytes = encoded_collab.encode_to_bytes().unwrap();
+    assert!(EncodedCollab::decode_from_bytes(&bytes).is_err());
+    let err = EncodedCollab::decode_and_verify(&bytes).unwrap_err();
+    assert!(matches!(err, CollabError::ChecksumMismatch { .. }));
+  }
+
+  #[test]
+  fn encoded_collab_without_version_decoded_from_json() {
+    let json = r#"{"state_vector":[1,2,3],"doc_state":[4,5,6]}"#;
+    let encoded_collab: EncodedCollab = serde_json::from_str(json).unwrap();
+    assert_eq!(encoded_collab.version, EncoderVersion::V1);
+    assert_eq!(encoded_collab.checksum, None);
+  }
 }
@@ -36,6 +36,18 @@ pub enum CollabError {
   #[error("Failed to apply update: {0}")]
   UpdateFailed(#[from] yrs::error::UpdateError),
 
+  #[error("The collab is read-only")]
+  ReadOnly,
+
+  #[error("The update is vetoed by the plugin: {0}")]
+  UpdateVetoed(String),
+
+  #[error("Checksum mismatch, expected: {expected}, actual: {actual}")]
+  ChecksumMismatch { expected: u32, actual: u32 },
+
+  #[error(transparent)]
+  Bincode(#[from] bincode::Error),
+
   #[error("Internal failure: {0}")]
   Internal(#[from] Box<dyn std::error::Error + Send + Sync>),
 }
@@ -0,0 +1,139 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use collab::core::async_plugin::{AsyncCollabPlugin, AsyncPluginAdapter};
+use collab::core::collab::DataSource;
+use collab::core::origin::CollabOrigin;
+use collab::preclude::{CollabBuilder, CollabPlugin};
+
+#[derive(Clone, Default)]
+struct RecordPlugin(Arc<Mutex<Vec<Vec<u8>>>>);
+
+impl CollabPlugin for RecordPlugin {
+  fn receive_local_update(
+    &self,
+    _origin: &CollabOrigin,
+    _object_id: &str,
+    update: &[u8],
+  ) -> Result<(), anyhow::Error> {
+    self.0.lock().unwrap().push(update.to_vec());
+    Ok(())
+  }
+}
+
+#[derive(Clone, Default)]
+struct SlowPlugin {
+  updates: Arc<Mutex<Vec<Vec<u8>>>>,
+  flushed: Arc<Mutex<bool>>,
+  fail: bool,
+}
+
+#[async_trait]
+impl AsyncCollabPlugin for SlowPlugin {
+  fn name(&self) -> &str {
+    "slow"
+  }
+
+  async fn receive_local_update(
+    &self,
+    _origin: &CollabOrigin,
+    _object_id: &str,
+    update: &[u8],
+  ) -> Result<(), anyhow::Error> {
+    // Simulate the I/O
+    for _ in 0..10 {
+      tokio::task::yield_now().await;
+    }
+    if self.fail {
+      return Err(anyhow!("connection lost"));
+    }
+    self.updates.lock().unwrap().push(update.to_vec());
+    Ok(())
+  }
+
+  async fn flush(&self, _object_id: &str) -> Result<(), anyhow::Error> {
+    *self.flushed.lock().unwrap() = true;
+    Ok(())
+  }
+}
+
+#[tokio::test]
+async fn async_plugin_receive_updates_in_order_test() {
+  let plugin = SlowPlugin::default();
+  let sync_plugin = RecordPlugin::default();
+  let mut collab = CollabBuilder::new(1, "1", DataSource::Disk(None))
+    .with_device_id("1")
+    .with_plugin(AsyncPluginAdapter::new(plugin.clone()))
+    .with_plugin(sync_plugin.clone())
+    .build()
+    .unwrap();
+  collab.initialize();
+  for i in 0..20 {
+    collab.insert(&i.to_string(), i);
+  }
+  assert!(!*plugin.flushed.lock().unwrap());
+
+  collab.flush_plugins().await;
+  assert!(*plugin.flushed.lock().unwrap());
+
+  // The updates are received in the same order as the sync plugin received them
+  let updates = plugin.updates.lock().unwrap().clone();
+  assert_eq!(updates.len(), 20);
+  assert_eq!(updates, *sync_plugin.0.lock().unwrap());
+}
+
+#[tokio::test]
+async fn async_plugin_full_queue_reports_error_test() {
+  let plugin = SlowPlugin::default();
+  let sync_plugin = RecordPlugin::default();
+  let mut collab = CollabBuilder::new(1, "1", DataSource::Disk(None))
+    .with_device_id("1")
+    .with_plugin(AsyncPluginAdapter::with_capacity(plugin.clone(), 3))
+    .with_plugin(sync_plugin.clone())
+    .build()
+    .unwrap();
+  collab.initialize();
+  let mut errors = collab.subscribe_plugin_error();
+  // The background task can't run on the current-thread runtime until the test awaits, so the
+  // queue is filled by the init event and the two events of the first update. The events of the
+  // other updates are dropped instead of blocking the observers.
+  for i in 0..5 {
+    collab.insert(&i.to_string(), i);
+  }
+  for _ in 0..8 {
+    let error = errors.try_recv().unwrap();
+    assert_eq!(error.plugin_name, "slow");
+  }
+  assert!(errors.try_recv().is_err());
+
+  // The flush waits for room in the queue
+  collab.flush_plugins().await;
+  assert!(*plugin.flushed.lock().unwrap());
+  assert_eq!(
+    *plugin.updates.lock().unwrap(),
+    sync_plugin.0.lock().unwrap()[..1]
+  );
+  assert_eq!(sync_plugin.0.lock().unwrap().len(), 5);
+}
+
+#[tokio::test]
+async fn async_plugin_error_is_reported_test() {
+  let plugin = SlowPlugin {
+    fail: true,
+    ..Default::default()
+  };
+  let mut collab = CollabBuilder::new(1, "1", DataSource::Disk(None))
+    .with_device_id("1")
+    .with_plugin(AsyncPluginAdapter::new(plugin))
+    .build()
+    .unwrap();
+  collab.initialize();
+  let mut errors = collab.subscribe_plugin_error();
+  collab.insert("1", "a");
+  collab.flush_plugins().await;
+
+  let error = errors.try_recv().unwrap();
+  assert_eq!(error.plugin_name, "slow");
+  assert_eq!(error.error.to_string(), "connection lost");
+}
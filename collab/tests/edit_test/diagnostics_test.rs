@@ -0,0 +1,130 @@
+use collab::preclude::Collab;
+use yrs::updates::decoder::Decode;
+use yrs::{ReadTxn, Update};
+
+fn update_since(collab: &mut Collab, f: impl FnOnce(&mut Collab)) -> Vec<u8> {
+  let state_vector = collab.transact().state_vector();
+  f(collab);
+  collab.transact().encode_state_as_update_v1(&state_vector)
+}
+
+fn apply(collab: &mut Collab, update: &[u8]) {
+  collab
+    .transact_mut()
+    .apply_update(Update::decode_v1(update).unwrap())
+    .unwrap();
+}
+
+#[test]
+fn diagnose_in_sync_collab_test() {
+  let mut local = Collab::new(1, "1", "1", vec![], false);
+  local.insert("1", "a");
+  let remote = local.encode_collab_v2();
+
+  let diagnostics = local.diagnose_sync(&remote).unwrap();
+  assert!(diagnostics.is_in_sync);
+  assert!(diagnostics.diverged_clients.is_empty());
+  assert!(diagnostics.remote_checksum_valid);
+  assert!(diagnostics.remote_state_vector_consistent);
+  assert!(diagnostics.local.pending_update.is_none());
+  assert!(diagnostics.remote.pending_update.is_none());
+}
+
+#[test]
+fn diagnose_diverged_collab_test() {
+  let mut local = Collab::new(1, "1", "1", vec![], false);
+  let mut remote = Collab::new(1, "1", "2", vec![], false);
+  let update = update_since(&mut remote, |collab| {
+    collab.insert("1", "a");
+  });
+  apply(&mut local, &update);
+  local.insert("2", "b");
+  remote.insert("3", "c".repeat(100));
+  let update = update_since(&mut remote, |collab| {
+    collab.remove("1");
+  });
+
+  let diagnostics = local
+    .diagnose_sync(
+      &remote
+        .encode_collab_v1(|_| Ok::<(), anyhow::Error>(()))
+        .unwrap(),
+    )
+    .unwrap();
+  assert!(!diagnostics.is_in_sync);
+  let local_client = diagnostics
+    .diverged_clients
+    .iter()
+    .find(|client| client.client_id == local.client_id())
+    .unwrap();
+  assert!(local_client.local_clock > 0);
+  assert_eq!(local_client.remote_clock, 0);
+  let remote_client = diagnostics
+    .diverged_clients
+    .iter()
+    .find(|client| client.client_id == remote.client_id())
+    .unwrap();
+  assert!(remote_client.remote_clock > remote_client.local_clock);
+  assert!(remote_client.remote_deleted > remote_client.local_deleted);
+  assert!(diagnostics.remote.bytes_to_send > 100);
+  assert!(diagnostics.local.bytes_to_send < diagnostics.remote.bytes_to_send);
+
+  // The deletion alone doesn't change the state vector, but it's reported by the delete set.
+  apply(&mut local, &update);
+  let diagnostics = local
+    .diagnose_sync(
+      &remote
+        .encode_collab_v1(|_| Ok::<(), anyhow::Error>(()))
+        .unwrap(),
+    )
+    .unwrap();
+  let remote_client = diagnostics
+    .diverged_clients
+    .iter()
+    .find(|client| client.client_id == remote.client_id())
+    .unwrap();
+  assert_eq!(remote_client.local_deleted, remote_client.remote_deleted);
+}
+
+#[test]
+fn diagnose_pending_update_test() {
+  let mut local = Collab::new(1, "1", "1", vec![], false);
+  let mut remote = Collab::new(1, "1", "2", vec![], false);
+  let first = update_since(&mut remote, |collab| {
+    collab.insert("1", "a");
+  });
+  let _second = update_since(&mut remote, |collab| {
+    collab.insert("2", "b");
+  });
+  let third = update_since(&mut remote, |collab| {
+    collab.insert("3", "c");
+  });
+  let deletion = update_since(&mut remote, |collab| {
+    collab.remove("3");
+  });
+  // The third update depends on the second one, which the local collab never received. The
+  // deletion is kept pending only if the local collab knows some structs of the remote client,
+  // otherwise yrs drops it.
+  apply(&mut local, &first);
+  apply(&mut local, &third);
+  apply(&mut local, &deletion);
+
+  let diagnostics = local.diagnose_sync(&remote.encode_collab_v2()).unwrap();
+  assert!(!diagnostics.is_in_sync);
+  let pending_update = diagnostics.local.pending_update.clone().unwrap();
+  assert!(pending_update.len > 0);
+  assert_eq!(pending_update.missing_dependencies.len(), 1);
+  let dependency = &pending_update.missing_dependencies[0];
+  assert_eq!(dependency.client_id, remote.client_id());
+  assert!(dependency.available_on_other_side);
+  assert!(!diagnostics.local.pending_delete_set.is_empty());
+  assert!(diagnostics.remote.pending_update.is_none());
+
+  let json = diagnostics.to_json_value();
+  assert_eq!(json["object_id"], "1");
+  assert_eq!(json["is_in_sync"], false);
+  assert_eq!(
+    json["local"]["pending_update"]["missing_dependencies"][0]["client_id"],
+    remote.client_id()
+  );
+}
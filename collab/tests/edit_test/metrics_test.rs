@@ -0,0 +1,90 @@
+use collab::core::collab::DataSource;
+use collab::core::origin::CollabOrigin;
+use collab::preclude::{Collab, CollabBuilder};
+use yrs::updates::decoder::Decode;
+use yrs::{Map, Transact, Update};
+
+#[tokio::test]
+async fn metrics_record_local_and_remote_updates_test() {
+  let mut collab = Collab::new(1, "1", "1", vec![], false);
+  let metrics = collab.enable_metrics();
+  collab.enable_undo_redo();
+  collab.initialize();
+
+  collab.insert("1", "a");
+  collab.insert("2", "b");
+
+  let snapshot = metrics.snapshot();
+  assert_eq!(snapshot.object_id, "1");
+  assert_eq!(snapshot.local_update_count, 2);
+  assert!(snapshot.local_update_bytes > 0);
+  assert_eq!(snapshot.remote_update_count, 0);
+  assert_eq!(snapshot.transaction_count, 2);
+  assert_eq!(snapshot.undo_stack_depth, 1);
+
+  // The transactions created by transact_mut are measured too.
+  {
+    let mut txn = collab.context.transact_mut();
+    collab.data.insert(&mut txn, "4", "d");
+  }
+  let snapshot = metrics.snapshot();
+  assert_eq!(snapshot.local_update_count, 3);
+  assert_eq!(snapshot.transaction_count, 3);
+
+  let mut remote_collab = Collab::new(2, "1", "2", vec![], false);
+  remote_collab.insert("3", "c");
+  let doc_state = remote_collab
+    .encode_collab_v1(|_| Ok::<(), anyhow::Error>(()))
+    .unwrap()
+    .doc_state;
+  {
+    // Apply the update with the server origin, so it's treated as a remote update.
+    let doc = collab.get_awareness().doc();
+    let mut txn = doc.transact_mut_with(CollabOrigin::Server);
+    txn
+      .apply_update(Update::decode_v1(&doc_state).unwrap())
+      .unwrap();
+  }
+
+  let snapshot = metrics.snapshot();
+  assert_eq!(snapshot.local_update_count, 3);
+  assert_eq!(snapshot.remote_update_count, 1);
+  // The transaction wasn't created by the collab.
+  assert_eq!(snapshot.transaction_count, 3);
+  assert_eq!(snapshot.remote_update_bytes, doc_state.len() as u64);
+}
+
+#[tokio::test]
+async fn metrics_record_awareness_update_test() {
+  let mut collab = Collab::new(1, "1", "1", vec![], false);
+  let metrics = collab.enable_metrics();
+  collab.initialize();
+
+  collab.emit_awareness_state();
+  let snapshot = metrics.snapshot();
+  assert_eq!(snapshot.local_awareness_update_count, 1);
+  assert!(snapshot.local_awareness_update_bytes > 0);
+  assert_eq!(snapshot.remote_awareness_update_count, 0);
+
+  let mut remote_collab = Collab::new(2, "1", "2", vec![], false);
+  remote_collab.emit_awareness_state();
+  let update = remote_collab.get_awareness().update().unwrap();
+  collab.get_awareness().apply_update(update).unwrap();
+  let snapshot = metrics.snapshot();
+  assert_eq!(snapshot.local_awareness_update_count, 1);
+  assert_eq!(snapshot.remote_awareness_update_count, 1);
+  assert!(snapshot.remote_awareness_update_bytes > 0);
+}
+
+#[tokio::test]
+async fn metrics_disabled_by_default_test() {
+  let collab = Collab::new_with_origin(CollabOrigin::Empty, "1", vec![], false);
+  assert!(collab.metrics().is_none());
+
+  let collab = CollabBuilder::new(1, "1", DataSource::Disk(None))
+    .with_device_id("1")
+    .with_metrics(true)
+    .build()
+    .unwrap();
+  assert!(collab.metrics().is_some());
+}
@@ -1,5 +1,10 @@
+mod async_plugin_test;
 mod awareness_test;
+mod diagnostics_test;
 mod insert_test;
+mod metrics_test;
 mod observer_test;
+mod permission_test;
+mod plugin_test;
 mod restore_test;
 mod state_vec_test;
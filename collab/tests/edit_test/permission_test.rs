@@ -0,0 +1,152 @@
+use std::sync::{Arc, Mutex};
+
+use collab::core::collab::{DataSource, Path};
+use collab::core::origin::CollabOrigin;
+use collab::error::CollabError;
+use collab::preclude::{Collab, CollabBuilder, CollabPlugin, Transact, Update};
+use yrs::updates::decoder::Decode;
+
+use crate::util::CollabStateCachePlugin;
+
+/// Return the value of the key in the document encoded by [Collab::encode_collab_v2], which is
+/// what gets persisted and synced.
+fn encoded_value(collab: &Collab, key: &str) -> Option<String> {
+  let encoded = collab.encode_collab_v2();
+  let restored =
+    Collab::new_with_source(CollabOrigin::Empty, "1", encoded.into(), vec![], false).unwrap();
+  restored.get::<String>(key)
+}
+
+#[tokio::test]
+async fn read_only_collab_rejects_local_update_test() {
+  let plugin = CollabStateCachePlugin::new();
+  let mut collab = CollabBuilder::new(1, "1", DataSource::Disk(None))
+    .with_device_id("1")
+    .with_plugin(plugin.clone())
+    .with_read_only(true)
+    .build()
+    .unwrap();
+  collab.initialize();
+  assert!(collab.is_read_only());
+
+  assert!(matches!(
+    collab.try_insert("1", "a"),
+    Err(CollabError::ReadOnly)
+  ));
+  assert!(matches!(
+    collab.with_txn(|_| ()),
+    Err(CollabError::ReadOnly)
+  ));
+  assert!(collab.try_transact_mut().is_err());
+  // The infallible APIs skip the write instead of panicking
+  assert!(collab.insert("1", "a").is_none());
+  assert!(collab.remove("1").is_none());
+  assert!(encoded_value(&collab, "1").is_none());
+
+  // Remote updates still apply and are dispatched to the plugins
+  let mut remote_collab = Collab::new(2, "1", "2", vec![], false);
+  remote_collab.insert("2", "b");
+  let doc_state = remote_collab
+    .encode_collab_v1(|_| Ok::<(), anyhow::Error>(()))
+    .unwrap()
+    .doc_state;
+  {
+    let doc = collab.get_awareness().doc();
+    let mut txn = doc.transact_mut_with(CollabOrigin::Server);
+    txn
+      .apply_update(Update::decode_v1(&doc_state).unwrap())
+      .unwrap();
+  }
+  remote_collab.insert("3", "c");
+  let doc_state = remote_collab.encode_collab_v2().doc_state;
+  collab
+    .apply_update(Update::decode_v2(&doc_state).unwrap())
+    .unwrap();
+  assert_eq!(encoded_value(&collab, "2").unwrap(), "b");
+  assert_eq!(encoded_value(&collab, "3").unwrap(), "c");
+
+  let restored = Collab::new_with_source(
+    CollabOrigin::Empty,
+    "1",
+    plugin.get_doc_state().unwrap(),
+    vec![],
+    false,
+  )
+  .unwrap();
+  assert_eq!(restored.get::<String>("2").unwrap(), "b");
+
+  collab.set_read_only(false);
+  collab.insert("4", "d");
+  let restored = Collab::new_with_source(
+    CollabOrigin::Empty,
+    "1",
+    plugin.get_doc_state().unwrap(),
+    vec![],
+    false,
+  )
+  .unwrap();
+  assert_eq!(restored.get::<String>("4").unwrap(), "d");
+  assert_eq!(encoded_value(&collab, "4").unwrap(), "d");
+}
+
+#[derive(Clone, Default)]
+struct DenyPathPlugin {
+  denied: String,
+  checked_paths: Arc<Mutex<Vec<Vec<String>>>>,
+}
+
+impl CollabPlugin for DenyPathPlugin {
+  fn check_update(&self, _object_id: &str, _origin: &CollabOrigin, changed_paths: &[Path]) -> bool {
+    let mut checked_paths = self.checked_paths.lock().unwrap();
+    checked_paths.extend(changed_paths.iter().map(|path| path.to_vec()));
+    !changed_paths
+      .iter()
+      .any(|path| path.get(1) == Some(&self.denied))
+  }
+}
+
+#[tokio::test]
+async fn plugin_veto_update_touching_denied_path_test() {
+  let deny_plugin = DenyPathPlugin {
+    denied: "secret".to_string(),
+    ..Default::default()
+  };
+  let cache_plugin = CollabStateCachePlugin::new();
+  let mut collab = Collab::new(1, "1", "1", vec![], false);
+  collab.add_plugins([
+    Box::new(deny_plugin.clone()) as Box<dyn CollabPlugin>,
+    Box::new(cache_plugin.clone()),
+  ]);
+  collab.initialize();
+
+  collab.insert("name", "a");
+  assert!(matches!(
+    collab.try_insert("secret", "b"),
+    Err(CollabError::UpdateVetoed(_))
+  ));
+  assert!(collab.try_remove("secret").is_err());
+  assert!(collab.insert("secret", "b").is_none());
+  assert_eq!(
+    *deny_plugin.checked_paths.lock().unwrap(),
+    vec![
+      vec!["data".to_string(), "name".to_string()],
+      vec!["data".to_string(), "secret".to_string()],
+      vec!["data".to_string(), "secret".to_string()],
+      vec!["data".to_string(), "secret".to_string()],
+    ]
+  );
+  assert!(collab.get::<String>("secret").is_none());
+  assert!(encoded_value(&collab, "secret").is_none());
+  assert_eq!(encoded_value(&collab, "name").unwrap(), "a");
+
+  let restored = Collab::new_with_source(
+    CollabOrigin::Empty,
+    "1",
+    cache_plugin.get_doc_state().unwrap(),
+    vec![],
+    false,
+  )
+  .unwrap();
+  assert_eq!(restored.get::<String>("name").unwrap(), "a");
+  assert!(restored.get::<String>("secret").is_none());
+}
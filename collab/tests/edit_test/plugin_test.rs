@@ -0,0 +1,176 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::anyhow;
+use collab::core::origin::CollabOrigin;
+use collab::preclude::{Collab, CollabPlugin};
+use yrs::{Doc, TransactionMut};
+
+#[derive(Clone)]
+struct NamedPlugin {
+  name: String,
+  priority: i32,
+  fail: bool,
+  calls: Arc<Mutex<Vec<String>>>,
+  destroyed: Arc<AtomicBool>,
+}
+
+impl NamedPlugin {
+  fn new(name: &str, priority: i32, calls: Arc<Mutex<Vec<String>>>) -> Self {
+    Self {
+      name: name.to_string(),
+      priority,
+      fail: false,
+      calls,
+      destroyed: Arc::new(AtomicBool::new(false)),
+    }
+  }
+}
+
+impl CollabPlugin for NamedPlugin {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn priority(&self) -> i32 {
+    self.priority
+  }
+
+  fn receive_update(
+    &self,
+    _object_id: &str,
+    _txn: &TransactionMut,
+    _update: &[u8],
+  ) -> Result<(), anyhow::Error> {
+    self.calls.lock().unwrap().push(self.name.clone());
+    if self.fail {
+      return Err(anyhow!("disk is full"));
+    }
+    Ok(())
+  }
+
+  fn destroy(&self) {
+    self.destroyed.store(true, Ordering::SeqCst);
+  }
+}
+
+#[tokio::test]
+async fn plugins_called_in_priority_order_test() {
+  let calls = Arc::new(Mutex::new(vec![]));
+  let mut collab = Collab::new_with_origin(CollabOrigin::Empty, "1", vec![], false);
+  collab.add_plugin(Box::new(NamedPlugin::new("low", -1, calls.clone())));
+  collab.add_plugin(Box::new(NamedPlugin::new("first", 0, calls.clone())));
+  collab.add_plugin(Box::new(NamedPlugin::new("high", 10, calls.clone())));
+  collab.add_plugin(Box::new(NamedPlugin::new("second", 0, calls.clone())));
+  collab.initialize();
+  assert_eq!(
+    collab.plugin_names(),
+    vec!["high", "second", "first", "low"]
+  );
+
+  collab.insert("1", "a");
+  assert_eq!(
+    calls.lock().unwrap().as_slice(),
+    &["high", "second", "first", "low"]
+  );
+}
+
+#[tokio::test]
+async fn remove_plugin_test() {
+  let calls = Arc::new(Mutex::new(vec![]));
+  let plugin = NamedPlugin::new("removable", 0, calls.clone());
+  let destroyed = plugin.destroyed.clone();
+  let mut collab = Collab::new_with_origin(CollabOrigin::Empty, "1", vec![], false);
+  collab.add_plugin(Box::new(plugin));
+  collab.initialize();
+
+  assert!(collab.remove_plugin("removable"));
+  assert!(destroyed.load(Ordering::SeqCst));
+  assert!(!collab.remove_plugin("removable"));
+  assert!(collab.plugin_names().is_empty());
+
+  collab.insert("1", "a");
+  assert!(calls.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn duplicate_plugin_name_is_rejected_test() {
+  let calls = Arc::new(Mutex::new(vec![]));
+  let first = NamedPlugin::new("same", 0, calls.clone());
+  let mut second = NamedPlugin::new("same", 1, calls.clone());
+  second.fail = true;
+  let mut collab = Collab::new_with_origin(CollabOrigin::Empty, "1", vec![], false);
+  collab.add_plugins([Box::new(first) as Box<dyn CollabPlugin>, Box::new(second)]);
+  collab.initialize();
+  assert_eq!(collab.plugin_names(), vec!["same"]);
+
+  // Only the first plugin is called, the second one would report an error
+  let mut errors = collab.subscribe_plugin_error();
+  collab.insert("1", "a");
+  assert_eq!(calls.lock().unwrap().as_slice(), &["same"]);
+  assert!(errors.try_recv().is_err());
+
+  assert!(collab.remove_plugin("same"));
+  assert!(collab.plugin_names().is_empty());
+}
+
+#[tokio::test]
+async fn plugin_error_is_reported_test() {
+  let calls = Arc::new(Mutex::new(vec![]));
+  let mut failing = NamedPlugin::new("failing", 1, calls.clone());
+  failing.fail = true;
+  let mut collab = Collab::new_with_origin(CollabOrigin::Empty, "1", vec![], false);
+  collab.add_plugin(Box::new(failing));
+  collab.add_plugin(Box::new(NamedPlugin::new("healthy", 0, calls.clone())));
+  collab.initialize();
+  let mut errors = collab.subscribe_plugin_error();
+
+  collab.insert("1", "a");
+  let error = errors.try_recv().unwrap();
+  assert_eq!(error.plugin_name, "failing");
+  assert_eq!(error.object_id, "1");
+  assert_eq!(error.error.to_string(), "disk is full");
+
+  // The failing plugin doesn't prevent the other plugins from receiving the update
+  assert_eq!(calls.lock().unwrap().as_slice(), &["failing", "healthy"]);
+}
+
+struct FailingHooksPlugin;
+
+impl CollabPlugin for FailingHooksPlugin {
+  fn name(&self) -> &str {
+    "failing_hooks"
+  }
+
+  fn init(
+    &self,
+    _object_id: &str,
+    _origin: &CollabOrigin,
+    _doc: &Doc,
+  ) -> Result<(), anyhow::Error> {
+    Err(anyhow!("init failed"))
+  }
+
+  fn after_transaction(
+    &self,
+    _object_id: &str,
+    _txn: &mut TransactionMut,
+  ) -> Result<(), anyhow::Error> {
+    Err(anyhow!("after transaction failed"))
+  }
+}
+
+#[tokio::test]
+async fn init_and_after_transaction_errors_are_reported_test() {
+  let mut collab = Collab::new_with_origin(CollabOrigin::Empty, "1", vec![], false);
+  collab.add_plugin(Box::new(FailingHooksPlugin));
+  let mut errors = collab.subscribe_plugin_error();
+  collab.initialize();
+  let error = errors.try_recv().unwrap();
+  assert_eq!(error.plugin_name, "failing_hooks");
+  assert_eq!(error.error.to_string(), "init failed");
+
+  collab.insert("1", "a");
+  let error = errors.try_recv().unwrap();
+  assert_eq!(error.error.to_string(), "after transaction failed");
+}
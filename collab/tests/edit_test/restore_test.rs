@@ -481,7 +481,13 @@ impl ReceiveUpdatesPlugin {
 }
 
 impl CollabPlugin for ReceiveUpdatesPlugin {
-  fn receive_update(&self, _object_id: &str, _txn: &TransactionMut, update: &[u8]) {
+  fn receive_update(
+    &self,
+    _object_id: &str,
+    _txn: &TransactionMut,
+    update: &[u8],
+  ) -> Result<(), anyhow::Error> {
     self.updates.lock().unwrap().push(update.to_vec());
+    Ok(())
   }
 }
@@ -66,13 +66,19 @@ impl CollabStateCachePlugin {
 }
 
 impl CollabPlugin for CollabStateCachePlugin {
-  fn receive_update(&self, _object_id: &str, txn: &TransactionMut, update: &[u8]) {
+  fn receive_update(
+    &self,
+    _object_id: &str,
+    txn: &TransactionMut,
+    update: &[u8],
+  ) -> Result<(), anyhow::Error> {
     let mut write_guard = self.0.write().unwrap();
     if write_guard.is_empty() {
       let doc_state = txn.encode_state_as_update_v1(&StateVector::default());
       write_guard.push(Bytes::from(doc_state));
     }
     write_guard.push(Bytes::from(update.to_vec()));
+    Ok(())
   }
 }
 